@@ -0,0 +1,261 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Export of asset genesis and consignments into binary, bech32, YAML and
+//! JSON formats and QR codes
+
+use std::path::Path;
+
+use lnpbp::strict_encoding::{self, strict_serialize};
+use qrcode_generator::{QRCodeError, QrCodeEcc};
+use rgb::{Consignment, ToBech32};
+
+/// Maximum number of characters encoded into a single QR code frame, which
+/// still can be reliably scanned from a screen by mobile devices
+pub const QR_FRAME_CAPACITY: usize = 1000;
+
+/// Size (in pixels) of the exported QR code images
+pub const QR_IMAGE_SIZE: usize = 1024;
+
+/// Interval (in milliseconds) between frames of animated QR codes
+pub const QR_FRAME_INTERVAL: u64 = 500;
+
+#[derive(Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// Unable to encode contract data: {0}
+    #[from]
+    Encoding(strict_encoding::Error),
+
+    /// Unable to serialize contract data into YAML: {0}
+    #[from]
+    Yaml(serde_yaml::Error),
+
+    /// Unable to serialize contract data into JSON: {0}
+    #[from]
+    Json(serde_json::Error),
+
+    /// Unable to generate QR code: {0}
+    #[from]
+    Qr(QRCodeError),
+}
+
+/// Contract data to export
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display)]
+pub enum ExportData {
+    /// Contract genesis only, which is sufficient to create asset
+    /// allocations, but not to verify its current state
+    #[display("genesis")]
+    Genesis,
+
+    /// Full consignment with all known state transitions
+    #[display("consignment")]
+    Consignment,
+}
+
+/// File format of the exported data
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display)]
+pub enum ExportFormat {
+    /// Strict-encoded binary data
+    #[display("binary")]
+    Binary,
+
+    /// Bech32-encoded text
+    #[display("bech32")]
+    Bech32,
+
+    /// Human-readable YAML dump
+    #[display("YAML")]
+    Yaml,
+
+    /// Human-readable JSON dump
+    #[display("JSON")]
+    Json,
+
+    /// PNG images with QR codes of bech32-encoded data, split into multiple
+    /// frames when the data exceed [`QR_FRAME_CAPACITY`]
+    #[display("QR code")]
+    Qr,
+}
+
+impl ExportFormat {
+    /// Detects export format from the file extension; defaults to binary
+    pub fn with_path(path: &Path) -> ExportFormat {
+        match path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .as_deref()
+        {
+            Some("txt") | Some("bech32") => ExportFormat::Bech32,
+            Some("yaml") | Some("yml") => ExportFormat::Yaml,
+            Some("json") => ExportFormat::Json,
+            Some("png") => ExportFormat::Qr,
+            _ => ExportFormat::Binary,
+        }
+    }
+}
+
+/// Serializes contract data into the given format. For the QR format, the
+/// returned data are bech32 string, which has to be passed to [`qr_frames`].
+pub fn export(
+    consignment: &Consignment,
+    data: ExportData,
+    format: ExportFormat,
+) -> Result<Vec<u8>, Error> {
+    let genesis = &consignment.genesis;
+    Ok(match (format, data) {
+        // Binary genesis has no prefix to be told apart from a consignment,
+        // so it is exported as a consignment without state transitions
+        (ExportFormat::Binary, ExportData::Genesis) => strict_serialize(
+            &Consignment::with(genesis.clone(), none!(), none!(), none!()),
+        )?,
+        (ExportFormat::Binary, ExportData::Consignment) => {
+            strict_serialize(consignment)?
+        }
+        (ExportFormat::Bech32, ExportData::Genesis)
+        | (ExportFormat::Qr, ExportData::Genesis) => {
+            genesis.to_bech32_string().into_bytes()
+        }
+        (ExportFormat::Bech32, ExportData::Consignment)
+        | (ExportFormat::Qr, ExportData::Consignment) => {
+            consignment.to_bech32_string().into_bytes()
+        }
+        (ExportFormat::Yaml, ExportData::Genesis) => {
+            serde_yaml::to_string(genesis)?.into_bytes()
+        }
+        (ExportFormat::Yaml, ExportData::Consignment) => {
+            serde_yaml::to_string(consignment)?.into_bytes()
+        }
+        (ExportFormat::Json, ExportData::Genesis) => {
+            serde_json::to_string_pretty(genesis)?.into_bytes()
+        }
+        (ExportFormat::Json, ExportData::Consignment) => {
+            serde_json::to_string_pretty(consignment)?.into_bytes()
+        }
+    })
+}
+
+/// Splits data into QR code frames. Data fitting a single frame are
+/// returned as is; otherwise each frame is prefixed with `p{n}of{total} `,
+/// following the animated QR convention of Specter and other wallets.
+pub fn qr_frames(data: &str) -> Vec<String> {
+    if data.len() <= QR_FRAME_CAPACITY {
+        return vec![data.to_owned()];
+    }
+    let chunks = data
+        .as_bytes()
+        .chunks(QR_FRAME_CAPACITY)
+        .collect::<Vec<_>>();
+    let total = chunks.len();
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(no, chunk)| {
+            format!("p{}of{} {}", no + 1, total, String::from_utf8_lossy(chunk))
+        })
+        .collect()
+}
+
+/// Renders QR code frame into PNG image
+pub fn qr_png(frame: &str, size: usize) -> Result<Vec<u8>, Error> {
+    Ok(qrcode_generator::to_png_to_vec(
+        frame,
+        QrCodeEcc::Low,
+        size,
+    )?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use lnpbp::Chain;
+
+    use super::*;
+    use crate::controller::asset_import;
+    use crate::model::fixtures::outpoint;
+
+    fn consignment() -> Consignment {
+        let (_, genesis) = rgb20::issue(
+            Chain::Testnet3,
+            s!("TST"),
+            s!("Test asset"),
+            Some(s!("Ricardian contract")),
+            8,
+            vec![(outpoint(1), 100_000_000)],
+            BTreeMap::new(),
+            None,
+            None,
+        )
+        .unwrap();
+        Consignment::with(genesis, none!(), none!(), none!())
+    }
+
+    #[test]
+    fn format_detection() {
+        for (path, format) in &[
+            ("asset.rgb", ExportFormat::Binary),
+            ("asset", ExportFormat::Binary),
+            ("asset.TXT", ExportFormat::Bech32),
+            ("asset.yml", ExportFormat::Yaml),
+            ("asset.json", ExportFormat::Json),
+            ("asset.png", ExportFormat::Qr),
+        ] {
+            assert_eq!(ExportFormat::with_path(Path::new(path)), *format);
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let consignment = consignment();
+        let contract_id = consignment.genesis.contract_id();
+        for data in &[ExportData::Genesis, ExportData::Consignment] {
+            for format in &[ExportFormat::Binary, ExportFormat::Bech32] {
+                let exported = export(&consignment, *data, *format).unwrap();
+                let imported = asset_import::parse(&exported).unwrap();
+                assert_eq!(imported.genesis.contract_id(), contract_id);
+                assert_eq!(
+                    strict_serialize(&imported).unwrap(),
+                    strict_serialize(&consignment).unwrap()
+                );
+            }
+        }
+
+        let json =
+            export(&consignment, ExportData::Genesis, ExportFormat::Json)
+                .unwrap();
+        assert!(String::from_utf8(json).unwrap().contains("TST"));
+    }
+
+    #[test]
+    fn frames() {
+        assert_eq!(qr_frames("genesis1qqq"), vec![s!("genesis1qqq")]);
+
+        let data = "q".repeat(QR_FRAME_CAPACITY * 2 + 10);
+        let frames = qr_frames(&data);
+        assert_eq!(frames.len(), 3);
+        assert!(frames[0].starts_with("p1of3 "));
+        assert_eq!(frames[2], format!("p3of3 {}", "q".repeat(10)));
+        assert_eq!(
+            frames
+                .iter()
+                .map(|frame| frame.splitn(2, ' ').nth(1).unwrap())
+                .collect::<String>(),
+            data
+        );
+    }
+}
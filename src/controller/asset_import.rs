@@ -0,0 +1,252 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Import of RGB consignments issued by third parties: parsing of binary and
+//! bech32 data and validation against the RGB20 schema
+
+use bitcoin::{Transaction, Txid};
+use electrum_client::{Client as ElectrumClient, ElectrumApi};
+use lnpbp::strict_encoding::{self, StrictDecode};
+use lnpbp::Chain;
+use rgb::validation::{self, TxResolver, TxResolverError, Validity};
+use rgb::{Consignment, ContractId, FromBech32, Genesis, SchemaId};
+
+/// Human-readable prefix of bech32-encoded consignments
+pub const CONSIGNMENT_HRP: &str = "consignment1";
+
+/// Human-readable prefix of bech32-encoded contract genesis
+pub const GENESIS_HRP: &str = "genesis1";
+
+#[derive(Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// The data are neither binary consignment nor bech32-encoded
+    /// consignment or genesis
+    UnrecognizedData,
+
+    /// Invalid bech32 data: {0}
+    #[from]
+    Bech32(rgb::bech32::Error),
+
+    /// Invalid consignment data: {0}
+    #[from]
+    DataEncoding(strict_encoding::Error),
+
+    /// Contract {0} uses schema {1}, while only RGB20 fungible assets are
+    /// supported
+    UnsupportedSchema(ContractId, SchemaId),
+
+    /// Contract is issued on {0}, while the document uses {1}
+    ChainMismatch(Chain, Chain),
+
+    /// Consignment for contract {0} is invalid:
+    /// {1}
+    Invalid(ContractId, String),
+}
+
+/// Parses consignment from a binary file data or from a bech32 string. When
+/// only contract genesis is given, it is wrapped into a consignment without
+/// state transitions.
+pub fn parse(data: &[u8]) -> Result<Consignment, Error> {
+    if let Ok(text) = std::str::from_utf8(data).map(str::trim) {
+        if text.starts_with(CONSIGNMENT_HRP) {
+            return Ok(Consignment::from_bech32_str(text)?);
+        }
+        if text.starts_with(GENESIS_HRP) {
+            let genesis = Genesis::from_bech32_str(text)?;
+            return Ok(Consignment::with(genesis, none!(), none!(), none!()));
+        }
+        // Binary consignments always contain non-printable bytes
+        if text
+            .chars()
+            .all(|c| c.is_ascii_graphic() || c.is_whitespace())
+        {
+            return Err(Error::UnrecognizedData);
+        }
+    }
+    Ok(Consignment::strict_decode(data)?)
+}
+
+/// Validates consignment against RGB20 schema and the blockchain of the
+/// document. Witness transactions are resolved through the Electrum server;
+/// without one the returned status reports them as unresolved.
+pub fn validate(
+    consignment: &Consignment,
+    chain: &Chain,
+    resolver: Option<&ElectrumClient>,
+) -> Result<validation::Status, Error> {
+    let contract_id = consignment.genesis.contract_id();
+    if consignment.genesis.chain() != chain {
+        return Err(Error::ChainMismatch(
+            consignment.genesis.chain().clone(),
+            chain.clone(),
+        ));
+    }
+    let schema = rgb20::schema::schema();
+    let schema_id = consignment.genesis.schema_id();
+    if schema_id != schema.schema_id() {
+        return Err(Error::UnsupportedSchema(contract_id, schema_id));
+    }
+
+    let status = consignment.validate(&schema, ElectrumTxResolver(resolver));
+    if status.validity() == Validity::Invalid {
+        return Err(Error::Invalid(
+            contract_id,
+            status
+                .failures
+                .iter()
+                .map(|failure| format!("- {:?}", failure))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ));
+    }
+    Ok(status)
+}
+
+/// Resolver of the witness transactions for RGB validation
+struct ElectrumTxResolver<'a>(Option<&'a ElectrumClient>);
+
+impl TxResolver for ElectrumTxResolver<'_> {
+    fn resolve(
+        &self,
+        txid: &Txid,
+    ) -> Result<Option<(Transaction, u64)>, TxResolverError> {
+        let resolver = match self.0 {
+            Some(resolver) => resolver,
+            None => return Ok(None),
+        };
+        let tx = match resolver.transaction_get(txid) {
+            Ok(tx) => tx,
+            Err(_) => return Ok(None),
+        };
+        let prev_txs = resolver
+            .batch_transaction_get(
+                tx.input.iter().map(|txin| &txin.previous_output.txid),
+            )
+            .map_err(|_| TxResolverError)?;
+        let input_amount = tx
+            .input
+            .iter()
+            .zip(prev_txs)
+            .filter_map(|(txin, prev_tx)| {
+                prev_tx
+                    .output
+                    .get(txin.previous_output.vout as usize)
+                    .map(|txout| txout.value)
+            })
+            .sum::<u64>();
+        let output_amount =
+            tx.output.iter().map(|txout| txout.value).sum::<u64>();
+        let fee = input_amount.saturating_sub(output_amount);
+        Ok(Some((tx, fee)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use lnpbp::bp::blind::OutpointReveal;
+    use rgb::ToBech32;
+
+    use super::*;
+    use crate::controller::asset_ops;
+    use crate::controller::fee_bump::Wallet;
+    use crate::model::fixtures::{self, outpoint};
+    use crate::model::Document;
+
+    /// Consignment of the test asset transfer
+    fn transfer() -> Consignment {
+        let descriptors = [fixtures::descriptor_account()];
+        let utxos = vec![(outpoint(0), fixtures::utxo(outpoint(0), 0, 10_000))]
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        let wallet = Wallet {
+            descriptors: &descriptors,
+            ..Wallet::with_utxos(&utxos)
+        };
+        let (_, outgoing) = asset_ops::transfer(
+            &wallet,
+            &fixtures::asset(),
+            OutpointReveal::from(outpoint(7)).outpoint_hash(),
+            400,
+            &fixtures::utxo(outpoint(5), 0, 10_000),
+            1.0,
+        )
+        .unwrap();
+        outgoing
+    }
+
+    fn encoded(consignment: &Consignment) -> Vec<u8> {
+        strict_encoding::strict_serialize(consignment).unwrap()
+    }
+
+    #[test]
+    fn parse_consignment() {
+        let consignment = transfer();
+        let binary = encoded(&consignment);
+        assert_eq!(encoded(&parse(&binary).unwrap()), binary);
+        let bech32 = format!("{}\n", consignment.to_bech32_string());
+        assert_eq!(encoded(&parse(bech32.as_bytes()).unwrap()), binary);
+
+        let genesis = fixtures::asset();
+        let bech32 = genesis.genesis.to_bech32_string();
+        assert_eq!(
+            encoded(&parse(bech32.as_bytes()).unwrap()),
+            encoded(&genesis)
+        );
+    }
+
+    #[test]
+    fn merge_asset() {
+        let genesis = fixtures::asset();
+        let contract_id = genesis.genesis.contract_id();
+        let transfer = transfer();
+        let mut doc = Document::new();
+        assert_eq!(doc.merge_asset(genesis.clone()).unwrap(), 1);
+        assert!(matches!(
+            doc.add_asset(genesis.clone()),
+            Err(crate::model::Error::DuplicatedContract(id)) if id == contract_id
+        ));
+
+        assert_eq!(doc.merge_asset(transfer.clone()).unwrap(), 1);
+        assert_eq!(doc.merge_asset(transfer.clone()).unwrap(), 0);
+        assert_eq!(doc.merge_asset(genesis).unwrap(), 0);
+        assert_eq!(
+            encoded(doc.asset_consignment(contract_id).unwrap()),
+            encoded(&transfer)
+        );
+
+        // Unknown contract is added with all of its nodes
+        let mut other = Document::new();
+        assert_eq!(other.merge_asset(transfer).unwrap(), 2);
+    }
+
+    #[test]
+    fn unrecognized_data() {
+        assert!(matches!(parse(b""), Err(Error::UnrecognizedData)));
+        assert!(matches!(
+            parse(b"  bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq\n"),
+            Err(Error::UnrecognizedData)
+        ));
+        assert!(matches!(parse(b"genesis1qqqqqq"), Err(Error::Bech32(_))));
+        assert!(matches!(
+            parse(&[0xFF, 0x00, 0x01]),
+            Err(Error::DataEncoding(_))
+        ));
+    }
+}
@@ -0,0 +1,158 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Invoices requesting RGB20 assets to be sent to a blinded UTXO
+
+use std::fmt::{self, Display, Formatter};
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+use rgb::{seal, AtomicValue, Bech32, ContractId};
+
+/// URI scheme of the invoices
+pub const INVOICE_SCHEME: &str = "rgb20:";
+
+#[derive(Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// Invalid bech32 data: {0}
+    #[from]
+    Bech32(rgb::bech32::Error),
+
+    /// Invoice must contain blinded UTXO (`utxob1...`), while {0} was given
+    NotBlindedUtxo(String),
+
+    /// Invalid invoice amount: {0}
+    #[from]
+    Amount(ParseIntError),
+
+    /// Invalid asset id `{0}`
+    ContractId(String),
+
+    /// Unknown invoice parameter `{0}`
+    UnknownParameter(String),
+}
+
+/// Request to send asset to the blinded UTXO, optionally specifying the
+/// asset and its amount in atomic units.
+///
+/// Invoice is represented as `rgb20:utxob1...?asset=rgb1...&amount=100`,
+/// where the scheme and both parameters are optional, so a plain bech32
+/// blinded UTXO is also a valid invoice.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Invoice {
+    pub seal: seal::Confidential,
+    pub contract_id: Option<ContractId>,
+    pub amount: Option<AtomicValue>,
+}
+
+impl Display for Invoice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", INVOICE_SCHEME, Bech32::BlindedUtxo(self.seal))?;
+        let mut params = vec![];
+        if let Some(contract_id) = self.contract_id {
+            params.push(format!("asset={}", contract_id));
+        }
+        if let Some(amount) = self.amount {
+            params.push(format!("amount={}", amount));
+        }
+        if !params.is_empty() {
+            write!(f, "?{}", params.join("&"))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Invoice {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = s.strip_prefix(INVOICE_SCHEME).unwrap_or(s);
+        let (seal, params) = match s.split_once('?') {
+            Some((seal, params)) => (seal, Some(params)),
+            None => (s, None),
+        };
+        let seal = match Bech32::from_str(seal)? {
+            Bech32::BlindedUtxo(seal) => seal,
+            _ => return Err(Error::NotBlindedUtxo(seal.to_owned())),
+        };
+
+        let mut invoice = Invoice {
+            seal,
+            contract_id: None,
+            amount: None,
+        };
+        for param in params.into_iter().flat_map(|params| params.split('&')) {
+            match param.split_once('=') {
+                Some(("asset", id)) => {
+                    invoice.contract_id = Some(
+                        ContractId::from_str(id)
+                            .map_err(|_| Error::ContractId(id.to_owned()))?,
+                    )
+                }
+                Some(("amount", amount)) => {
+                    invoice.amount = Some(amount.parse()?)
+                }
+                _ => return Err(Error::UnknownParameter(param.to_owned())),
+            }
+        }
+        Ok(invoice)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+
+    #[test]
+    fn invoice_roundtrip() {
+        let seal = seal::Confidential::hash(b"blinded utxo");
+        let invoice = Invoice {
+            seal,
+            contract_id: None,
+            amount: None,
+        };
+        let plain = invoice.to_string();
+        assert!(plain.starts_with("rgb20:utxob1"));
+        assert_eq!(Invoice::from_str(&plain).unwrap(), invoice);
+        assert_eq!(
+            Invoice::from_str(plain.trim_start_matches(INVOICE_SCHEME))
+                .unwrap(),
+            invoice
+        );
+
+        let invoice = Invoice {
+            amount: Some(100),
+            ..invoice
+        };
+        let s = invoice.to_string();
+        assert!(s.ends_with("?amount=100"));
+        assert_eq!(Invoice::from_str(&s).unwrap(), invoice);
+
+        assert!(matches!(
+            Invoice::from_str(&format!("{}&fee=1", s)),
+            Err(Error::UnknownParameter(_))
+        ));
+        assert!(matches!(
+            Invoice::from_str(&plain.replace("utxob1", "utxob2")),
+            Err(Error::Bech32(_))
+        ));
+    }
+}
@@ -0,0 +1,1359 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Operations with RGB20 assets performed by the owner of the asset rights:
+//! construction of state transitions closing the seals controlled by the
+//! document UTXOs and their anchoring into witness transactions.
+
+use std::collections::BTreeMap;
+
+use amplify::Wrapper;
+use bitcoin::blockdata::{opcodes, script::Builder};
+use bitcoin::secp256k1::rand::{thread_rng, RngCore};
+use bitcoin::secp256k1::{self, SecretKey};
+use bitcoin::util::psbt::{self, raw::ProprietaryKey};
+use bitcoin::{OutPoint, PublicKey, Script, Transaction, TxIn, TxOut};
+use lnpbp::bp::blind::OutpointReveal;
+use rgb::secp256k1zkp::key::ONE_KEY;
+use rgb::{
+    data, seal, value, Anchor, Assignments, AtomicValue, Consignment,
+    ContractId, Metadata, Node, OwnedRights, OwnedState, ParentOwnedRights,
+    Transition,
+};
+use rgb20::schema::{
+    FieldType, HistoryProofFormat, OwnedRightsType, TransitionType,
+};
+use wallet::{descriptor, Psbt};
+
+use super::asset_sync::SEALED_RIGHTS;
+use super::fee_bump::{self, derive, Wallet};
+use super::fee_estimator::{self, fee_for, tx_weight, InputWeight};
+use super::psbt_finalizer::dust_limit;
+use crate::model::{
+    allocation_state, burned_outpoints, known_seals, AssetSeal, Nomination,
+    UtxoEntry,
+};
+
+/// Sequence number of the witness transactions. Replacement of a witness
+/// transaction would change its txid, invalidating the anchor, so the
+/// transactions do not signal replaceability.
+pub const WITNESS_SEQUENCE: u32 = 0xffff_ffff;
+
+#[derive(Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// None of the seals controlling {0:?} right of the asset belongs to
+    /// the document UTXOs
+    NoRights(OwnedRightsType),
+
+    /// New supply of {0} exceeds remaining inflation cap of {1}
+    InflationCapExceeded(AtomicValue, AtomicValue),
+
+    /// No allocations for the new asset supply were given
+    NoAllocations,
+
+    /// No allocations to burn were given
+    NothingToBurn,
+
+    /// Output {0} has no known allocations of the asset
+    UnknownAllocation(OutPoint),
+
+    /// Allocation at {0} is already burned
+    AlreadyBurned(OutPoint),
+
+    /// Replaced supply of {0} exceeds burned supply of {1}
+    ReplacementExceeded(AtomicValue, AtomicValue),
+
+    /// Transfer amount must be positive
+    ZeroAmount,
+
+    /// Allocations controlled by the document UTXOs hold {0}, while {1} is
+    /// required for the transfer
+    InsufficientAssets(AtomicValue, AtomicValue),
+
+    /// Output {0} spent by the operation also holds {1:?} right of the
+    /// asset, which would be lost
+    RightsAtSpentOutput(OutPoint, OwnedRightsType),
+
+    /// Seal can't be defined on output {0}, since it is spent by the
+    /// witness transaction of the operation
+    SealAtSpentOutput(OutPoint),
+
+    /// Output {0} spent by the operation holds rights of another asset {1},
+    /// which would be lost
+    ContractAtSpentOutput(OutPoint, ContractId),
+
+    /// Seal outputs with {0} sat can't pay the witness transaction fee of
+    /// {1} sat
+    InsufficientFunds(u64, u64),
+
+    /// Output {0} controlling the asset seal is not a SegWit one; its
+    /// signature would change txid of the witness transaction
+    NonSegwitInput(OutPoint),
+
+    /// Asset amount exceeds the largest possible value
+    AmountOverflow,
+
+    /// Unable to commit to the state transition: {0}
+    Anchor(String),
+
+    /// {0}
+    #[from]
+    Wallet(fee_bump::Error),
+
+    /// {0}
+    #[from]
+    Weight(fee_estimator::Error),
+}
+
+/// State transition anchored into a witness transaction
+#[derive(Clone, Debug)]
+pub struct AssetOperation {
+    pub transition: Transition,
+    /// Unsigned witness transaction spending the outputs of the closed seals
+    /// and holding the commitment to the state transition
+    pub psbt: Psbt,
+    /// Consignment extended with the new state transition
+    pub consignment: Consignment,
+    /// Fee paid by the witness transaction
+    pub fee: u64,
+}
+
+/// Returns current seals of the asset right, which are controlled by the
+/// document UTXOs
+pub fn owned_seals<'a>(
+    wallet: &Wallet<'a>,
+    consignment: &Consignment,
+    right: OwnedRightsType,
+) -> Vec<(AssetSeal, &'a UtxoEntry)> {
+    let utxos = wallet.utxos;
+    known_seals(consignment, right)
+        .into_iter()
+        .filter_map(|seal| utxos.get(&seal.outpoint).map(|utxo| (seal, utxo)))
+        .collect()
+}
+
+/// Creates secondary issuance of the asset, closing all inflation seals
+/// controlled by the document UTXOs. New supply is allocated to the given
+/// outputs, while the rest of the inflation cap is re-assigned to the
+/// output of the witness transaction.
+pub fn inflate(
+    wallet: &Wallet,
+    consignment: &Consignment,
+    allocations: &[(OutPoint, AtomicValue)],
+    fee_rate: f32,
+) -> Result<AssetOperation, Error> {
+    if allocations.is_empty() {
+        return Err(Error::NoAllocations);
+    }
+    let seals = owned_seals(wallet, consignment, OwnedRightsType::Inflation);
+    if seals.is_empty() {
+        return Err(Error::NoRights(OwnedRightsType::Inflation));
+    }
+    let cap = checked_sum(seals.iter().filter_map(|(seal, _)| seal.value))?;
+    let issued = checked_sum(allocations.iter().map(|(_, value)| *value))?;
+    if issued > cap {
+        return Err(Error::InflationCapExceeded(issued, cap));
+    }
+
+    let transition = |vout| {
+        let mut owned_rights = OwnedRights::new();
+        owned_rights.insert(
+            OwnedRightsType::Assets.into(),
+            value_assignments(
+                issued,
+                allocations
+                    .iter()
+                    .map(|(outpoint, value)| (outpoint_seal(*outpoint), *value))
+                    .collect(),
+            ),
+        );
+        if cap > issued {
+            owned_rights.insert(
+                OwnedRightsType::Inflation.into(),
+                value_assignments(
+                    cap - issued,
+                    bmap! { witness_seal(vout) => cap - issued },
+                ),
+            );
+        }
+        Transition::with(
+            TransitionType::Issue.into(),
+            Metadata::from_inner(bmap! {
+                FieldType::IssuedSupply.into() => vec![data::Revealed::U64(issued)]
+            }),
+            empty!(),
+            owned_rights,
+            empty!(),
+            parent_rights(
+                seals.iter().map(|(seal, _)| seal),
+                OwnedRightsType::Inflation,
+            ),
+        )
+    };
+
+    let spent = seals.iter().map(|(_, utxo)| *utxo).collect::<Vec<_>>();
+    check_spent_outputs(
+        consignment,
+        &spent,
+        OwnedRightsType::Inflation,
+        allocations.iter().map(|(outpoint, _)| *outpoint),
+    )?;
+    anchor(wallet, consignment, transition, &spent, fee_rate)
+}
+
+/// Changes asset nomination, closing the renomination seal controlled by
+/// the document UTXOs. The renomination right is passed to the output of
+/// the witness transaction, so the asset can be renominated again.
+pub fn renominate(
+    wallet: &Wallet,
+    consignment: &Consignment,
+    nomination: Nomination,
+    fee_rate: f32,
+) -> Result<AssetOperation, Error> {
+    let seals = owned_seals(wallet, consignment, OwnedRightsType::Renomination);
+    if seals.is_empty() {
+        return Err(Error::NoRights(OwnedRightsType::Renomination));
+    }
+
+    let mut metadata = bmap! {
+        FieldType::Ticker.into() => vec![data::Revealed::AsciiString(nomination.ticker)],
+        FieldType::Name.into() => vec![data::Revealed::AsciiString(nomination.name)],
+        FieldType::Precision.into() => vec![data::Revealed::U8(nomination.precision)]
+    };
+    if let Some(contract) = nomination.contract {
+        metadata.insert(
+            FieldType::ContractText.into(),
+            vec![data::Revealed::AsciiString(contract)],
+        );
+    }
+
+    let transition = |vout| {
+        let mut owned_rights = OwnedRights::new();
+        owned_rights.insert(
+            OwnedRightsType::Renomination.into(),
+            declarative_assignments(vec![witness_seal(vout)]),
+        );
+        Transition::with(
+            TransitionType::Renomination.into(),
+            Metadata::from_inner(metadata.clone()),
+            empty!(),
+            owned_rights,
+            empty!(),
+            parent_rights(
+                seals.iter().map(|(seal, _)| seal),
+                OwnedRightsType::Renomination,
+            ),
+        )
+    };
+
+    let spent = seals.iter().map(|(_, utxo)| *utxo).collect::<Vec<_>>();
+    check_spent_outputs(
+        consignment,
+        &spent,
+        OwnedRightsType::Renomination,
+        None,
+    )?;
+    anchor(wallet, consignment, transition, &spent, fee_rate)
+}
+
+/// Opens new epoch, closing the epoch seal controlled by the document
+/// UTXOs. The burn & replace right of the opened epoch is assigned to the
+/// output of the witness transaction, while the seal of the next epoch is
+/// defined on the given `next_epoch` output, so burning the supply does not
+/// close it.
+pub fn open_epoch(
+    wallet: &Wallet,
+    consignment: &Consignment,
+    next_epoch: OutPoint,
+    fee_rate: f32,
+) -> Result<AssetOperation, Error> {
+    let seals = owned_seals(wallet, consignment, OwnedRightsType::OpenEpoch);
+    if seals.is_empty() {
+        return Err(Error::NoRights(OwnedRightsType::OpenEpoch));
+    }
+
+    let transition = |vout| {
+        let mut owned_rights = OwnedRights::new();
+        owned_rights.insert(
+            OwnedRightsType::OpenEpoch.into(),
+            declarative_assignments(vec![outpoint_seal(next_epoch)]),
+        );
+        owned_rights.insert(
+            OwnedRightsType::BurnReplace.into(),
+            declarative_assignments(vec![witness_seal(vout)]),
+        );
+        Transition::with(
+            TransitionType::Epoch.into(),
+            empty!(),
+            empty!(),
+            owned_rights,
+            empty!(),
+            parent_rights(
+                seals.iter().map(|(seal, _)| seal),
+                OwnedRightsType::OpenEpoch,
+            ),
+        )
+    };
+
+    let spent = seals.iter().map(|(_, utxo)| *utxo).collect::<Vec<_>>();
+    check_spent_outputs(
+        consignment,
+        &spent,
+        OwnedRightsType::OpenEpoch,
+        Some(next_epoch),
+    )?;
+    anchor(wallet, consignment, transition, &spent, fee_rate)
+}
+
+/// Burns asset allocations at the given outputs, closing the burn & replace
+/// seal controlled by the document UTXOs. The outputs are listed in the
+/// transition as a proof of the burned supply. When replacement allocations
+/// are given, the burned supply is re-issued to them.
+///
+/// The burn & replace right is re-assigned to the output of the witness
+/// transaction, so more supply can be burned within the same epoch.
+pub fn burn(
+    wallet: &Wallet,
+    consignment: &Consignment,
+    burned: &[OutPoint],
+    replacements: &[(OutPoint, AtomicValue)],
+    fee_rate: f32,
+) -> Result<AssetOperation, Error> {
+    if burned.is_empty() {
+        return Err(Error::NothingToBurn);
+    }
+    let seals = owned_seals(wallet, consignment, OwnedRightsType::BurnReplace);
+    if seals.is_empty() {
+        return Err(Error::NoRights(OwnedRightsType::BurnReplace));
+    }
+
+    let already_burned = burned_outpoints(consignment);
+    let allocations = known_seals(consignment, OwnedRightsType::Assets);
+    let mut amount: AtomicValue = 0;
+    for outpoint in burned {
+        if already_burned.contains(outpoint) {
+            return Err(Error::AlreadyBurned(*outpoint));
+        }
+        let values = allocations
+            .iter()
+            .filter(|seal| seal.outpoint == *outpoint)
+            .filter_map(|seal| seal.value)
+            .collect::<Vec<_>>();
+        if values.is_empty() {
+            return Err(Error::UnknownAllocation(*outpoint));
+        }
+        amount = amount
+            .checked_add(checked_sum(values)?)
+            .ok_or(Error::AmountOverflow)?;
+    }
+    let replaced = checked_sum(replacements.iter().map(|(_, value)| *value))?;
+    if replaced > amount {
+        return Err(Error::ReplacementExceeded(replaced, amount));
+    }
+
+    let metadata = bmap! {
+        FieldType::BurnedSupply.into() => vec![data::Revealed::U64(amount)],
+        FieldType::BurnUtxo.into() => burned
+            .iter()
+            .map(|outpoint| data::Revealed::TxOutPoint(*outpoint))
+            .collect(),
+        FieldType::HistoryProofFormat.into() => vec![
+            data::Revealed::U8(HistoryProofFormat::ProofAbsent as u8)
+        ]
+    };
+    let transition = |vout| {
+        let mut owned_rights = OwnedRights::new();
+        owned_rights.insert(
+            OwnedRightsType::BurnReplace.into(),
+            declarative_assignments(vec![witness_seal(vout)]),
+        );
+        let transition_type = if replacements.is_empty() {
+            TransitionType::Burn
+        } else {
+            owned_rights.insert(
+                OwnedRightsType::Assets.into(),
+                value_assignments(
+                    replaced,
+                    replacements
+                        .iter()
+                        .map(|(outpoint, value)| {
+                            (outpoint_seal(*outpoint), *value)
+                        })
+                        .collect(),
+                ),
+            );
+            TransitionType::BurnAndReplace
+        };
+        Transition::with(
+            transition_type.into(),
+            Metadata::from_inner(metadata.clone()),
+            empty!(),
+            owned_rights,
+            empty!(),
+            parent_rights(
+                seals.iter().map(|(seal, _)| seal),
+                OwnedRightsType::BurnReplace,
+            ),
+        )
+    };
+
+    let spent = seals.iter().map(|(_, utxo)| *utxo).collect::<Vec<_>>();
+    check_spent_outputs(
+        consignment,
+        &spent,
+        OwnedRightsType::BurnReplace,
+        replacements.iter().map(|(outpoint, _)| *outpoint),
+    )?;
+    anchor(wallet, consignment, transition, &spent, fee_rate)
+}
+
+/// Transfers asset amount to the recipient blinded UTXO, spending
+/// allocations controlled by the document UTXOs (largest first) and
+/// assigning the change to the given UTXO.
+///
+/// All allocations at the outputs selected for spending are closed by the
+/// transfer, and whatever is not sent to the beneficiary goes to the change.
+/// Besides the operation updating the document consignment, returns the
+/// consignment for the recipient with the transfer endpoint.
+pub fn transfer(
+    wallet: &Wallet,
+    consignment: &Consignment,
+    beneficiary: seal::Confidential,
+    amount: AtomicValue,
+    change: &UtxoEntry,
+    fee_rate: f32,
+) -> Result<(AssetOperation, Consignment), Error> {
+    if amount == 0 {
+        return Err(Error::ZeroAmount);
+    }
+    let burned = burned_outpoints(consignment);
+    let mut allocations =
+        owned_seals(wallet, consignment, OwnedRightsType::Assets)
+            .into_iter()
+            .filter(|(seal, _)| !burned.contains(&seal.outpoint))
+            .filter_map(|(seal, utxo)| {
+                allocation_state(consignment, &seal)
+                    .map(|state| (seal, state, utxo))
+            })
+            .collect::<Vec<_>>();
+    allocations.sort_by_key(|(_, state, _)| std::cmp::Reverse(state.value));
+
+    let mut spent: Vec<&UtxoEntry> = vec![];
+    let mut selected: AtomicValue = 0;
+    for (_, state, utxo) in &allocations {
+        if selected >= amount {
+            break;
+        }
+        selected = selected
+            .checked_add(state.value)
+            .ok_or(Error::AmountOverflow)?;
+        if !spent.iter().any(|entry| entry.outpoint == utxo.outpoint) {
+            spent.push(*utxo);
+        }
+    }
+
+    // The witness transaction closes all seals of the spent outputs, so the
+    // allocations not needed for the amount are moved to the change as well
+    let mut inputs = vec![];
+    let mut parents = vec![];
+    let mut total: AtomicValue = 0;
+    for (seal, state, utxo) in allocations {
+        if !spent.iter().any(|entry| entry.outpoint == utxo.outpoint) {
+            continue;
+        }
+        total = total
+            .checked_add(state.value)
+            .ok_or(Error::AmountOverflow)?;
+        inputs.push(state);
+        parents.push(seal);
+    }
+    if total < amount {
+        return Err(Error::InsufficientAssets(total, amount));
+    }
+    check_spent_outputs(
+        consignment,
+        &spent,
+        OwnedRightsType::Assets,
+        Some(change.outpoint),
+    )?;
+
+    let ours = if total > amount {
+        bmap! { outpoint_seal(change.outpoint) => total - amount }
+    } else {
+        empty!()
+    };
+    let mut owned_rights = OwnedRights::new();
+    owned_rights.insert(
+        OwnedRightsType::Assets.into(),
+        Assignments::zero_balanced(
+            inputs,
+            ours,
+            bmap! { beneficiary => amount },
+        ),
+    );
+
+    let transition = Transition::with(
+        TransitionType::Transfer.into(),
+        empty!(),
+        empty!(),
+        owned_rights,
+        empty!(),
+        parent_rights(&parents, OwnedRightsType::Assets),
+    );
+
+    let operation = anchor(
+        wallet,
+        consignment,
+        |_| transition.clone(),
+        &spent,
+        fee_rate,
+    )?;
+    let mut outgoing = operation.consignment.clone();
+    outgoing.endpoints = vec![(operation.transition.node_id(), beneficiary)];
+    Ok((operation, outgoing))
+}
+
+/// Checks that the witness transaction of the operation does not spend
+/// outputs holding rights of the other contracts known to the document
+pub fn check_other_contracts(
+    operation: &AssetOperation,
+    contracts: &BTreeMap<ContractId, Consignment>,
+) -> Result<(), Error> {
+    let contract_id = operation.consignment.genesis.contract_id();
+    let inputs = &operation.psbt.global.unsigned_tx.input;
+    for (id, consignment) in contracts {
+        if *id == contract_id {
+            continue;
+        }
+        let burned = burned_outpoints(consignment);
+        for right in SEALED_RIGHTS {
+            if let Some(seal) =
+                known_seals(consignment, right).into_iter().find(|seal| {
+                    inputs
+                        .iter()
+                        .any(|txin| txin.previous_output == seal.outpoint)
+                        && !(right == OwnedRightsType::Assets
+                            && burned.contains(&seal.outpoint))
+                })
+            {
+                return Err(Error::ContractAtSpentOutput(seal.outpoint, *id));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Defines seal on an existing transaction output
+pub fn outpoint_seal(outpoint: OutPoint) -> seal::Revealed {
+    seal::Revealed::TxOutpoint(OutpointReveal::from(outpoint))
+}
+
+/// Defines seal on the output of the witness transaction
+pub fn witness_seal(vout: u32) -> seal::Revealed {
+    seal::Revealed::WitnessVout {
+        vout,
+        blinding: thread_rng().next_u64(),
+    }
+}
+
+/// Checks that the witness transaction spending the given outputs neither
+/// closes seals of the asset rights other than the `closed` one, nor spends
+/// outputs on which the operation defines new seals
+fn check_spent_outputs(
+    consignment: &Consignment,
+    spent: &[&UtxoEntry],
+    closed: OwnedRightsType,
+    new_seals: impl IntoIterator<Item = OutPoint>,
+) -> Result<(), Error> {
+    let is_spent = |outpoint: &OutPoint| {
+        spent.iter().any(|utxo| utxo.outpoint == *outpoint)
+    };
+    if let Some(outpoint) = new_seals.into_iter().find(is_spent) {
+        return Err(Error::SealAtSpentOutput(outpoint));
+    }
+    let burned = burned_outpoints(consignment);
+    for right in SEALED_RIGHTS {
+        if right == closed {
+            continue;
+        }
+        if let Some(seal) =
+            known_seals(consignment, right).into_iter().find(|seal| {
+                is_spent(&seal.outpoint)
+                    && !(right == OwnedRightsType::Assets
+                        && burned.contains(&seal.outpoint))
+            })
+        {
+            return Err(Error::RightsAtSpentOutput(seal.outpoint, right));
+        }
+    }
+    Ok(())
+}
+
+/// Sums asset amounts, failing on overflow
+fn checked_sum(
+    values: impl IntoIterator<Item = AtomicValue>,
+) -> Result<AtomicValue, Error> {
+    values.into_iter().try_fold(0, |sum: AtomicValue, value| {
+        sum.checked_add(value).ok_or(Error::AmountOverflow)
+    })
+}
+
+/// Assigns values to the seals, balancing their Pedersen commitments
+/// against a single input with the given value
+fn value_assignments(
+    input: AtomicValue,
+    seals: BTreeMap<seal::Revealed, AtomicValue>,
+) -> Assignments {
+    Assignments::zero_balanced(
+        vec![value::Revealed {
+            value: input,
+            blinding: ONE_KEY,
+        }],
+        seals,
+        empty!(),
+    )
+}
+
+/// Assigns rights without state to the seals
+fn declarative_assignments(seals: Vec<seal::Revealed>) -> Assignments {
+    Assignments::Declarative(
+        seals
+            .into_iter()
+            .map(|seal_definition| OwnedState::Revealed {
+                seal_definition,
+                assigned_state: data::Void(),
+            })
+            .collect(),
+    )
+}
+
+/// References to the closed seals of the given right
+fn parent_rights<'a>(
+    seals: impl IntoIterator<Item = &'a AssetSeal>,
+    right: OwnedRightsType,
+) -> ParentOwnedRights {
+    let mut parents = ParentOwnedRights::new();
+    for seal in seals {
+        parents
+            .entry(seal.node_id)
+            .or_insert_with(BTreeMap::new)
+            .entry(right.into())
+            .or_insert_with(Vec::new)
+            .push(seal.index);
+    }
+    parents
+}
+
+/// Anchors state transition into a witness transaction spending outputs
+/// of the closed seals. Whole bitcoin amount of the spent outputs less the
+/// fee goes to the change output paid to the script of the first spent
+/// output, so it is found by the UTXO lookup and signed as any other
+/// document output. The transition is constructed by `transition` for the
+/// number of the change output, which receives the seals defined with
+/// [`witness_seal`].
+///
+/// The commitment is placed into a separate `OP_RETURN` output. Position
+/// of the commitment output is selected by the anchoring procedure from the
+/// contract id, so both orders of the outputs are tried.
+fn anchor(
+    wallet: &Wallet,
+    consignment: &Consignment,
+    transition: impl Fn(u32) -> Transition,
+    spent: &[&UtxoEntry],
+    fee_rate: f32,
+) -> Result<AssetOperation, Error> {
+    if let Some(utxo) = spent.iter().find(|utxo| {
+        matches!(
+            utxo.descriptor_category,
+            descriptor::Category::Bare | descriptor::Category::Hashed
+        )
+    }) {
+        return Err(Error::NonSegwitInput(utxo.outpoint));
+    }
+    let change = spent[0];
+    let script_pubkey = wallet.script_pubkey(change)?;
+
+    // The key is used only to construct the commitment and is never spent
+    let commitment_key = PublicKey {
+        compressed: true,
+        key: secp256k1::PublicKey::from_secret_key(
+            &wallet::SECP256K1,
+            &SecretKey::new(&mut thread_rng()),
+        ),
+    };
+    let commitment_output = TxOut {
+        value: 0,
+        script_pubkey: Builder::new()
+            .push_opcode(opcodes::all::OP_RETURN)
+            .push_slice(&commitment_key.to_bytes())
+            .into_script(),
+    };
+
+    let inputs = spent
+        .iter()
+        .map(|utxo| {
+            InputWeight::with(
+                &utxo.descriptor_template,
+                utxo.descriptor_category,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let fee = fee_for(
+        tx_weight(
+            &inputs,
+            &[
+                commitment_output.script_pubkey.clone(),
+                script_pubkey.clone(),
+            ],
+        ),
+        fee_rate,
+    );
+    let amount = spent.iter().map(|utxo| utxo.amount).sum::<u64>();
+    let change_output = TxOut {
+        value: amount.saturating_sub(fee),
+        script_pubkey,
+    };
+    if change_output.value < dust_limit(&change_output) {
+        return Err(Error::InsufficientFunds(amount, fee));
+    }
+
+    let contract_id = consignment.genesis.contract_id();
+    let mut failure = s!("no anchor was produced");
+    for commitment_vout in 0..2usize {
+        let change_vout = 1 - commitment_vout;
+        let mut output = vec![change_output.clone()];
+        output.insert(commitment_vout, commitment_output.clone());
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: spent
+                .iter()
+                .map(|utxo| TxIn {
+                    previous_output: utxo.outpoint,
+                    script_sig: Script::new(),
+                    sequence: WITNESS_SEQUENCE,
+                    witness: vec![],
+                })
+                .collect(),
+            output,
+        };
+
+        let mut psbt = wallet.psbt(tx, spent)?;
+        fill_output(&mut psbt.outputs[change_vout], change)?;
+        psbt.outputs[commitment_vout].proprietary.insert(
+            ProprietaryKey {
+                prefix: rgb::PSBT_PREFIX.to_vec(),
+                subtype: rgb::PSBT_OUT_PUBKEY,
+                key: vec![],
+            },
+            commitment_key.to_bytes(),
+        );
+
+        let transition = transition(change_vout as u32);
+        let anchor = match Anchor::commit(
+            bmap! { contract_id => transition.node_id() },
+            &mut psbt,
+        ) {
+            Ok((anchors, _)) => anchors.into_iter().next(),
+            Err(err) => {
+                failure = err.to_string();
+                continue;
+            }
+        };
+        let committed = &psbt.global.unsigned_tx.output;
+        if committed[change_vout] != change_output
+            || committed[commitment_vout] == commitment_output
+        {
+            failure = s!("commitment was not placed into OP_RETURN output");
+            continue;
+        }
+        let anchor = match anchor {
+            Some(anchor) => anchor,
+            None => continue,
+        };
+
+        let mut updated = consignment.clone();
+        updated.state_transitions.push((anchor, transition.clone()));
+        return Ok(AssetOperation {
+            transition,
+            psbt,
+            consignment: updated,
+            fee,
+        });
+    }
+    Err(Error::Anchor(failure))
+}
+
+/// Fills PSBT output paid to the script of the document UTXO with the key
+/// origins, so signers can recognize it as a change
+fn fill_output(
+    output: &mut psbt::Output,
+    utxo: &UtxoEntry,
+) -> Result<(), Error> {
+    let keys = match &utxo.descriptor_template {
+        descriptor::Template::SingleSig(key) => vec![key],
+        descriptor::Template::MultiSig(multisig) => {
+            multisig.pubkeys.iter().collect()
+        }
+        _ => vec![],
+    };
+    for key in keys {
+        if let (pubkey, Some(origin)) = derive(key, utxo.derivation_index)? {
+            output.bip32_derivation.insert(pubkey, origin);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use bitcoin::hashes::Hash;
+    use lnpbp::Chain;
+    use wallet::bip32::{ChildIndex, UnhardenedIndex};
+
+    use super::*;
+    use crate::model::fixtures::outpoint;
+    use crate::model::{current_nomination, fixtures, known_supply};
+
+    fn utxo(outpoint: OutPoint) -> UtxoEntry {
+        fixtures::utxo(outpoint, 0, 10_000)
+    }
+
+    fn utxos(outpoints: &[OutPoint]) -> HashMap<OutPoint, UtxoEntry> {
+        outpoints
+            .iter()
+            .map(|outpoint| (*outpoint, utxo(*outpoint)))
+            .collect()
+    }
+
+    #[test]
+    fn inflation_checks() {
+        let consignment = fixtures::asset();
+        let mut utxos = HashMap::new();
+        let wallet = Wallet::with_utxos(&utxos);
+        assert!(matches!(
+            inflate(&wallet, &consignment, &[], 1.0),
+            Err(Error::NoAllocations)
+        ));
+        assert!(matches!(
+            inflate(&wallet, &consignment, &[(outpoint(2), 100)], 1.0),
+            Err(Error::NoRights(OwnedRightsType::Inflation))
+        ));
+
+        utxos.insert(outpoint(1), utxo(outpoint(1)));
+        let wallet = Wallet::with_utxos(&utxos);
+        let seals =
+            owned_seals(&wallet, &consignment, OwnedRightsType::Inflation);
+        assert_eq!(seals.len(), 1);
+        assert_eq!(seals[0].0.value, Some(500));
+        assert!(matches!(
+            inflate(
+                &wallet,
+                &consignment,
+                &[(outpoint(2), 300), (outpoint(3), 300)],
+                1.0
+            ),
+            Err(Error::InflationCapExceeded(600, 500))
+        ));
+    }
+
+    #[test]
+    fn inflation_spent_outputs() {
+        let consignment = fixtures::asset();
+        let descriptors = [fixtures::descriptor_account()];
+        let utxos = utxos(&[outpoint(0), outpoint(1)]);
+        let wallet = Wallet {
+            descriptors: &descriptors,
+            ..Wallet::with_utxos(&utxos)
+        };
+        assert!(matches!(
+            inflate(&wallet, &consignment, &[(outpoint(1), 100)], 1.0),
+            Err(Error::SealAtSpentOutput(seal)) if seal == outpoint(1)
+        ));
+
+        // Change of the transfer shares the output with the inflation seal
+        let beneficiary = seal::Confidential::hash(b"beneficiary");
+        let (operation, _) = transfer(
+            &wallet,
+            &consignment,
+            beneficiary,
+            400,
+            &utxo(outpoint(1)),
+            1.0,
+        )
+        .unwrap();
+        assert!(matches!(
+            inflate(
+                &wallet,
+                &operation.consignment,
+                &[(outpoint(2), 100)],
+                1.0
+            ),
+            Err(Error::RightsAtSpentOutput(seal, OwnedRightsType::Assets))
+                if seal == outpoint(1)
+        ));
+    }
+
+    #[test]
+    fn renomination_checks() {
+        let consignment = fixtures::asset();
+        let utxos = utxos(&[outpoint(0), outpoint(1)]);
+        let wallet = Wallet::with_utxos(&utxos);
+        let nomination = Nomination {
+            ticker: s!("NEW"),
+            name: s!("Renamed asset"),
+            contract: None,
+            precision: 2,
+        };
+        assert!(matches!(
+            renominate(&wallet, &consignment, nomination.clone(), 1.0),
+            Err(Error::NoRights(OwnedRightsType::Renomination))
+        ));
+
+        // Change of the transfer shares the output with the renomination seal
+        let descriptors = [fixtures::descriptor_account()];
+        let utxos = self::utxos(&[outpoint(0), outpoint(3)]);
+        let wallet = Wallet {
+            descriptors: &descriptors,
+            ..Wallet::with_utxos(&utxos)
+        };
+        let beneficiary = seal::Confidential::hash(b"beneficiary");
+        let (operation, _) = transfer(
+            &wallet,
+            &consignment,
+            beneficiary,
+            400,
+            &utxo(outpoint(3)),
+            1.0,
+        )
+        .unwrap();
+        assert!(matches!(
+            renominate(&wallet, &operation.consignment, nomination, 1.0),
+            Err(Error::RightsAtSpentOutput(seal, OwnedRightsType::Assets))
+                if seal == outpoint(3)
+        ));
+    }
+
+    #[test]
+    fn renomination() {
+        let consignment = fixtures::asset();
+        let utxos = utxos(&[outpoint(3)]);
+        let descriptors = [fixtures::descriptor_account()];
+        let wallet = Wallet {
+            descriptors: &descriptors,
+            ..Wallet::with_utxos(&utxos)
+        };
+        let nomination = Nomination {
+            ticker: s!("NEW"),
+            name: s!("Renamed asset"),
+            contract: None,
+            precision: 2,
+        };
+        let operation =
+            renominate(&wallet, &consignment, nomination.clone(), 1.0).unwrap();
+        let change = check_anchor(&operation, &[outpoint(3)]);
+        assert_eq!(
+            current_nomination(&consignment),
+            Nomination {
+                ticker: s!("TST"),
+                name: s!("Test asset"),
+                contract: None,
+                precision: 0,
+            }
+        );
+        assert_eq!(current_nomination(&operation.consignment), nomination);
+        assert_eq!(nomination.divisor(), 100);
+
+        // The next renomination closes the seal of the witness output
+        let seal =
+            OutPoint::new(operation.psbt.global.unsigned_tx.txid(), change);
+        let utxos = vec![(seal, utxo(seal))]
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        let wallet = Wallet {
+            descriptors: &descriptors,
+            ..Wallet::with_utxos(&utxos)
+        };
+        let renamed = Nomination {
+            name: s!("Second name"),
+            ..nomination
+        };
+        let second =
+            renominate(&wallet, &operation.consignment, renamed.clone(), 1.0)
+                .unwrap();
+        check_anchor(&second, &[seal]);
+        assert_eq!(current_nomination(&second.consignment), renamed);
+    }
+
+    #[test]
+    fn burn_checks() {
+        let consignment = fixtures::asset();
+        let utxos = utxos(&[outpoint(0)]);
+        let wallet = Wallet::with_utxos(&utxos);
+        assert!(matches!(
+            burn(&wallet, &consignment, &[], &[], 1.0),
+            Err(Error::NothingToBurn)
+        ));
+        assert!(matches!(
+            burn(&wallet, &consignment, &[outpoint(0)], &[], 1.0),
+            Err(Error::NoRights(OwnedRightsType::BurnReplace))
+        ));
+        assert!(matches!(
+            open_epoch(&wallet, &consignment, outpoint(6), 1.0),
+            Err(Error::NoRights(OwnedRightsType::OpenEpoch))
+        ));
+
+        let supply = known_supply(&consignment);
+        assert_eq!(supply.issued, 1000);
+        assert_eq!(supply.circulating(), 1000);
+    }
+
+    #[test]
+    fn epoch_spent_outputs() {
+        let consignment = fixtures::asset();
+        let descriptors = [fixtures::descriptor_account()];
+        let utxos = utxos(&[outpoint(0), outpoint(4)]);
+        let wallet = Wallet {
+            descriptors: &descriptors,
+            ..Wallet::with_utxos(&utxos)
+        };
+        assert!(matches!(
+            open_epoch(&wallet, &consignment, outpoint(4), 1.0),
+            Err(Error::SealAtSpentOutput(seal)) if seal == outpoint(4)
+        ));
+
+        // Change of the transfer shares the output with the epoch seal
+        let beneficiary = seal::Confidential::hash(b"beneficiary");
+        let (operation, _) = transfer(
+            &wallet,
+            &consignment,
+            beneficiary,
+            400,
+            &utxo(outpoint(4)),
+            1.0,
+        )
+        .unwrap();
+        assert!(matches!(
+            open_epoch(&wallet, &operation.consignment, outpoint(6), 1.0),
+            Err(Error::RightsAtSpentOutput(seal, OwnedRightsType::Assets))
+                if seal == outpoint(4)
+        ));
+    }
+
+    #[test]
+    fn epoch_and_burn() {
+        let consignment = fixtures::asset();
+        let descriptors = [fixtures::descriptor_account()];
+        let utxos = utxos(&[outpoint(4)]);
+        let wallet = Wallet {
+            descriptors: &descriptors,
+            ..Wallet::with_utxos(&utxos)
+        };
+        let epoch =
+            open_epoch(&wallet, &consignment, outpoint(6), 1.0).unwrap();
+        let change = check_anchor(&epoch, &[outpoint(4)]);
+        let burn_seal =
+            OutPoint::new(epoch.psbt.global.unsigned_tx.txid(), change);
+        let epochs =
+            known_seals(&epoch.consignment, OwnedRightsType::OpenEpoch);
+        assert_eq!(epochs.len(), 1);
+        assert_eq!(epochs[0].outpoint, outpoint(6));
+        let burn_seals =
+            known_seals(&epoch.consignment, OwnedRightsType::BurnReplace);
+        assert_eq!(burn_seals.len(), 1);
+        assert_eq!(burn_seals[0].outpoint, burn_seal);
+
+        let utxos = vec![(burn_seal, utxo(burn_seal))]
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        let wallet = Wallet {
+            descriptors: &descriptors,
+            ..Wallet::with_utxos(&utxos)
+        };
+        assert!(matches!(
+            burn(
+                &wallet,
+                &epoch.consignment,
+                &[outpoint(0)],
+                &[(burn_seal, 400)],
+                1.0
+            ),
+            Err(Error::SealAtSpentOutput(seal)) if seal == burn_seal
+        ));
+        let operation =
+            burn(&wallet, &epoch.consignment, &[outpoint(0)], &[], 1.0)
+                .unwrap();
+        check_anchor(&operation, &[burn_seal]);
+        // The seal of the next epoch is kept
+        assert_eq!(
+            known_seals(&operation.consignment, OwnedRightsType::OpenEpoch),
+            epochs
+        );
+    }
+
+    #[test]
+    fn transfer_checks() {
+        let consignment = fixtures::asset();
+        let utxos = utxos(&[outpoint(0)]);
+        let wallet = Wallet::with_utxos(&utxos);
+        let beneficiary = seal::Confidential::hash(b"beneficiary");
+        let change = utxo(outpoint(5));
+        assert!(matches!(
+            transfer(&wallet, &consignment, beneficiary, 0, &change, 1.0),
+            Err(Error::ZeroAmount)
+        ));
+        assert!(matches!(
+            transfer(&wallet, &consignment, beneficiary, 2000, &change, 1.0),
+            Err(Error::InsufficientAssets(1000, 2000))
+        ));
+        let spent = utxo(outpoint(0));
+        assert!(matches!(
+            transfer(&wallet, &consignment, beneficiary, 400, &spent, 1.0),
+            Err(Error::SealAtSpentOutput(seal)) if seal == outpoint(0)
+        ));
+
+        // Seals of other rights can't be closed by the transfer
+        let utxos = self::utxos(&[outpoint(1), outpoint(3)]);
+        let descriptors = [fixtures::descriptor_account()];
+        let wallet = Wallet {
+            descriptors: &descriptors,
+            ..Wallet::with_utxos(&utxos)
+        };
+        let issued =
+            inflate(&wallet, &consignment, &[(outpoint(3), 200)], 1.0).unwrap();
+        assert!(matches!(
+            transfer(
+                &wallet,
+                &issued.consignment,
+                beneficiary,
+                100,
+                &change,
+                1.0
+            ),
+            Err(Error::RightsAtSpentOutput(seal, OwnedRightsType::Renomination))
+                if seal == outpoint(3)
+        ));
+    }
+
+    #[test]
+    fn transfer_all_allocations() {
+        let consignment = fixtures::asset();
+        let descriptors = [fixtures::descriptor_account()];
+        let utxos = utxos(&[outpoint(0), outpoint(1)]);
+        let wallet = Wallet {
+            descriptors: &descriptors,
+            ..Wallet::with_utxos(&utxos)
+        };
+        let issued =
+            inflate(&wallet, &consignment, &[(outpoint(0), 200)], 1.0).unwrap();
+        let beneficiary = seal::Confidential::hash(b"beneficiary");
+        let (operation, _) = transfer(
+            &wallet,
+            &issued.consignment,
+            beneficiary,
+            400,
+            &utxo(outpoint(5)),
+            1.0,
+        )
+        .unwrap();
+        check_anchor(&operation, &[outpoint(0)]);
+        let assets =
+            known_seals(&operation.consignment, OwnedRightsType::Assets);
+        assert!(!assets.iter().any(|seal| seal.outpoint == outpoint(0)));
+        assert!(assets.iter().any(
+            |seal| seal.outpoint == outpoint(5) && seal.value == Some(800)
+        ));
+    }
+
+    #[test]
+    fn other_contracts() {
+        let consignment = fixtures::asset();
+        let descriptors = [fixtures::descriptor_account()];
+        let utxos = utxos(&[outpoint(0)]);
+        let wallet = Wallet {
+            descriptors: &descriptors,
+            ..Wallet::with_utxos(&utxos)
+        };
+        let beneficiary = seal::Confidential::hash(b"beneficiary");
+        let (operation, _) = transfer(
+            &wallet,
+            &consignment,
+            beneficiary,
+            400,
+            &utxo(outpoint(5)),
+            1.0,
+        )
+        .unwrap();
+        let contract_id = consignment.genesis.contract_id();
+        let mut contracts = bmap! { contract_id => consignment };
+        assert!(check_other_contracts(&operation, &contracts).is_ok());
+
+        let (_, genesis) = rgb20::issue(
+            Chain::Testnet3,
+            s!("OTH"),
+            s!("Other asset"),
+            None,
+            0,
+            vec![(outpoint(0), 100)],
+            empty!(),
+            None,
+            None,
+        )
+        .unwrap();
+        let other_id = genesis.contract_id();
+        contracts.insert(
+            other_id,
+            Consignment::with(genesis, none!(), none!(), none!()),
+        );
+        assert!(matches!(
+            check_other_contracts(&operation, &contracts),
+            Err(Error::ContractAtSpentOutput(seal, id))
+                if seal == outpoint(0) && id == other_id
+        ));
+    }
+
+    /// Checks that the witness transaction spends the given outputs and
+    /// commits to the state transition in OP_RETURN output; returns the
+    /// change output
+    fn check_anchor(operation: &AssetOperation, spent: &[OutPoint]) -> u32 {
+        let tx = &operation.psbt.global.unsigned_tx;
+        assert_eq!(
+            tx.input
+                .iter()
+                .map(|txin| txin.previous_output)
+                .collect::<Vec<_>>(),
+            spent
+        );
+        assert!(tx
+            .input
+            .iter()
+            .all(|txin| txin.sequence == WITNESS_SEQUENCE));
+        assert_eq!(tx.output.len(), 2);
+        let commitment = tx
+            .output
+            .iter()
+            .position(|txout| txout.script_pubkey.is_op_return())
+            .unwrap();
+        assert_eq!(tx.output[commitment].value, 0);
+        let change = 1 - commitment;
+        assert_eq!(
+            tx.output[change].script_pubkey,
+            fixtures::descriptor_account()
+                .pubkey_scripts(UnhardenedIndex::from_index(0u32).unwrap())
+                .unwrap()[&descriptor::Category::SegWit]
+        );
+        assert_eq!(
+            tx.output[change].value + operation.fee,
+            10_000 * spent.len() as u64
+        );
+
+        let (anchor, transition) =
+            operation.consignment.state_transitions.last().unwrap();
+        assert_eq!(anchor.txid, tx.txid());
+        assert_eq!(transition, &operation.transition);
+        change as u32
+    }
+
+    #[test]
+    fn inflation() {
+        let consignment = fixtures::asset();
+        let utxos = utxos(&[outpoint(1)]);
+        let descriptors = [fixtures::descriptor_account()];
+        let wallet = Wallet {
+            descriptors: &descriptors,
+            ..Wallet::with_utxos(&utxos)
+        };
+        let operation =
+            inflate(&wallet, &consignment, &[(outpoint(2), 200)], 1.0).unwrap();
+        let change = check_anchor(&operation, &[outpoint(1)]);
+        let txid = operation.psbt.global.unsigned_tx.txid();
+
+        let updated = &operation.consignment;
+        assert_eq!(updated.state_transitions.len(), 1);
+        assert_eq!(known_supply(updated).issued, 1200);
+        let inflation = known_seals(updated, OwnedRightsType::Inflation);
+        assert_eq!(inflation.len(), 1);
+        assert_eq!(inflation[0].outpoint, OutPoint::new(txid, change));
+        assert_eq!(inflation[0].value, Some(300));
+        assert!(known_seals(updated, OwnedRightsType::Assets).iter().any(
+            |seal| seal.outpoint == outpoint(2) && seal.value == Some(200)
+        ));
+    }
+
+    #[test]
+    fn transfer_to_beneficiary() {
+        let consignment = fixtures::asset();
+        let utxos = utxos(&[outpoint(0)]);
+        let descriptors = [fixtures::descriptor_account()];
+        let wallet = Wallet {
+            descriptors: &descriptors,
+            ..Wallet::with_utxos(&utxos)
+        };
+        let beneficiary = seal::Confidential::hash(b"beneficiary");
+        let change = utxo(outpoint(5));
+        let (operation, outgoing) =
+            transfer(&wallet, &consignment, beneficiary, 400, &change, 1.0)
+                .unwrap();
+        check_anchor(&operation, &[outpoint(0)]);
+
+        let updated = &operation.consignment;
+        assert_eq!(updated.state_transitions.len(), 1);
+        let assets = known_seals(updated, OwnedRightsType::Assets);
+        assert!(!assets.iter().any(|seal| seal.outpoint == outpoint(0)));
+        assert!(assets.iter().any(
+            |seal| seal.outpoint == outpoint(5) && seal.value == Some(600)
+        ));
+
+        assert_eq!(outgoing.state_transitions, updated.state_transitions);
+        assert_eq!(
+            outgoing.endpoints,
+            vec![(operation.transition.node_id(), beneficiary)]
+        );
+    }
+
+    #[test]
+    fn overflow() {
+        let consignment = fixtures::asset();
+        let utxos = utxos(&[outpoint(1)]);
+        let wallet = Wallet::with_utxos(&utxos);
+        assert!(matches!(
+            inflate(
+                &wallet,
+                &consignment,
+                &[(outpoint(2), AtomicValue::MAX), (outpoint(3), 1)],
+                1.0
+            ),
+            Err(Error::AmountOverflow)
+        ));
+    }
+
+    #[test]
+    fn non_segwit_seal() {
+        let consignment = fixtures::asset();
+        let mut utxos = utxos(&[outpoint(1)]);
+        utxos.get_mut(&outpoint(1)).unwrap().descriptor_category =
+            descriptor::Category::Hashed;
+        let wallet = Wallet::with_utxos(&utxos);
+        assert!(matches!(
+            inflate(&wallet, &consignment, &[(outpoint(2), 100)], 1.0),
+            Err(Error::NonSegwitInput(seal)) if seal == outpoint(1)
+        ));
+    }
+}
@@ -0,0 +1,246 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Synchronization of the asset seals with the blockchain: detection of the
+//! seals closed by state transitions which are not known to the document.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::thread;
+
+use bitcoin::{OutPoint, Script, Transaction, Txid};
+use electrum_client::{Client as ElectrumClient, ElectrumApi};
+use rgb::{Consignment, ContractId};
+use rgb20::schema::OwnedRightsType;
+
+use super::utxo_lookup::Error;
+use crate::model::{known_seals, AssetSeal};
+
+/// Asset rights controlled by single-use seals
+pub const SEALED_RIGHTS: [OwnedRightsType; 5] = [
+    OwnedRightsType::Assets,
+    OwnedRightsType::Inflation,
+    OwnedRightsType::Renomination,
+    OwnedRightsType::OpenEpoch,
+    OwnedRightsType::BurnReplace,
+];
+
+/// State of the contract seals according to the blockchain
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct SyncStatus {
+    /// Number of checked seals
+    pub checked: usize,
+    /// Seals which are not closed by the known state transitions, while
+    /// their outputs are spent; the allocations and rights defined by them
+    /// are stale
+    pub spent: Vec<(OwnedRightsType, AssetSeal)>,
+    /// Seals pointing to transactions not known to the server
+    pub unresolved: Vec<(OwnedRightsType, AssetSeal)>,
+}
+
+impl SyncStatus {
+    /// Outputs of the spent seals
+    pub fn spent_outpoints(&self) -> BTreeSet<OutPoint> {
+        self.spent.iter().map(|(_, seal)| seal.outpoint).collect()
+    }
+}
+
+/// Checks whether the outputs of all current contract seals are still
+/// unspent
+pub fn sync(
+    resolver: &ElectrumClient,
+    consignment: &Consignment,
+) -> Result<SyncStatus, Error> {
+    resolver.ping()?;
+
+    let seals = SEALED_RIGHTS
+        .iter()
+        .flat_map(|right| {
+            known_seals(consignment, *right)
+                .into_iter()
+                .map(move |seal| (*right, seal))
+        })
+        .collect::<Vec<_>>();
+
+    // Unknown and not yet broadcasted transactions are reported by the
+    // server as errors, so they are treated as unresolved
+    let mut txs = HashMap::<Txid, Option<Transaction>>::new();
+    for (_, seal) in &seals {
+        let txid = seal.outpoint.txid;
+        if !txs.contains_key(&txid) {
+            txs.insert(txid, resolver.transaction_get(&txid).ok());
+        }
+    }
+
+    let mut unspent = HashMap::<Script, HashSet<OutPoint>>::new();
+    for (_, seal) in &seals {
+        let script = match seal_script(&txs, seal.outpoint) {
+            Some(script) if !unspent.contains_key(script) => script,
+            _ => continue,
+        };
+        let outpoints = resolver
+            .script_list_unspent(script)?
+            .iter()
+            .map(|res| OutPoint::new(res.tx_hash, res.tx_pos as u32))
+            .collect();
+        unspent.insert(script.clone(), outpoints);
+    }
+
+    Ok(classify(seals, &txs, &unspent))
+}
+
+/// Classifies seals by the state of their outputs. Transactions defining
+/// the outputs are given in `txs` (with `None` for the ones unknown to the
+/// server), and unspent outputs of their scripts in `unspent`.
+fn classify(
+    seals: Vec<(OwnedRightsType, AssetSeal)>,
+    txs: &HashMap<Txid, Option<Transaction>>,
+    unspent: &HashMap<Script, HashSet<OutPoint>>,
+) -> SyncStatus {
+    let mut status = SyncStatus::default();
+    for (right, seal) in seals {
+        status.checked += 1;
+        let script = match seal_script(txs, seal.outpoint) {
+            Some(script) => script,
+            None => {
+                status.unresolved.push((right, seal));
+                continue;
+            }
+        };
+        let is_unspent = unspent
+            .get(script)
+            .map(|outpoints| outpoints.contains(&seal.outpoint))
+            .unwrap_or_default();
+        if !is_unspent {
+            status.spent.push((right, seal));
+        }
+    }
+    status
+}
+
+/// Script of the seal output, if its transaction is known
+fn seal_script(
+    txs: &HashMap<Txid, Option<Transaction>>,
+    outpoint: OutPoint,
+) -> Option<&Script> {
+    txs.get(&outpoint.txid)
+        .and_then(Option::as_ref)
+        .and_then(|tx| tx.output.get(outpoint.vout as usize))
+        .map(|txout| &txout.script_pubkey)
+}
+
+/// Synchronizes all the given contracts in a worker thread, since Electrum
+/// requests are blocking. The result is sent to the `result` channel once
+/// all contracts are checked; the first failure cancels the synchronization.
+pub fn spawn(
+    server: String,
+    consignments: Vec<Consignment>,
+    result: glib::Sender<Result<Vec<(ContractId, SyncStatus)>, Error>>,
+) {
+    thread::spawn(move || {
+        let status = ElectrumClient::new(&server)
+            .map_err(Error::from)
+            .and_then(|resolver| {
+                consignments
+                    .iter()
+                    .map(|consignment| {
+                        sync(&resolver, consignment).map(|status| {
+                            (consignment.genesis.contract_id(), status)
+                        })
+                    })
+                    .collect()
+            });
+        // The window may be closed while the synchronization runs
+        let _ = result.send(status);
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::TxOut;
+    use rgb::Node;
+
+    use super::*;
+    use crate::model::fixtures::{self, numbered_outpoint};
+
+    fn tx(scripts: &[&Script]) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: scripts
+                .iter()
+                .map(|script| TxOut {
+                    value: 1000,
+                    script_pubkey: (*script).clone(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn seal_classification() {
+        let first = Script::from(vec![0x51]);
+        let second = Script::from(vec![0x52]);
+        let tx = tx(&[&first, &second, &first]);
+        let txid = tx.txid();
+        let unknown = numbered_outpoint(1, 0);
+        let txs = vec![(txid, Some(tx)), (unknown.txid, None)]
+            .into_iter()
+            .collect();
+        let unspent = vec![
+            (first, set![OutPoint::new(txid, 0)]),
+            (second, HashSet::new()),
+        ]
+        .into_iter()
+        .collect();
+
+        let node_id = fixtures::asset().genesis.node_id();
+        let seal = |outpoint| AssetSeal {
+            node_id,
+            index: 0,
+            outpoint,
+            value: None,
+        };
+        let unspent_seal =
+            (OwnedRightsType::Assets, seal(OutPoint::new(txid, 0)));
+        let spent = (OwnedRightsType::Inflation, seal(OutPoint::new(txid, 1)));
+        let spent_same_script =
+            (OwnedRightsType::Renomination, seal(OutPoint::new(txid, 2)));
+        let unknown_tx = (OwnedRightsType::OpenEpoch, seal(unknown));
+        let unknown_vout =
+            (OwnedRightsType::BurnReplace, seal(OutPoint::new(txid, 3)));
+
+        let status = classify(
+            vec![
+                unspent_seal,
+                spent,
+                spent_same_script,
+                unknown_tx,
+                unknown_vout,
+            ],
+            &txs,
+            &unspent,
+        );
+        assert_eq!(status.checked, 5);
+        assert_eq!(status.spent, vec![spent, spent_same_script]);
+        assert_eq!(status.unresolved, vec![unknown_tx, unknown_vout]);
+        assert_eq!(
+            status.spent_outpoints().into_iter().collect::<Vec<_>>(),
+            vec![OutPoint::new(txid, 1), OutPoint::new(txid, 2)]
+        );
+    }
+}
@@ -0,0 +1,520 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Validation of RGB20 asset issuance parameters, performed before the
+//! asset genesis is created, and conversion of the amounts given in
+//! accounting units into atomic ones.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::iter;
+
+use bitcoin::OutPoint;
+use rgb::AtomicValue;
+use rgb20::schema::OwnedRightsType;
+
+use crate::model::UtxoEntry;
+
+/// Minimal number of characters in the asset ticker
+pub const TICKER_MIN_LEN: usize = 3;
+/// Maximal number of characters in the asset ticker
+pub const TICKER_MAX_LEN: usize = 8;
+/// Maximal number of decimal digits in the fractional part of the asset
+pub const MAX_PRECISION: u8 = 18;
+
+/// Inconsistency of the asset issuance parameters
+#[derive(Clone, PartialEq, Eq, Debug, Display)]
+#[display(doc_comments)]
+pub enum Issue {
+    /// Asset ticker is not specified
+    NoTicker,
+
+    /// Ticker `{0}` must consist of 3 to 8 capital latin letters and
+    /// digits, starting with a letter
+    TickerFormat(String),
+
+    /// Asset name is not specified
+    NoName,
+
+    /// Precision of {0} decimal digits exceeds the maximum of 18
+    Precision(u8),
+
+    /// Amount `{0}` is not a decimal number
+    AmountFormat(String),
+
+    /// Amount `{0}` has more fractional digits than the asset precision of
+    /// {1}
+    AmountPrecision(String, u8),
+
+    /// Asset has neither issued supply nor inflation rights
+    NoSupply,
+
+    /// Issued supply does not fit into 64-bit atomic units; reduce the
+    /// allocated amounts or the asset precision
+    IssueOverflow,
+
+    /// Total supply including the inflation cap does not fit into 64-bit
+    /// atomic units; reduce the inflation cap or the asset precision
+    TotalOverflow,
+
+    /// Sum of custom inflation allowances exceeds the inflation cap
+    InflationExceeded,
+
+    /// Output {0} is used more than once for the same asset right
+    DuplicatedSeal(OutPoint),
+
+    /// Allocation at {0} has zero amount
+    ZeroAllocation(OutPoint),
+
+    /// Output {0} controls several asset rights; operations with the asset
+    /// refuse to spend it, since it would close the seals of the other
+    /// rights
+    SharedSeal(OutPoint),
+
+    /// Output {0} is not present in the wallet UTXO cache
+    UnknownSeal(OutPoint),
+
+    /// Output {0} is not confirmed yet; asset rights assigned to it are lost
+    /// if the transaction gets replaced
+    UnconfirmedSeal(OutPoint),
+}
+
+impl Issue {
+    /// Errors prevent asset issuance, while the rest of the issues are
+    /// warnings which may be ignored by the user
+    pub fn is_error(&self) -> bool {
+        !matches!(
+            self,
+            Issue::ZeroAllocation(_)
+                | Issue::UnknownSeal(_)
+                | Issue::UnconfirmedSeal(_)
+        )
+    }
+}
+
+/// Splits decimal amount into its integer and fractional digits, dropping
+/// trailing zeros of the fractional part. Both dot and comma are accepted as
+/// the decimal separator, since spin buttons format values with the locale.
+fn decimal_digits(amount: &str) -> Option<(&str, &str)> {
+    let amount = amount.trim();
+    let (int, fract) = amount.split_once(['.', ',']).unwrap_or((amount, ""));
+    if (int.is_empty() && fract.is_empty())
+        || !int.chars().chain(fract.chars()).all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    Some((int, fract.trim_end_matches('0')))
+}
+
+/// Converts decimal amount in accounting units into atomic units for the
+/// given precision; returns `None` if the amount is malformed, has more
+/// fractional digits than the precision or does not fit into 64 bits.
+pub fn atomic_value(amount: &str, precision: u8) -> Option<AtomicValue> {
+    let (int, fract) = decimal_digits(amount)?;
+    let padding = (precision as usize).checked_sub(fract.len())?;
+    int.chars()
+        .chain(fract.chars())
+        .chain(iter::repeat('0').take(padding))
+        .try_fold(0u64, |value, c| {
+            value.checked_mul(10)?.checked_add(c.to_digit(10)? as u64)
+        })
+}
+
+/// Reports amount which can't be converted into atomic units for reasons
+/// other than an overflow
+fn amount_issue(amount: &str, precision: u8) -> Option<Issue> {
+    match decimal_digits(amount) {
+        None => Some(Issue::AmountFormat(amount.to_owned())),
+        Some((_, fract)) if fract.len() > precision as usize => {
+            Some(Issue::AmountPrecision(amount.to_owned(), precision))
+        }
+        Some(_) => None,
+    }
+}
+
+/// Checks that ticker consists of capital latin letters and digits,
+/// starts with a letter and has a length within the allowed limits
+pub fn is_valid_ticker(ticker: &str) -> bool {
+    (TICKER_MIN_LEN..=TICKER_MAX_LEN).contains(&ticker.len())
+        && ticker.starts_with(|c: char| c.is_ascii_uppercase())
+        && ticker
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+}
+
+/// Asset issuance parameters, with amounts given as decimal text in
+/// accounting units
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Issuance {
+    pub ticker: String,
+    pub name: String,
+    pub precision: u8,
+    pub allocation: Vec<(UtxoEntry, String)>,
+    /// Inflation cap; `None` stands for the maximal inflation which keeps
+    /// the total supply within 64-bit atomic units
+    pub inflation_cap: Option<String>,
+    /// Seals controlling the inflation with their custom allowances; seals
+    /// without an allowance get equal parts of the rest of the cap
+    pub inflation: Vec<(UtxoEntry, Option<String>)>,
+    pub renomination: Option<UtxoEntry>,
+    pub epoch: Option<UtxoEntry>,
+}
+
+impl Issuance {
+    fn atomic(&self, amount: &str) -> Option<AtomicValue> {
+        atomic_value(amount, self.precision)
+    }
+
+    /// Issued supply in atomic units, if it does not overflow
+    pub fn issued_supply(&self) -> Option<AtomicValue> {
+        self.allocation.iter().try_fold(0u64, |sum, (_, amount)| {
+            sum.checked_add(self.atomic(amount)?)
+        })
+    }
+
+    /// Inflation cap in atomic units, if it does not overflow
+    pub fn inflation_supply(&self) -> Option<AtomicValue> {
+        match &self.inflation_cap {
+            Some(cap) => self.atomic(cap),
+            None => u64::MAX.checked_sub(self.issued_supply()?),
+        }
+    }
+
+    /// Part of the inflation cap for each of the seals without a custom
+    /// allowance
+    pub fn equal_inflation(&self) -> Option<AtomicValue> {
+        let custom = self.custom_inflation()?;
+        let count = self
+            .inflation
+            .iter()
+            .filter(|(_, amount)| amount.is_none())
+            .count()
+            .max(1);
+        Some(self.inflation_supply()?.checked_sub(custom)? / count as u64)
+    }
+
+    fn custom_inflation(&self) -> Option<AtomicValue> {
+        self.inflation.iter().try_fold(0u64, |sum, (_, amount)| {
+            sum.checked_add(
+                amount.as_ref().map_or(Some(0), |a| self.atomic(a))?,
+            )
+        })
+    }
+
+    /// Allocations of the issued supply in atomic units
+    pub fn allocation_values(&self) -> Option<Vec<(OutPoint, AtomicValue)>> {
+        self.allocation
+            .iter()
+            .map(|(utxo, amount)| Some((utxo.outpoint, self.atomic(amount)?)))
+            .collect()
+    }
+
+    /// Inflation allowances in atomic units
+    pub fn inflation_values(&self) -> Option<BTreeMap<OutPoint, AtomicValue>> {
+        let equal = self.equal_inflation()?;
+        self.inflation
+            .iter()
+            .map(|(utxo, amount)| {
+                let value = match amount {
+                    Some(amount) => self.atomic(amount)?,
+                    None => equal,
+                };
+                Some((utxo.outpoint, value))
+            })
+            .collect()
+    }
+
+    /// Reports all inconsistencies of the issuance parameters, checking
+    /// the seals against the wallet UTXO cache
+    pub fn validate(&self, utxos: &HashSet<UtxoEntry>) -> Vec<Issue> {
+        let mut issues = vec![];
+
+        if self.ticker.is_empty() {
+            issues.push(Issue::NoTicker);
+        } else if !is_valid_ticker(&self.ticker) {
+            issues.push(Issue::TickerFormat(self.ticker.clone()));
+        }
+        if self.name.trim().is_empty() {
+            issues.push(Issue::NoName);
+        }
+        if self.precision > MAX_PRECISION {
+            issues.push(Issue::Precision(self.precision));
+        }
+
+        if self.allocation.is_empty() && self.inflation.is_empty() {
+            issues.push(Issue::NoSupply);
+        }
+        let amounts = self
+            .allocation
+            .iter()
+            .map(|(_, amount)| amount)
+            .chain(&self.inflation_cap)
+            .chain(
+                self.inflation
+                    .iter()
+                    .filter_map(|(_, amount)| amount.as_ref()),
+            );
+        let amount_issues = amounts
+            .filter_map(|amount| amount_issue(amount, self.precision))
+            .collect::<Vec<_>>();
+        match self.issued_supply() {
+            // Malformed amounts are reported instead of the overflows
+            _ if !amount_issues.is_empty() => issues.extend(amount_issues),
+            None => issues.push(Issue::IssueOverflow),
+            Some(issued) if !self.inflation.is_empty() => {
+                match self
+                    .inflation_supply()
+                    .and_then(|inflation| issued.checked_add(inflation))
+                {
+                    None => issues.push(Issue::TotalOverflow),
+                    Some(_) if self.equal_inflation().is_none() => {
+                        issues.push(Issue::InflationExceeded)
+                    }
+                    Some(_) => {}
+                }
+            }
+            Some(_) => {}
+        }
+
+        let seals = self
+            .allocation
+            .iter()
+            .map(|(utxo, _)| (OwnedRightsType::Assets, utxo))
+            .chain(
+                self.inflation
+                    .iter()
+                    .map(|(utxo, _)| (OwnedRightsType::Inflation, utxo)),
+            )
+            .chain(
+                self.renomination
+                    .iter()
+                    .map(|utxo| (OwnedRightsType::Renomination, utxo)),
+            )
+            .chain(
+                self.epoch
+                    .iter()
+                    .map(|utxo| (OwnedRightsType::OpenEpoch, utxo)),
+            );
+        let mut rights = HashMap::<OutPoint, Vec<OwnedRightsType>>::new();
+        let mut outpoints = vec![];
+        for (right, utxo) in seals {
+            let entry = rights.entry(utxo.outpoint).or_insert_with(|| {
+                outpoints.push(utxo);
+                vec![]
+            });
+            if entry.contains(&right) {
+                issues.push(Issue::DuplicatedSeal(utxo.outpoint));
+            }
+            entry.push(right);
+        }
+
+        for (utxo, amount) in &self.allocation {
+            if self.atomic(amount) == Some(0) {
+                issues.push(Issue::ZeroAllocation(utxo.outpoint));
+            }
+        }
+        for utxo in outpoints {
+            let mut rights = rights[&utxo.outpoint].clone();
+            rights.dedup();
+            if rights.len() > 1 {
+                issues.push(Issue::SharedSeal(utxo.outpoint));
+            }
+            match utxos.iter().find(|u| u.outpoint == utxo.outpoint) {
+                None => issues.push(Issue::UnknownSeal(utxo.outpoint)),
+                Some(cached) if cached.height == 0 => {
+                    issues.push(Issue::UnconfirmedSeal(utxo.outpoint))
+                }
+                Some(_) => {}
+            }
+        }
+
+        issues
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::fixtures::{self, outpoint};
+
+    fn utxo(vout: u32, height: u32) -> UtxoEntry {
+        fixtures::utxo(outpoint(vout), height, 10_000)
+    }
+
+    fn issuance() -> Issuance {
+        Issuance {
+            ticker: s!("TST"),
+            name: s!("Test asset"),
+            precision: 2,
+            allocation: vec![
+                (utxo(0, 100), s!("10.5")),
+                (utxo(1, 100), s!("0.29")),
+            ],
+            ..Issuance::default()
+        }
+    }
+
+    fn cache() -> HashSet<UtxoEntry> {
+        (0..4).map(|vout| utxo(vout, 100)).collect()
+    }
+
+    #[test]
+    fn atomic_conversion() {
+        assert_eq!(atomic_value("0.29", 2), Some(29));
+        assert_eq!(atomic_value("0,29000000", 2), Some(29));
+        assert_eq!(atomic_value(" 12 ", 2), Some(1200));
+        assert_eq!(atomic_value(".5", 1), Some(5));
+        assert_eq!(atomic_value("1", 18), Some(10u64.pow(18)));
+        assert_eq!(atomic_value("18.5", 18), None);
+        assert_eq!(atomic_value("18446744073709551615", 0), Some(u64::MAX));
+        assert_eq!(atomic_value("1844674407370955161.5", 1), Some(u64::MAX));
+        assert_eq!(atomic_value("18446744073709551616", 0), None);
+        assert_eq!(atomic_value("0.295", 2), None);
+        assert_eq!(atomic_value("-1", 0), None);
+        assert_eq!(atomic_value("1e3", 0), None);
+        assert_eq!(atomic_value("1.2.3", 2), None);
+        assert_eq!(atomic_value(".", 2), None);
+        assert_eq!(atomic_value("", 2), None);
+    }
+
+    #[test]
+    fn ticker_checks() {
+        assert!(is_valid_ticker("USDT"));
+        assert!(is_valid_ticker("B2B"));
+        assert!(!is_valid_ticker("US"));
+        assert!(!is_valid_ticker("1INCH"));
+        assert!(!is_valid_ticker("TOOLONGTK"));
+        assert!(!is_valid_ticker("Usd"));
+
+        assert_eq!(issuance().validate(&cache()), vec![]);
+        let mut issuance = issuance();
+        issuance.ticker = s!("T-1");
+        issuance.name = s!(" ");
+        issuance.precision = 19;
+        assert_eq!(
+            issuance.validate(&cache()),
+            vec![
+                Issue::TickerFormat(s!("T-1")),
+                Issue::NoName,
+                Issue::Precision(19),
+                Issue::IssueOverflow,
+            ]
+        );
+        issuance.ticker = s!("");
+        assert!(issuance.validate(&cache()).contains(&Issue::NoTicker));
+    }
+
+    #[test]
+    fn supply_checks() {
+        let mut issuance = issuance();
+        assert_eq!(issuance.issued_supply(), Some(1079));
+        assert_eq!(
+            issuance.allocation_values(),
+            Some(vec![(utxo(0, 0).outpoint, 1050), (utxo(1, 0).outpoint, 29)])
+        );
+
+        issuance.inflation = vec![(utxo(2, 100), None), (utxo(3, 100), None)];
+        assert_eq!(issuance.inflation_supply(), Some(u64::MAX - 1079));
+        assert_eq!(issuance.equal_inflation(), Some((u64::MAX - 1079) / 2));
+        assert_eq!(issuance.validate(&cache()), vec![]);
+
+        issuance.inflation_cap = Some(s!("100"));
+        issuance.inflation[0].1 = Some(s!("30"));
+        assert_eq!(
+            issuance.inflation_values(),
+            Some(bmap! {
+                utxo(2, 0).outpoint => 3000,
+                utxo(3, 0).outpoint => 7000
+            })
+        );
+        issuance.inflation[0].1 = Some(s!("130"));
+        assert_eq!(issuance.validate(&cache()), vec![Issue::InflationExceeded]);
+
+        issuance.allocation[0].1 = s!("100000000000000000");
+        issuance.inflation_cap = Some(s!("100000000000000000"));
+        assert_eq!(issuance.issued_supply(), Some(10u64.pow(19) + 29));
+        assert_eq!(issuance.validate(&cache()), vec![Issue::TotalOverflow]);
+
+        issuance
+            .allocation
+            .push((utxo(3, 100), s!("100000000000000000")));
+        assert_eq!(issuance.issued_supply(), None);
+        assert!(issuance.validate(&cache()).contains(&Issue::IssueOverflow));
+
+        let empty = Issuance {
+            allocation: vec![],
+            ..self::issuance()
+        };
+        assert_eq!(empty.validate(&cache()), vec![Issue::NoSupply]);
+    }
+
+    #[test]
+    fn amount_checks() {
+        let mut issuance = issuance();
+        issuance.allocation[0].1 = s!("10.505");
+        issuance.allocation[1].1 = s!("1e3");
+        issuance.inflation = vec![(utxo(2, 100), Some(s!("-5")))];
+        assert_eq!(
+            issuance.validate(&cache()),
+            vec![
+                Issue::AmountPrecision(s!("10.505"), 2),
+                Issue::AmountFormat(s!("1e3")),
+                Issue::AmountFormat(s!("-5")),
+            ]
+        );
+        assert_eq!(issuance.issued_supply(), None);
+
+        issuance.allocation[0].1 = s!("10.50000000");
+        issuance.allocation[1].1 = s!("1000");
+        issuance.inflation[0].1 = Some(s!("5"));
+        assert_eq!(issuance.validate(&cache()), vec![]);
+        assert_eq!(issuance.issued_supply(), Some(101_050));
+    }
+
+    #[test]
+    fn seal_checks() {
+        let mut issuance = issuance();
+        issuance.allocation.push((utxo(0, 100), s!("0")));
+        issuance.inflation = vec![(utxo(1, 100), None)];
+        issuance.renomination = Some(utxo(4, 100));
+        issuance.epoch = Some(utxo(3, 0));
+        let mut cache = cache();
+        cache.remove(&utxo(3, 100));
+        cache.insert(utxo(3, 0));
+
+        let issues = issuance.validate(&cache);
+        assert_eq!(
+            issues,
+            vec![
+                Issue::DuplicatedSeal(utxo(0, 0).outpoint),
+                Issue::ZeroAllocation(utxo(0, 0).outpoint),
+                Issue::SharedSeal(utxo(1, 0).outpoint),
+                Issue::UnknownSeal(utxo(4, 0).outpoint),
+                Issue::UnconfirmedSeal(utxo(3, 0).outpoint),
+            ]
+        );
+        assert_eq!(
+            issues
+                .into_iter()
+                .filter(Issue::is_error)
+                .collect::<Vec<_>>(),
+            vec![
+                Issue::DuplicatedSeal(utxo(0, 0).outpoint),
+                Issue::SharedSeal(utxo(1, 0).outpoint),
+            ]
+        );
+    }
+}
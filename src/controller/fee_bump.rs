@@ -0,0 +1,772 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Fee bumping of unconfirmed transactions: replace-by-fee (BIP125) and
+//! child-pays-for-parent. Both produce new unsigned PSBTs, constructed from
+//! the cached UTXO data of the document.
+
+use std::collections::HashMap;
+
+use bitcoin::blockdata::{opcodes, script::Builder};
+use bitcoin::util::bip32::{self, ChildNumber, DerivationPath, KeySource};
+use bitcoin::util::{address, psbt};
+use bitcoin::{
+    Address, Network, OutPoint, PublicKey, Script, Transaction, TxIn, TxOut,
+    Txid,
+};
+use wallet::bip32::{ChildIndex, UnhardenedIndex};
+use wallet::{descriptor, Psbt};
+
+use super::fee_estimator::{self, fee_for, tx_weight, vsize, InputWeight};
+use super::psbt_finalizer::{dust_limit, MIN_RELAY_FEE_RATE};
+use super::psbt_signer::PSBT_IN_TAP_KEY_SIG;
+use crate::model::{DescriptorAccount, UtxoEntry};
+
+/// Sequence number used by the created transactions, signalling
+/// replaceability according to BIP125
+pub const RBF_SEQUENCE: u32 = 0xffff_fffd;
+
+#[derive(Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// Transaction {0} is already confirmed
+    Confirmed(Txid),
+
+    /// Transaction {0} does not signal replaceability, so its fee can be
+    /// bumped only with child-pays-for-parent
+    NotReplaceable(Txid),
+
+    /// Output {0} spent by the transaction is not found among cached UTXOs,
+    /// transactions or PSBTs; please run UTXO lookup for its descriptor
+    UnknownOutput(OutPoint),
+
+    /// Descriptor which has produced output {0} is not present in the
+    /// document
+    UnknownDescriptor(OutPoint),
+
+    /// Output {0} uses descriptor which is not supported for spending
+    UnsupportedTemplate(OutPoint),
+
+    /// Transaction {0} has no unconfirmed outputs belonging to the document
+    /// descriptors
+    NoChange(Txid),
+
+    /// Change amount of {0} sat is not sufficient to pay additional fee of
+    /// {1} sat
+    InsufficientFunds(u64, u64),
+
+    /// Output {0} has hardened derivation index
+    HardenedIndex(OutPoint),
+
+    /// {0}
+    #[from]
+    Weight(fee_estimator::Error),
+
+    /// Unable to derive public key: {0}
+    #[from]
+    Bip32(bip32::Error),
+
+    /// Unable to derive script: {0}
+    #[from]
+    Descriptor(descriptor::Error),
+
+    /// Unable to construct script: {0}
+    #[from]
+    Address(address::Error),
+
+    /// Unable to construct PSBT: {0}
+    #[from]
+    Psbt(psbt::Error),
+}
+
+/// Unsigned PSBT bumping transaction fee
+#[derive(Clone, PartialEq, Debug)]
+pub struct FeeBump {
+    pub psbt: Psbt,
+    /// Fee paid by the new transaction
+    pub fee: u64,
+    /// Predicted virtual size of the new transaction
+    pub vsize: usize,
+    /// Fee rate of the new transaction; for child-pays-for-parent this is
+    /// effective fee rate of both parent and child transactions
+    pub fee_rate: f32,
+}
+
+/// Document data used for fee bumping
+#[derive(Copy, Clone, Debug)]
+pub struct Wallet<'a> {
+    pub descriptors: &'a [DescriptorAccount],
+    /// Cached UTXOs indexed by their outpoints
+    pub utxos: &'a HashMap<OutPoint, UtxoEntry>,
+    /// Known transactions, used as previous transactions for the inputs
+    pub transactions: &'a [Transaction],
+    /// PSBTs of the document, providing signing data for the outputs which
+    /// were already spent and removed from the UTXO cache
+    pub psbts: &'a [Psbt],
+}
+
+#[cfg(test)]
+impl<'a> Wallet<'a> {
+    /// Wallet without descriptors, transactions and PSBTs
+    pub fn with_utxos(utxos: &'a HashMap<OutPoint, UtxoEntry>) -> Self {
+        Wallet {
+            descriptors: &[],
+            utxos,
+            transactions: &[],
+            psbts: &[],
+        }
+    }
+}
+
+impl Wallet<'_> {
+    /// Creates replacement for the transaction, paying the increased fee
+    /// from its change output.
+    ///
+    /// Replacement fee is not less than the target fee rate and exceeds
+    /// the original fee by at least the minimal relay fee of the
+    /// replacement, as required by BIP125.
+    ///
+    /// Outputs spent by the transaction are usually removed from the UTXO
+    /// cache once it is broadcast, so their data are taken from the known
+    /// transactions and PSBTs instead. In this case the weight of the
+    /// signed original transaction is used for the fee computation.
+    pub fn replace_by_fee(
+        &self,
+        tx: &Transaction,
+        sat_per_vbyte: f32,
+    ) -> Result<FeeBump, Error> {
+        let txid = tx.txid();
+        if !tx.input.iter().any(|txin| txin.sequence < 0xffff_fffe) {
+            return Err(Error::NotReplaceable(txid));
+        }
+        let change_vout = self.change_output(tx)?.outpoint.vout as usize;
+        let input_amount = tx
+            .input
+            .iter()
+            .map(|txin| {
+                let prevout = txin.previous_output;
+                self.spent_amount(prevout)
+                    .ok_or(Error::UnknownOutput(prevout))
+            })
+            .sum::<Result<u64, _>>()?;
+        let spent = tx
+            .input
+            .iter()
+            .map(|txin| self.utxo(txin.previous_output))
+            .collect::<Option<Vec<_>>>();
+        let weight = match spent {
+            Some(spent) => {
+                let inputs = spent
+                    .iter()
+                    .map(|utxo| {
+                        InputWeight::with(
+                            &utxo.descriptor_template,
+                            utxo.descriptor_category,
+                        )
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let outputs = tx
+                    .output
+                    .iter()
+                    .map(|txout| txout.script_pubkey.clone())
+                    .collect::<Vec<_>>();
+                tx_weight(&inputs, &outputs)
+            }
+            None => {
+                if let Some(txin) = tx.input.iter().find(|txin| {
+                    txin.script_sig.is_empty() && txin.witness.is_empty()
+                }) {
+                    return Err(Error::UnknownOutput(txin.previous_output));
+                }
+                tx.get_weight()
+            }
+        };
+
+        let output_amount =
+            tx.output.iter().map(|txout| txout.value).sum::<u64>();
+        let old_fee = input_amount.saturating_sub(output_amount);
+        let fee = fee_for(weight, sat_per_vbyte)
+            .max(old_fee + fee_for(weight, MIN_RELAY_FEE_RATE));
+        let extra_fee = fee - old_fee;
+
+        let mut replacement = tx.clone();
+        let change = &mut replacement.output[change_vout];
+        if change.value < extra_fee + dust_limit(change) {
+            return Err(Error::InsufficientFunds(change.value, extra_fee));
+        }
+        change.value -= extra_fee;
+        for txin in &mut replacement.input {
+            txin.script_sig = Script::new();
+            txin.witness = vec![];
+        }
+
+        let mut psbt = Psbt::from_unsigned_tx(replacement)?;
+        for (input, txin) in psbt.inputs.iter_mut().zip(&tx.input) {
+            self.fill_spent_input(input, txin.previous_output)?;
+        }
+
+        let vsize = vsize(weight);
+        Ok(FeeBump {
+            psbt,
+            fee,
+            vsize,
+            fee_rate: fee as f32 / vsize as f32,
+        })
+    }
+
+    /// Creates child transaction spending unconfirmed change output of the
+    /// parent, paying fee for both transactions at the target fee rate.
+    ///
+    /// If the destination is not given, the funds are sent back to the
+    /// change output script. When some of the parent inputs are not known
+    /// the parent is assumed to pay no fee, so the package fee rate may
+    /// exceed the target.
+    pub fn child_pays_for_parent(
+        &self,
+        parent: &Transaction,
+        sat_per_vbyte: f32,
+        destination: Option<Script>,
+    ) -> Result<FeeBump, Error> {
+        let change = self.change_output(parent)?;
+        let destination = destination.unwrap_or_else(|| {
+            parent.output[change.outpoint.vout as usize]
+                .script_pubkey
+                .clone()
+        });
+        let input = InputWeight::with(
+            &change.descriptor_template,
+            change.descriptor_category,
+        )?;
+        let weight = tx_weight(&[input], &[destination.clone()]);
+        let parent_weight = parent.get_weight();
+        let parent_fee = self.fee(parent).unwrap_or_default();
+        let fee = fee_for(weight + parent_weight, sat_per_vbyte)
+            .saturating_sub(parent_fee)
+            .max(fee_for(weight, MIN_RELAY_FEE_RATE));
+
+        let output = TxOut {
+            value: change.amount.saturating_sub(fee),
+            script_pubkey: destination,
+        };
+        if output.value < dust_limit(&output) {
+            return Err(Error::InsufficientFunds(change.amount, fee));
+        }
+        let child = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: change.outpoint,
+                script_sig: Script::new(),
+                sequence: RBF_SEQUENCE,
+                witness: vec![],
+            }],
+            output: vec![output],
+        };
+
+        let package_vsize = vsize(weight + parent_weight);
+        Ok(FeeBump {
+            psbt: self.psbt(child, &[change])?,
+            fee,
+            vsize: vsize(weight),
+            fee_rate: (fee + parent_fee) as f32 / package_vsize as f32,
+        })
+    }
+
+    fn utxo(&self, outpoint: OutPoint) -> Option<&UtxoEntry> {
+        self.utxos.get(&outpoint)
+    }
+
+    fn transaction(&self, txid: Txid) -> Option<&Transaction> {
+        self.transactions.iter().find(|tx| tx.txid() == txid)
+    }
+
+    /// Input of a document PSBT spending the given output
+    fn psbt_input(&self, outpoint: OutPoint) -> Option<&psbt::Input> {
+        self.psbts.iter().find_map(|psbt| {
+            psbt.global
+                .unsigned_tx
+                .input
+                .iter()
+                .position(|txin| txin.previous_output == outpoint)
+                .and_then(|pos| psbt.inputs.get(pos))
+        })
+    }
+
+    /// Amount of the output, taken from the UTXO cache, known transactions
+    /// or PSBTs spending it
+    fn spent_amount(&self, outpoint: OutPoint) -> Option<u64> {
+        self.utxo(outpoint)
+            .map(|utxo| utxo.amount)
+            .or_else(|| {
+                self.transaction(outpoint.txid)
+                    .and_then(|tx| tx.output.get(outpoint.vout as usize))
+                    .map(|txout| txout.value)
+            })
+            .or_else(|| {
+                let input = self.psbt_input(outpoint)?;
+                input
+                    .witness_utxo
+                    .as_ref()
+                    .map(|txout| txout.value)
+                    .or_else(|| {
+                        input
+                            .non_witness_utxo
+                            .as_ref()?
+                            .output
+                            .get(outpoint.vout as usize)
+                            .map(|txout| txout.value)
+                    })
+            })
+    }
+
+    /// Largest output of the transaction known to the document; fails if
+    /// any of the known outputs is confirmed
+    fn change_output(&self, tx: &Transaction) -> Result<&UtxoEntry, Error> {
+        let txid = tx.txid();
+        let own = (0..tx.output.len() as u32)
+            .filter_map(|vout| self.utxo(OutPoint::new(txid, vout)))
+            .collect::<Vec<_>>();
+        if own.iter().any(|utxo| utxo.height > 0) {
+            return Err(Error::Confirmed(txid));
+        }
+        own.into_iter()
+            .max_by_key(|utxo| utxo.amount)
+            .ok_or(Error::NoChange(txid))
+    }
+
+    /// Fee paid by the transaction, if all of its spent outputs are known
+    fn fee(&self, tx: &Transaction) -> Option<u64> {
+        let input_amount = tx
+            .input
+            .iter()
+            .map(|txin| self.spent_amount(txin.previous_output))
+            .sum::<Option<u64>>()?;
+        let output_amount =
+            tx.output.iter().map(|txout| txout.value).sum::<u64>();
+        input_amount.checked_sub(output_amount)
+    }
+
+    /// Constructs PSBT for the transaction spending the given UTXOs, filling
+    /// in the data required for signing
+    pub fn psbt(
+        &self,
+        tx: Transaction,
+        spent: &[&UtxoEntry],
+    ) -> Result<Psbt, Error> {
+        let mut psbt = Psbt::from_unsigned_tx(tx)?;
+        for (input, utxo) in psbt.inputs.iter_mut().zip(spent) {
+            self.fill_input(input, utxo)?;
+        }
+        Ok(psbt)
+    }
+
+    /// Fills PSBT input spending the given output. Cached UTXOs are
+    /// preferred; otherwise signing data are copied from the document PSBT
+    /// spending the same output, with all signatures removed. If neither is
+    /// known, only the previous transaction is provided.
+    fn fill_spent_input(
+        &self,
+        input: &mut psbt::Input,
+        outpoint: OutPoint,
+    ) -> Result<(), Error> {
+        if let Some(utxo) = self.utxo(outpoint) {
+            return self.fill_input(input, utxo);
+        }
+        if let Some(spent) = self.psbt_input(outpoint) {
+            *input = spent.clone();
+            input.partial_sigs.clear();
+            input.final_script_sig = None;
+            input.final_script_witness = None;
+            input
+                .unknown
+                .retain(|key, _| key.type_value != PSBT_IN_TAP_KEY_SIG);
+            return Ok(());
+        }
+        let prev_tx = self
+            .transaction(outpoint.txid)
+            .ok_or(Error::UnknownOutput(outpoint))?;
+        input.witness_utxo =
+            prev_tx.output.get(outpoint.vout as usize).cloned();
+        input.non_witness_utxo = Some(prev_tx.clone());
+        Ok(())
+    }
+
+    /// Derives script pubkey of the UTXO from its descriptor account
+    pub fn script_pubkey(&self, utxo: &UtxoEntry) -> Result<Script, Error> {
+        let outpoint = utxo.outpoint;
+        let account = self
+            .descriptors
+            .iter()
+            .find(|account| utxo.has_match(account))
+            .ok_or(Error::UnknownDescriptor(outpoint))?;
+        let index = UnhardenedIndex::from_index(utxo.derivation_index)
+            .map_err(|_| Error::HardenedIndex(outpoint))?;
+        account
+            .pubkey_scripts(index)?
+            .remove(&utxo.descriptor_category)
+            .ok_or(Error::UnknownDescriptor(outpoint))
+    }
+
+    /// Fills PSBT input with the spent output, scripts and key origins
+    /// required for signing. Previous transaction is provided when known;
+    /// otherwise only the spent output is given, which is sufficient for
+    /// the built-in signer, but may be rejected by hardware signers for
+    /// non-segwit inputs.
+    fn fill_input(
+        &self,
+        input: &mut psbt::Input,
+        utxo: &UtxoEntry,
+    ) -> Result<(), Error> {
+        let outpoint = utxo.outpoint;
+        let script_pubkey = self.script_pubkey(utxo)?;
+
+        let segwit = !matches!(
+            utxo.descriptor_category,
+            descriptor::Category::Bare | descriptor::Category::Hashed
+        );
+        input.non_witness_utxo = self.transaction(outpoint.txid).cloned();
+        if segwit || input.non_witness_utxo.is_none() {
+            input.witness_utxo = Some(TxOut {
+                value: utxo.amount,
+                script_pubkey,
+            });
+        }
+
+        let keys = match &utxo.descriptor_template {
+            descriptor::Template::SingleSig(key) => vec![key],
+            descriptor::Template::MultiSig(multisig) => {
+                multisig.pubkeys.iter().collect()
+            }
+            _ => return Err(Error::UnsupportedTemplate(outpoint)),
+        };
+        let mut pubkeys = vec![];
+        for key in keys {
+            let (pubkey, origin) = derive(key, utxo.derivation_index)?;
+            if let Some(origin) = origin {
+                input.bip32_derivation.insert(pubkey, origin);
+            }
+            pubkeys.push(pubkey);
+        }
+
+        match (&utxo.descriptor_template, utxo.descriptor_category) {
+            (
+                descriptor::Template::SingleSig(_),
+                descriptor::Category::Nested,
+            ) => {
+                input.redeem_script = Some(
+                    Address::p2wpkh(&pubkeys[0], Network::Bitcoin)?
+                        .script_pubkey(),
+                );
+            }
+            (descriptor::Template::MultiSig(multisig), category) => {
+                if multisig.reorder {
+                    pubkeys.sort_by_key(|key| key.key.serialize());
+                }
+                let script = pubkeys
+                    .iter()
+                    .fold(
+                        Builder::new().push_int(multisig.threshold() as i64),
+                        |builder, key| builder.push_key(key),
+                    )
+                    .push_int(pubkeys.len() as i64)
+                    .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+                    .into_script();
+                match category {
+                    descriptor::Category::Hashed => {
+                        input.redeem_script = Some(script)
+                    }
+                    descriptor::Category::Nested => {
+                        input.redeem_script = Some(
+                            Address::p2wsh(&script, Network::Bitcoin)
+                                .script_pubkey(),
+                        );
+                        input.witness_script = Some(script);
+                    }
+                    descriptor::Category::SegWit => {
+                        input.witness_script = Some(script)
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Derives public key with the given index together with its origin
+pub fn derive(
+    key: &descriptor::SingleSig,
+    index: u32,
+) -> Result<(PublicKey, Option<KeySource>), Error> {
+    match key {
+        descriptor::SingleSig::Pubkey(single) => {
+            Ok((single.key, single.origin.clone()))
+        }
+        descriptor::SingleSig::XPubDerivable(components) => {
+            let path = components
+                .terminal_path
+                .iter()
+                .chain(Some(&index))
+                .map(|index| ChildNumber::from_normal_idx(*index))
+                .collect::<Result<Vec<_>, _>>()?;
+            let xpub = components
+                .branch_xpub
+                .derive_pub(&wallet::SECP256K1, &path)?;
+            let branch_path: &[ChildNumber] = components.branch_path.as_ref();
+            let full_path = DerivationPath::from(
+                branch_path.iter().chain(&path).copied().collect::<Vec<_>>(),
+            );
+            Ok((
+                xpub.public_key,
+                Some((components.master_xpub.fingerprint(), full_path)),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+    use crate::controller::psbt_signer::PsbtSigner;
+    use crate::model::{fixtures, MnemonicSeed};
+
+    const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon \
+                            abandon abandon abandon abandon abandon about";
+
+    fn seed() -> MnemonicSeed {
+        MnemonicSeed::with(MNEMONIC, "").unwrap()
+    }
+
+    fn account() -> DescriptorAccount {
+        let components = seed()
+            .derivation_components(
+                Network::Testnet,
+                &DerivationPath::from_str("m/84'/1'/0'/0").unwrap(),
+                None,
+            )
+            .unwrap();
+        DescriptorAccount {
+            name: s!("Treasury"),
+            generator: descriptor::Generator {
+                template: descriptor::Template::SingleSig(
+                    descriptor::SingleSig::XPubDerivable(components),
+                ),
+                variants: descriptor::Variants {
+                    bare: false,
+                    hashed: false,
+                    nested: false,
+                    segwit: true,
+                    taproot: false,
+                },
+            },
+        }
+    }
+
+    fn utxo(outpoint: OutPoint, amount: u64, index: u32) -> UtxoEntry {
+        UtxoEntry {
+            descriptor_template: account().generator.template,
+            derivation_index: index,
+            ..fixtures::utxo(outpoint, 0, amount)
+        }
+    }
+
+    fn script(index: u32) -> Script {
+        account()
+            .pubkey_scripts(UnhardenedIndex::from_index(index).unwrap())
+            .unwrap()
+            .remove(&descriptor::Category::SegWit)
+            .unwrap()
+    }
+
+    /// Transaction spending 100000 sat with 1000 sat fee: payment of 60000
+    /// sat to a foreign address and change to index 5
+    fn setup(sequence: u32) -> (Transaction, HashMap<OutPoint, UtxoEntry>) {
+        let funding = OutPoint::new(Txid::hash(b"funding"), 1);
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: funding,
+                script_sig: Script::new(),
+                sequence,
+                witness: vec![],
+            }],
+            output: vec![
+                TxOut {
+                    value: 60_000,
+                    script_pubkey: Script::from(vec![0u8; 22]),
+                },
+                TxOut {
+                    value: 39_000,
+                    script_pubkey: script(5),
+                },
+            ],
+        };
+        let utxos = vec![
+            utxo(funding, 100_000, 2),
+            utxo(OutPoint::new(tx.txid(), 1), 39_000, 5),
+        ]
+        .into_iter()
+        .map(|utxo| (utxo.outpoint, utxo))
+        .collect();
+        (tx, utxos)
+    }
+
+    fn sign(psbt: &mut Psbt) -> usize {
+        PsbtSigner::with_seed(&seed(), Network::Testnet)
+            .unwrap()
+            .sign(psbt)
+            .unwrap()
+            .signed
+    }
+
+    #[test]
+    fn rbf() {
+        let (tx, utxos) = setup(RBF_SEQUENCE);
+        let descriptors = [account()];
+        let wallet = Wallet {
+            descriptors: &descriptors,
+            utxos: &utxos,
+            transactions: &[],
+            psbts: &[],
+        };
+        let mut bump = wallet.replace_by_fee(&tx, 10.0).unwrap();
+        assert_eq!(bump.vsize, 141);
+        assert_eq!(bump.fee, 1410);
+        assert!(bump.fee_rate >= 10.0);
+        let unsigned = &bump.psbt.global.unsigned_tx;
+        assert_eq!(unsigned.output[0].value, 60_000);
+        assert_eq!(unsigned.output[1].value, 39_000 - 410);
+        assert_eq!(
+            bump.psbt.inputs[0].witness_utxo,
+            Some(TxOut {
+                value: 100_000,
+                script_pubkey: script(2)
+            })
+        );
+        assert_eq!(sign(&mut bump.psbt), 1);
+
+        // Replacement must pay for its own relay on top of the original fee
+        let bump = wallet.replace_by_fee(&tx, 1.0).unwrap();
+        assert_eq!(bump.fee, 1000 + 141);
+
+        assert!(matches!(
+            wallet.replace_by_fee(&tx, 300.0),
+            Err(Error::InsufficientFunds(39_000, _))
+        ));
+
+        let (final_tx, utxos) = setup(0xffff_ffff);
+        let wallet = Wallet {
+            utxos: &utxos,
+            ..wallet
+        };
+        assert!(matches!(
+            wallet.replace_by_fee(&final_tx, 10.0),
+            Err(Error::NotReplaceable(_))
+        ));
+    }
+
+    #[test]
+    fn cpfp() {
+        let (tx, utxos) = setup(0xffff_ffff);
+        let descriptors = [account()];
+        let wallet = Wallet {
+            descriptors: &descriptors,
+            utxos: &utxos,
+            transactions: &[],
+            psbts: &[],
+        };
+        let mut bump = wallet.child_pays_for_parent(&tx, 10.0, None).unwrap();
+        let child = &bump.psbt.global.unsigned_tx;
+        assert_eq!(child.input[0].previous_output, OutPoint::new(tx.txid(), 1));
+        assert_eq!(child.output[0].script_pubkey, script(5));
+        assert_eq!(bump.vsize, 110);
+        // Unsigned parent weights 452 WU and pays 1000 sat, so the package
+        // of 890 WU requires 2230 sat
+        assert_eq!(bump.fee, 2230 - 1000);
+        assert!(bump.fee_rate >= 10.0);
+        assert_eq!(sign(&mut bump.psbt), 1);
+
+        let confirmed = utxos
+            .values()
+            .cloned()
+            .map(|utxo| (utxo.outpoint, UtxoEntry { height: 10, ..utxo }))
+            .collect();
+        let wallet = Wallet {
+            utxos: &confirmed,
+            ..wallet
+        };
+        assert!(matches!(
+            wallet.child_pays_for_parent(&tx, 10.0, None),
+            Err(Error::Confirmed(_))
+        ));
+    }
+
+    #[test]
+    fn rbf_spent_outputs() {
+        let (tx, mut utxos) = setup(RBF_SEQUENCE);
+        let descriptors = [account()];
+        let funding = tx.input[0].previous_output;
+        let mut original = Wallet {
+            descriptors: &descriptors,
+            utxos: &utxos,
+            transactions: &[],
+            psbts: &[],
+        }
+        .psbt(tx.clone(), &[&utxos[&funding]])
+        .unwrap();
+        assert_eq!(sign(&mut original), 1);
+        let mut signed = tx.clone();
+        signed.input[0].witness = vec![vec![0u8; 72], vec![0u8; 33]];
+
+        // Broadcasted transaction removes the spent output from the cache
+        utxos.remove(&funding);
+        let psbts = [original];
+        let wallet = Wallet {
+            descriptors: &descriptors,
+            utxos: &utxos,
+            transactions: &[],
+            psbts: &psbts,
+        };
+        let mut bump = wallet.replace_by_fee(&signed, 10.0).unwrap();
+        assert_eq!(bump.vsize, 141);
+        assert_eq!(bump.fee, 1410);
+        assert!(bump.psbt.inputs[0].partial_sigs.is_empty());
+        assert!(!bump.psbt.inputs[0].bip32_derivation.is_empty());
+        assert_eq!(sign(&mut bump.psbt), 1);
+
+        // Unsigned transaction weight can't be known without UTXO data
+        assert!(matches!(
+            wallet.replace_by_fee(&tx, 10.0),
+            Err(Error::UnknownOutput(outpoint)) if outpoint == funding
+        ));
+
+        let wallet = Wallet {
+            psbts: &[],
+            ..wallet
+        };
+        assert!(matches!(
+            wallet.replace_by_fee(&signed, 10.0),
+            Err(Error::UnknownOutput(outpoint)) if outpoint == funding
+        ));
+    }
+}
@@ -0,0 +1,377 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Fee rate estimation and prediction of transaction weight before the
+//! transaction is signed
+
+use std::str::FromStr;
+
+use bitcoin::consensus::encode::VarInt;
+use bitcoin::{PublicKey, Script};
+use electrum_client::{Client as ElectrumClient, ElectrumApi};
+use miniscript::{Descriptor, DescriptorTrait};
+use wallet::descriptor;
+
+use super::psbt_finalizer::MIN_RELAY_FEE_RATE;
+
+/// Fee rate (in sat/vbyte) used when the resolver is not able to provide
+/// any estimate
+pub const DEFAULT_FEE_RATE: f32 = 5.0;
+
+/// Maximum confirmation target (in blocks) supported by Bitcoin Core fee
+/// estimator
+pub const MAX_CONFIRMATION_TARGET: usize = 1008;
+
+/// Weight of the non-witness part of transaction input: previous outpoint
+/// and sequence number; script length is accounted in the satisfaction
+/// weight
+const INPUT_BASE_WEIGHT: usize = 4 * (32 + 4 + 4);
+
+/// Satisfaction weight of taproot key path spend with default sighash type:
+/// empty `scriptSig`, witness stack with a single 64-byte signature
+const TAPROOT_KEY_SPEND_WEIGHT: usize = 4 + 1 + 1 + 64;
+
+#[derive(Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// Only single-key and multi-signature descriptors support weight
+    /// prediction
+    UnsupportedTemplate,
+
+    /// Taproot spending is supported only for single-key descriptors
+    UnsupportedTaproot,
+
+    /// Unable to construct miniscript descriptor: {0}
+    #[from]
+    Miniscript(miniscript::Error),
+}
+
+/// Origin of the fee rate estimate
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Display)]
+pub enum FeeSource {
+    /// Estimate for confirmation within the given number of blocks
+    #[display("estimate for {0} blocks")]
+    Estimate(usize),
+
+    /// Minimal fee rate relayed by the server
+    #[display("server relay fee")]
+    RelayFee,
+
+    /// Default value used when the resolver gave no estimate
+    #[display("default")]
+    Default,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Display)]
+#[display("{sat_per_vbyte:.1} sat/vbyte ({source})")]
+pub struct FeeRate {
+    pub sat_per_vbyte: f32,
+    pub source: FeeSource,
+}
+
+/// Retrieves fee rate required for confirmation within the target number
+/// of blocks.
+///
+/// If the server has no estimate for the target, longer targets are tried
+/// (doubling the number of blocks), followed by the server relay fee. When
+/// the server is not able to provide any of them, [`DEFAULT_FEE_RATE`] is
+/// used. The returned fee rate is never below [`MIN_RELAY_FEE_RATE`].
+pub fn estimate_fee_rate(resolver: &ElectrumClient, target: usize) -> FeeRate {
+    estimate_with(
+        target,
+        |blocks| resolver.estimate_fee(blocks).ok(),
+        || resolver.relay_fee().ok(),
+    )
+}
+
+fn estimate_with(
+    target: usize,
+    mut estimate: impl FnMut(usize) -> Option<f64>,
+    relay_fee: impl FnOnce() -> Option<f64>,
+) -> FeeRate {
+    // Electrum servers provide fee rates in BTC/kvbyte and return -1 when
+    // no estimate is available
+    let to_sat_per_vbyte = |btc_per_kvb: f64| -> Option<f32> {
+        if btc_per_kvb > 0.0 {
+            Some((btc_per_kvb * 100_000.0) as f32)
+        } else {
+            None
+        }
+    };
+    let mut blocks = target.max(1);
+    let mut rate = None;
+    while blocks <= MAX_CONFIRMATION_TARGET {
+        if let Some(sat_per_vbyte) = estimate(blocks).and_then(to_sat_per_vbyte)
+        {
+            rate = Some(FeeRate {
+                sat_per_vbyte,
+                source: FeeSource::Estimate(blocks),
+            });
+            break;
+        }
+        blocks *= 2;
+    }
+    let mut rate = rate
+        .or_else(|| {
+            relay_fee().and_then(to_sat_per_vbyte).map(|sat_per_vbyte| {
+                FeeRate {
+                    sat_per_vbyte,
+                    source: FeeSource::RelayFee,
+                }
+            })
+        })
+        .unwrap_or(FeeRate {
+            sat_per_vbyte: DEFAULT_FEE_RATE,
+            source: FeeSource::Default,
+        });
+    rate.sat_per_vbyte = rate.sat_per_vbyte.max(MIN_RELAY_FEE_RATE);
+    rate
+}
+
+/// Predicted weight of a transaction input
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct InputWeight {
+    /// Maximum weight of the input, including its satisfaction, assuming
+    /// the largest possible ECDSA signatures
+    pub weight: usize,
+    /// Whether the input has witness data
+    pub segwit: bool,
+}
+
+impl InputWeight {
+    /// Predicts weight of the input spending output generated by the
+    /// descriptor template for the given script category
+    pub fn with(
+        template: &descriptor::Template,
+        category: descriptor::Category,
+    ) -> Result<InputWeight, Error> {
+        let satisfaction_weight = if category == descriptor::Category::Taproot {
+            match template {
+                descriptor::Template::SingleSig(_) => TAPROOT_KEY_SPEND_WEIGHT,
+                _ => return Err(Error::UnsupportedTaproot),
+            }
+        } else {
+            satisfaction_descriptor(template, category)?
+                .max_satisfaction_weight()?
+        };
+        Ok(InputWeight {
+            weight: INPUT_BASE_WEIGHT + satisfaction_weight,
+            segwit: category != descriptor::Category::Bare
+                && category != descriptor::Category::Hashed,
+        })
+    }
+}
+
+/// Constructs miniscript descriptor with the same structure as the template.
+/// Satisfaction weight does not depend on the derivation index, so the
+/// keys are taken without derivation.
+fn satisfaction_descriptor(
+    template: &descriptor::Template,
+    category: descriptor::Category,
+) -> Result<Descriptor<PublicKey>, Error> {
+    let key = |key: &descriptor::SingleSig| match key {
+        descriptor::SingleSig::XPubDerivable(components) => {
+            components.branch_xpub.public_key
+        }
+        descriptor::SingleSig::Pubkey(single) => single.key,
+    };
+    let desc = match template {
+        descriptor::Template::SingleSig(single) => {
+            let key = key(single);
+            match category {
+                descriptor::Category::Bare => format!("pk({})", key),
+                descriptor::Category::Hashed => format!("pkh({})", key),
+                descriptor::Category::Nested => format!("sh(wpkh({}))", key),
+                _ => format!("wpkh({})", key),
+            }
+        }
+        descriptor::Template::MultiSig(multisig) => {
+            let multi = format!(
+                "multi({},{})",
+                multisig.threshold(),
+                multisig
+                    .pubkeys
+                    .iter()
+                    .map(|single| key(single).to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            );
+            match category {
+                descriptor::Category::Bare => multi,
+                descriptor::Category::Hashed => format!("sh({})", multi),
+                descriptor::Category::Nested => format!("sh(wsh({}))", multi),
+                _ => format!("wsh({})", multi),
+            }
+        }
+        _ => return Err(Error::UnsupportedTemplate),
+    };
+    Ok(Descriptor::from_str(&desc)?)
+}
+
+/// Predicts maximal weight of a transaction with the given inputs and
+/// outputs, including segwit marker and flag when required
+pub fn tx_weight(inputs: &[InputWeight], outputs: &[Script]) -> usize {
+    let segwit = inputs.iter().any(|input| input.segwit);
+    let base = 4
+        + VarInt(inputs.len() as u64).len()
+        + VarInt(outputs.len() as u64).len()
+        + outputs
+            .iter()
+            .map(|script| 8 + VarInt(script.len() as u64).len() + script.len())
+            .sum::<usize>()
+        + 4;
+    let inputs_weight = inputs
+        .iter()
+        .map(|input| {
+            // Inputs without witness still have zero-length witness stack
+            // in segwit transactions
+            input.weight + (segwit && !input.segwit) as usize
+        })
+        .sum::<usize>();
+    base * 4 + inputs_weight + if segwit { 2 } else { 0 }
+}
+
+/// Virtual size of the transaction with the given weight
+pub fn vsize(weight: usize) -> usize {
+    (weight + 3) / 4
+}
+
+/// Fee (in satoshis) required for the transaction of the given weight to
+/// reach the fee rate. Since the weight is predicted with maximal signature
+/// sizes, the final transaction fee rate is never below the target.
+pub fn fee_for(weight: usize, sat_per_vbyte: f32) -> u64 {
+    (vsize(weight) as f64 * sat_per_vbyte as f64).ceil() as u64
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::util::bip32::DerivationPath;
+    use bitcoin::Network;
+    use wallet::bip32::DerivationComponents;
+
+    use super::*;
+    use crate::model::MnemonicSeed;
+
+    const MNEMONICS: [&str; 3] = [
+        "abandon abandon abandon abandon abandon abandon abandon abandon \
+         abandon abandon abandon about",
+        "legal winner thank year wave sausage worth useful legal winner thank \
+         yellow",
+        "letter advice cage absurd amount doctor acoustic avoid letter advice \
+         cage above",
+    ];
+
+    fn key(no: usize) -> descriptor::SingleSig {
+        let components: DerivationComponents =
+            MnemonicSeed::with(MNEMONICS[no], "")
+                .unwrap()
+                .derivation_components(
+                    Network::Testnet,
+                    &DerivationPath::from_str("m/48'/1'/0'/2'/0").unwrap(),
+                    None,
+                )
+                .unwrap();
+        descriptor::SingleSig::XPubDerivable(components)
+    }
+
+    fn vbytes(
+        template: &descriptor::Template,
+        category: descriptor::Category,
+    ) -> f32 {
+        InputWeight::with(template, category).unwrap().weight as f32 / 4.0
+    }
+
+    #[test]
+    fn input_weights() {
+        let single = descriptor::Template::SingleSig(key(0));
+        assert_eq!(vbytes(&single, descriptor::Category::Hashed), 148.0);
+        assert_eq!(vbytes(&single, descriptor::Category::Nested), 91.0);
+        assert_eq!(vbytes(&single, descriptor::Category::SegWit), 68.0);
+        assert_eq!(vbytes(&single, descriptor::Category::Taproot), 57.5);
+
+        let multi = descriptor::Template::MultiSig(descriptor::MultiSig {
+            threshold: Some(2),
+            pubkeys: (0..3).map(key).collect(),
+            reorder: true,
+        });
+        assert_eq!(vbytes(&multi, descriptor::Category::SegWit), 104.5);
+        assert_eq!(vbytes(&multi, descriptor::Category::Hashed), 295.0);
+        assert!(
+            !InputWeight::with(&multi, descriptor::Category::Hashed)
+                .unwrap()
+                .segwit
+        );
+        assert!(matches!(
+            InputWeight::with(&multi, descriptor::Category::Taproot),
+            Err(Error::UnsupportedTaproot)
+        ));
+    }
+
+    #[test]
+    fn transaction_weight() {
+        let single = descriptor::Template::SingleSig(key(0));
+        let segwit =
+            InputWeight::with(&single, descriptor::Category::SegWit).unwrap();
+        let legacy =
+            InputWeight::with(&single, descriptor::Category::Hashed).unwrap();
+        let output = Script::from(vec![0u8; 22]);
+
+        let weight = tx_weight(&[segwit], &[output.clone(), output.clone()]);
+        assert_eq!(weight, 562);
+        assert_eq!(vsize(weight), 141);
+        assert_eq!(fee_for(weight, 2.0), 282);
+        assert_eq!(fee_for(weight, 1.5), 212);
+
+        assert_eq!(tx_weight(&[legacy], &[output.clone()]), 4 * 189);
+        assert_eq!(
+            tx_weight(&[segwit, legacy], &[output]),
+            4 * 41 + 2 + segwit.weight + legacy.weight + 1 + 4 * 31
+        );
+    }
+
+    #[test]
+    fn fee_rate_fallbacks() {
+        let rate =
+            estimate_with(2, |blocks| Some(blocks as f64 * 0.0001), || None);
+        assert_eq!(rate.source, FeeSource::Estimate(2));
+        assert!((rate.sat_per_vbyte - 20.0).abs() < 0.001);
+
+        let rate = estimate_with(
+            3,
+            |blocks| {
+                if blocks >= 12 {
+                    Some(0.00002)
+                } else {
+                    Some(-1.0)
+                }
+            },
+            || None,
+        );
+        assert_eq!(rate.source, FeeSource::Estimate(12));
+
+        let rate = estimate_with(6, |_| None, || Some(0.00003));
+        assert_eq!(rate.source, FeeSource::RelayFee);
+        assert!((rate.sat_per_vbyte - 3.0).abs() < 0.001);
+
+        let rate = estimate_with(6, |_| None, || Some(0.000001));
+        assert_eq!(rate.sat_per_vbyte, MIN_RELAY_FEE_RATE);
+
+        let rate = estimate_with(6, |_| Some(-1.0), || None);
+        assert_eq!(rate.source, FeeSource::Default);
+        assert_eq!(rate.sat_per_vbyte, DEFAULT_FEE_RATE);
+    }
+}
@@ -15,4 +15,16 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+pub mod asset_export;
+pub mod asset_import;
+pub mod asset_invoice;
+pub mod asset_ops;
+pub mod asset_sync;
+pub mod asset_validation;
+pub mod fee_bump;
+pub mod fee_estimator;
+pub mod psbt_coordinator;
+pub mod psbt_finalizer;
+pub mod psbt_signer;
+pub mod subscriptions;
 pub mod utxo_lookup;
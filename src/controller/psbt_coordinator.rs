@@ -0,0 +1,580 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Coordination of multi-signature PSBTs: combining PSBTs signed by
+//! different cosigners and tracking signing progress of each input
+
+use std::fs;
+use std::path::Path;
+
+use bitcoin::blockdata::opcodes;
+use bitcoin::consensus::{deserialize, encode};
+use bitcoin::util::bip32::{ChildNumber, KeySource};
+use bitcoin::util::psbt;
+use bitcoin::{PublicKey, Script, Txid};
+use wallet::{descriptor, Psbt};
+
+use crate::model::{DescriptorAccount, KeyOrigin, KeyOrigins, TrackingAccount};
+
+#[derive(Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// Unable to read PSBT file: {0}
+    #[display("{0}")]
+    #[from]
+    Io(std::io::Error),
+
+    /// Data are neither binary nor Base64-encoded PSBT: {0}
+    #[from]
+    Encoding(encode::Error),
+
+    /// Nothing to combine: no PSBTs were provided
+    NoPsbts,
+
+    /// PSBTs can't be combined since they spend different transactions ({0}
+    /// and {1})
+    DifferentTransactions(Txid, Txid),
+
+    /// PSBTs contain different signatures for the key {1} in input #{0}
+    ConflictingSignature(usize, PublicKey),
+
+    /// PSBTs contain different key origins for the key {1} in input #{0}
+    ConflictingOrigin(usize, PublicKey),
+
+    /// PSBTs contain different redeem or witness scripts for input #{0}
+    ConflictingScript(usize),
+
+    /// PSBTs can't be combined: {0}
+    #[display("{0}")]
+    #[from]
+    Merge(psbt::Error),
+}
+
+/// Parses PSBT from either binary or Base64 representation
+pub fn parse(data: &[u8]) -> Result<Psbt, Error> {
+    if let Ok(psbt) = deserialize(data) {
+        return Ok(psbt);
+    }
+    let text = String::from_utf8_lossy(data);
+    let binary = base64::decode(text.trim()).map_err(|_| {
+        encode::Error::ParseFailed("PSBT is neither binary nor Base64 encoded")
+    })?;
+    Ok(deserialize(&binary)?)
+}
+
+/// Reads PSBTs from multiple files at once. Each file is parsed
+/// independently, so the failure of a single file does not prevent the
+/// rest from being imported.
+pub fn read_files<P: AsRef<Path>>(
+    paths: impl IntoIterator<Item = P>,
+) -> Vec<(P, Result<Psbt, Error>)> {
+    paths
+        .into_iter()
+        .map(|path| {
+            let psbt = fs::read(path.as_ref())
+                .map_err(Error::from)
+                .and_then(|data| parse(&data));
+            (path, psbt)
+        })
+        .collect()
+}
+
+/// Combines PSBTs signed by different cosigners into a single PSBT, as
+/// defined by BIP174 "Combiner" role. Unlike plain merge, fails if the PSBTs
+/// provide conflicting data for the same input, which means that at least
+/// one of them is corrupted or malicious.
+pub fn combine(psbts: &[Psbt]) -> Result<Psbt, Error> {
+    let (first, rest) = psbts.split_first().ok_or(Error::NoPsbts)?;
+    let txid = first.global.unsigned_tx.txid();
+    let mut combined = first.clone();
+    for psbt in rest {
+        let other_txid = psbt.global.unsigned_tx.txid();
+        if other_txid != txid {
+            return Err(Error::DifferentTransactions(txid, other_txid));
+        }
+        check_conflicts(&combined, psbt)?;
+        combined.merge(psbt.clone())?;
+    }
+    Ok(combined)
+}
+
+fn check_conflicts(psbt: &Psbt, other: &Psbt) -> Result<(), Error> {
+    for (index, (input, other_input)) in
+        psbt.inputs.iter().zip(&other.inputs).enumerate()
+    {
+        for (pubkey, sig) in &other_input.partial_sigs {
+            match input.partial_sigs.get(pubkey) {
+                Some(existing) if existing != sig => {
+                    return Err(Error::ConflictingSignature(index, *pubkey))
+                }
+                _ => {}
+            }
+        }
+        for (pubkey, origin) in &other_input.bip32_derivation {
+            match input.bip32_derivation.get(pubkey) {
+                Some(existing) if existing != origin => {
+                    return Err(Error::ConflictingOrigin(index, *pubkey))
+                }
+                _ => {}
+            }
+        }
+        let conflicting = |a: &Option<Script>, b: &Option<Script>| matches!((a, b), (Some(a), Some(b)) if a != b);
+        if conflicting(&input.redeem_script, &other_input.redeem_script)
+            || conflicting(&input.witness_script, &other_input.witness_script)
+        {
+            return Err(Error::ConflictingScript(index));
+        }
+    }
+    Ok(())
+}
+
+/// Status of a single key participating in input signing
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct KeyStatus {
+    pub pubkey: PublicKey,
+    /// Name of the tracking account owning the key, if it is known to the
+    /// document
+    pub account: Option<String>,
+    pub signed: bool,
+}
+
+/// Signing status of a single PSBT input
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct InputStatus {
+    pub keys: Vec<KeyStatus>,
+    pub threshold: usize,
+    pub finalized: bool,
+}
+
+impl InputStatus {
+    pub fn signed(&self) -> usize {
+        self.keys.iter().filter(|key| key.signed).count()
+    }
+
+    /// Number of signatures which are still required to reach the
+    /// threshold
+    pub fn remaining(&self) -> usize {
+        if self.finalized {
+            0
+        } else {
+            self.threshold.saturating_sub(self.signed())
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.remaining() == 0
+    }
+}
+
+/// Computes signing status matrix for the PSBT: which of the keys have
+/// signed each of the inputs and how many signatures remain to reach the
+/// threshold of the multi-signature descriptor. Accounts created from keys
+/// without their master key are matched using the imported key `origins`.
+pub fn signing_status(
+    psbt: &Psbt,
+    tracking: &[TrackingAccount],
+    origins: &KeyOrigins,
+    descriptors: &[DescriptorAccount],
+) -> Vec<InputStatus> {
+    psbt.inputs
+        .iter()
+        .map(|input| {
+            let matched = input
+                .bip32_derivation
+                .iter()
+                .map(|(pubkey, origin)| {
+                    (
+                        *pubkey,
+                        tracking_account(tracking, origins, *pubkey, origin),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let threshold = descriptor_threshold(descriptors, &matched)
+                .unwrap_or_else(|| {
+                    input
+                        .witness_script
+                        .as_ref()
+                        .or_else(|| input.redeem_script.as_ref())
+                        .and_then(script_threshold)
+                        .unwrap_or(1)
+                });
+            InputStatus {
+                keys: matched
+                    .into_iter()
+                    .map(|(pubkey, account)| KeyStatus {
+                        pubkey,
+                        account: account.map(|account| account.name.clone()),
+                        signed: input.partial_sigs.contains_key(&pubkey),
+                    })
+                    .collect(),
+                threshold,
+                finalized: input.final_script_sig.is_some()
+                    || input.final_script_witness.is_some(),
+            }
+        })
+        .collect()
+}
+
+/// Finds tracking account which has produced the key with the given origin
+fn tracking_account<'a>(
+    tracking: &'a [TrackingAccount],
+    origins: &KeyOrigins,
+    pubkey: PublicKey,
+    (fingerprint, path): &KeySource,
+) -> Option<&'a TrackingAccount> {
+    let path: &[ChildNumber] = path.as_ref();
+    tracking.iter().find(|account| match &account.key {
+        descriptor::SingleSig::Pubkey(single) => single.key == pubkey,
+        descriptor::SingleSig::XPubDerivable(components) => {
+            KeyOrigin::with_components(components, origins)
+                .map(|origin| {
+                    let branch: &[ChildNumber] = origin.path.as_ref();
+                    origin.fingerprint == *fingerprint
+                        && path.starts_with(branch)
+                })
+                .unwrap_or_default()
+                || components.branch_xpub.fingerprint() == *fingerprint
+        }
+    })
+}
+
+/// Threshold of the multi-signature descriptor which uses all of the known
+/// tracking accounts signing the input
+fn descriptor_threshold(
+    descriptors: &[DescriptorAccount],
+    matched: &[(PublicKey, Option<&TrackingAccount>)],
+) -> Option<usize> {
+    let accounts = matched
+        .iter()
+        .filter_map(|(_, account)| *account)
+        .collect::<Vec<_>>();
+    if accounts.is_empty() {
+        return None;
+    }
+    descriptors.iter().find_map(|descriptor| {
+        match &descriptor.generator.template {
+            descriptor::Template::MultiSig(multisig)
+                if accounts
+                    .iter()
+                    .all(|account| multisig.pubkeys.contains(&account.key)) =>
+            {
+                Some(multisig.threshold())
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Extracts threshold from bare `OP_CHECKMULTISIG` script
+fn script_threshold(script: &Script) -> Option<usize> {
+    let bytes = script.as_bytes();
+    let first = opcodes::all::OP_PUSHNUM_1.into_u8();
+    let last = opcodes::all::OP_PUSHNUM_16.into_u8();
+    if bytes.len() > 3
+        && (first..=last).contains(&bytes[0])
+        && bytes[bytes.len() - 1] == opcodes::all::OP_CHECKMULTISIG.into_u8()
+    {
+        Some((bytes[0] - first + 1) as usize)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::blockdata::script::Builder;
+    use bitcoin::consensus::serialize;
+    use bitcoin::hashes::hex::FromHex;
+    use bitcoin::util::bip32::{DerivationPath, ExtendedPubKey, Fingerprint};
+    use bitcoin::{Network, OutPoint, Transaction, TxIn, TxOut};
+    use wallet::bip32::DerivationComponents;
+
+    use super::*;
+    use crate::controller::psbt_signer::PsbtSigner;
+    use crate::model::MnemonicSeed;
+
+    const COSIGNERS: [&str; 3] = [
+        "abandon abandon abandon abandon abandon abandon abandon abandon \
+         abandon abandon abandon about",
+        "legal winner thank year wave sausage worth useful legal winner thank \
+         yellow",
+        "letter advice cage absurd amount doctor acoustic avoid letter advice \
+         cage above",
+    ];
+
+    struct Cosigner {
+        signer: PsbtSigner,
+        account: TrackingAccount,
+        pubkey: PublicKey,
+        origin: KeySource,
+    }
+
+    fn cosigner(no: usize) -> Cosigner {
+        let seed = MnemonicSeed::with(COSIGNERS[no], "").unwrap();
+        let components = seed
+            .derivation_components(
+                Network::Testnet,
+                &DerivationPath::from_str("m/48'/1'/0'/2'").unwrap(),
+                None,
+            )
+            .unwrap();
+        let master = seed.master_xpriv(Network::Testnet).unwrap();
+        let path = DerivationPath::from_str("m/48'/1'/0'/2'/0/0").unwrap();
+        let pubkey = ExtendedPubKey::from_private(
+            &wallet::SECP256K1,
+            &master.derive_priv(&wallet::SECP256K1, &path).unwrap(),
+        )
+        .public_key;
+        Cosigner {
+            signer: PsbtSigner::with(master),
+            origin: (components.master_xpub.fingerprint(), path),
+            account: TrackingAccount {
+                name: format!("Cosigner {}", no + 1),
+                key: descriptor::SingleSig::XPubDerivable(components),
+            },
+            pubkey,
+        }
+    }
+
+    fn multisig_psbt(cosigners: &[Cosigner]) -> Psbt {
+        let mut builder =
+            Builder::new().push_opcode(opcodes::all::OP_PUSHNUM_2);
+        for cosigner in cosigners {
+            builder = builder.push_key(&cosigner.pubkey);
+        }
+        let witness_script = builder
+            .push_opcode(opcodes::all::OP_PUSHNUM_3)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(
+                    Txid::from_hex(
+                        "f2a1b5c3d4e5f60718293a4b5c6d7e8f9a0b1c2d3e4f5a6b7c8d9e0f1a2b3c4d",
+                    )
+                    .unwrap(),
+                    0,
+                ),
+                script_sig: Script::new(),
+                sequence: 0xFFFFFFFD,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: 99_000,
+                script_pubkey: witness_script.to_v0_p2wsh(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 100_000,
+            script_pubkey: witness_script.to_v0_p2wsh(),
+        });
+        psbt.inputs[0].witness_script = Some(witness_script);
+        psbt.inputs[0].bip32_derivation = cosigners
+            .iter()
+            .map(|cosigner| (cosigner.pubkey, cosigner.origin.clone()))
+            .collect();
+        psbt
+    }
+
+    #[test]
+    fn combine_cosigners() {
+        let cosigners = (0..3).map(cosigner).collect::<Vec<_>>();
+        let tracking = cosigners
+            .iter()
+            .map(|cosigner| cosigner.account.clone())
+            .collect::<Vec<_>>();
+        let unsigned = multisig_psbt(&cosigners);
+
+        let status = signing_status(&unsigned, &tracking, &none!(), &[]);
+        assert_eq!(status.len(), 1);
+        assert_eq!(status[0].threshold, 2);
+        assert_eq!(status[0].signed(), 0);
+        assert_eq!(status[0].remaining(), 2);
+        assert!(status[0].keys.iter().all(|key| key
+            .account
+            .as_deref()
+            .unwrap()
+            .starts_with("Cosigner")));
+
+        let mut first = unsigned.clone();
+        assert_eq!(cosigners[0].signer.sign(&mut first).unwrap().signed, 1);
+        let mut third = unsigned.clone();
+        assert_eq!(cosigners[2].signer.sign(&mut third).unwrap().signed, 1);
+
+        let combined = combine(&[unsigned, first, third]).unwrap();
+        let status = signing_status(&combined, &tracking, &none!(), &[]);
+        assert_eq!(status[0].signed(), 2);
+        assert!(status[0].is_complete());
+        let signed = status[0]
+            .keys
+            .iter()
+            .filter(|key| key.signed)
+            .map(|key| key.account.clone().unwrap())
+            .collect::<Vec<_>>();
+        assert!(signed.contains(&s!("Cosigner 1")));
+        assert!(signed.contains(&s!("Cosigner 3")));
+    }
+
+    #[test]
+    fn detect_conflicts() {
+        let cosigners = (0..3).map(cosigner).collect::<Vec<_>>();
+        let unsigned = multisig_psbt(&cosigners);
+
+        let mut signed = unsigned.clone();
+        cosigners[0].signer.sign(&mut signed).unwrap();
+        let mut forged = signed.clone();
+        let sig = forged.inputs[0]
+            .partial_sigs
+            .get_mut(&cosigners[0].pubkey)
+            .unwrap();
+        sig[5] ^= 0x01;
+        assert!(matches!(
+            combine(&[signed.clone(), forged]),
+            Err(Error::ConflictingSignature(0, key)) if key == cosigners[0].pubkey
+        ));
+
+        let mut other_origin = unsigned.clone();
+        other_origin.inputs[0]
+            .bip32_derivation
+            .insert(cosigners[1].pubkey, (Fingerprint::default(), empty!()));
+        assert!(matches!(
+            combine(&[unsigned.clone(), other_origin]),
+            Err(Error::ConflictingOrigin(0, _))
+        ));
+
+        let mut other_script = unsigned.clone();
+        other_script.inputs[0].witness_script = Some(Script::new());
+        assert!(matches!(
+            combine(&[unsigned.clone(), other_script]),
+            Err(Error::ConflictingScript(0))
+        ));
+
+        let mut other_tx = unsigned.clone();
+        other_tx.global.unsigned_tx.lock_time = 1;
+        assert!(matches!(
+            combine(&[unsigned, other_tx]),
+            Err(Error::DifferentTransactions(..))
+        ));
+
+        assert!(matches!(combine(&[]), Err(Error::NoPsbts)));
+    }
+
+    #[test]
+    fn parse_encodings() {
+        let cosigners = (0..3).map(cosigner).collect::<Vec<_>>();
+        let psbt = multisig_psbt(&cosigners);
+        let binary = serialize(&psbt);
+        assert_eq!(parse(&binary).unwrap(), psbt);
+        let text = format!("{}\n", base64::encode(&binary));
+        assert_eq!(parse(text.as_bytes()).unwrap(), psbt);
+        assert!(parse(b"not a psbt").is_err());
+    }
+
+    #[test]
+    fn thresholds() {
+        let cosigners = (0..3).map(cosigner).collect::<Vec<_>>();
+        let psbt = multisig_psbt(&cosigners);
+        assert_eq!(
+            script_threshold(psbt.inputs[0].witness_script.as_ref().unwrap()),
+            Some(2)
+        );
+        assert_eq!(script_threshold(&Script::new()), None);
+
+        // Keys unknown to the document are still listed
+        let status = signing_status(&psbt, &[], &none!(), &[]);
+        assert_eq!(status[0].keys.len(), 3);
+        assert!(status[0].keys.iter().all(|key| key.account.is_none()));
+        assert_eq!(status[0].threshold, 2);
+
+        // Descriptor threshold takes precedence over the script
+        let tracking = cosigners
+            .iter()
+            .map(|cosigner| cosigner.account.clone())
+            .collect::<Vec<_>>();
+        let descriptor = DescriptorAccount {
+            name: s!("Vault"),
+            generator: descriptor::Generator {
+                template: descriptor::Template::MultiSig(
+                    descriptor::MultiSig {
+                        threshold: Some(3),
+                        pubkeys: tracking
+                            .iter()
+                            .map(|account| account.key.clone())
+                            .collect(),
+                        reorder: true,
+                    },
+                ),
+                variants: descriptor::Variants {
+                    bare: false,
+                    hashed: false,
+                    nested: false,
+                    segwit: true,
+                    taproot: false,
+                },
+            },
+        };
+        let status = signing_status(&psbt, &tracking, &none!(), &[descriptor]);
+        assert_eq!(status[0].threshold, 3);
+        assert_eq!(status[0].remaining(), 3);
+    }
+
+    #[test]
+    fn imported_cosigner_origins() {
+        let cosigners = (0..3).map(cosigner).collect::<Vec<_>>();
+        let psbt = multisig_psbt(&cosigners);
+
+        // Multisig setups are imported without the master keys of the
+        // cosigners, keeping origins of their branch keys separately
+        let mut origins = KeyOrigins::new();
+        let tracking = cosigners
+            .iter()
+            .map(|cosigner| match &cosigner.account.key {
+                descriptor::SingleSig::XPubDerivable(components) => {
+                    origins.insert(
+                        components.branch_xpub.public_key,
+                        KeyOrigin {
+                            fingerprint: components.master_xpub.fingerprint(),
+                            path: components.branch_path.clone(),
+                        },
+                    );
+                    TrackingAccount {
+                        name: cosigner.account.name.clone(),
+                        key: descriptor::SingleSig::XPubDerivable(
+                            DerivationComponents {
+                                master_xpub: components.branch_xpub,
+                                branch_path: DerivationPath::from(vec![]),
+                                ..components.clone()
+                            },
+                        ),
+                    }
+                }
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+
+        let status = signing_status(&psbt, &tracking, &none!(), &[]);
+        assert!(status[0].keys.iter().all(|key| key.account.is_none()));
+
+        let status = signing_status(&psbt, &tracking, &origins, &[]);
+        assert!(status[0].keys.iter().all(|key| key.account.is_some()));
+    }
+}
@@ -0,0 +1,516 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Finalization of fully signed PSBTs, extraction of the network transaction
+//! and its sanity checks before the broadcast
+
+use bitcoin::blockdata::opcodes;
+use bitcoin::consensus::serialize;
+use bitcoin::util::psbt;
+use bitcoin::{Script, Transaction, TxOut, Txid};
+use electrum_client::{Client as ElectrumClient, ElectrumApi};
+use wallet::Psbt;
+
+use super::psbt_signer::{is_v1_p2tr, tap_key_sig_key};
+
+/// Maximum fee rate (in sat/vbyte) which is still considered sane
+pub const MAX_FEE_RATE: f32 = 1000.0;
+
+/// Minimum relay fee rate (in sat/vbyte) used by Bitcoin Core by default
+pub const MIN_RELAY_FEE_RATE: f32 = 1.0;
+
+/// Maximum standard transaction weight
+pub const MAX_STANDARD_TX_WEIGHT: usize = 400_000;
+
+/// Maximum size of a standard `OP_RETURN` output script
+pub const MAX_OP_RETURN_RELAY: usize = 83;
+
+/// Types of the BIP371 taproot input fields, which are kept by the `bitcoin`
+/// library among the unknown fields
+const PSBT_IN_TAP_FIELDS: std::ops::RangeInclusive<u8> = 0x13..=0x18;
+
+#[derive(Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// PSBT can't be finalized: {0}
+    #[from]
+    Finalization(miniscript::psbt::Error),
+
+    /// Input #{0} does not provide information about the spent output, so
+    /// the transaction fee can't be computed
+    NoSpentOutput(usize),
+
+    /// Input #{0} spends taproot output, but has no key path signature;
+    /// script path spending is not supported yet
+    NoTapKeySig(usize),
+
+    /// PSBT spends both taproot and pre-taproot outputs; such transactions
+    /// can't be finalized yet
+    MixedTaproot,
+
+    /// Transaction outputs ({1} sat) exceed the amount of spent inputs ({0}
+    /// sat)
+    NegativeFee(u64, u64),
+
+    /// Transaction fee rate {0:.1} sat/vbyte is below minimal relay fee rate
+    LowFee(f32),
+
+    /// Transaction fee {0} sat ({1:.1} sat/vbyte) is absurdly high
+    AbsurdFee(u64, f32),
+
+    /// Output #{0} amount of {1} sat is below dust limit of {2} sat
+    Dust(usize, u64, u64),
+
+    /// Output #{0} has non-standard `scriptPubkey`
+    NonStandardOutput(usize),
+
+    /// Transaction contains more than one `OP_RETURN` output
+    MultipleOpReturn,
+
+    /// Transaction version {0} is non-standard
+    NonStandardVersion(i32),
+
+    /// Transaction weight {0} WU exceeds standard limit
+    TooHeavy(usize),
+
+    /// Transaction can't be broadcasted: {0}
+    Broadcast(String),
+}
+
+impl From<electrum_client::Error> for Error {
+    fn from(err: electrum_client::Error) -> Self {
+        Error::Broadcast(format!("{:?}", err))
+    }
+}
+
+/// Transaction parameters computed during sanity checks
+#[derive(Clone, Copy, PartialEq, Debug, Display)]
+#[display("{fee} sat fee, {vsize} vbytes ({fee_rate:.1} sat/vbyte)")]
+pub struct TxSummary {
+    pub txid: Txid,
+    pub fee: u64,
+    pub vsize: usize,
+    pub fee_rate: f32,
+}
+
+/// Finalizes PSBT inputs using miniscript satisfier, which covers all of
+/// the descriptor templates: single-sig, multi-sig and scripted. Miniscript
+/// does not support taproot yet, so taproot inputs are finalized with their
+/// key path signatures instead. The original PSBT is not modified, so it
+/// can be kept in the document until the transaction is confirmed.
+pub fn finalize(psbt: &Psbt) -> Result<Psbt, Error> {
+    let mut psbt = psbt.clone();
+    let spent = spent_outputs(&psbt)?;
+    let taproot = spent
+        .iter()
+        .filter(|txout| is_v1_p2tr(&txout.script_pubkey))
+        .count();
+    if taproot == 0 {
+        miniscript::psbt::finalize(&mut psbt, &wallet::SECP256K1)?;
+    } else if taproot < spent.len() {
+        return Err(Error::MixedTaproot);
+    } else {
+        for (index, input) in psbt.inputs.iter_mut().enumerate() {
+            finalize_key_path(input, index)?;
+        }
+    }
+    Ok(psbt)
+}
+
+/// Finalizes taproot input with its key path signature, removing the
+/// signing data as BIP174 requires
+fn finalize_key_path(
+    input: &mut psbt::Input,
+    index: usize,
+) -> Result<(), Error> {
+    let sig = input
+        .unknown
+        .get(&tap_key_sig_key())
+        .cloned()
+        .ok_or(Error::NoTapKeySig(index))?;
+    input.final_script_witness = Some(vec![sig]);
+    input.partial_sigs.clear();
+    input.sighash_type = None;
+    input.redeem_script = None;
+    input.witness_script = None;
+    input.bip32_derivation.clear();
+    input
+        .unknown
+        .retain(|key, _| !PSBT_IN_TAP_FIELDS.contains(&key.type_value));
+    Ok(())
+}
+
+/// Finalizes PSBT and extracts signed transaction from it, checking the
+/// result for the fee and standardness rules
+pub fn extract(psbt: &Psbt) -> Result<(Transaction, TxSummary), Error> {
+    let finalized = finalize(psbt)?;
+    let spent = spent_outputs(&finalized)?;
+    let tx = finalized.extract_tx();
+    let summary = check(&tx, &spent)?;
+    Ok((tx, summary))
+}
+
+/// Broadcasts transaction through Electrum server
+pub fn broadcast(
+    resolver: &ElectrumClient,
+    tx: &Transaction,
+) -> Result<Txid, Error> {
+    Ok(resolver.transaction_broadcast(tx)?)
+}
+
+/// Performs pre-broadcast sanity checks of the transaction spending the
+/// provided outputs
+pub fn check(tx: &Transaction, spent: &[TxOut]) -> Result<TxSummary, Error> {
+    if tx.version < 1 || tx.version > 2 {
+        return Err(Error::NonStandardVersion(tx.version));
+    }
+
+    let weight = tx.get_weight();
+    if weight > MAX_STANDARD_TX_WEIGHT {
+        return Err(Error::TooHeavy(weight));
+    }
+
+    let mut op_returns = 0usize;
+    for (index, txout) in tx.output.iter().enumerate() {
+        let script = &txout.script_pubkey;
+        if script.is_op_return() {
+            op_returns += 1;
+            if script.len() > MAX_OP_RETURN_RELAY {
+                return Err(Error::NonStandardOutput(index));
+            }
+        } else if !is_standard(script) {
+            return Err(Error::NonStandardOutput(index));
+        }
+        let dust_limit = dust_limit(txout);
+        if txout.value < dust_limit {
+            return Err(Error::Dust(index, txout.value, dust_limit));
+        }
+    }
+    if op_returns > 1 {
+        return Err(Error::MultipleOpReturn);
+    }
+
+    let input_value = spent.iter().map(|txout| txout.value).sum::<u64>();
+    let output_value = tx.output.iter().map(|txout| txout.value).sum::<u64>();
+    if output_value > input_value {
+        return Err(Error::NegativeFee(input_value, output_value));
+    }
+    let fee = input_value - output_value;
+    let vsize = (weight + 3) / 4;
+    let fee_rate = fee as f32 / vsize as f32;
+    if fee_rate < MIN_RELAY_FEE_RATE {
+        return Err(Error::LowFee(fee_rate));
+    }
+    if fee_rate > MAX_FEE_RATE {
+        return Err(Error::AbsurdFee(fee, fee_rate));
+    }
+
+    Ok(TxSummary {
+        txid: tx.txid(),
+        fee,
+        vsize,
+        fee_rate,
+    })
+}
+
+/// Collects outputs spent by the PSBT inputs
+pub fn spent_outputs(psbt: &Psbt) -> Result<Vec<TxOut>, Error> {
+    psbt.global
+        .unsigned_tx
+        .input
+        .iter()
+        .zip(&psbt.inputs)
+        .enumerate()
+        .map(|(index, (txin, input))| {
+            input
+                .witness_utxo
+                .clone()
+                .or_else(|| {
+                    input.non_witness_utxo.as_ref().and_then(|prev_tx| {
+                        prev_tx
+                            .output
+                            .get(txin.previous_output.vout as usize)
+                            .cloned()
+                    })
+                })
+                .ok_or(Error::NoSpentOutput(index))
+        })
+        .collect()
+}
+
+/// Dust limit for the output computed with Bitcoin Core default dust relay
+/// fee of 3 sat/vbyte
+pub fn dust_limit(txout: &TxOut) -> u64 {
+    let script = &txout.script_pubkey;
+    if script.is_op_return() {
+        return 0;
+    }
+    // Size of the input which will spend the output
+    let spending_size = if script.is_witness_program() {
+        32 + 4 + 1 + 107 / 4 + 4
+    } else {
+        32 + 4 + 1 + 107 + 4
+    };
+    (serialize(txout).len() as u64 + spending_size) * 3
+}
+
+fn is_standard(script: &Script) -> bool {
+    script.is_p2pkh()
+        || script.is_p2sh()
+        || script.is_p2pk()
+        || script.is_witness_program()
+        || is_bare_multisig(script)
+}
+
+fn is_bare_multisig(script: &Script) -> bool {
+    let bytes = script.as_bytes();
+    let first = opcodes::all::OP_PUSHNUM_1.into_u8();
+    let third = opcodes::all::OP_PUSHNUM_3.into_u8();
+    bytes.len() > 3
+        && (first..=third).contains(&bytes[0])
+        && (first..=third).contains(&bytes[bytes.len() - 2])
+        && bytes[0] <= bytes[bytes.len() - 2]
+        && bytes[bytes.len() - 1] == opcodes::all::OP_CHECKMULTISIG.into_u8()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+
+    use bitcoin::blockdata::script::Builder;
+    use bitcoin::hashes::hex::FromHex;
+    use bitcoin::util::bip32::{DerivationPath, ExtendedPubKey, Fingerprint};
+    use bitcoin::util::psbt::raw;
+    use bitcoin::{Network, OutPoint, PublicKey, TxIn};
+
+    use super::*;
+    use crate::controller::psbt_signer::{
+        PsbtSigner, PSBT_IN_TAP_BIP32_DERIVATION,
+    };
+    use crate::model::MnemonicSeed;
+
+    const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon \
+                            abandon abandon abandon abandon abandon about";
+
+    fn key(path: &str) -> (PublicKey, DerivationPath) {
+        let seed = MnemonicSeed::with(MNEMONIC, "").unwrap();
+        let path = DerivationPath::from_str(path).unwrap();
+        let xpriv = seed
+            .master_xpriv(Network::Testnet)
+            .unwrap()
+            .derive_priv(&wallet::SECP256K1, &path)
+            .unwrap();
+        let xpub = ExtendedPubKey::from_private(&wallet::SECP256K1, &xpriv);
+        (xpub.public_key, path)
+    }
+
+    fn unsigned_tx(outputs: Vec<TxOut>) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(
+                    Txid::from_hex(
+                        "f2a1b5c3d4e5f60718293a4b5c6d7e8f9a0b1c2d3e4f5a6b7c8d9e0f1a2b3c4d",
+                    )
+                    .unwrap(),
+                    0,
+                ),
+                script_sig: Script::new(),
+                sequence: 0xFFFFFFFD,
+                witness: vec![],
+            }],
+            output: outputs,
+        }
+    }
+
+    fn signed_psbt(value: u64, outputs: Vec<TxOut>) -> Psbt {
+        let (pubkey, path) = key("m/84'/1'/0'/0/0");
+        let mut psbt = Psbt::from_unsigned_tx(unsigned_tx(outputs)).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value,
+            script_pubkey: Script::new_v0_wpkh(&pubkey.wpubkey_hash()),
+        });
+        psbt.inputs[0].bip32_derivation = bmap! {
+            pubkey => (Fingerprint::from_hex("73c5da0a").unwrap(), path)
+        };
+        let seed = MnemonicSeed::with(MNEMONIC, "").unwrap();
+        let signer = PsbtSigner::with_seed(&seed, Network::Testnet).unwrap();
+        assert_eq!(signer.sign(&mut psbt).unwrap().signed, 1);
+        psbt
+    }
+
+    fn p2wpkh_output(value: u64) -> TxOut {
+        let (pubkey, _) = key("m/84'/1'/0'/1/0");
+        TxOut {
+            value,
+            script_pubkey: Script::new_v0_wpkh(&pubkey.wpubkey_hash()),
+        }
+    }
+
+    #[test]
+    fn finalize_p2wpkh() {
+        let psbt = signed_psbt(100_000, vec![p2wpkh_output(99_000)]);
+        let (tx, summary) = extract(&psbt).unwrap();
+        assert_eq!(tx.input[0].witness.len(), 2);
+        assert!(tx.input[0].script_sig.is_empty());
+        assert_eq!(summary.txid, tx.txid());
+        assert_eq!(summary.fee, 1_000);
+        assert_eq!(summary.vsize, 110);
+        // Original PSBT is kept intact
+        assert!(psbt.inputs[0].final_script_witness.is_none());
+    }
+
+    #[test]
+    fn finalize_unsigned() {
+        let mut psbt = signed_psbt(100_000, vec![p2wpkh_output(99_000)]);
+        psbt.inputs[0].partial_sigs = BTreeMap::new();
+        assert!(matches!(extract(&psbt), Err(Error::Finalization(_))));
+    }
+
+    #[test]
+    fn finalize_p2tr_key_path() {
+        // BIP86 test vector for the first receiving address
+        let (pubkey, path) = key("m/86'/0'/0'/0/0");
+        let mut script = vec![0x51, 0x20];
+        script.extend(
+            Vec::<u8>::from_hex(
+                "a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c",
+            )
+            .unwrap(),
+        );
+        let mut psbt =
+            Psbt::from_unsigned_tx(unsigned_tx(vec![p2wpkh_output(99_000)]))
+                .unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 100_000,
+            script_pubkey: Script::from(script),
+        });
+        let mut origin = vec![0u8];
+        origin.extend(Vec::<u8>::from_hex("73c5da0a").unwrap());
+        for child in path.as_ref() {
+            origin.extend(&u32::from(*child).to_le_bytes());
+        }
+        psbt.inputs[0].unknown.insert(
+            raw::Key {
+                type_value: PSBT_IN_TAP_BIP32_DERIVATION,
+                key: pubkey.key.serialize()[1..].to_vec(),
+            },
+            origin,
+        );
+        let seed = MnemonicSeed::with(MNEMONIC, "").unwrap();
+        let signer = PsbtSigner::with_seed(&seed, Network::Testnet).unwrap();
+        assert_eq!(signer.sign(&mut psbt).unwrap().signed, 1);
+
+        let sig = psbt.inputs[0].unknown[&tap_key_sig_key()].clone();
+        let finalized = finalize(&psbt).unwrap();
+        assert_eq!(
+            finalized.inputs[0].final_script_witness,
+            Some(vec![sig.clone()])
+        );
+        assert!(finalized.inputs[0].unknown.is_empty());
+
+        let (tx, summary) = extract(&psbt).unwrap();
+        assert_eq!(tx.input[0].witness, vec![sig]);
+        assert!(tx.input[0].script_sig.is_empty());
+        assert_eq!(summary.fee, 1_000);
+        assert_eq!(summary.vsize, 99);
+
+        // Key path signature is required
+        let mut unsigned = psbt.clone();
+        unsigned.inputs[0].unknown.remove(&tap_key_sig_key());
+        assert!(matches!(finalize(&unsigned), Err(Error::NoTapKeySig(0))));
+
+        // Taproot inputs can't be combined with the ones finalized by
+        // miniscript
+        let mut mixed = psbt;
+        let mut txin = mixed.global.unsigned_tx.input[0].clone();
+        txin.previous_output.vout = 1;
+        mixed.global.unsigned_tx.input.push(txin);
+        mixed.inputs.push(psbt::Input {
+            witness_utxo: Some(p2wpkh_output(10_000)),
+            ..Default::default()
+        });
+        assert!(matches!(finalize(&mixed), Err(Error::MixedTaproot)));
+    }
+
+    #[test]
+    fn fee_checks() {
+        let psbt = signed_psbt(100_000, vec![p2wpkh_output(100_001)]);
+        assert!(matches!(
+            extract(&psbt),
+            Err(Error::NegativeFee(100_000, 100_001))
+        ));
+
+        let psbt = signed_psbt(100_000, vec![p2wpkh_output(99_990)]);
+        assert!(matches!(extract(&psbt), Err(Error::LowFee(_))));
+
+        let psbt = signed_psbt(1_000_000, vec![p2wpkh_output(10_000)]);
+        assert!(matches!(extract(&psbt), Err(Error::AbsurdFee(990_000, _))));
+    }
+
+    #[test]
+    fn dust_limits() {
+        assert_eq!(dust_limit(&p2wpkh_output(0)), 294);
+        let (pubkey, _) = key("m/44'/1'/0'/0/0");
+        assert_eq!(
+            dust_limit(&TxOut {
+                value: 0,
+                script_pubkey: Script::new_p2pkh(&pubkey.pubkey_hash()),
+            }),
+            546
+        );
+        let psbt = signed_psbt(100_000, vec![p2wpkh_output(293)]);
+        assert!(matches!(extract(&psbt), Err(Error::Dust(0, 293, 294))));
+    }
+
+    #[test]
+    fn standardness() {
+        let op_return = TxOut {
+            value: 0,
+            script_pubkey: Builder::new()
+                .push_opcode(opcodes::all::OP_RETURN)
+                .push_slice(&[0u8; 32])
+                .into_script(),
+        };
+        let tx = unsigned_tx(vec![op_return.clone(), p2wpkh_output(9_000)]);
+        let spent = [p2wpkh_output(10_000)];
+        assert!(check(&tx, &spent).is_ok());
+
+        let tx = unsigned_tx(vec![op_return.clone(), op_return]);
+        assert!(matches!(check(&tx, &spent), Err(Error::MultipleOpReturn)));
+
+        let tx = unsigned_tx(vec![TxOut {
+            value: 9_000,
+            script_pubkey: Builder::new()
+                .push_opcode(opcodes::all::OP_DUP)
+                .into_script(),
+        }]);
+        assert!(matches!(
+            check(&tx, &spent),
+            Err(Error::NonStandardOutput(0))
+        ));
+
+        let mut tx = unsigned_tx(vec![p2wpkh_output(9_000)]);
+        tx.version = 3;
+        assert!(matches!(
+            check(&tx, &spent),
+            Err(Error::NonStandardVersion(3))
+        ));
+    }
+}
@@ -0,0 +1,845 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Signing of PSBT inputs with private keys provided for a single session
+
+use bitcoin::blockdata::opcodes;
+use bitcoin::consensus::encode::{self, serialize, VarInt};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{self, schnorrsig, Message};
+use bitcoin::util::bip143::SigHashCache;
+use bitcoin::util::bip32::{
+    self, ChainCode, ChildNumber, DerivationPath, ExtendedPrivKey, Fingerprint,
+};
+use bitcoin::util::psbt::{self, raw};
+use bitcoin::{
+    Network, PubkeyHash, PublicKey, Script, SigHashType, Transaction, TxOut,
+};
+use wallet::Psbt;
+use zeroize::Zeroize;
+
+use crate::model::{MnemonicSeed, SeedError};
+
+/// Taproot PSBT input fields from BIP371. The `bitcoin` library does not
+/// know them yet and keeps them in the map of unknown input fields.
+pub(crate) const PSBT_IN_TAP_KEY_SIG: u8 = 0x13;
+pub(crate) const PSBT_IN_TAP_BIP32_DERIVATION: u8 = 0x16;
+const PSBT_IN_TAP_MERKLE_ROOT: u8 = 0x18;
+
+/// BIP341 signature hash type which commits to all inputs and outputs and
+/// is not serialized into the signature
+const SIGHASH_DEFAULT: u8 = 0x00;
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// Input #{0} does not provide information about the spent output
+    NoSpentOutput(usize),
+
+    /// Input #{0} spends P2SH output, but PSBT does not provide the redeem
+    /// script
+    NoRedeemScript(usize),
+
+    /// Input #{0} spends P2WSH output, but PSBT does not provide the witness
+    /// script
+    NoWitnessScript(usize),
+
+    /// Input #{0} requires signing of a taproot script path, which is not
+    /// supported yet
+    TapScriptUnsupported(usize),
+
+    /// Input #{0} has malformed taproot key origin
+    InvalidTapDerivation(usize),
+
+    /// Input #{0} uses signature hash type {1:#04x}, which is not valid for
+    /// taproot
+    InvalidSighashType(usize, u8),
+
+    /// Input #{0} uses SIGHASH_SINGLE, but the transaction has no output
+    /// with the same index
+    NoSingleOutput(usize),
+
+    /// Input #{0} spends witness program of unknown version
+    UnsupportedWitness(usize),
+
+    /// Unable to derive private key for input #{0}: {1}
+    Bip32(usize, bip32::Error),
+
+    /// Key derived for input #{0} does not match public key {1} from the
+    /// PSBT key origin; the PSBT or the signing key is wrong
+    KeyMismatch(usize, PublicKey),
+
+    /// Taproot key derived for input #{0} does not match the key from the
+    /// PSBT key origin or the spent output; the PSBT or the signing key is
+    /// wrong
+    TaprootKeyMismatch(usize),
+}
+
+impl Error {
+    /// Whether the error means that the input can't be signed by this
+    /// signer, so it must be left for some other tool, rather than that the
+    /// PSBT or the key is wrong
+    pub fn is_unsupported(&self) -> bool {
+        matches!(
+            self,
+            Error::TapScriptUnsupported(_) | Error::UnsupportedWitness(_)
+        )
+    }
+}
+
+/// Outcome of a signing session
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct SigningReport {
+    /// Number of signatures added to the PSBT
+    pub signed: usize,
+
+    /// Inputs with key origins matching the signer, which were left
+    /// unsigned since their script type is not supported
+    pub skipped: Vec<Error>,
+}
+
+/// PSBT signer holding master private key for the duration of a signing
+/// session.
+///
+/// The key is zeroized when the signer is dropped; the same happens to each
+/// of the derived child keys right after it was used for signing.
+pub struct PsbtSigner {
+    master: ExtendedPrivKey,
+    fingerprint: Fingerprint,
+}
+
+impl Drop for PsbtSigner {
+    fn drop(&mut self) {
+        wipe(&mut self.master)
+    }
+}
+
+impl PsbtSigner {
+    pub fn with(master: ExtendedPrivKey) -> PsbtSigner {
+        PsbtSigner {
+            fingerprint: master.fingerprint(&wallet::SECP256K1),
+            master,
+        }
+    }
+
+    pub fn with_seed(
+        seed: &MnemonicSeed,
+        network: Network,
+    ) -> Result<PsbtSigner, SeedError> {
+        Ok(PsbtSigner::with(seed.master_xpriv(network)?))
+    }
+
+    /// Fingerprint of the signing key which is matched against PSBT key
+    /// origins
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.fingerprint
+    }
+
+    /// Signs all PSBT inputs which have key origins matching the signer
+    /// master key fingerprint. Keys which already have a signature in the
+    /// input are skipped, as well as inputs of unsupported types, which are
+    /// listed in the report.
+    ///
+    /// Signing is atomic: if any of the inputs fails, the PSBT is left
+    /// unchanged.
+    pub fn sign(&self, psbt: &mut Psbt) -> Result<SigningReport, Error> {
+        let mut signed = psbt.clone();
+        let report = self.sign_inputs(&mut signed)?;
+        *psbt = signed;
+        Ok(report)
+    }
+
+    fn sign_inputs(&self, psbt: &mut Psbt) -> Result<SigningReport, Error> {
+        let tx = psbt.global.unsigned_tx.clone();
+        let mut sig_hasher = SigHashCache::new(&tx);
+        let mut report = SigningReport::default();
+
+        for index in 0..psbt.inputs.len() {
+            let input = &psbt.inputs[index];
+            let tap_keys = tap_derivations(input, index)?
+                .into_iter()
+                .filter(|origin| origin.fingerprint == self.fingerprint)
+                .collect::<Vec<_>>();
+            let keys = input
+                .bip32_derivation
+                .iter()
+                .filter(|(pubkey, (fingerprint, _))| {
+                    *fingerprint == self.fingerprint
+                        && !input.partial_sigs.contains_key(pubkey)
+                })
+                .map(|(pubkey, (_, path))| (*pubkey, path.clone()))
+                .collect::<Vec<(PublicKey, DerivationPath)>>();
+            if keys.is_empty() && tap_keys.is_empty() {
+                continue;
+            }
+
+            let spent = spent_output(&tx, input, index)?;
+            if is_v1_p2tr(&spent.script_pubkey) {
+                match self.sign_taproot(psbt, &tap_keys, index) {
+                    Ok(count) => report.signed += count,
+                    Err(err) if err.is_unsupported() => {
+                        report.skipped.push(err)
+                    }
+                    Err(err) => return Err(err),
+                }
+                continue;
+            }
+            if keys.is_empty() {
+                continue;
+            }
+
+            let sighash_type = input.sighash_type.unwrap_or(SigHashType::All);
+            let sighash = match script_code(input, &spent.script_pubkey, index)
+            {
+                Ok(ScriptCode::Legacy(script_code)) => tx.signature_hash(
+                    index,
+                    &script_code,
+                    sighash_type.as_u32(),
+                ),
+                Ok(ScriptCode::SegWit(script_code)) => sig_hasher
+                    .signature_hash(
+                        index,
+                        &script_code,
+                        spent.value,
+                        sighash_type,
+                    ),
+                Err(err) if err.is_unsupported() => {
+                    report.skipped.push(err);
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            let message = Message::from_slice(&sighash[..])
+                .expect("signature hash is always 32 bytes long");
+
+            for (pubkey, path) in keys {
+                let mut child = self.derive(&path, index)?;
+                if child.private_key.public_key(&wallet::SECP256K1).key
+                    != pubkey.key
+                {
+                    wipe(&mut child);
+                    return Err(Error::KeyMismatch(index, pubkey));
+                }
+                let signature =
+                    wallet::SECP256K1.sign(&message, &child.private_key.key);
+                wipe(&mut child);
+
+                let mut sig = signature.serialize_der().to_vec();
+                sig.push(sighash_type.as_u32() as u8);
+                psbt.inputs[index].partial_sigs.insert(pubkey, sig);
+                report.signed += 1;
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Produces BIP341 key path signature for the input, if one of the keys
+    /// is the taproot internal key
+    fn sign_taproot(
+        &self,
+        psbt: &mut Psbt,
+        tap_keys: &[TapDerivation],
+        index: usize,
+    ) -> Result<usize, Error> {
+        let input = &psbt.inputs[index];
+        if input.unknown.contains_key(&tap_key_sig_key()) {
+            return Ok(0);
+        }
+        let origin = match tap_keys.iter().find(|origin| !origin.script_path) {
+            Some(origin) => origin,
+            None if tap_keys.is_empty() => return Ok(0),
+            None => return Err(Error::TapScriptUnsupported(index)),
+        };
+
+        let tx = &psbt.global.unsigned_tx;
+        let prevouts = (0..tx.input.len())
+            .map(|no| spent_output(tx, &psbt.inputs[no], no))
+            .collect::<Result<Vec<_>, _>>()?;
+        let hash_type = input
+            .sighash_type
+            .map(|sighash_type| sighash_type.as_u32() as u8)
+            .unwrap_or(SIGHASH_DEFAULT);
+        let sighash = taproot_sighash(tx, &prevouts, index, hash_type)?;
+        let message = Message::from_slice(&sighash[..])
+            .expect("signature hash is always 32 bytes long");
+        let merkle_root = input
+            .unknown
+            .get(&raw::Key {
+                type_value: PSBT_IN_TAP_MERKLE_ROOT,
+                key: vec![],
+            })
+            .cloned();
+
+        let mut child = self.derive(&origin.path, index)?;
+        let keypair = schnorrsig::KeyPair::from_seckey_slice(
+            &wallet::SECP256K1,
+            &child.private_key.key[..],
+        );
+        wipe(&mut child);
+        let mut keypair = keypair.expect("derived private keys are valid");
+
+        let internal_key =
+            schnorrsig::PublicKey::from_keypair(&wallet::SECP256K1, &keypair);
+        if internal_key.serialize() != origin.xonly {
+            return Err(Error::TaprootKeyMismatch(index));
+        }
+        let mut engine = tagged_engine("TapTweak");
+        engine.input(&internal_key.serialize());
+        if let Some(merkle_root) = merkle_root {
+            engine.input(&merkle_root);
+        }
+        let tweak = sha256::Hash::from_engine(engine);
+        keypair
+            .tweak_add_assign(&wallet::SECP256K1, &tweak[..])
+            .map_err(|_| Error::TaprootKeyMismatch(index))?;
+        let output_key =
+            schnorrsig::PublicKey::from_keypair(&wallet::SECP256K1, &keypair);
+        if output_key.serialize()[..] != prevouts[index].script_pubkey[2..] {
+            return Err(Error::TaprootKeyMismatch(index));
+        }
+
+        let signature =
+            wallet::SECP256K1.schnorrsig_sign_no_aux_rand(&message, &keypair);
+        let mut sig = signature[..].to_vec();
+        if hash_type != SIGHASH_DEFAULT {
+            sig.push(hash_type);
+        }
+        psbt.inputs[index].unknown.insert(tap_key_sig_key(), sig);
+        Ok(1)
+    }
+
+    fn derive(
+        &self,
+        path: &DerivationPath,
+        index: usize,
+    ) -> Result<ExtendedPrivKey, Error> {
+        self.master
+            .derive_priv(&wallet::SECP256K1, path)
+            .map_err(|err| Error::Bip32(index, err))
+    }
+}
+
+/// Taproot key origin from `PSBT_IN_TAP_BIP32_DERIVATION` field
+struct TapDerivation {
+    xonly: [u8; 32],
+    /// Whether the key is used in script leaves rather than as the
+    /// internal key
+    script_path: bool,
+    fingerprint: Fingerprint,
+    path: DerivationPath,
+}
+
+/// Key of the `PSBT_IN_TAP_KEY_SIG` field holding the key path signature
+pub(crate) fn tap_key_sig_key() -> raw::Key {
+    raw::Key {
+        type_value: PSBT_IN_TAP_KEY_SIG,
+        key: vec![],
+    }
+}
+
+fn tap_derivations(
+    input: &psbt::Input,
+    index: usize,
+) -> Result<Vec<TapDerivation>, Error> {
+    input
+        .unknown
+        .iter()
+        .filter(|(key, _)| key.type_value == PSBT_IN_TAP_BIP32_DERIVATION)
+        .map(|(key, value)| {
+            let invalid = Error::InvalidTapDerivation(index);
+            if key.key.len() != 32 {
+                return Err(invalid);
+            }
+            let (leaf_count, consumed) =
+                encode::deserialize_partial::<VarInt>(value)
+                    .map_err(|_| invalid.clone())?;
+            let origin = leaf_count
+                .0
+                .checked_mul(32)
+                .and_then(|len| consumed.checked_add(len as usize))
+                .and_then(|start| value.get(start..))
+                .filter(|origin| origin.len() >= 4 && origin.len() % 4 == 0)
+                .ok_or_else(|| invalid.clone())?;
+            let mut xonly = [0u8; 32];
+            xonly.copy_from_slice(&key.key);
+            let path = origin[4..]
+                .chunks(4)
+                .map(|chunk| {
+                    let mut index = [0u8; 4];
+                    index.copy_from_slice(chunk);
+                    ChildNumber::from(u32::from_le_bytes(index))
+                })
+                .collect::<Vec<_>>();
+            Ok(TapDerivation {
+                xonly,
+                script_path: leaf_count.0 > 0,
+                fingerprint: Fingerprint::from(&origin[..4]),
+                path: DerivationPath::from(path),
+            })
+        })
+        .collect()
+}
+
+fn tagged_engine(tag: &str) -> sha256::HashEngine {
+    let tag = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag[..]);
+    engine.input(&tag[..]);
+    engine
+}
+
+/// Computes BIP341 signature hash for a key path spending with no annex
+fn taproot_sighash(
+    tx: &Transaction,
+    prevouts: &[TxOut],
+    index: usize,
+    hash_type: u8,
+) -> Result<sha256::Hash, Error> {
+    const SIGHASH_NONE: u8 = 0x02;
+    const SIGHASH_SINGLE: u8 = 0x03;
+    const SIGHASH_ANYONECANPAY: u8 = 0x80;
+
+    if !matches!(hash_type, 0x00..=0x03 | 0x81..=0x83) {
+        return Err(Error::InvalidSighashType(index, hash_type));
+    }
+    let output_type = hash_type & 0x03;
+    let anyone_can_pay = hash_type & SIGHASH_ANYONECANPAY != 0;
+    let hash_all = |data: &mut dyn Iterator<Item = Vec<u8>>| {
+        let mut engine = sha256::Hash::engine();
+        data.for_each(|item| engine.input(&item));
+        sha256::Hash::from_engine(engine)
+    };
+
+    let mut engine = tagged_engine("TapSighash");
+    // Signature hash epoch
+    engine.input(&[0u8, hash_type]);
+    engine.input(&serialize(&tx.version));
+    engine.input(&serialize(&tx.lock_time));
+    if !anyone_can_pay {
+        let prevout_hash = hash_all(
+            &mut tx.input.iter().map(|txin| serialize(&txin.previous_output)),
+        );
+        let amount_hash =
+            hash_all(&mut prevouts.iter().map(|txout| serialize(&txout.value)));
+        let script_hash = hash_all(
+            &mut prevouts.iter().map(|txout| serialize(&txout.script_pubkey)),
+        );
+        let sequence_hash = hash_all(
+            &mut tx.input.iter().map(|txin| serialize(&txin.sequence)),
+        );
+        engine.input(&prevout_hash[..]);
+        engine.input(&amount_hash[..]);
+        engine.input(&script_hash[..]);
+        engine.input(&sequence_hash[..]);
+    }
+    if output_type != SIGHASH_NONE && output_type != SIGHASH_SINGLE {
+        let output_hash = hash_all(&mut tx.output.iter().map(serialize));
+        engine.input(&output_hash[..]);
+    }
+    // Key path spending without annex
+    engine.input(&[0u8]);
+    if anyone_can_pay {
+        let txin = &tx.input[index];
+        engine.input(&serialize(&txin.previous_output));
+        engine.input(&serialize(&prevouts[index].value));
+        engine.input(&serialize(&prevouts[index].script_pubkey));
+        engine.input(&serialize(&txin.sequence));
+    } else {
+        engine.input(&serialize(&(index as u32)));
+    }
+    if output_type == SIGHASH_SINGLE {
+        let output =
+            tx.output.get(index).ok_or(Error::NoSingleOutput(index))?;
+        engine.input(&sha256::Hash::hash(&serialize(output))[..]);
+    }
+    Ok(sha256::Hash::from_engine(engine))
+}
+
+enum ScriptCode {
+    Legacy(Script),
+    SegWit(Script),
+}
+
+fn spent_output(
+    tx: &Transaction,
+    input: &psbt::Input,
+    index: usize,
+) -> Result<TxOut, Error> {
+    if let Some(txout) = &input.witness_utxo {
+        return Ok(txout.clone());
+    }
+    let vout = tx.input[index].previous_output.vout as usize;
+    input
+        .non_witness_utxo
+        .as_ref()
+        .and_then(|prev_tx| prev_tx.output.get(vout))
+        .cloned()
+        .ok_or(Error::NoSpentOutput(index))
+}
+
+fn script_code(
+    input: &psbt::Input,
+    script_pubkey: &Script,
+    index: usize,
+) -> Result<ScriptCode, Error> {
+    let script = if script_pubkey.is_p2sh() {
+        input
+            .redeem_script
+            .clone()
+            .ok_or(Error::NoRedeemScript(index))?
+    } else {
+        script_pubkey.clone()
+    };
+
+    if script.is_v0_p2wpkh() {
+        let pubkey_hash = PubkeyHash::from_slice(&script[2..])
+            .expect("P2WPKH script always contains 20-byte hash");
+        Ok(ScriptCode::SegWit(Script::new_p2pkh(&pubkey_hash)))
+    } else if script.is_v0_p2wsh() {
+        input
+            .witness_script
+            .clone()
+            .map(ScriptCode::SegWit)
+            .ok_or(Error::NoWitnessScript(index))
+    } else if script.is_witness_program() {
+        Err(Error::UnsupportedWitness(index))
+    } else {
+        Ok(ScriptCode::Legacy(script))
+    }
+}
+
+pub(crate) fn is_v1_p2tr(script: &Script) -> bool {
+    script.len() == 34
+        && script[0] == opcodes::all::OP_PUSHNUM_1.into_u8()
+        && script[1] == opcodes::all::OP_PUSHBYTES_32.into_u8()
+}
+
+/// Zeroizes private key material. Copies made by the libraries during the
+/// derivation are out of our control, but at least the keys owned by the
+/// signer do not outlive the signing session.
+fn wipe(key: &mut ExtendedPrivKey) {
+    // SAFETY: secret key is a plain 32-byte array, and the slice does not
+    // outlive the mutable borrow of the key
+    unsafe {
+        std::slice::from_raw_parts_mut(
+            key.private_key.key.as_mut_ptr(),
+            secp256k1::constants::SECRET_KEY_SIZE,
+        )
+    }
+    .zeroize();
+    key.chain_code = ChainCode::from(&[0u8; 32][..]);
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+
+    use bitcoin::blockdata::script::Builder;
+    use bitcoin::hashes::hex::FromHex;
+    use bitcoin::secp256k1::Signature;
+    use bitcoin::util::bip32::ExtendedPubKey;
+    use bitcoin::{OutPoint, TxIn, Txid};
+
+    use super::*;
+
+    const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon \
+                            abandon abandon abandon abandon abandon about";
+
+    fn signer() -> PsbtSigner {
+        let seed = MnemonicSeed::with(MNEMONIC, "").unwrap();
+        PsbtSigner::with_seed(&seed, Network::Testnet).unwrap()
+    }
+
+    fn derive(path: &str) -> (PublicKey, DerivationPath) {
+        let seed = MnemonicSeed::with(MNEMONIC, "").unwrap();
+        let path = DerivationPath::from_str(path).unwrap();
+        let xpub = ExtendedPubKey::from_private(
+            &wallet::SECP256K1,
+            &seed
+                .master_xpriv(Network::Testnet)
+                .unwrap()
+                .derive_priv(&wallet::SECP256K1, &path)
+                .unwrap(),
+        );
+        (xpub.public_key, path)
+    }
+
+    fn make_psbt(
+        keys: &[(PublicKey, DerivationPath)],
+        fingerprint: &str,
+    ) -> Psbt {
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(
+                    Txid::from_hex(
+                        "f2a1b5c3d4e5f60718293a4b5c6d7e8f9a0b1c2d3e4f5a6b7c8d9e0f1a2b3c4d",
+                    )
+                    .unwrap(),
+                    1,
+                ),
+                script_sig: Script::new(),
+                sequence: 0xFFFFFFFD,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: 90_000,
+                script_pubkey: Script::new_v0_wpkh(&keys[0].0.wpubkey_hash()),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        let fingerprint = Fingerprint::from_hex(fingerprint).unwrap();
+        psbt.inputs[0].bip32_derivation = keys
+            .iter()
+            .map(|(pubkey, path)| (*pubkey, (fingerprint, path.clone())))
+            .collect::<BTreeMap<_, _>>();
+        psbt
+    }
+
+    fn verify(psbt: &Psbt, script_code: &Script, value: u64, legacy: bool) {
+        let tx = &psbt.global.unsigned_tx;
+        let sighash = if legacy {
+            tx.signature_hash(0, script_code, SigHashType::All.as_u32())
+        } else {
+            SigHashCache::new(tx).signature_hash(
+                0,
+                script_code,
+                value,
+                SigHashType::All,
+            )
+        };
+        let message = Message::from_slice(&sighash[..]).unwrap();
+        assert!(!psbt.inputs[0].partial_sigs.is_empty());
+        for (pubkey, sig) in &psbt.inputs[0].partial_sigs {
+            assert_eq!(*sig.last().unwrap(), SigHashType::All.as_u32() as u8);
+            let signature = Signature::from_der(&sig[..sig.len() - 1]).unwrap();
+            wallet::SECP256K1
+                .verify(&message, &signature, &pubkey.key)
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn sign_p2wpkh() {
+        let key = derive("m/84'/1'/0'/0/0");
+        let mut psbt = make_psbt(&[key.clone()], "73c5da0a");
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 100_000,
+            script_pubkey: Script::new_v0_wpkh(&key.0.wpubkey_hash()),
+        });
+        assert_eq!(signer().sign(&mut psbt).unwrap().signed, 1);
+        verify(
+            &psbt,
+            &Script::new_p2pkh(&key.0.pubkey_hash()),
+            100_000,
+            false,
+        );
+
+        // Second pass must not add signatures
+        assert_eq!(signer().sign(&mut psbt).unwrap().signed, 0);
+    }
+
+    #[test]
+    fn sign_p2sh_p2wpkh() {
+        let key = derive("m/49'/1'/0'/0/0");
+        let mut psbt = make_psbt(&[key.clone()], "73c5da0a");
+        let redeem_script = Script::new_v0_wpkh(&key.0.wpubkey_hash());
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 100_000,
+            script_pubkey: redeem_script.to_p2sh(),
+        });
+        assert_eq!(signer().sign(&mut psbt), Err(Error::NoRedeemScript(0)));
+        assert!(psbt.inputs[0].partial_sigs.is_empty());
+
+        psbt.inputs[0].redeem_script = Some(redeem_script);
+        assert_eq!(signer().sign(&mut psbt).unwrap().signed, 1);
+        verify(
+            &psbt,
+            &Script::new_p2pkh(&key.0.pubkey_hash()),
+            100_000,
+            false,
+        );
+    }
+
+    #[test]
+    fn sign_p2wsh_multisig() {
+        let keys = [derive("m/48'/1'/0'/2'/0/0"), derive("m/48'/1'/1'/2'/0/0")];
+        let witness_script = Builder::new()
+            .push_opcode(opcodes::all::OP_PUSHNUM_2)
+            .push_key(&keys[0].0)
+            .push_key(&keys[1].0)
+            .push_opcode(opcodes::all::OP_PUSHNUM_2)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        let mut psbt = make_psbt(&keys, "73c5da0a");
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 100_000,
+            script_pubkey: witness_script.to_v0_p2wsh(),
+        });
+        assert_eq!(signer().sign(&mut psbt), Err(Error::NoWitnessScript(0)));
+
+        psbt.inputs[0].witness_script = Some(witness_script.clone());
+        assert_eq!(signer().sign(&mut psbt).unwrap().signed, 2);
+        verify(&psbt, &witness_script, 100_000, false);
+    }
+
+    #[test]
+    fn sign_legacy_p2pkh() {
+        let key = derive("m/44'/1'/0'/0/0");
+        let mut psbt = make_psbt(&[key.clone()], "73c5da0a");
+        let script_pubkey = Script::new_p2pkh(&key.0.pubkey_hash());
+        assert_eq!(signer().sign(&mut psbt), Err(Error::NoSpentOutput(0)));
+
+        psbt.inputs[0].non_witness_utxo = Some(Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![],
+            output: vec![
+                TxOut {
+                    value: 1,
+                    script_pubkey: Script::new(),
+                },
+                TxOut {
+                    value: 100_000,
+                    script_pubkey: script_pubkey.clone(),
+                },
+            ],
+        });
+        assert_eq!(signer().sign(&mut psbt).unwrap().signed, 1);
+        verify(&psbt, &script_pubkey, 100_000, true);
+    }
+
+    #[test]
+    fn foreign_and_mismatched_keys() {
+        let key = derive("m/84'/1'/0'/0/0");
+
+        let mut psbt = make_psbt(&[key.clone()], "deadbeef");
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 100_000,
+            script_pubkey: Script::new_v0_wpkh(&key.0.wpubkey_hash()),
+        });
+        assert_eq!(signer().sign(&mut psbt).unwrap().signed, 0);
+        assert!(psbt.inputs[0].partial_sigs.is_empty());
+
+        let other = derive("m/84'/1'/0'/0/1");
+        let mut psbt = make_psbt(&[(other.0, key.1)], "73c5da0a");
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 100_000,
+            script_pubkey: Script::new_v0_wpkh(&other.0.wpubkey_hash()),
+        });
+        assert_eq!(
+            signer().sign(&mut psbt),
+            Err(Error::KeyMismatch(0, other.0))
+        );
+    }
+
+    fn taproot_psbt(path: &str, leaf_hashes: u8) -> Psbt {
+        let key = derive(path);
+        let mut psbt = make_psbt(&[key.clone()], "73c5da0a");
+        psbt.inputs[0].bip32_derivation.clear();
+        let mut origin = vec![leaf_hashes];
+        origin.extend(vec![0xAAu8; 32 * leaf_hashes as usize]);
+        origin.extend(Vec::<u8>::from_hex("73c5da0a").unwrap());
+        for child in key.1.as_ref() {
+            origin.extend(&u32::from(*child).to_le_bytes());
+        }
+        psbt.inputs[0].unknown.insert(
+            raw::Key {
+                type_value: PSBT_IN_TAP_BIP32_DERIVATION,
+                key: key.0.key.serialize()[1..].to_vec(),
+            },
+            origin,
+        );
+        psbt
+    }
+
+    #[test]
+    fn sign_taproot_key_path() {
+        // BIP86 test vector for the first receiving address
+        let mut psbt = taproot_psbt("m/86'/0'/0'/0/0", 0);
+        let output_key = schnorrsig::PublicKey::from_slice(
+            &Vec::<u8>::from_hex(
+                "a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let mut script = vec![0x51, 0x20];
+        script.extend_from_slice(&output_key.serialize());
+        let prevout = TxOut {
+            value: 100_000,
+            script_pubkey: Script::from(script),
+        };
+        psbt.inputs[0].witness_utxo = Some(prevout.clone());
+        assert_eq!(signer().sign(&mut psbt).unwrap().signed, 1);
+
+        let sig = &psbt.inputs[0].unknown[&tap_key_sig_key()];
+        assert_eq!(sig.len(), 64);
+        let sighash = taproot_sighash(
+            &psbt.global.unsigned_tx,
+            &[prevout],
+            0,
+            SIGHASH_DEFAULT,
+        )
+        .unwrap();
+        wallet::SECP256K1
+            .schnorrsig_verify(
+                &schnorrsig::Signature::from_slice(sig).unwrap(),
+                &Message::from_slice(&sighash[..]).unwrap(),
+                &output_key,
+            )
+            .unwrap();
+
+        // Second pass must not add signatures
+        assert_eq!(signer().sign(&mut psbt).unwrap().signed, 0);
+    }
+
+    #[test]
+    fn taproot_script_path_is_skipped() {
+        let key = derive("m/86'/1'/0'/0/0");
+        let mut psbt = taproot_psbt("m/86'/1'/0'/0/0", 1);
+        let mut script = vec![0x51, 0x20];
+        script.extend_from_slice(&key.0.key.serialize()[1..]);
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 100_000,
+            script_pubkey: Script::from(script),
+        });
+        let report = signer().sign(&mut psbt).unwrap();
+        assert_eq!(report.signed, 0);
+        assert_eq!(report.skipped, vec![Error::TapScriptUnsupported(0)]);
+        assert!(!psbt.inputs[0].unknown.contains_key(&tap_key_sig_key()));
+    }
+
+    #[test]
+    fn failed_signing_keeps_psbt() {
+        let key = derive("m/84'/1'/0'/0/0");
+        let mut psbt = make_psbt(&[key.clone()], "73c5da0a");
+        let txin = psbt.global.unsigned_tx.input[0].clone();
+        psbt.global.unsigned_tx.input.push(txin);
+        psbt.inputs.push(psbt.inputs[0].clone());
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 100_000,
+            script_pubkey: Script::new_v0_wpkh(&key.0.wpubkey_hash()),
+        });
+        let original = psbt.clone();
+        assert_eq!(signer().sign(&mut psbt), Err(Error::NoSpentOutput(1)));
+        assert_eq!(psbt, original);
+    }
+}
@@ -0,0 +1,495 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Live wallet updates with Electrum scripthash and block header
+//! subscriptions.
+//!
+//! Electrum requests are blocking, so the subscriptions are served by a
+//! worker thread (see [`spawn`]), which receives snapshots of the document
+//! data and reports results back to the UI thread.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bitcoin::Script;
+use electrum_client::{Client as ElectrumClient, ElectrumApi};
+use wallet::bip32::{ChildIndex, UnhardenedIndex};
+use wallet::descriptor;
+
+use super::utxo_lookup::Error;
+use crate::model::{DerivationCache, DescriptorAccount, UtxoEntry};
+use crate::util::index_space::IndexSpace;
+
+/// Number of consecutive indexes without known UTXOs which are watched
+/// after the last used index of each descriptor account
+pub const GAP_LIMIT: u32 = 20;
+
+/// Interval (in seconds) between polls for the server notifications
+pub const POLL_INTERVAL: u32 = 10;
+
+/// Longest delay (in seconds) before reconnecting to the server after
+/// repeated failures
+pub const MAX_BACKOFF: u32 = 300;
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+struct WatchedScript {
+    template: descriptor::Template,
+    category: descriptor::Category,
+    index: u32,
+}
+
+/// Changes to the UTXO set and blockchain tip reported by the server since
+/// the previous poll
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Update {
+    /// New blockchain tip height
+    pub tip: Option<u32>,
+    /// Incoming payments which were not known before
+    pub added: Vec<UtxoEntry>,
+    /// Known outputs which have changed their mining height, i.e. got
+    /// confirmed or were re-orged
+    pub updated: Vec<UtxoEntry>,
+    /// Cached entries which must be removed: spent outputs and previous
+    /// versions of the updated ones
+    pub removed: Vec<UtxoEntry>,
+}
+
+impl Update {
+    /// Detects whether the update changes UTXO set
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.updated.is_empty()
+            && self.removed.is_empty()
+    }
+}
+
+/// Snapshot of the document data watched by the subscriptions worker
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Watched {
+    /// Electrum server address
+    pub server: String,
+    pub descriptors: Vec<DescriptorAccount>,
+    pub utxos: HashSet<UtxoEntry>,
+}
+
+/// Events reported by the subscriptions worker
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Event {
+    /// Subscriptions are established with the server
+    Connected {
+        /// Current blockchain tip height
+        tip: u32,
+        /// Number of watched scripts
+        scripts: usize,
+    },
+
+    /// Changes reported by the server
+    Update {
+        update: Update,
+        /// Current blockchain tip height
+        tip: u32,
+    },
+
+    /// Server request has failed; the next attempt is made after
+    /// `retry_in` seconds
+    Failed { error: String, retry_in: u32 },
+}
+
+/// Starts worker thread performing all server requests for the
+/// subscriptions. Each snapshot sent to the returned channel triggers a
+/// poll, reported to the `events` channel. After failures polls are
+/// skipped with exponentially increasing delay. The worker stops once
+/// either of the channels is closed.
+pub fn spawn(events: glib::Sender<Event>) -> mpsc::Sender<Watched> {
+    let (sender, receiver) = mpsc::channel::<Watched>();
+    thread::spawn(move || {
+        let mut worker = Worker::default();
+        while let Ok(mut watched) = receiver.recv() {
+            // Snapshots queued during a slow poll are outdated
+            while let Ok(newer) = receiver.try_recv() {
+                watched = newer;
+            }
+            for event in worker.poll(&watched) {
+                if events.send(event).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    sender
+}
+
+#[derive(Default)]
+struct Worker {
+    connection: Option<(String, Subscriptions)>,
+    cache: DerivationCache,
+    backoff: u32,
+    retry_at: Option<Instant>,
+}
+
+impl Worker {
+    fn poll(&mut self, watched: &Watched) -> Vec<Event> {
+        if matches!(self.retry_at, Some(at) if Instant::now() < at) {
+            return vec![];
+        }
+        let mut events = vec![];
+        match self.try_poll(watched, &mut events) {
+            Ok(()) => {
+                self.backoff = 0;
+                self.retry_at = None;
+            }
+            Err(err) => {
+                self.connection = None;
+                self.backoff =
+                    (self.backoff * 2).clamp(POLL_INTERVAL, MAX_BACKOFF);
+                self.retry_at = Some(
+                    Instant::now() + Duration::from_secs(self.backoff as u64),
+                );
+                events.push(Event::Failed {
+                    error: err.to_string(),
+                    retry_in: self.backoff,
+                });
+            }
+        }
+        events
+    }
+
+    /// Connects to the server (or reconnects when the server was changed
+    /// or the connection has failed) and polls for the notifications
+    fn try_poll(
+        &mut self,
+        watched: &Watched,
+        events: &mut Vec<Event>,
+    ) -> Result<(), Error> {
+        if !matches!(&self.connection, Some((server, _)) if *server == watched.server)
+        {
+            self.connection = None;
+            let subscriptions = Subscriptions::with(
+                ElectrumClient::new(&watched.server)?,
+                &watched.descriptors,
+                &watched.utxos,
+                &mut self.cache,
+            )?;
+            events.push(Event::Connected {
+                tip: subscriptions.tip(),
+                scripts: subscriptions.len(),
+            });
+            self.connection = Some((watched.server.clone(), subscriptions));
+        }
+        let subscriptions = match &mut self.connection {
+            Some((_, subscriptions)) => subscriptions,
+            None => unreachable!("connection is established above"),
+        };
+        subscriptions.watch(
+            &watched.descriptors,
+            &watched.utxos,
+            &mut self.cache,
+        )?;
+        let update = subscriptions.poll(&watched.utxos)?;
+        if update.tip.is_some() || !update.is_empty() {
+            events.push(Event::Update {
+                update,
+                tip: subscriptions.tip(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Subscription manager keeping Electrum server subscriptions for all
+/// watched scripts of the document descriptor accounts.
+///
+/// Electrum client receives notifications only while performing requests,
+/// so [`Subscriptions::poll`] must be called periodically.
+pub struct Subscriptions {
+    resolver: ElectrumClient,
+    scripts: HashMap<Script, WatchedScript>,
+    pending: HashSet<Script>,
+    tip: u32,
+}
+
+impl Subscriptions {
+    /// Subscribes to new block headers and to the watched scripts of the
+    /// provided descriptor accounts
+    pub fn with(
+        resolver: ElectrumClient,
+        descriptors: &[DescriptorAccount],
+        utxos: &HashSet<UtxoEntry>,
+        cache: &mut DerivationCache,
+    ) -> Result<Subscriptions, Error> {
+        let tip = resolver.block_headers_subscribe()?.height as u32;
+        let mut subscriptions = Subscriptions {
+            resolver,
+            scripts: empty!(),
+            pending: empty!(),
+            tip,
+        };
+        subscriptions.watch(descriptors, utxos, cache)?;
+        Ok(subscriptions)
+    }
+
+    /// Current blockchain tip height
+    pub fn tip(&self) -> u32 {
+        self.tip
+    }
+
+    /// Number of watched scripts
+    pub fn len(&self) -> usize {
+        self.scripts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scripts.is_empty()
+    }
+
+    /// Brings subscriptions in sync with the descriptor accounts: subscribes
+    /// to the scripts which became watched (for new accounts or after new
+    /// payments extended the gap) and unsubscribes from the scripts of the
+    /// removed accounts. Returns number of new subscriptions.
+    pub fn watch(
+        &mut self,
+        descriptors: &[DescriptorAccount],
+        utxos: &HashSet<UtxoEntry>,
+        cache: &mut DerivationCache,
+    ) -> Result<usize, Error> {
+        let mut watched = HashMap::new();
+        for account in descriptors {
+            let used = utxos
+                .iter()
+                .filter(|utxo| utxo.has_match(account))
+                .map(|utxo| utxo.derivation_index)
+                .collect::<HashSet<_>>();
+            for index in watched_indexes(&account.index_space(), &used) {
+                let scripts = cache
+                    .pubkey_scripts(
+                        account,
+                        UnhardenedIndex::from_index(index)
+                            .map_err(|_| Error::HardenedIndex)?,
+                    )
+                    .map_err(|err| {
+                        Error::Descriptor(index, account.descriptor(), err)
+                    })?;
+                watched.extend(scripts.into_iter().map(
+                    |(category, script)| {
+                        (
+                            script,
+                            WatchedScript {
+                                template: account.generator.template.clone(),
+                                category,
+                                index,
+                            },
+                        )
+                    },
+                ));
+            }
+        }
+
+        let removed = self
+            .scripts
+            .keys()
+            .filter(|script| !watched.contains_key(script))
+            .cloned()
+            .collect::<Vec<_>>();
+        for script in removed {
+            self.resolver.script_unsubscribe(&script)?;
+            self.scripts.remove(&script);
+            self.pending.remove(&script);
+        }
+
+        let mut count = 0usize;
+        for (script, item) in watched {
+            if self.scripts.contains_key(&script) {
+                continue;
+            }
+            // Scripts with history may have received payments while the
+            // document was closed, so their UTXOs are re-read on the next
+            // poll
+            if self.resolver.script_subscribe(&script)?.is_some() {
+                self.pending.insert(script.clone());
+            }
+            self.scripts.insert(script, item);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Processes notifications received from the server, requesting UTXO
+    /// lists for the scripts which have changed their status
+    pub fn poll(
+        &mut self,
+        utxos: &HashSet<UtxoEntry>,
+    ) -> Result<Update, Error> {
+        // Notifications are read from the connection only during requests
+        self.resolver.ping()?;
+
+        let mut update = Update::default();
+        while let Some(header) = self.resolver.block_headers_pop()? {
+            self.tip = header.height as u32;
+            update.tip = Some(self.tip);
+        }
+
+        for script in self.scripts.keys() {
+            if self.resolver.script_pop(script)?.is_some() {
+                self.pending.insert(script.clone());
+            }
+        }
+        for script in self.pending.drain() {
+            let item = match self.scripts.get(&script) {
+                Some(item) => item,
+                None => continue,
+            };
+            let current = self
+                .resolver
+                .script_list_unspent(&script)?
+                .iter()
+                .map(|res| {
+                    UtxoEntry::with(
+                        res,
+                        item.template.clone(),
+                        item.category,
+                        item.index,
+                    )
+                })
+                .collect::<Vec<_>>();
+            let known = utxos
+                .iter()
+                .filter(|utxo| {
+                    utxo.descriptor_template == item.template
+                        && utxo.descriptor_category == item.category
+                        && utxo.derivation_index == item.index
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            diff(&known, current, &mut update);
+        }
+        Ok(update)
+    }
+}
+
+/// Indexes from the index space up to [`GAP_LIMIT`] indexes past the last
+/// used one
+fn watched_indexes(space: &IndexSpace, used: &HashSet<u32>) -> Vec<u32> {
+    let mut indexes = vec![];
+    let mut gap = 0u32;
+    let mut n = 0u64;
+    while gap < GAP_LIMIT {
+        let index = match space.nth(n) {
+            Some(index) => index,
+            None => break,
+        };
+        gap = if used.contains(&index) { 0 } else { gap + 1 };
+        indexes.push(index);
+        n += 1;
+    }
+    indexes
+}
+
+/// Compares cached UTXOs of a script with the server-provided list
+fn diff(known: &[UtxoEntry], current: Vec<UtxoEntry>, update: &mut Update) {
+    for utxo in known {
+        match current.iter().find(|entry| entry.outpoint == utxo.outpoint) {
+            Some(entry) if entry.height == utxo.height => {}
+            Some(_) | None => update.removed.push(utxo.clone()),
+        }
+    }
+    for entry in current {
+        match known.iter().find(|utxo| utxo.outpoint == entry.outpoint) {
+            None => update.added.push(entry),
+            Some(utxo) if utxo.height != entry.height => {
+                update.updated.push(entry)
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::fixtures::{self, numbered_outpoint};
+
+    fn utxo(no: u32, height: u32) -> UtxoEntry {
+        fixtures::utxo(numbered_outpoint(no, 0), height, 1000 * no as u64)
+    }
+
+    #[test]
+    fn gap_limit() {
+        let space = IndexSpace::full();
+        assert_eq!(
+            watched_indexes(&space, &empty!()),
+            (0..GAP_LIMIT).collect::<Vec<_>>()
+        );
+        let used = [3u32, 10].iter().copied().collect();
+        assert_eq!(
+            watched_indexes(&space, &used),
+            (0..=10 + GAP_LIMIT).collect::<Vec<_>>()
+        );
+
+        let space = IndexSpace::with(vec![5..=7, 100..=101]);
+        assert_eq!(watched_indexes(&space, &empty!()), vec![5, 6, 7, 100, 101]);
+    }
+
+    #[test]
+    fn utxo_diff() {
+        let known = vec![utxo(1, 0), utxo(2, 100), utxo(3, 120)];
+        let current = vec![utxo(1, 130), utxo(3, 120), utxo(4, 0)];
+        let mut update = Update::default();
+        diff(&known, current, &mut update);
+        assert_eq!(update.added, vec![utxo(4, 0)]);
+        assert_eq!(update.updated, vec![utxo(1, 130)]);
+        assert_eq!(update.removed, vec![utxo(1, 0), utxo(2, 100)]);
+        assert!(!update.is_empty());
+
+        let mut update = Update::default();
+        diff(&known, known.clone(), &mut update);
+        assert!(update.is_empty());
+    }
+
+    #[test]
+    fn backoff() {
+        // Nothing listens on port 1, so each connection attempt fails
+        let watched = Watched {
+            server: s!("127.0.0.1:1"),
+            descriptors: vec![],
+            utxos: empty!(),
+        };
+        let mut worker = Worker::default();
+        assert!(matches!(
+            worker.poll(&watched)[..],
+            [Event::Failed {
+                retry_in: POLL_INTERVAL,
+                ..
+            }]
+        ));
+        // Polls are skipped until the retry time
+        assert!(worker.poll(&watched).is_empty());
+
+        worker.retry_at = None;
+        assert!(matches!(
+            worker.poll(&watched)[..],
+            [Event::Failed { retry_in, .. }] if retry_in == 2 * POLL_INTERVAL
+        ));
+        worker.backoff = MAX_BACKOFF;
+        worker.retry_at = None;
+        assert!(matches!(
+            worker.poll(&watched)[..],
+            [Event::Failed { retry_in, .. }] if retry_in == MAX_BACKOFF
+        ));
+    }
+}
@@ -28,7 +28,7 @@ use electrum_client::{
 use wallet::bip32::{ChildIndex, UnhardenedIndex};
 use wallet::descriptor;
 
-use crate::model::{DescriptorAccount, UtxoEntry};
+use crate::model::{DerivationCache, DescriptorAccount, UtxoEntry};
 use crate::util::resolver_mode::ResolverModeType;
 
 #[derive(Clone, PartialEq, Eq, Debug, Display, From, Error)]
@@ -45,6 +45,10 @@ pub enum Error {
 
     /// Unable to generate key with index {0} for descriptor {1}: {2}
     Descriptor(u32, String, descriptor::Error),
+
+    /// Index ranges of the descriptor keys do not have common derivation
+    /// indexes, so no scripts can be derived for the lookup
+    NoCommonIndexes,
 }
 
 impl From<ElectrumError> for Error {
@@ -59,6 +63,7 @@ pub trait UtxoLookup {
         resolver: ElectrumClient,
         lookup_type: ResolverModeType,
         account: DescriptorAccount,
+        cache: Rc<RefCell<DerivationCache>>,
         utxo_set: Rc<RefCell<HashSet<UtxoEntry>>>,
         uxto_store: Option<&gtk::ListStore>,
     ) -> Result<usize, Error> {
@@ -69,16 +74,23 @@ pub trait UtxoLookup {
             pub derivation_index: u32,
         }
 
+        let index_space = account.index_space();
+        if index_space.is_empty() {
+            return Err(Error::NoCommonIndexes);
+        }
+
         let mut total_found = 0usize;
-        let mut lookup_iter = lookup_type.into_iter();
+        let mut lookup_iter = lookup_type.iter_within(index_space);
         loop {
             let mut lookup: Vec<LookupItem> = Vec::with_capacity(
                 lookup_type.count() as usize
                     * account.pubkey_scripts_count() as usize,
             );
             for offset in lookup_iter.by_ref() {
-                let scripts = account
+                let scripts = cache
+                    .borrow_mut()
                     .pubkey_scripts(
+                        &account,
                         UnhardenedIndex::from_index(offset)
                             .map_err(|_| Error::HardenedIndex)?,
                     )
@@ -15,6 +15,7 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+#![cfg_attr(all(test, feature = "bench"), feature(test))]
 #![allow(dead_code)]
 // TODO: Remove once bugs in amplify_derive and strict_encode are fixed
 #![allow(clippy::if_same_then_else, clippy::init_numbered_fields)]
@@ -27,6 +28,8 @@ extern crate amplify_derive;
 extern crate lnpbp;
 #[macro_use]
 extern crate glib;
+#[cfg(all(test, feature = "bench"))]
+extern crate test;
 
 #[macro_export]
 macro_rules! glade_load {
@@ -0,0 +1,673 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+
+use amplify::Wrapper;
+use bitcoin::{OutPoint, Txid};
+use lnpbp::bp::blind::OutpointReveal;
+use rgb::schema::{self, OwnedRightType};
+use rgb::{
+    data, seal, value, Assignments, AtomicValue, Consignment, Metadata, Node,
+    NodeId, OwnedState,
+};
+use rgb20::schema::{FieldType, OwnedRightsType, TransitionType};
+
+/// Single-use seal controlling some right of the asset
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display)]
+#[display("{outpoint} ({node_id}:{index})")]
+pub struct AssetSeal {
+    /// Contract node (genesis, state transition or extension) which has
+    /// defined the seal
+    pub node_id: NodeId,
+    /// Index of the seal within the node owned rights of the given type
+    pub index: u16,
+    pub outpoint: OutPoint,
+    /// Revealed value assigned to the seal, for the rights having value
+    /// state (assets, inflation and burn rights)
+    pub value: Option<AtomicValue>,
+}
+
+/// Returns revealed seals for the given right of the contract which are not
+/// closed by any state transition known to the consignment, i.e. the
+/// seals controlling the current state of the right.
+pub fn known_seals(
+    consignment: &Consignment,
+    right: OwnedRightsType,
+) -> Vec<AssetSeal> {
+    let right_type: OwnedRightType = right.into();
+
+    let mut nodes: Vec<(&dyn Node, Option<Txid>)> =
+        vec![(&consignment.genesis, None)];
+    nodes.extend(consignment.state_transitions.iter().map(
+        |(anchor, transition)| (transition as &dyn Node, Some(anchor.txid)),
+    ));
+    nodes.extend(
+        consignment
+            .state_extensions
+            .iter()
+            .map(|extension| (extension as &dyn Node, None)),
+    );
+
+    let closed = nodes
+        .iter()
+        .flat_map(|(node, _)| {
+            node.parent_owned_rights()
+                .iter()
+                .flat_map(|(node_id, rights)| {
+                    rights
+                        .get(&right_type)
+                        .into_iter()
+                        .flatten()
+                        .map(move |index| (*node_id, *index))
+                })
+        })
+        .collect::<HashSet<_>>();
+
+    let mut seals = vec![];
+    for (node, witness_txid) in nodes {
+        let node_id = node.node_id();
+        let assignments = match node.owned_rights_by_type(right_type) {
+            Some(assignments) => assignments,
+            None => continue,
+        };
+        for index in 0..assignments.len() as u16 {
+            if closed.contains(&(node_id, index)) {
+                continue;
+            }
+            if let Some(outpoint) = assignments
+                .revealed_seal_at(index)
+                .ok()
+                .flatten()
+                .and_then(|seal| seal_outpoint(seal, witness_txid))
+            {
+                seals.push(AssetSeal {
+                    node_id,
+                    index,
+                    outpoint,
+                    value: revealed_value(assignments, index),
+                })
+            }
+        }
+    }
+    seals
+}
+
+/// Asset supply known from the consignment, in atomic units
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct AssetSupply {
+    /// Supply issued by genesis and secondary issuances
+    pub issued: AtomicValue,
+    /// Supply destroyed by burn and burn & replace transitions
+    pub burned: AtomicValue,
+    /// Supply re-issued by burn & replace transitions
+    pub replaced: AtomicValue,
+}
+
+impl AssetSupply {
+    /// Supply which is not burned
+    pub fn circulating(&self) -> AtomicValue {
+        (self.issued + self.replaced).saturating_sub(self.burned)
+    }
+}
+
+/// Computes asset supply from the genesis and the known state transitions
+pub fn known_supply(consignment: &Consignment) -> AssetSupply {
+    let mut supply = AssetSupply {
+        issued: metadata_u64(
+            consignment.genesis.metadata(),
+            FieldType::IssuedSupply,
+        ),
+        ..AssetSupply::default()
+    };
+    let assets: OwnedRightType = OwnedRightsType::Assets.into();
+    let burn_replace: schema::TransitionType =
+        TransitionType::BurnAndReplace.into();
+    for (_, transition) in &consignment.state_transitions {
+        let metadata = transition.metadata();
+        supply.issued += metadata_u64(metadata, FieldType::IssuedSupply);
+        supply.burned += metadata_u64(metadata, FieldType::BurnedSupply);
+        if transition.transition_type() != burn_replace {
+            continue;
+        }
+        if let Some(assignments) = transition.owned_rights_by_type(assets) {
+            supply.replaced += (0..assignments.len() as u16)
+                .filter_map(|index| revealed_value(assignments, index))
+                .sum::<AtomicValue>();
+        }
+    }
+    supply
+}
+
+/// Remaining inflation cap assigned to the known inflation seals, which are
+/// not closed yet
+pub fn inflation_cap(consignment: &Consignment) -> AtomicValue {
+    known_seals(consignment, OwnedRightsType::Inflation)
+        .iter()
+        .filter_map(|seal| seal.value)
+        .fold(0, AtomicValue::saturating_add)
+}
+
+/// Asset nomination: ticker, name, contract text and decimal precision
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Nomination {
+    pub ticker: String,
+    pub name: String,
+    pub contract: Option<String>,
+    pub precision: u8,
+}
+
+impl Nomination {
+    /// Number of atomic units in a single unit of the asset
+    pub fn divisor(&self) -> u64 {
+        10_u64.pow(self.precision as u32)
+    }
+}
+
+/// Returns current nomination of the asset: the genesis nomination updated
+/// by the chain of the known renomination transitions. Fields which are not
+/// given by a renomination keep their previous values.
+pub fn current_nomination(consignment: &Consignment) -> Nomination {
+    let genesis = consignment.genesis.metadata();
+    let mut nomination = Nomination {
+        ticker: metadata_string(genesis, FieldType::Ticker).unwrap_or_default(),
+        name: metadata_string(genesis, FieldType::Name).unwrap_or_default(),
+        contract: metadata_string(genesis, FieldType::ContractText),
+        precision: metadata_u8(genesis, FieldType::Precision)
+            .unwrap_or_default(),
+    };
+
+    // Each renomination closes the renomination seal defined by the
+    // previous one, starting with the genesis
+    let renomination: schema::TransitionType =
+        TransitionType::Renomination.into();
+    let right: OwnedRightType = OwnedRightsType::Renomination.into();
+    let mut node_id = consignment.genesis.node_id();
+    while let Some(transition) = consignment
+        .state_transitions
+        .iter()
+        .map(|(_, transition)| transition)
+        .find(|transition| {
+            transition.transition_type() == renomination
+                && transition
+                    .parent_owned_rights()
+                    .get(&node_id)
+                    .map_or(false, |rights| rights.contains_key(&right))
+        })
+    {
+        let metadata = transition.metadata();
+        if let Some(ticker) = metadata_string(metadata, FieldType::Ticker) {
+            nomination.ticker = ticker;
+        }
+        if let Some(name) = metadata_string(metadata, FieldType::Name) {
+            nomination.name = name;
+        }
+        if let Some(contract) =
+            metadata_string(metadata, FieldType::ContractText)
+        {
+            nomination.contract = Some(contract);
+        }
+        if let Some(precision) = metadata_u8(metadata, FieldType::Precision) {
+            nomination.precision = precision;
+        }
+        node_id = transition.node_id();
+    }
+    nomination
+}
+
+/// Returns outputs with the asset allocations burned by the known burn and
+/// burn & replace transitions
+pub fn burned_outpoints(consignment: &Consignment) -> HashSet<OutPoint> {
+    let field_type: schema::FieldType = FieldType::BurnUtxo.into();
+    consignment
+        .state_transitions
+        .iter()
+        .filter_map(|(_, transition)| {
+            transition.metadata().as_inner().get(&field_type)
+        })
+        .flatten()
+        .filter_map(|data| match data {
+            data::Revealed::TxOutPoint(outpoint) => Some(*outpoint),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Sums all 64-bit values of the metadata field
+fn metadata_u64(metadata: &Metadata, field: FieldType) -> AtomicValue {
+    let field_type: schema::FieldType = field.into();
+    metadata
+        .as_inner()
+        .get(&field_type)
+        .into_iter()
+        .flatten()
+        .filter_map(|data| match data {
+            data::Revealed::U64(value) => Some(*value),
+            _ => None,
+        })
+        .sum()
+}
+
+/// Returns the first string value of the metadata field
+fn metadata_string(metadata: &Metadata, field: FieldType) -> Option<String> {
+    let field_type: schema::FieldType = field.into();
+    metadata
+        .as_inner()
+        .get(&field_type)?
+        .iter()
+        .find_map(|data| match data {
+            data::Revealed::AsciiString(value) => Some(value.clone()),
+            _ => None,
+        })
+}
+
+/// Returns the first 8-bit value of the metadata field
+fn metadata_u8(metadata: &Metadata, field: FieldType) -> Option<u8> {
+    let field_type: schema::FieldType = field.into();
+    metadata
+        .as_inner()
+        .get(&field_type)?
+        .iter()
+        .find_map(|data| match data {
+            data::Revealed::U8(value) => Some(*value),
+            _ => None,
+        })
+}
+
+/// Returns value assigned to the seal with the given index, if the
+/// assignments have value state and the value is not concealed
+fn revealed_value(
+    assignments: &Assignments,
+    index: u16,
+) -> Option<AtomicValue> {
+    revealed_state(assignments, index).map(|state| state.value)
+}
+
+/// Returns value together with its blinding factor assigned to the seal
+/// with the given index
+fn revealed_state(
+    assignments: &Assignments,
+    index: u16,
+) -> Option<&value::Revealed> {
+    match assignments {
+        Assignments::DiscreteFiniteField(states) => {
+            match states.get(index as usize)? {
+                OwnedState::Revealed { assigned_state, .. }
+                | OwnedState::ConfidentialSeal { assigned_state, .. } => {
+                    Some(assigned_state)
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Returns revealed asset value assigned to the seal, which is required
+/// for spending the seal by a state transition
+pub fn allocation_state(
+    consignment: &Consignment,
+    seal: &AssetSeal,
+) -> Option<value::Revealed> {
+    let assets: OwnedRightType = OwnedRightsType::Assets.into();
+    let assignments = if consignment.genesis.node_id() == seal.node_id {
+        consignment.genesis.owned_rights_by_type(assets)
+    } else {
+        consignment
+            .state_transitions
+            .iter()
+            .map(|(_, transition)| transition)
+            .find(|transition| transition.node_id() == seal.node_id)?
+            .owned_rights_by_type(assets)
+    }?;
+    revealed_state(assignments, seal.index).cloned()
+}
+
+/// Resolves seal definition into a transaction output. Seals pointing to
+/// outputs of the witness transaction can be resolved only for the state
+/// transitions, which are anchored to that transaction.
+pub fn seal_outpoint(
+    seal: seal::Revealed,
+    witness_txid: Option<Txid>,
+) -> Option<OutPoint> {
+    match seal {
+        seal::Revealed::TxOutpoint(reveal) => {
+            Some(OutPoint::new(reveal.txid, reveal.vout))
+        }
+        seal::Revealed::WitnessVout { vout, .. } => {
+            witness_txid.map(|txid| OutPoint::new(txid, vout))
+        }
+    }
+}
+
+/// Reveals blinded seals of the state transitions which were defined by
+/// the document invoices, so the received allocations become known.
+/// Returns number of revealed seals.
+pub fn reveal_seals(
+    consignment: &mut Consignment,
+    known: &[OutpointReveal],
+) -> usize {
+    let known = known
+        .iter()
+        .map(|reveal| seal::Revealed::TxOutpoint(*reveal))
+        .collect::<Vec<_>>();
+    consignment
+        .state_transitions
+        .iter_mut()
+        .map(|(_, transition)| {
+            transition
+                .owned_rights_mut()
+                .values_mut()
+                .map(|assignments| assignments.reveal_seals(&known))
+                .sum::<usize>()
+        })
+        .sum()
+}
+
+/// Adds state transitions, state extensions and endpoints of another
+/// consignment for the same contract to the known one, skipping already
+/// known items. Returns number of new contract nodes.
+pub fn merge_consignment(known: &mut Consignment, other: Consignment) -> usize {
+    let mut known_nodes = known
+        .state_transitions
+        .iter()
+        .map(|(_, transition)| transition.node_id())
+        .chain(
+            known
+                .state_extensions
+                .iter()
+                .map(|extension| extension.node_id()),
+        )
+        .collect::<HashSet<_>>();
+
+    let mut count = 0usize;
+    for (anchor, transition) in other.state_transitions {
+        if known_nodes.insert(transition.node_id()) {
+            known.state_transitions.push((anchor, transition));
+            count += 1;
+        }
+    }
+    for extension in other.state_extensions {
+        if known_nodes.insert(extension.node_id()) {
+            known.state_extensions.push(extension);
+            count += 1;
+        }
+    }
+    for endpoint in other.endpoints {
+        if !known.endpoints.contains(&endpoint) {
+            known.endpoints.push(endpoint);
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::controller::asset_ops;
+    use crate::controller::fee_bump::Wallet;
+    use crate::model::fixtures::{self, outpoint};
+    use crate::model::{DescriptorAccount, UtxoEntry};
+
+    fn utxos(outpoints: &[OutPoint]) -> HashMap<OutPoint, UtxoEntry> {
+        outpoints
+            .iter()
+            .map(|outpoint| (*outpoint, fixtures::utxo(*outpoint, 0, 10_000)))
+            .collect()
+    }
+
+    fn wallet<'a>(
+        descriptors: &'a [DescriptorAccount],
+        utxos: &'a HashMap<OutPoint, UtxoEntry>,
+    ) -> Wallet<'a> {
+        Wallet {
+            descriptors,
+            ..Wallet::with_utxos(utxos)
+        }
+    }
+
+    #[test]
+    fn genesis_state() {
+        let consignment = fixtures::asset();
+        let node_id = consignment.genesis.node_id();
+        let seal = |outpoint, value| AssetSeal {
+            node_id,
+            index: 0,
+            outpoint,
+            value,
+        };
+        assert_eq!(
+            known_seals(&consignment, OwnedRightsType::Assets),
+            vec![seal(outpoint(0), Some(1000))]
+        );
+        assert_eq!(
+            known_seals(&consignment, OwnedRightsType::Inflation),
+            vec![seal(outpoint(1), Some(500))]
+        );
+        assert_eq!(
+            known_seals(&consignment, OwnedRightsType::Renomination),
+            vec![seal(outpoint(3), None)]
+        );
+        assert_eq!(
+            known_seals(&consignment, OwnedRightsType::OpenEpoch),
+            vec![seal(outpoint(4), None)]
+        );
+        assert!(
+            known_seals(&consignment, OwnedRightsType::BurnReplace).is_empty()
+        );
+
+        assert_eq!(
+            known_supply(&consignment),
+            AssetSupply {
+                issued: 1000,
+                burned: 0,
+                replaced: 0,
+            }
+        );
+        assert_eq!(inflation_cap(&consignment), 500);
+        assert!(burned_outpoints(&consignment).is_empty());
+        assert_eq!(
+            current_nomination(&consignment),
+            Nomination {
+                ticker: s!("TST"),
+                name: s!("Test asset"),
+                contract: None,
+                precision: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn secondary_issue() {
+        let descriptors = [fixtures::descriptor_account()];
+        let utxos = utxos(&[outpoint(1)]);
+        let operation = asset_ops::inflate(
+            &wallet(&descriptors, &utxos),
+            &fixtures::asset(),
+            &[(outpoint(2), 200)],
+            1.0,
+        )
+        .unwrap();
+        let consignment = &operation.consignment;
+        let node_id = operation.transition.node_id();
+
+        let assets = known_seals(consignment, OwnedRightsType::Assets);
+        assert_eq!(assets.len(), 2);
+        assert!(assets.iter().any(
+            |seal| seal.outpoint == outpoint(0) && seal.value == Some(1000)
+        ));
+        assert!(assets.iter().any(|seal| seal.node_id == node_id
+            && seal.outpoint == outpoint(2)
+            && seal.value == Some(200)));
+
+        // Genesis inflation seal is closed, the rest of the cap is assigned
+        // to the witness transaction output
+        let inflation = known_seals(consignment, OwnedRightsType::Inflation);
+        assert_eq!(inflation.len(), 1);
+        assert_eq!(inflation[0].node_id, node_id);
+        assert_eq!(
+            inflation[0].outpoint.txid,
+            operation.psbt.global.unsigned_tx.txid()
+        );
+        assert_eq!(inflation_cap(consignment), 300);
+        assert_eq!(known_supply(consignment).issued, 1200);
+    }
+
+    #[test]
+    fn burn_and_replace() {
+        let descriptors = [fixtures::descriptor_account()];
+        let utxos = utxos(&[outpoint(4)]);
+        let epoch = asset_ops::open_epoch(
+            &wallet(&descriptors, &utxos),
+            &fixtures::asset(),
+            outpoint(6),
+            1.0,
+        )
+        .unwrap();
+        let burn_seals =
+            known_seals(&epoch.consignment, OwnedRightsType::BurnReplace);
+        assert_eq!(burn_seals.len(), 1);
+
+        let utxos = self::utxos(&[burn_seals[0].outpoint]);
+        let operation = asset_ops::burn(
+            &wallet(&descriptors, &utxos),
+            &epoch.consignment,
+            &[outpoint(0)],
+            &[(outpoint(5), 400)],
+            1.0,
+        )
+        .unwrap();
+        let consignment = &operation.consignment;
+        assert_eq!(burned_outpoints(consignment), set![outpoint(0)]);
+        let supply = known_supply(consignment);
+        assert_eq!(
+            supply,
+            AssetSupply {
+                issued: 1000,
+                burned: 1000,
+                replaced: 400,
+            }
+        );
+        assert_eq!(supply.circulating(), 400);
+    }
+
+    #[test]
+    fn renomination_fields() {
+        let descriptors = [fixtures::descriptor_account()];
+        let utxos = utxos(&[outpoint(3)]);
+        let nomination = Nomination {
+            ticker: s!("NEW"),
+            name: s!("Renamed asset"),
+            contract: Some(s!("Contract text")),
+            precision: 2,
+        };
+        let first = asset_ops::renominate(
+            &wallet(&descriptors, &utxos),
+            &fixtures::asset(),
+            nomination.clone(),
+            1.0,
+        )
+        .unwrap();
+        assert_eq!(current_nomination(&first.consignment), nomination);
+
+        // Contract text is not changed when it is not given
+        let seal =
+            known_seals(&first.consignment, OwnedRightsType::Renomination)[0]
+                .outpoint;
+        let utxos = self::utxos(&[seal]);
+        let second = asset_ops::renominate(
+            &wallet(&descriptors, &utxos),
+            &first.consignment,
+            Nomination {
+                ticker: s!("LAST"),
+                contract: None,
+                ..nomination.clone()
+            },
+            1.0,
+        )
+        .unwrap();
+        assert_eq!(
+            current_nomination(&second.consignment),
+            Nomination {
+                ticker: s!("LAST"),
+                ..nomination
+            }
+        );
+    }
+
+    #[test]
+    fn received_allocations() {
+        let descriptors = [fixtures::descriptor_account()];
+        let utxos = utxos(&[outpoint(0)]);
+        let reveal = OutpointReveal::from(outpoint(7));
+        let (_, mut received) = asset_ops::transfer(
+            &wallet(&descriptors, &utxos),
+            &fixtures::asset(),
+            reveal.outpoint_hash(),
+            400,
+            &fixtures::utxo(outpoint(5), 0, 10_000),
+            1.0,
+        )
+        .unwrap();
+        let allocation = |consignment: &Consignment| {
+            known_seals(consignment, OwnedRightsType::Assets)
+                .into_iter()
+                .find(|seal| seal.outpoint == outpoint(7))
+        };
+        assert_eq!(allocation(&received), None);
+
+        let other = OutpointReveal::from(outpoint(8));
+        assert_eq!(reveal_seals(&mut received, &[other]), 0);
+        assert_eq!(reveal_seals(&mut received, &[other, reveal]), 1);
+        let seal = allocation(&received).unwrap();
+        assert_eq!(seal.value, Some(400));
+        assert_eq!(
+            allocation_state(&received, &seal).map(|state| state.value),
+            Some(400)
+        );
+        assert_eq!(reveal_seals(&mut received, &[reveal]), 0);
+    }
+
+    #[test]
+    fn merge() {
+        let descriptors = [fixtures::descriptor_account()];
+        let utxos = utxos(&[outpoint(0)]);
+        let genesis = fixtures::asset();
+        let beneficiary = OutpointReveal::from(outpoint(7)).outpoint_hash();
+        let (_, outgoing) = asset_ops::transfer(
+            &wallet(&descriptors, &utxos),
+            &genesis,
+            beneficiary,
+            400,
+            &fixtures::utxo(outpoint(5), 0, 10_000),
+            1.0,
+        )
+        .unwrap();
+
+        let mut known = genesis;
+        assert_eq!(merge_consignment(&mut known, outgoing.clone()), 1);
+        assert_eq!(known.state_transitions, outgoing.state_transitions);
+        assert_eq!(known.endpoints, outgoing.endpoints);
+
+        // Merging the same data again adds nothing
+        assert_eq!(merge_consignment(&mut known, outgoing), 0);
+        assert_eq!(known.state_transitions.len(), 1);
+        assert_eq!(known.endpoints.len(), 1);
+    }
+}
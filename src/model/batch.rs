@@ -0,0 +1,195 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use std::ops::Range;
+
+use bitcoin::util::bip32::{self, ExtendedPrivKey, ExtendedPubKey};
+use bitcoin::Network;
+use wallet::bip32::{DerivationComponents, HardenedNormalSplit};
+use wallet::descriptor;
+
+use super::{DerivationPreset, DerivationScheme, TrackingAccount};
+
+/// Parameters for creating multiple tracking accounts from a single master
+/// private key.
+///
+/// The master key is used only during [`AccountBatch::derive`]; produced
+/// tracking accounts contain public data only, with the master key
+/// fingerprint kept as the key origin.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct AccountBatch {
+    /// Prefix for the names of the created accounts
+    pub name: String,
+
+    pub schemes: Vec<DerivationScheme>,
+
+    /// Account numbers to create for each of the schemes. For BIP45 these
+    /// are used as cosigner indexes.
+    pub accounts: Range<u32>,
+
+    /// Whether to create an additional tracking account for the change
+    /// branch of each of the accounts
+    pub change: bool,
+}
+
+impl AccountBatch {
+    /// Derivation presets for all tracking accounts in the batch, ordered by
+    /// scheme, then by account number and then by branch
+    pub fn presets(&self, network: Network) -> Vec<DerivationPreset> {
+        let branches: &[u32] = if self.change { &[0, 1] } else { &[0] };
+        let mut presets = vec![];
+        for scheme in &self.schemes {
+            for account in self.accounts.clone() {
+                for change in branches {
+                    presets.push(DerivationPreset {
+                        change: Some(*change),
+                        ..DerivationPreset::with(*scheme, network, account)
+                    });
+                }
+            }
+        }
+        presets
+    }
+
+    pub fn account_name(&self, preset: &DerivationPreset) -> String {
+        let name = format!(
+            "{} {} #{}",
+            self.name,
+            preset.scheme.to_string().to_uppercase(),
+            preset.account
+        );
+        match preset.change {
+            Some(1) => format!("{} change", name),
+            _ => name,
+        }
+    }
+
+    pub fn derive(
+        &self,
+        master: &ExtendedPrivKey,
+    ) -> Result<Vec<TrackingAccount>, bip32::Error> {
+        let master_xpub =
+            ExtendedPubKey::from_private(&wallet::SECP256K1, master);
+        self.presets(master.network)
+            .into_iter()
+            .map(|preset| {
+                let (branch_path, terminal_path) =
+                    preset.derivation_path().hardened_normal_split();
+                let branch_xpriv =
+                    master.derive_priv(&wallet::SECP256K1, &branch_path)?;
+                let branch_xpub = ExtendedPubKey::from_private(
+                    &wallet::SECP256K1,
+                    &branch_xpriv,
+                );
+                Ok(TrackingAccount {
+                    name: self.account_name(&preset),
+                    key: descriptor::SingleSig::XPubDerivable(
+                        DerivationComponents {
+                            master_xpub,
+                            branch_path,
+                            branch_xpub,
+                            terminal_path,
+                            index_ranges: None,
+                        },
+                    ),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::model::Bip48ScriptType;
+
+    // Master key for "abandon abandon ... about" mnemonic without passphrase
+    const XPRV: &str = "xprv9s21ZrQH143K3GJpoapnV8SFfukcVBSfeCficPSGfubmSFDxo1kuHnLisriDvSnRRuL2Qrg5ggqHKNVpxR86QEC8w35uxmGoggxtQTPvfUu";
+
+    fn components(account: &TrackingAccount) -> &DerivationComponents {
+        match &account.key {
+            descriptor::SingleSig::XPubDerivable(components) => components,
+            _ => panic!("batch must produce extended keys"),
+        }
+    }
+
+    #[test]
+    fn batch_derivation() {
+        let master = ExtendedPrivKey::from_str(XPRV).unwrap();
+        let batch = AccountBatch {
+            name: s!("Test"),
+            schemes: vec![
+                DerivationScheme::Bip44,
+                DerivationScheme::Bip84,
+                DerivationScheme::Bip48(Bip48ScriptType::SegWit),
+            ],
+            accounts: 0..2,
+            change: true,
+        };
+        let accounts = batch.derive(&master).unwrap();
+        assert_eq!(accounts.len(), 12);
+
+        for account in &accounts {
+            let components = components(account);
+            assert_eq!(
+                components.master_xpub.fingerprint().to_string(),
+                "73c5da0a"
+            );
+            assert!(!account.details().contains("prv"));
+        }
+
+        assert_eq!(accounts[0].name, "Test BIP44 #0");
+        assert_eq!(
+            components(&accounts[0]).branch_xpub.to_string(),
+            "xpub6BosfCnifzxcFwrSzQiqu2DBVTshkCXacvNsWGYJVVhhawA7d4R5WSWGFNbi8Aw6ZRc1brxMyWMzG3DSSSSoekkudhUd9yLb6qx39T9nMdj"
+        );
+        assert_eq!(components(&accounts[0]).terminal_path, vec![0]);
+
+        assert_eq!(accounts[5].name, "Test BIP84 #0 change");
+        assert_eq!(components(&accounts[5]).terminal_path, vec![1]);
+        assert_eq!(
+            components(&accounts[4]).branch_xpub.to_string(),
+            "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V"
+        );
+        assert_eq!(
+            components(&accounts[6]).branch_xpub.to_string(),
+            "xpub6CatWdiZiodmYVtWLtEQsAg1H9ooS1bmsJUBwQ83FE1Fyk386FWcyicJgEZv3quZSJKA5dh5Lo2PbubMGxCfZtRthV6ST2qquL9w3HSzcUn"
+        );
+        assert_eq!(
+            components(&accounts[8]).branch_xpub.to_string(),
+            "xpub6DkFAXWQ2dHxq2vatrt9qyA3bXYU4ToWQwCHbf5XB2mSTexcHZCeKS1VZYcPoBd5X8yVcbXFHJR9R8UCVpt82VX1VhR28mCyxUFL4r6KFrf"
+        );
+    }
+
+    #[test]
+    fn batch_bip45_cosigner() {
+        let master = ExtendedPrivKey::from_str(XPRV).unwrap();
+        let batch = AccountBatch {
+            name: s!("Multisig"),
+            schemes: vec![DerivationScheme::Bip45],
+            accounts: 3..4,
+            change: false,
+        };
+        let accounts = batch.derive(&master).unwrap();
+        assert_eq!(accounts.len(), 1);
+        let components = components(&accounts[0]);
+        assert_eq!(components.branch_path.to_string(), "m/45'");
+        assert_eq!(components.terminal_path, vec![3, 0]);
+    }
+}
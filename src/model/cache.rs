@@ -0,0 +1,278 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeMap, HashMap};
+
+use bitcoin::Script;
+use wallet::bip32::UnhardenedIndex;
+use wallet::descriptor;
+
+use super::DescriptorAccount;
+
+/// Derived scripts for a single descriptor account, indexed both by the
+/// derivation index and by the script itself
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ScriptIndex {
+    variants: descriptor::Variants,
+    scripts: BTreeMap<u32, HashMap<descriptor::Category, Script>>,
+    reverse: HashMap<Script, (u32, descriptor::Category)>,
+}
+
+impl ScriptIndex {
+    pub fn with(variants: descriptor::Variants) -> ScriptIndex {
+        ScriptIndex {
+            variants,
+            scripts: bmap! {},
+            reverse: empty!(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.scripts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scripts.is_empty()
+    }
+
+    pub fn scripts(
+        &self,
+        index: u32,
+    ) -> Option<&HashMap<descriptor::Category, Script>> {
+        self.scripts.get(&index)
+    }
+
+    pub fn script_index(
+        &self,
+        script: &Script,
+    ) -> Option<(u32, descriptor::Category)> {
+        self.reverse.get(script).copied()
+    }
+
+    fn insert(
+        &mut self,
+        index: u32,
+        scripts: HashMap<descriptor::Category, Script>,
+    ) {
+        for (category, script) in &scripts {
+            self.reverse.insert(script.clone(), (index, *category));
+        }
+        self.scripts.insert(index, scripts);
+    }
+}
+
+/// Cache of derived public key scripts for all descriptor accounts of a
+/// document.
+///
+/// The cache is not persisted with the document and is filled lazily during
+/// UTXO lookup rounds, so each key is derived only once per session.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct DerivationCache {
+    accounts: HashMap<descriptor::Template, ScriptIndex>,
+}
+
+impl DerivationCache {
+    pub fn new() -> DerivationCache {
+        DerivationCache::default()
+    }
+
+    pub fn pubkey_scripts(
+        &mut self,
+        account: &DescriptorAccount,
+        index: UnhardenedIndex,
+    ) -> Result<HashMap<descriptor::Category, Script>, descriptor::Error> {
+        let variants = account.generator.variants;
+        let script_index = self
+            .accounts
+            .entry(account.generator.template.clone())
+            .or_insert_with(|| ScriptIndex::with(variants));
+        if script_index.variants != variants {
+            *script_index = ScriptIndex::with(variants);
+        }
+
+        let offset = u32::from(index);
+        if let Some(scripts) = script_index.scripts(offset) {
+            return Ok(scripts.clone());
+        }
+        let scripts = account.pubkey_scripts(index)?;
+        script_index.insert(offset, scripts.clone());
+        Ok(scripts)
+    }
+
+    pub fn script_index(
+        &self,
+        account: &DescriptorAccount,
+        script: &Script,
+    ) -> Option<(u32, descriptor::Category)> {
+        self.accounts
+            .get(&account.generator.template)
+            .and_then(|script_index| script_index.script_index(script))
+    }
+
+    pub fn account_index(
+        &self,
+        account: &DescriptorAccount,
+    ) -> Option<&ScriptIndex> {
+        self.accounts.get(&account.generator.template)
+    }
+
+    pub fn invalidate(&mut self, account: &DescriptorAccount) {
+        self.accounts.remove(&account.generator.template);
+    }
+
+    pub fn clear(&mut self) {
+        self.accounts.clear()
+    }
+}
+
+#[cfg(all(test, feature = "bench"))]
+mod bench {
+    use std::collections::HashSet;
+    use std::str::FromStr;
+
+    use bitcoin::OutPoint;
+    use lnpbp::Chain;
+    use rgb::Consignment;
+    use test::Bencher;
+    use wallet::bip32::{ChildIndex, DerivationComponents, UnhardenedIndex};
+    use wallet::descriptor;
+
+    use super::DerivationCache;
+    use crate::model::fixtures::{self, numbered_outpoint};
+    use crate::model::{DescriptorAccount, Document, UtxoEntry};
+
+    const UTXO_COUNT: u32 = 5000;
+    const ALLOCATION_COUNT: u32 = 500;
+    const XPUB: &str = "[xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw]/0/*";
+
+    fn outpoint(no: u32) -> OutPoint {
+        numbered_outpoint(no, no % 4)
+    }
+
+    fn utxo_set() -> HashSet<UtxoEntry> {
+        (0..UTXO_COUNT)
+            .map(|no| UtxoEntry {
+                derivation_index: no,
+                ..fixtures::utxo(outpoint(no), no, 1000 + no as u64)
+            })
+            .collect()
+    }
+
+    fn document() -> Document {
+        let mut doc = Document::new();
+        doc.update_utxo_set(utxo_set()).unwrap();
+        doc
+    }
+
+    fn account() -> DescriptorAccount {
+        DescriptorAccount {
+            name: s!("Bench"),
+            generator: descriptor::Generator {
+                template: descriptor::Template::SingleSig(
+                    descriptor::SingleSig::XPubDerivable(
+                        DerivationComponents::from_str(XPUB).unwrap(),
+                    ),
+                ),
+                variants: descriptor::Variants {
+                    bare: false,
+                    hashed: true,
+                    nested: true,
+                    segwit: true,
+                    taproot: false,
+                },
+            },
+        }
+    }
+
+    #[bench]
+    fn utxo_by_outpoint_linear_scan(b: &mut Bencher) {
+        let utxo_set = utxo_set();
+        b.iter(|| {
+            (0..UTXO_COUNT).step_by(50).for_each(|no| {
+                let outpoint = outpoint(no);
+                assert!(utxo_set.iter().any(|utxo| utxo.outpoint == outpoint));
+            })
+        });
+    }
+
+    #[bench]
+    fn utxo_by_outpoint_indexed(b: &mut Bencher) {
+        let doc = document();
+        b.iter(|| {
+            (0..UTXO_COUNT).step_by(50).for_each(|no| {
+                assert!(doc.utxo_by_outpoint(outpoint(no)).is_some());
+            })
+        });
+    }
+
+    #[bench]
+    fn asset_balance(b: &mut Bencher) {
+        // Half of the allocations are assigned to the document UTXOs
+        let (_, genesis) = rgb20::issue(
+            Chain::Testnet3,
+            s!("BNCH"),
+            s!("Bench asset"),
+            None,
+            0,
+            (UTXO_COUNT - ALLOCATION_COUNT..UTXO_COUNT + ALLOCATION_COUNT)
+                .map(|no| (outpoint(no), 1))
+                .collect(),
+            bmap! {},
+            None,
+            None,
+        )
+        .unwrap();
+        let contract_id = genesis.contract_id();
+        let mut doc = document();
+        doc.add_asset(Consignment::with(genesis, none!(), none!(), none!()))
+            .unwrap();
+        b.iter(|| {
+            assert_eq!(
+                doc.asset_balance(contract_id),
+                Some(ALLOCATION_COUNT as u64)
+            )
+        });
+    }
+
+    #[bench]
+    fn pubkey_scripts_uncached(b: &mut Bencher) {
+        let account = account();
+        b.iter(|| {
+            (0..100u32).for_each(|no| {
+                account
+                    .pubkey_scripts(UnhardenedIndex::from_index(no).unwrap())
+                    .unwrap();
+            })
+        });
+    }
+
+    #[bench]
+    fn pubkey_scripts_cached(b: &mut Bencher) {
+        let account = account();
+        let mut cache = DerivationCache::new();
+        b.iter(|| {
+            (0..100u32).for_each(|no| {
+                cache
+                    .pubkey_scripts(
+                        &account,
+                        UnhardenedIndex::from_index(no).unwrap(),
+                    )
+                    .unwrap();
+            })
+        });
+    }
+}
@@ -0,0 +1,527 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Export of descriptor accounts as Bitcoin Core `importdescriptors` RPC
+//! payload
+
+use serde_json::{json, Value};
+use wallet::descriptor;
+
+use super::DescriptorAccount;
+use crate::util::desc_checksum;
+use crate::util::index_space::IndexSpace;
+
+/// Last index of the range exported for descriptors without index
+/// restrictions, matching Bitcoin Core default keypool size
+pub const DEFAULT_RANGE_END: u32 = 999;
+
+/// Maximum number of indexes Bitcoin Core accepts for a single descriptor
+pub const MAX_RANGE_SIZE: u32 = 1_000_000;
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// Only single-key and multi-signature descriptors can be exported to
+    /// Bitcoin Core
+    UnsupportedTemplate,
+
+    /// Descriptor has no script variants enabled
+    NoVariants,
+
+    /// Multi-signature descriptors can't be exported with taproot variant;
+    /// disable it for the descriptor before the export
+    TaprootMultisig,
+
+    /// Uncompressed public keys can't be used in SegWit and taproot
+    /// scripts; disable these variants for the descriptor before the export
+    UncompressedKey,
+
+    /// {0}
+    #[from]
+    Checksum(desc_checksum::Error),
+}
+
+/// Time from which Bitcoin Core has to rescan blockchain for the imported
+/// descriptors
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+pub enum Timestamp {
+    /// No rescan: only new transactions will be tracked
+    #[display("now")]
+    Now,
+
+    /// UNIX timestamp of the earliest key usage; zero rescans the whole
+    /// blockchain
+    #[display("{0}")]
+    Time(u64),
+}
+
+/// Single request of `importdescriptors` RPC call
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CoreDescriptor {
+    /// Descriptor with checksum
+    pub desc: String,
+    pub timestamp: Timestamp,
+    pub active: bool,
+    pub internal: bool,
+    /// Inclusive derivation index range for ranged descriptors
+    pub range: Option<(u32, u32)>,
+}
+
+impl CoreDescriptor {
+    pub fn to_json(&self) -> Value {
+        let mut json = json!({
+            "desc": self.desc,
+            "timestamp": match self.timestamp {
+                Timestamp::Now => json!("now"),
+                Timestamp::Time(time) => json!(time),
+            },
+            "active": self.active,
+            "internal": self.internal,
+        });
+        if let Some((start, end)) = self.range {
+            json["range"] = json!([start, end]);
+        }
+        json
+    }
+}
+
+impl DescriptorAccount {
+    /// Constructs `importdescriptors` requests for all enabled script
+    /// variants of the account.
+    ///
+    /// Accounts which keys derive receive addresses (terminal path ending
+    /// with 0) are exported with an additional change descriptor. Bitcoin
+    /// Core supports only a single index range per descriptor, so accounts
+    /// with several index ranges are exported with the range covering all
+    /// of them.
+    pub fn core_descriptors(
+        &self,
+        timestamp: Timestamp,
+    ) -> Result<Vec<CoreDescriptor>, Error> {
+        let keys = match &self.generator.template {
+            descriptor::Template::SingleSig(key) => vec![key],
+            descriptor::Template::MultiSig(multisig) => {
+                multisig.pubkeys.iter().collect()
+            }
+            _ => return Err(Error::UnsupportedTemplate),
+        };
+        let terminal_ends = keys
+            .iter()
+            .filter_map(|key| match key {
+                descriptor::SingleSig::XPubDerivable(components) => {
+                    Some(components.terminal_path.last().copied())
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let ranged = !terminal_ends.is_empty();
+        // Pairs of flags whether to switch keys to change branch and whether
+        // the descriptor is internal
+        let branches = if ranged && terminal_ends.iter().all(|t| *t == Some(0))
+        {
+            vec![(false, false), (true, true)]
+        } else {
+            vec![(false, ranged && terminal_ends.iter().all(|t| *t == Some(1)))]
+        };
+        let range = if ranged {
+            Some(self.core_range())
+        } else {
+            None
+        };
+
+        let variants = &self.generator.variants;
+        let scripts = [
+            (variants.bare, Script::Bare),
+            (variants.hashed, Script::Hashed),
+            (variants.nested, Script::Nested),
+            (variants.segwit, Script::SegWit),
+            (variants.taproot, Script::Taproot),
+        ];
+        let mut descriptors = vec![];
+        for (_, script) in scripts.iter().filter(|(enabled, _)| *enabled) {
+            for (change, internal) in &branches {
+                let desc = self.core_descriptor(*script, *change)?;
+                descriptors.push(CoreDescriptor {
+                    desc: desc_checksum::with_checksum(&desc)?,
+                    timestamp,
+                    // Bare scripts have no addresses and can't be used for
+                    // generating new ones
+                    active: ranged && *script != Script::Bare,
+                    internal: *internal,
+                    range,
+                });
+            }
+        }
+        if descriptors.is_empty() {
+            return Err(Error::NoVariants);
+        }
+        Ok(descriptors)
+    }
+
+    /// Exports all enabled script variants of the account as a JSON array
+    /// which can be passed to `importdescriptors` RPC call
+    pub fn to_core_import(
+        &self,
+        timestamp: Timestamp,
+    ) -> Result<String, Error> {
+        let requests = self
+            .core_descriptors(timestamp)?
+            .iter()
+            .map(CoreDescriptor::to_json)
+            .collect::<Vec<_>>();
+        Ok(serde_json::to_string_pretty(&Value::Array(requests))
+            .expect("JSON values are always serializable"))
+    }
+
+    fn core_range(&self) -> (u32, u32) {
+        let space = self.index_space();
+        if space == IndexSpace::full() {
+            return (0, DEFAULT_RANGE_END);
+        }
+        match (space.ranges().first(), space.ranges().last()) {
+            (Some(first), Some(last)) => {
+                let start = *first.start();
+                (start, (*last.end()).min(start + (MAX_RANGE_SIZE - 1)))
+            }
+            _ => (0, 0),
+        }
+    }
+
+    fn core_descriptor(
+        &self,
+        script: Script,
+        change: bool,
+    ) -> Result<String, Error> {
+        Ok(match &self.generator.template {
+            descriptor::Template::SingleSig(key) => {
+                let key = key_expression(key, change, script)?;
+                match script {
+                    Script::Bare => format!("pk({})", key),
+                    Script::Hashed => format!("pkh({})", key),
+                    Script::Nested => format!("sh(wpkh({}))", key),
+                    Script::SegWit => format!("wpkh({})", key),
+                    Script::Taproot => format!("tr({})", key),
+                }
+            }
+            descriptor::Template::MultiSig(multisig) => {
+                let multi = format!(
+                    "{}({},{})",
+                    if multisig.reorder {
+                        "sortedmulti"
+                    } else {
+                        "multi"
+                    },
+                    multisig.threshold(),
+                    multisig
+                        .pubkeys
+                        .iter()
+                        .map(|key| key_expression(key, change, script))
+                        .collect::<Result<Vec<_>, _>>()?
+                        .join(",")
+                );
+                match script {
+                    Script::Bare => multi,
+                    Script::Hashed => format!("sh({})", multi),
+                    Script::Nested => format!("sh(wsh({}))", multi),
+                    Script::SegWit => format!("wsh({})", multi),
+                    Script::Taproot => return Err(Error::TaprootMultisig),
+                }
+            }
+            _ => return Err(Error::UnsupportedTemplate),
+        })
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Script {
+    Bare,
+    Hashed,
+    Nested,
+    SegWit,
+    Taproot,
+}
+
+/// Key expression with origin, if it is known. For the change branch the
+/// last terminal derivation index is switched from 0 to 1.
+fn key_expression(
+    key: &descriptor::SingleSig,
+    change: bool,
+    script: Script,
+) -> Result<String, Error> {
+    Ok(match key {
+        descriptor::SingleSig::XPubDerivable(components) => {
+            let mut expression = s!("");
+            if components.master_xpub != components.branch_xpub {
+                expression += &format!(
+                    "[{}{}]",
+                    components.master_xpub.fingerprint(),
+                    components.branch_path.to_string().trim_start_matches('m')
+                );
+            }
+            expression += &components.branch_xpub.to_string();
+            let terminal_len = components.terminal_path.len();
+            for (pos, index) in components.terminal_path.iter().enumerate() {
+                let index = if change && pos + 1 == terminal_len {
+                    1
+                } else {
+                    *index
+                };
+                expression += &format!("/{}", index);
+            }
+            expression + "/*"
+        }
+        descriptor::SingleSig::Pubkey(single) => {
+            let mut expression = s!("");
+            if let Some((fingerprint, path)) = &single.origin {
+                expression += &format!(
+                    "[{}{}]",
+                    fingerprint,
+                    path.to_string().trim_start_matches('m')
+                );
+            }
+            if !single.key.compressed
+                && matches!(
+                    script,
+                    Script::Nested | Script::SegWit | Script::Taproot
+                )
+            {
+                return Err(Error::UncompressedKey);
+            }
+            let key = single.key.to_bytes();
+            // Taproot uses x-only public keys
+            let key = if script == Script::Taproot {
+                &key[1..]
+            } else {
+                &key[..]
+            };
+            expression
+                + &key.iter().map(|b| format!("{:02x}", b)).collect::<String>()
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::ops::RangeInclusive;
+    use std::str::FromStr;
+
+    use bitcoin::util::bip32::DerivationPath;
+    use bitcoin::Network;
+    use miniscript::descriptor::DescriptorSinglePub;
+    use wallet::bip32::{DerivationComponents, DerivationRangeVec};
+
+    use super::*;
+    use crate::model::MnemonicSeed;
+
+    fn components(mnemonic: &str, path: &str) -> DerivationComponents {
+        MnemonicSeed::with(mnemonic, "")
+            .unwrap()
+            .derivation_components(
+                Network::Testnet,
+                &DerivationPath::from_str(path).unwrap(),
+                None,
+            )
+            .unwrap()
+    }
+
+    fn variants(nested: bool, segwit: bool) -> descriptor::Variants {
+        descriptor::Variants {
+            bare: false,
+            hashed: false,
+            nested,
+            segwit,
+            taproot: false,
+        }
+    }
+
+    const ABANDON: &str = "abandon abandon abandon abandon abandon abandon \
+                           abandon abandon abandon abandon abandon about";
+    const LEGAL: &str = "legal winner thank year wave sausage worth useful \
+                         legal winner thank yellow";
+
+    #[test]
+    fn single_sig() {
+        let mut components = components(ABANDON, "m/84'/1'/0'/0");
+        let xpub = components.branch_xpub.to_string();
+        components.index_ranges = Some(
+            DerivationRangeVec::try_from(vec![
+                RangeInclusive::new(10, 19).into(),
+                RangeInclusive::new(30, 39).into(),
+            ])
+            .unwrap(),
+        );
+        let account = DescriptorAccount {
+            name: s!("Savings"),
+            generator: descriptor::Generator {
+                template: descriptor::Template::SingleSig(
+                    descriptor::SingleSig::XPubDerivable(components),
+                ),
+                variants: variants(false, true),
+            },
+        };
+        let descriptors = account.core_descriptors(Timestamp::Now).unwrap();
+        assert_eq!(descriptors.len(), 2);
+        assert_eq!(
+            desc_checksum::verify(&descriptors[0].desc).unwrap(),
+            format!("wpkh([73c5da0a/84'/1'/0']{}/0/*)", xpub)
+        );
+        assert_eq!(
+            desc_checksum::verify(&descriptors[1].desc).unwrap(),
+            format!("wpkh([73c5da0a/84'/1'/0']{}/1/*)", xpub)
+        );
+        assert!(descriptors.iter().all(|d| d.active));
+        assert!(!descriptors[0].internal);
+        assert!(descriptors[1].internal);
+        assert_eq!(descriptors[0].range, Some((10, 39)));
+
+        let json: Value = serde_json::from_str(
+            &account.to_core_import(Timestamp::Time(1600000000)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(json[1]["timestamp"], json!(1600000000));
+        assert_eq!(json[1]["internal"], json!(true));
+        assert_eq!(json[1]["range"], json!([10, 39]));
+        assert_eq!(
+            CoreDescriptor {
+                timestamp: Timestamp::Now,
+                range: None,
+                ..descriptors[0].clone()
+            }
+            .to_json()
+            .get("range"),
+            None
+        );
+    }
+
+    #[test]
+    fn multi_sig() {
+        let keys = [ABANDON, LEGAL]
+            .iter()
+            .map(|mnemonic| {
+                descriptor::SingleSig::XPubDerivable(components(
+                    mnemonic,
+                    "m/48'/1'/0'/2'/0",
+                ))
+            })
+            .collect::<Vec<_>>();
+        let mut account = DescriptorAccount {
+            name: s!("Vault"),
+            generator: descriptor::Generator {
+                template: descriptor::Template::MultiSig(
+                    descriptor::MultiSig {
+                        threshold: Some(2),
+                        pubkeys: keys,
+                        reorder: true,
+                    },
+                ),
+                variants: variants(true, true),
+            },
+        };
+        let descriptors = account.core_descriptors(Timestamp::Now).unwrap();
+        assert_eq!(descriptors.len(), 4);
+        assert!(descriptors[0]
+            .desc
+            .starts_with("sh(wsh(sortedmulti(2,[73c5da0a/48'/1'/0'/2']tpub"));
+        assert!(descriptors[3].desc.starts_with("wsh(sortedmulti(2,"));
+        assert_eq!(descriptors[3].desc.matches("/1/*").count(), 2);
+        assert_eq!(descriptors[0].range, Some((0, DEFAULT_RANGE_END)));
+
+        account.generator.variants.taproot = true;
+        assert_eq!(
+            account.core_descriptors(Timestamp::Now),
+            Err(Error::TaprootMultisig)
+        );
+        account.generator.variants = variants(false, false);
+        assert_eq!(
+            account.core_descriptors(Timestamp::Now),
+            Err(Error::NoVariants)
+        );
+    }
+
+    #[test]
+    fn plain_pubkey() {
+        let key = bitcoin::PublicKey::from_str(
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        )
+        .unwrap();
+        let account = DescriptorAccount {
+            name: s!("Cold"),
+            generator: descriptor::Generator {
+                template: descriptor::Template::SingleSig(
+                    descriptor::SingleSig::Pubkey(DescriptorSinglePub {
+                        origin: None,
+                        key,
+                    }),
+                ),
+                variants: descriptor::Variants {
+                    taproot: true,
+                    ..variants(false, true)
+                },
+            },
+        };
+        let descriptors = account.core_descriptors(Timestamp::Now).unwrap();
+        assert_eq!(descriptors.len(), 2);
+        assert_eq!(
+            desc_checksum::verify(&descriptors[0].desc).unwrap(),
+            format!("wpkh({})", key)
+        );
+        assert_eq!(
+            desc_checksum::verify(&descriptors[1].desc).unwrap(),
+            "tr(79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)"
+        );
+        assert!(descriptors
+            .iter()
+            .all(|d| !d.active && !d.internal && d.range.is_none()));
+    }
+
+    #[test]
+    fn uncompressed_pubkey() {
+        let key = bitcoin::PublicKey::from_str(
+            "0479be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798\
+             483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8",
+        )
+        .unwrap();
+        assert!(!key.compressed);
+        let mut account = DescriptorAccount {
+            name: s!("Legacy"),
+            generator: descriptor::Generator {
+                template: descriptor::Template::SingleSig(
+                    descriptor::SingleSig::Pubkey(DescriptorSinglePub {
+                        origin: None,
+                        key,
+                    }),
+                ),
+                variants: descriptor::Variants {
+                    hashed: true,
+                    ..variants(false, false)
+                },
+            },
+        };
+        let descriptors = account.core_descriptors(Timestamp::Now).unwrap();
+        assert_eq!(descriptors.len(), 1);
+        assert_eq!(
+            desc_checksum::verify(&descriptors[0].desc).unwrap(),
+            format!("pkh({})", key)
+        );
+
+        account.generator.variants.segwit = true;
+        assert_eq!(
+            account.core_descriptors(Timestamp::Now),
+            Err(Error::UncompressedKey)
+        );
+    }
+}
@@ -21,6 +21,8 @@ use bitcoin::Script;
 use wallet::bip32::UnhardenedIndex;
 use wallet::descriptor;
 
+use crate::util::index_space::IndexSpace;
+
 #[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
 #[strict_encoding_crate(lnpbp::strict_encoding)]
 pub struct DescriptorAccount {
@@ -57,4 +59,33 @@ impl DescriptorAccount {
     ) -> Result<HashMap<descriptor::Category, Script>, descriptor::Error> {
         self.generator.pubkey_scripts(index)
     }
+
+    /// Derivation indexes which can be used by all keys of the descriptor,
+    /// i.e. intersection of the index ranges defined by each of its keys
+    pub fn index_space(&self) -> IndexSpace {
+        match self.generator.template {
+            descriptor::Template::SingleSig(ref key) => key_index_space(key),
+            descriptor::Template::MultiSig(ref multisig) => multisig
+                .pubkeys
+                .iter()
+                .map(key_index_space)
+                .fold(IndexSpace::full(), |space, key_space| {
+                    space.intersect(&key_space)
+                }),
+            // TODO: Extract keys from custom scripts once script parsing
+            //       will be supported
+            _ => IndexSpace::full(),
+        }
+    }
+}
+
+fn key_index_space(key: &descriptor::SingleSig) -> IndexSpace {
+    match key {
+        descriptor::SingleSig::XPubDerivable(components) => components
+            .index_ranges
+            .as_ref()
+            .map(IndexSpace::from)
+            .unwrap_or_default(),
+        _ => IndexSpace::full(),
+    }
 }
@@ -17,30 +17,46 @@
 
 use gtk::prelude::*;
 use once_cell::sync::Lazy;
-use std::collections::{BTreeMap, HashSet};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::ffi::OsStr;
 use std::fs::{File, OpenOptions};
 use std::io::{self, Seek, SeekFrom};
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::rc::Rc;
 use std::str::FromStr;
 use std::sync::Mutex;
 
-use bitcoin::OutPoint;
-use bitcoin::Transaction;
+use bitcoin::{OutPoint, Transaction, Txid};
 use electrum_client::{Client as ElectrumClient, Error as ElectrumError};
+use lnpbp::bp::blind::OutpointReveal;
 use lnpbp::strict_encoding::{self, StrictDecode, StrictEncode};
 use lnpbp::Chain;
-use rgb::{Consignment, ContractId, Genesis, Schema, SchemaId};
+use rgb::{AtomicValue, Consignment, ContractId, Genesis, Schema, SchemaId};
+use rgb20::schema::{OwnedRightsType, TransitionType};
 use wallet::{descriptor, Psbt};
 
-use super::{operation, DescriptorAccount, TrackingAccount, UtxoEntry};
+use super::{
+    burned_outpoints, current_nomination, known_seals, known_supply,
+    merge_consignment, operation, reveal_seals, DerivationCache,
+    DescriptorAccount, KeyOrigins, NetworkMismatch, Nomination,
+    TrackingAccount, UtxoEntry,
+};
 
 /// Equals to first 4 bytes of SHA256("pandoracore:bpro")
 /// = dbe2b664ee4e81d3a55d53aeba1915c468927c79a03587ddfc5c3aec483028ab
 /// Check with `echo -n "pandoracore:bpro" | shasum -a 256`
 const DOC_MAGIC: u32 = 0xdbe2b664;
+/// Version of the document data layout. Documents of the older versions are
+/// read with the fields added after them set to the default values.
+///
+/// Version 1 adds blinding secrets of the invoice UTXOs; version 2 adds
+/// origins of the imported extended keys; version 3 adds state transitions
+/// pending broadcast of their witness transactions; version 4 adds spent
+/// asset seals.
+const DOC_VERSION: u16 = 4;
 const DOC_NAME: &str = "Untitled";
 static DOC_NO: Lazy<Mutex<u32>> = Lazy::new(|| Mutex::new(0));
 
@@ -76,6 +92,8 @@ pub struct Document {
     name: String,
     file: Option<File>,
     profile: Profile,
+    utxo_index: HashMap<OutPoint, UtxoEntry>,
+    derivation_cache: Rc<RefCell<DerivationCache>>,
 }
 
 impl Document {
@@ -94,6 +112,7 @@ impl Document {
         //       will be updated
         profile.magic = DOC_MAGIC;
         let file = OpenOptions::new().write(true).open(path.clone())?;
+        let utxo_index = utxo_index(&profile.utxo_cache);
         Ok(Document {
             file: Some(file),
             name: path
@@ -105,6 +124,8 @@ impl Document {
                     format!("{}{}", DOC_NAME, *DOC_NO.lock().unwrap())
                 }),
             profile,
+            utxo_index,
+            derivation_cache: none!(),
         })
     }
 
@@ -182,6 +203,10 @@ impl Document {
         });
     }
 
+    pub fn tracking_accounts(&self) -> &[TrackingAccount] {
+        &self.profile.tracking
+    }
+
     pub fn tracking_account_at(&self, pos: usize) -> Option<TrackingAccount> {
         self.profile.tracking.get(pos).cloned()
     }
@@ -197,6 +222,22 @@ impl Document {
             .cloned()
     }
 
+    pub fn key_origins(&self) -> &KeyOrigins {
+        &self.profile.key_origins
+    }
+
+    /// Keeps origins of the extended keys imported without their master
+    /// keys; origins of the already known keys are not changed
+    pub fn add_key_origins(
+        &mut self,
+        key_origins: KeyOrigins,
+    ) -> Result<bool, Error> {
+        for (pubkey, origin) in key_origins {
+            self.profile.key_origins.entry(pubkey).or_insert(origin);
+        }
+        self.save()
+    }
+
     pub fn add_tracking_account(
         &mut self,
         tracking_account: TrackingAccount,
@@ -258,6 +299,17 @@ impl Document {
         }
     }
 
+    /// Checks all tracking accounts against the document chain, returning
+    /// the accounts which keys belong to a different network
+    pub fn audit_tracking_accounts(&self) -> Vec<NetworkMismatch> {
+        let chain = self.chain();
+        self.profile
+            .tracking
+            .iter()
+            .filter_map(|account| account.check_network(chain).err())
+            .collect()
+    }
+
     pub fn fill_descriptor_store(&self, store: &gtk::ListStore) {
         store.clear();
         self.profile
@@ -275,6 +327,10 @@ impl Document {
             });
     }
 
+    pub fn descriptors(&self) -> &[DescriptorAccount] {
+        &self.profile.descriptors
+    }
+
     pub fn descriptor_by_generator(
         &self,
         generator_str: &str,
@@ -310,6 +366,9 @@ impl Document {
         descriptor_generator: &DescriptorAccount,
         new_descriptor_generator: DescriptorAccount,
     ) -> Result<bool, Error> {
+        self.derivation_cache
+            .borrow_mut()
+            .invalidate(descriptor_generator);
         if let Some(descriptor) = self
             .profile
             .descriptors
@@ -325,6 +384,9 @@ impl Document {
         &mut self,
         descriptor_generator: DescriptorAccount,
     ) -> Result<bool, Error> {
+        self.derivation_cache
+            .borrow_mut()
+            .invalidate(&descriptor_generator);
         self.profile
             .descriptors
             .iter()
@@ -361,23 +423,76 @@ impl Document {
         &mut self,
         utxo_set_update: HashSet<UtxoEntry>,
     ) -> Result<bool, Error> {
+        self.utxo_index.extend(
+            utxo_set_update
+                .iter()
+                .map(|utxo| (utxo.outpoint, utxo.clone())),
+        );
         self.profile.utxo_cache.extend(utxo_set_update);
         self.save()
     }
 
+    /// Removes outdated UTXO entries and adds new ones with a single save
+    /// of the document
+    /// Applies UTXO changes reported by the server subscriptions. The
+    /// document file is not written: the changes are saved together with
+    /// the next user edit, and otherwise are received from the server again
+    /// once the document is re-opened.
+    pub fn replace_utxos(
+        &mut self,
+        removed: &[UtxoEntry],
+        added: impl IntoIterator<Item = UtxoEntry>,
+    ) {
+        for utxo in removed {
+            self.remove_cached_utxo(utxo);
+        }
+        for utxo in added {
+            self.utxo_index.insert(utxo.outpoint, utxo.clone());
+            self.profile.utxo_cache.insert(utxo);
+        }
+    }
+
+    pub fn utxos(&self) -> &HashSet<UtxoEntry> {
+        &self.profile.utxo_cache
+    }
+
+    /// Cached UTXOs indexed by their outpoints
+    pub fn utxo_index(&self) -> &HashMap<OutPoint, UtxoEntry> {
+        &self.utxo_index
+    }
+
     pub fn utxo_by_outpoint(&self, outpoint: OutPoint) -> Option<UtxoEntry> {
-        self.profile
-            .utxo_cache
-            .iter()
-            .find(|utxo| utxo.outpoint == outpoint)
-            .cloned()
+        self.utxo_index.get(&outpoint).cloned()
     }
 
     pub fn remove_utxo(&mut self, utxo: UtxoEntry) -> Result<bool, Error> {
-        self.profile.utxo_cache.remove(&utxo);
+        self.remove_cached_utxo(&utxo);
         self.save()
     }
 
+    /// Removes entry from the UTXO cache; if the outpoint index was pointing
+    /// to it, the index is switched to other entry with the same outpoint,
+    /// if any
+    fn remove_cached_utxo(&mut self, utxo: &UtxoEntry) {
+        self.profile.utxo_cache.remove(utxo);
+        if self.utxo_index.get(&utxo.outpoint) != Some(utxo) {
+            return;
+        }
+        match self
+            .profile
+            .utxo_cache
+            .iter()
+            .find(|other| other.outpoint == utxo.outpoint)
+        {
+            Some(other) => {
+                self.utxo_index.insert(utxo.outpoint, other.clone());
+            }
+            None => {
+                self.utxo_index.remove(&utxo.outpoint);
+            }
+        }
+    }
+
     pub fn remove_utxo_by_descriptor(
         &mut self,
         descriptor_generator: DescriptorAccount,
@@ -389,62 +504,184 @@ impl Document {
             .filter(|utxo| !utxo.has_match(&descriptor_generator))
             .cloned()
             .collect();
+        self.utxo_index = utxo_index(&self.profile.utxo_cache);
         self.save()
     }
 
     pub fn is_outpoint_known(&self, outpoint: OutPoint) -> bool {
+        self.utxo_index.contains_key(&outpoint)
+    }
+
+    pub fn derivation_cache(&self) -> Rc<RefCell<DerivationCache>> {
+        self.derivation_cache.clone()
+    }
+
+    pub fn psbts(&self) -> &[Psbt] {
+        &self.profile.psbts
+    }
+
+    pub fn psbt_at(&self, pos: usize) -> Option<Psbt> {
+        self.profile.psbts.get(pos).cloned()
+    }
+
+    pub fn psbt_position(&self, txid: Txid) -> Option<usize> {
         self.profile
-            .utxo_cache
+            .psbts
             .iter()
-            .any(|utxo| utxo.outpoint == outpoint)
+            .position(|psbt| psbt.global.unsigned_tx.txid() == txid)
+    }
+
+    pub fn add_psbt(&mut self, psbt: Psbt) -> Result<bool, Error> {
+        self.profile.psbts.push(psbt);
+        self.save()
+    }
+
+    pub fn update_psbt_at(
+        &mut self,
+        pos: usize,
+        psbt: Psbt,
+    ) -> Result<bool, Error> {
+        *self
+            .profile
+            .psbts
+            .get_mut(pos)
+            .ok_or(Error::WrongPosition(pos))? = psbt;
+        self.save()
+    }
+
+    pub fn remove_psbt_at(&mut self, pos: usize) -> Result<bool, Error> {
+        if pos >= self.profile.psbts.len() {
+            return Err(Error::WrongPosition(pos));
+        }
+        self.profile.psbts.remove(pos);
+        self.save()
+    }
+
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.profile.tx_cache
+    }
+
+    /// Signed transaction finalized from the PSBT. Signatures of non-segwit
+    /// inputs change the txid, so transactions are matched by their txid
+    /// without signatures.
+    pub fn psbt_transaction(&self, psbt: &Psbt) -> Option<&Transaction> {
+        let ntxid = psbt.global.unsigned_tx.ntxid();
+        self.profile.tx_cache.iter().find(|tx| tx.ntxid() == ntxid)
+    }
+
+    /// Adds signed transaction to the transaction cache, replacing previous
+    /// version of the same transaction (with the same txid), if any
+    pub fn add_transaction(&mut self, tx: Transaction) -> Result<bool, Error> {
+        let txid = tx.txid();
+        self.profile.tx_cache.retain(|cached| cached.txid() != txid);
+        self.profile.tx_cache.push(tx);
+        self.save()
     }
 
     pub fn fill_asset_store(&self, store: &gtk::ListStore) {
         store.clear();
-        self.profile.assets.iter().for_each(|(contract_id, _)| {
-            if let Some((asset, _)) = self.asset_by_id(*contract_id) {
-                store.insert_with_values(
-                    None,
-                    &[
-                        (0, &asset.ticker()),
-                        (1, &asset.name()),
-                        (
-                            2,
-                            &asset.known_filtered_accounting_value(
-                                |allocation| {
-                                    self.is_outpoint_known(
-                                        *allocation.outpoint(),
-                                    )
-                                },
-                            ),
-                        ),
-                        (
-                            3,
-                            &asset.accounting_supply(
-                                rgb20::SupplyMeasure::KnownCirculating,
+        self.profile
+            .assets
+            .iter()
+            .for_each(|(contract_id, consignment)| {
+                if let Some((_, nomination, _)) = self.asset_by_id(*contract_id)
+                {
+                    let divisor = nomination.divisor();
+                    let owned =
+                        self.asset_balance(*contract_id).unwrap_or_default();
+                    let circulating = known_supply(consignment).circulating();
+                    let count = |transition_type: TransitionType| {
+                        consignment
+                            .state_transitions
+                            .iter()
+                            .filter(|(_, transition)| {
+                                transition.transition_type()
+                                    == transition_type.into()
+                            })
+                            .count() as u32
+                    };
+                    store.insert_with_values(
+                        None,
+                        &[
+                            (0, &nomination.ticker),
+                            (1, &nomination.name),
+                            (2, &(owned as f32 / divisor as f32)),
+                            (3, &(circulating as f32 / divisor as f32)),
+                            (4, &(1 + count(TransitionType::Issue))),
+                            (
+                                5,
+                                &(!known_seals(
+                                    consignment,
+                                    OwnedRightsType::Inflation,
+                                )
+                                .is_empty()),
                             ),
-                        ),
-                        (4, &1),
-                        (5, &(!asset.known_inflation().is_empty())),
-                        (6, &0),
-                        (7, &contract_id.to_string()),
-                    ],
-                );
-            };
-        });
+                            (6, &count(TransitionType::Epoch)),
+                            (7, &contract_id.to_string()),
+                        ],
+                    );
+                };
+            });
+    }
+
+    /// Amount of the asset, in atomic units, allocated to the document UTXOs,
+    /// excluding burned allocations and allocations which seals were found
+    /// spent by the asset synchronization
+    pub fn asset_balance(
+        &self,
+        contract_id: ContractId,
+    ) -> Option<AtomicValue> {
+        let consignment = self.profile.assets.get(&contract_id)?;
+        let burned = burned_outpoints(consignment);
+        Some(
+            known_seals(consignment, OwnedRightsType::Assets)
+                .iter()
+                .filter(|seal| {
+                    self.is_outpoint_known(seal.outpoint)
+                        && !burned.contains(&seal.outpoint)
+                        && !self.is_seal_spent(contract_id, seal.outpoint)
+                })
+                .filter_map(|seal| seal.value)
+                .fold(0, AtomicValue::saturating_add),
+        )
     }
 
+    /// Returns asset data together with its current nomination. Nomination
+    /// of the [`rgb20::Asset`] is always read from the genesis and must not
+    /// be used, since the asset may be renominated.
     pub fn asset_by_id(
         &self,
         asset_id: ContractId,
-    ) -> Option<(rgb20::Asset, &Genesis)> {
+    ) -> Option<(rgb20::Asset, Nomination, &Genesis)> {
         self.profile.assets.get(&asset_id).and_then(|consignment| {
             rgb20::Asset::try_from(consignment.genesis.clone())
                 .ok()
-                .map(|asset| (asset, &consignment.genesis))
+                .map(|asset| {
+                    (
+                        asset,
+                        current_nomination(consignment),
+                        &consignment.genesis,
+                    )
+                })
         })
     }
 
+    /// Current nomination of the asset, taking renominations into account
+    pub fn asset_nomination(&self, asset_id: ContractId) -> Option<Nomination> {
+        self.profile.assets.get(&asset_id).map(current_nomination)
+    }
+
+    pub fn assets(&self) -> &BTreeMap<ContractId, Consignment> {
+        &self.profile.assets
+    }
+
+    pub fn asset_consignment(
+        &self,
+        contract_id: ContractId,
+    ) -> Option<&Consignment> {
+        self.profile.assets.get(&contract_id)
+    }
+
     pub fn add_asset(
         &mut self,
         consignment: Consignment,
@@ -457,11 +694,119 @@ impl Document {
         self.save()
     }
 
+    /// Merges newer state of an already known contract from the provided
+    /// consignment, returning number of new contract nodes. If the contract
+    /// is not known yet, the consignment is added as a new asset.
+    pub fn merge_asset(
+        &mut self,
+        consignment: Consignment,
+    ) -> Result<usize, Error> {
+        let contract_id = consignment.genesis.contract_id();
+        let count = match self.profile.assets.get_mut(&contract_id) {
+            Some(known) => merge_consignment(known, consignment),
+            None => {
+                let count = 1
+                    + consignment.state_transitions.len()
+                    + consignment.state_extensions.len();
+                self.profile.assets.insert(contract_id, consignment);
+                count
+            }
+        };
+        self.save()?;
+        Ok(count)
+    }
+
+    /// Keeps consignment extended with a state transition until its witness
+    /// transaction is broadcast
+    pub fn add_pending_transition(
+        &mut self,
+        txid: Txid,
+        consignment: Consignment,
+    ) -> Result<bool, Error> {
+        self.profile.pending_transitions.insert(txid, consignment);
+        self.save()
+    }
+
+    /// Merges the state transition anchored to the broadcast witness
+    /// transaction into the asset, returning number of new contract nodes.
+    /// Returns `None` if the transaction does not anchor any transition.
+    pub fn apply_pending_transition(
+        &mut self,
+        txid: Txid,
+    ) -> Result<Option<usize>, Error> {
+        match self.profile.pending_transitions.remove(&txid) {
+            Some(consignment) => self.merge_asset(consignment).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Discards the state transition anchored to the witness transaction
+    pub fn remove_pending_transition(
+        &mut self,
+        txid: Txid,
+    ) -> Result<bool, Error> {
+        if self.profile.pending_transitions.remove(&txid).is_none() {
+            return Ok(false);
+        }
+        self.save()
+    }
+
+    pub fn is_transition_pending(&self, txid: Txid) -> bool {
+        self.profile.pending_transitions.contains_key(&txid)
+    }
+
+    /// Creates blinded seal for the UTXO to be used in an invoice, keeping
+    /// its blinding secret
+    pub fn blind_utxo(
+        &mut self,
+        outpoint: OutPoint,
+    ) -> Result<OutpointReveal, Error> {
+        let reveal = OutpointReveal::from(outpoint);
+        self.profile.blinded_utxos.push(reveal);
+        self.save()?;
+        Ok(reveal)
+    }
+
+    pub fn blinded_utxos(&self) -> &[OutpointReveal] {
+        &self.profile.blinded_utxos
+    }
+
+    /// Reveals seals of the incoming consignment which were defined by the
+    /// document invoices, returning number of received allocations
+    pub fn reveal_seals(&self, consignment: &mut Consignment) -> usize {
+        reveal_seals(consignment, &self.profile.blinded_utxos)
+    }
+
+    /// Records seal outputs of the contracts found spent by the asset
+    /// synchronization
+    pub fn update_spent_seals(
+        &mut self,
+        spent_seals: impl IntoIterator<Item = (ContractId, BTreeSet<OutPoint>)>,
+    ) -> Result<bool, Error> {
+        self.profile.spent_seals.extend(spent_seals);
+        self.save()
+    }
+
+    /// Detects whether the contract seal at the given output was found spent
+    /// by the last asset synchronization
+    pub fn is_seal_spent(
+        &self,
+        contract_id: ContractId,
+        outpoint: OutPoint,
+    ) -> bool {
+        self.profile
+            .spent_seals
+            .get(&contract_id)
+            .map(|spent| spent.contains(&outpoint))
+            .unwrap_or_default()
+    }
+
     pub fn remove_asset(
         &mut self,
         contract_id: ContractId,
     ) -> Result<bool, Error> {
         self.profile.assets.remove(&contract_id);
+        self.profile.spent_seals.remove(&contract_id);
         self.save()
     }
 
@@ -474,7 +819,15 @@ impl Document {
     }
 }
 
-#[derive(Clone, PartialEq, Debug, StrictEncode, StrictDecode)]
+/// Indexes UTXO entries by their outpoints
+fn utxo_index(utxos: &HashSet<UtxoEntry>) -> HashMap<OutPoint, UtxoEntry> {
+    utxos
+        .iter()
+        .map(|utxo| (utxo.outpoint, utxo.clone()))
+        .collect()
+}
+
+#[derive(Clone, PartialEq, Debug)]
 pub struct Profile {
     pub magic: u32,
     pub version: u16,
@@ -492,13 +845,103 @@ pub struct Profile {
     pub contracts: BTreeMap<ContractId, Consignment>,
     pub history: Vec<operation::LogEntry>,
     pub settings: Settings,
+    /// Blinding secrets of the UTXOs used in the invoices; since version 1
+    pub blinded_utxos: Vec<OutpointReveal>,
+    /// Origins of the extended keys imported without their master keys;
+    /// since version 2
+    pub key_origins: KeyOrigins,
+    /// Consignments with the state transitions by txid of their witness
+    /// transactions, which were not broadcast yet; since version 3
+    pub pending_transitions: BTreeMap<Txid, Consignment>,
+    /// Outputs of the contract seals found spent during the last asset
+    /// synchronization; since version 4
+    pub spent_seals: BTreeMap<ContractId, BTreeSet<OutPoint>>,
+}
+
+impl StrictEncode for Profile {
+    fn strict_encode<E: io::Write>(
+        &self,
+        mut e: E,
+    ) -> Result<usize, strict_encoding::Error> {
+        let mut len = self.magic.strict_encode(&mut e)?;
+        // Documents are always saved with the most recent layout
+        len += DOC_VERSION.strict_encode(&mut e)?;
+        len += self.description.strict_encode(&mut e)?;
+        len += self.tracking.strict_encode(&mut e)?;
+        len += self.descriptors.strict_encode(&mut e)?;
+        len += self.utxo_cache.strict_encode(&mut e)?;
+        len += self.tx_cache.strict_encode(&mut e)?;
+        len += self.psbts.strict_encode(&mut e)?;
+        len += self.schemata.strict_encode(&mut e)?;
+        len += self.assets.strict_encode(&mut e)?;
+        len += self.nfts.strict_encode(&mut e)?;
+        len += self.identities.strict_encode(&mut e)?;
+        len += self.auditlogs.strict_encode(&mut e)?;
+        len += self.contracts.strict_encode(&mut e)?;
+        len += self.history.strict_encode(&mut e)?;
+        len += self.settings.strict_encode(&mut e)?;
+        len += self.blinded_utxos.strict_encode(&mut e)?;
+        len += self.key_origins.strict_encode(&mut e)?;
+        len += self.pending_transitions.strict_encode(&mut e)?;
+        len += self.spent_seals.strict_encode(&mut e)?;
+        Ok(len)
+    }
+}
+
+impl StrictDecode for Profile {
+    fn strict_decode<D: io::Read>(
+        mut d: D,
+    ) -> Result<Self, strict_encoding::Error> {
+        let magic = u32::strict_decode(&mut d)?;
+        let version = u16::strict_decode(&mut d)?;
+        if version > DOC_VERSION {
+            return Err(strict_encoding::Error::UnsupportedDataStructure(
+                "document was saved by a newer version of the application",
+            ));
+        }
+        let mut profile = Profile {
+            magic,
+            version,
+            description: StrictDecode::strict_decode(&mut d)?,
+            tracking: StrictDecode::strict_decode(&mut d)?,
+            descriptors: StrictDecode::strict_decode(&mut d)?,
+            utxo_cache: StrictDecode::strict_decode(&mut d)?,
+            tx_cache: StrictDecode::strict_decode(&mut d)?,
+            psbts: StrictDecode::strict_decode(&mut d)?,
+            schemata: StrictDecode::strict_decode(&mut d)?,
+            assets: StrictDecode::strict_decode(&mut d)?,
+            nfts: StrictDecode::strict_decode(&mut d)?,
+            identities: StrictDecode::strict_decode(&mut d)?,
+            auditlogs: StrictDecode::strict_decode(&mut d)?,
+            contracts: StrictDecode::strict_decode(&mut d)?,
+            history: StrictDecode::strict_decode(&mut d)?,
+            settings: StrictDecode::strict_decode(&mut d)?,
+            blinded_utxos: vec![],
+            key_origins: bmap![],
+            pending_transitions: bmap![],
+            spent_seals: bmap![],
+        };
+        if version >= 1 {
+            profile.blinded_utxos = StrictDecode::strict_decode(&mut d)?;
+        }
+        if version >= 2 {
+            profile.key_origins = StrictDecode::strict_decode(&mut d)?;
+        }
+        if version >= 3 {
+            profile.pending_transitions = StrictDecode::strict_decode(&mut d)?;
+        }
+        if version >= 4 {
+            profile.spent_seals = StrictDecode::strict_decode(&mut d)?;
+        }
+        Ok(profile)
+    }
 }
 
 impl Default for Profile {
     fn default() -> Self {
         Profile {
             magic: DOC_MAGIC,
-            version: 0,
+            version: DOC_VERSION,
             description: None,
             tracking: vec![],
             descriptors: vec![],
@@ -513,6 +956,10 @@ impl Default for Profile {
             contracts: bmap![],
             history: vec![],
             settings: Settings::default(),
+            blinded_utxos: vec![],
+            key_origins: bmap![],
+            pending_transitions: bmap![],
+            spent_seals: bmap![],
         }
     }
 }
@@ -571,3 +1018,113 @@ impl Default for Settings {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::fixtures::{self, outpoint};
+
+    #[test]
+    fn utxo_index_sync() {
+        let mut doc = Document::new();
+        let confirmed = fixtures::utxo(outpoint(0), 100, 1000);
+        let unconfirmed = UtxoEntry {
+            height: 0,
+            ..confirmed.clone()
+        };
+        doc.update_utxo_set(
+            vec![confirmed.clone(), unconfirmed.clone()]
+                .into_iter()
+                .collect(),
+        )
+        .unwrap();
+
+        let indexed = doc.utxo_by_outpoint(outpoint(0)).unwrap();
+        doc.remove_utxo(indexed.clone()).unwrap();
+        let other = doc.utxo_by_outpoint(outpoint(0)).unwrap();
+        assert_ne!(other, indexed);
+        assert!(other == confirmed || other == unconfirmed);
+
+        doc.remove_utxo(other).unwrap();
+        assert!(!doc.is_outpoint_known(outpoint(0)));
+        assert!(doc.utxos().is_empty());
+    }
+
+    #[test]
+    fn spent_seals() {
+        let mut doc = Document::new();
+        let consignment = fixtures::asset();
+        let contract_id = consignment.genesis.contract_id();
+        doc.merge_asset(consignment).unwrap();
+        assert_eq!(doc.asset_balance(contract_id), Some(0));
+
+        doc.update_utxo_set(set![
+            fixtures::utxo(outpoint(0), 100, 1000),
+            fixtures::utxo(outpoint(1), 100, 1000)
+        ])
+        .unwrap();
+        assert_eq!(doc.asset_balance(contract_id), Some(1000));
+
+        doc.update_spent_seals(vec![(
+            contract_id,
+            BTreeSet::from([outpoint(1)]),
+        )])
+        .unwrap();
+        assert!(doc.is_seal_spent(contract_id, outpoint(1)));
+        assert_eq!(doc.asset_balance(contract_id), Some(1000));
+
+        doc.update_spent_seals(vec![(
+            contract_id,
+            BTreeSet::from([outpoint(0)]),
+        )])
+        .unwrap();
+        assert!(doc.is_seal_spent(contract_id, outpoint(0)));
+        assert!(!doc.is_seal_spent(contract_id, outpoint(1)));
+        assert_eq!(doc.asset_balance(contract_id), Some(0));
+
+        doc.remove_asset(contract_id).unwrap();
+        assert!(!doc.is_seal_spent(contract_id, outpoint(0)));
+        assert_eq!(doc.asset_balance(contract_id), None);
+    }
+
+    #[test]
+    fn profile_versions() {
+        let profile = Profile {
+            description: Some(s!("Test")),
+            utxo_cache: set![fixtures::utxo(outpoint(0), 100, 1000)],
+            ..Profile::default()
+        };
+        let data = strict_encoding::strict_serialize(&profile).unwrap();
+        assert_eq!(
+            strict_encoding::strict_deserialize::<Profile>(&data).unwrap(),
+            profile
+        );
+
+        // Older layouts lack the trailing fields added after them; each of
+        // the empty collections takes two bytes
+        for version in 0..DOC_VERSION {
+            let missing = 2 * (DOC_VERSION - version) as usize;
+            let mut old = data[..data.len() - missing].to_vec();
+            old[4..6].copy_from_slice(&version.to_le_bytes());
+            let upgraded =
+                strict_encoding::strict_deserialize::<Profile>(&old).unwrap();
+            assert_eq!(
+                upgraded,
+                Profile {
+                    version,
+                    ..profile.clone()
+                }
+            );
+            assert_eq!(
+                strict_encoding::strict_serialize(&upgraded).unwrap(),
+                data
+            );
+        }
+
+        let mut future = data;
+        future[4..6].copy_from_slice(&(DOC_VERSION + 1).to_le_bytes());
+        assert!(
+            strict_encoding::strict_deserialize::<Profile>(&future).is_err()
+        );
+    }
+}
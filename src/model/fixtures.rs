@@ -0,0 +1,104 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Fixtures shared by the model and controller unit tests
+
+use std::str::FromStr;
+
+use bitcoin::hashes::Hash;
+use bitcoin::{OutPoint, Txid};
+use lnpbp::Chain;
+use miniscript::descriptor::DescriptorSinglePub;
+use rgb::Consignment;
+use wallet::descriptor;
+
+use super::{DescriptorAccount, UtxoEntry};
+
+/// Public key of the single-key test descriptor
+pub const PUBKEY: &str =
+    "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+/// Transaction containing test outputs
+pub const TXID: &str =
+    "5d7c2f0d8f4d4f2f0d5a1b9c7d1e0e1f2a3b4c5d6e7f8091a2b3c4d5e6f70819";
+
+/// Output of the [`TXID`] transaction
+pub fn outpoint(vout: u32) -> OutPoint {
+    OutPoint::new(Txid::from_str(TXID).unwrap(), vout)
+}
+
+/// Output of a distinct transaction for each of the numbers
+pub fn numbered_outpoint(no: u32, vout: u32) -> OutPoint {
+    OutPoint::new(Txid::hash(&no.to_be_bytes()), vout)
+}
+
+/// Single-key descriptor template for the [`PUBKEY`]
+pub fn pubkey_template() -> descriptor::Template {
+    descriptor::Template::SingleSig(descriptor::SingleSig::Pubkey(
+        DescriptorSinglePub {
+            origin: None,
+            key: bitcoin::PublicKey::from_str(PUBKEY).unwrap(),
+        },
+    ))
+}
+
+/// SegWit output of the [`pubkey_template`] descriptor
+pub fn utxo(outpoint: OutPoint, height: u32, amount: u64) -> UtxoEntry {
+    UtxoEntry {
+        outpoint,
+        height,
+        amount,
+        descriptor_template: pubkey_template(),
+        descriptor_category: descriptor::Category::SegWit,
+        derivation_index: 0,
+    }
+}
+
+/// Descriptor account of the [`pubkey_template`] with SegWit outputs
+pub fn descriptor_account() -> DescriptorAccount {
+    DescriptorAccount {
+        name: s!("Test"),
+        generator: descriptor::Generator {
+            template: pubkey_template(),
+            variants: descriptor::Variants {
+                bare: false,
+                hashed: false,
+                nested: false,
+                segwit: true,
+                taproot: false,
+            },
+        },
+    }
+}
+
+/// RGB20 asset with 1000 units allocated to [`outpoint`] #0, inflation cap
+/// of 500 at #1, renomination right at #3 and epoch seal at #4
+pub fn asset() -> Consignment {
+    let (_, genesis) = rgb20::issue(
+        Chain::Testnet3,
+        s!("TST"),
+        s!("Test asset"),
+        None,
+        0,
+        vec![(outpoint(0), 1000)],
+        bmap! { outpoint(1) => 500 },
+        Some(outpoint(3)),
+        Some(outpoint(4)),
+    )
+    .unwrap();
+    Consignment::with(genesis, none!(), none!(), none!())
+}
@@ -15,13 +15,41 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+mod asset;
+mod batch;
+mod cache;
+mod core_export;
 mod descriptors;
 mod document;
+#[cfg(test)]
+pub mod fixtures;
+mod multisig_setup;
 pub mod operation;
+mod scheme;
+mod seed;
 mod tracking;
 mod utxo;
+mod wallet_import;
 
+pub use asset::{
+    allocation_state, burned_outpoints, current_nomination, inflation_cap,
+    known_seals, known_supply, merge_consignment, reveal_seals, seal_outpoint,
+    AssetSeal, AssetSupply, Nomination,
+};
+pub use batch::AccountBatch;
+pub use cache::{DerivationCache, ScriptIndex};
+pub use core_export::{CoreDescriptor, Error as CoreExportError, Timestamp};
 pub use descriptors::DescriptorAccount;
 pub use document::{Document, Error, Profile, ResolverError};
-pub use tracking::TrackingAccount;
+pub use multisig_setup::{
+    Error as SetupError, MultisigFormat, MultisigSetup, SetupCosigner,
+};
+pub use scheme::{
+    Bip48ScriptType, DerivationPreset, DerivationScheme, SchemeParseError,
+};
+pub use seed::{Error as SeedError, MnemonicSeed};
+pub use tracking::{KeyOrigin, KeyOrigins, NetworkMismatch, TrackingAccount};
 pub use utxo::UtxoEntry;
+pub use wallet_import::{
+    Error as ImportError, WalletFile, WalletFormat, WalletImport,
+};
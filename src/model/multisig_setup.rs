@@ -0,0 +1,931 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Multi-signature setup files: BIP129 (BSMS) descriptor records and text
+//! configuration files used by air-gapped signing devices
+
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use bitcoin::blockdata::{opcodes, script::Builder};
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::util::bip32::{
+    self, ChildNumber, DerivationPath, ExtendedPubKey, Fingerprint,
+};
+use bitcoin::{Address, Network, PublicKey, Script};
+use slip132::FromSlip132;
+use wallet::bip32::DerivationComponents;
+use wallet::descriptor;
+
+use super::{DescriptorAccount, KeyOrigin, KeyOrigins, TrackingAccount};
+use crate::util::desc_checksum;
+
+/// Maximum number of cosigners supported by the setup files
+pub const MAX_COSIGNERS: usize = 15;
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// Descriptor account is not a multi-signature one
+    NotMultisig,
+
+    /// Multi-signature key #{0} is not an extended public key with known
+    /// master key fingerprint
+    NoKeyOrigin(usize),
+
+    /// Descriptor account must use P2SH, P2SH-P2WSH or P2WSH variant to be
+    /// exported as a multi-signature setup
+    UnsupportedVariant,
+
+    /// Setup file is empty or has unknown format
+    UnknownFormat,
+
+    /// Only BSMS 1.0 descriptor records are supported
+    UnsupportedVersion,
+
+    /// Descriptor {0} is not a multi-signature descriptor
+    InvalidDescriptor(String),
+
+    /// Invalid extended public key {0}
+    InvalidXpub(String),
+
+    /// Invalid derivation path {0}
+    InvalidPath(String),
+
+    /// Invalid key fingerprint {0}
+    InvalidFingerprint(String),
+
+    /// Unknown script format {0}
+    InvalidFormat(String),
+
+    /// Invalid multi-signature policy {0}; it must look like "2 of 3"
+    InvalidPolicy(String),
+
+    /// Setup file does not specify derivation path for the key {0}
+    NoDerivation(Fingerprint),
+
+    /// Descriptor checksum error: {0}
+    #[from]
+    Checksum(desc_checksum::Error),
+
+    /// Threshold {0} is invalid for {1} cosigners
+    InvalidThreshold(u8, usize),
+
+    /// Key with fingerprint {0} is used more than once
+    DuplicateKey(Fingerprint),
+
+    /// Depth of the extended key with fingerprint {0} does not match its
+    /// derivation path {1}
+    DepthMismatch(Fingerprint, DerivationPath),
+
+    /// Extended key with fingerprint {0} was not derived with the last
+    /// index of its derivation path {1}
+    PathMismatch(Fingerprint, DerivationPath),
+
+    /// Cosigner keys belong to different networks
+    MixedNetworks,
+
+    /// Address {0} from the setup file does not match address {1} derived
+    /// from the descriptor
+    AddressMismatch(String, String),
+
+    /// Tracking account '{0}' uses the same extended key, but with a
+    /// different master key fingerprint or derivation path
+    OriginMismatch(String),
+
+    /// Unable to derive cosigner keys: {0}
+    #[from]
+    Bip32(bip32::Error),
+}
+
+/// Script type of the multi-signature setup
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Display)]
+pub enum MultisigFormat {
+    #[display("P2SH")]
+    P2sh,
+
+    #[display("P2SH-P2WSH")]
+    P2shP2wsh,
+
+    #[display("P2WSH")]
+    P2wsh,
+}
+
+impl FromStr for MultisigFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().replace('_', "-").as_str() {
+            "P2SH" => Ok(MultisigFormat::P2sh),
+            "P2SH-P2WSH" | "P2WSH-P2SH" => Ok(MultisigFormat::P2shP2wsh),
+            "P2WSH" => Ok(MultisigFormat::P2wsh),
+            _ => Err(Error::InvalidFormat(s.to_owned())),
+        }
+    }
+}
+
+impl MultisigFormat {
+    pub fn with_variants(
+        variants: &descriptor::Variants,
+    ) -> Option<MultisigFormat> {
+        if variants.segwit {
+            Some(MultisigFormat::P2wsh)
+        } else if variants.nested {
+            Some(MultisigFormat::P2shP2wsh)
+        } else if variants.hashed {
+            Some(MultisigFormat::P2sh)
+        } else {
+            None
+        }
+    }
+
+    pub fn variants(self) -> descriptor::Variants {
+        descriptor::Variants {
+            bare: false,
+            hashed: self == MultisigFormat::P2sh,
+            nested: self == MultisigFormat::P2shP2wsh,
+            segwit: self == MultisigFormat::P2wsh,
+            taproot: false,
+        }
+    }
+
+    fn wrap(self, inner: &str) -> String {
+        match self {
+            MultisigFormat::P2sh => format!("sh({})", inner),
+            MultisigFormat::P2shP2wsh => format!("sh(wsh({}))", inner),
+            MultisigFormat::P2wsh => format!("wsh({})", inner),
+        }
+    }
+
+    fn unwrap(desc: &str) -> Option<(MultisigFormat, &str)> {
+        if let Some(inner) = desc
+            .strip_prefix("sh(wsh(")
+            .and_then(|s| s.strip_suffix("))"))
+        {
+            Some((MultisigFormat::P2shP2wsh, inner))
+        } else if let Some(inner) =
+            desc.strip_prefix("wsh(").and_then(|s| s.strip_suffix(')'))
+        {
+            Some((MultisigFormat::P2wsh, inner))
+        } else {
+            desc.strip_prefix("sh(")
+                .and_then(|s| s.strip_suffix(')'))
+                .map(|inner| (MultisigFormat::P2sh, inner))
+        }
+    }
+
+    fn address(self, script: &Script, network: Network) -> Address {
+        match self {
+            MultisigFormat::P2sh => Address::p2sh(script, network),
+            MultisigFormat::P2shP2wsh => Address::p2shwsh(script, network),
+            MultisigFormat::P2wsh => Address::p2wsh(script, network),
+        }
+    }
+}
+
+/// Cosigner key with its origin
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SetupCosigner {
+    /// Fingerprint of the cosigner master key
+    pub fingerprint: Fingerprint,
+    /// Derivation path from the master key to the account key
+    pub path: DerivationPath,
+    /// Account-level extended public key
+    pub xpub: ExtendedPubKey,
+}
+
+impl SetupCosigner {
+    fn key_expression(&self) -> String {
+        format!(
+            "[{}{}]{}/**",
+            self.fingerprint,
+            self.path.to_string().trim_start_matches('m'),
+            self.xpub
+        )
+    }
+
+    fn parse_key_expression(s: &str) -> Result<SetupCosigner, Error> {
+        let s = s.trim();
+        let (origin, key) = s
+            .strip_prefix('[')
+            .and_then(|s| s.split_once(']'))
+            .ok_or_else(|| Error::InvalidXpub(s.to_owned()))?;
+        let (fingerprint, path) =
+            origin.split_once('/').unwrap_or((origin, ""));
+        let xpub = key.split('/').next().unwrap_or_default();
+        Ok(SetupCosigner {
+            fingerprint: parse_fingerprint(fingerprint)?,
+            path: if path.is_empty() {
+                DerivationPath::from(vec![])
+            } else {
+                parse_path(&format!("m/{}", path))?
+            },
+            xpub: parse_xpub(xpub)?,
+        })
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        let path: &[ChildNumber] = self.path.as_ref();
+        if self.xpub.depth as usize != path.len() {
+            return Err(Error::DepthMismatch(
+                self.fingerprint,
+                self.path.clone(),
+            ));
+        }
+        match path.last() {
+            Some(child) if *child != self.xpub.child_number => {
+                Err(Error::PathMismatch(self.fingerprint, self.path.clone()))
+            }
+            None if self.xpub.fingerprint() != self.fingerprint => {
+                Err(Error::PathMismatch(self.fingerprint, self.path.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Multi-signature setup which can be shared between cosigners and signing
+/// devices
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MultisigSetup {
+    pub name: String,
+    pub threshold: u8,
+    pub format: MultisigFormat,
+    /// Whether keys are sorted lexicographically (`sortedmulti`)
+    pub sorted: bool,
+    pub cosigners: Vec<SetupCosigner>,
+}
+
+impl MultisigSetup {
+    /// Constructs setup from a multi-signature descriptor account. If the
+    /// account has several script variants, the most modern one is used.
+    ///
+    /// Origins of the keys which were imported without their master key are
+    /// taken from `origins`.
+    pub fn with_account(
+        account: &DescriptorAccount,
+        origins: &KeyOrigins,
+    ) -> Result<MultisigSetup, Error> {
+        let multisig = match &account.generator.template {
+            descriptor::Template::MultiSig(multisig) => multisig,
+            _ => return Err(Error::NotMultisig),
+        };
+        let cosigners = multisig
+            .pubkeys
+            .iter()
+            .enumerate()
+            .map(|(index, key)| match key {
+                descriptor::SingleSig::XPubDerivable(components) => {
+                    KeyOrigin::with_components(components, origins)
+                        .map(|origin| SetupCosigner {
+                            fingerprint: origin.fingerprint,
+                            path: origin.path,
+                            xpub: components.branch_xpub,
+                        })
+                        .ok_or(Error::NoKeyOrigin(index))
+                }
+                _ => Err(Error::NoKeyOrigin(index)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let setup = MultisigSetup {
+            name: account.name(),
+            threshold: multisig.threshold() as u8,
+            format: MultisigFormat::with_variants(&account.generator.variants)
+                .ok_or(Error::UnsupportedVariant)?,
+            sorted: multisig.reorder,
+            cosigners,
+        };
+        setup.validate()?;
+        Ok(setup)
+    }
+
+    /// Checks threshold, key uniqueness and consistency of the key origins
+    pub fn validate(&self) -> Result<(), Error> {
+        let count = self.cosigners.len();
+        if self.threshold == 0
+            || self.threshold as usize > count
+            || count > MAX_COSIGNERS
+        {
+            return Err(Error::InvalidThreshold(self.threshold, count));
+        }
+        let mut keys = HashSet::new();
+        for cosigner in &self.cosigners {
+            if !keys.insert(cosigner.xpub.public_key) {
+                return Err(Error::DuplicateKey(cosigner.fingerprint));
+            }
+            if cosigner.xpub.network != self.network() {
+                return Err(Error::MixedNetworks);
+            }
+            cosigner.check()?;
+        }
+        Ok(())
+    }
+
+    pub fn network(&self) -> Network {
+        self.cosigners
+            .first()
+            .map(|cosigner| cosigner.xpub.network)
+            .unwrap_or(Network::Bitcoin)
+    }
+
+    /// Descriptor template covering both receive and change branches
+    /// (BIP129 `/**` notation), without checksum
+    pub fn descriptor(&self) -> String {
+        let keys = self
+            .cosigners
+            .iter()
+            .map(SetupCosigner::key_expression)
+            .collect::<Vec<_>>()
+            .join(",");
+        self.format.wrap(&format!(
+            "{}({},{})",
+            if self.sorted { "sortedmulti" } else { "multi" },
+            self.threshold,
+            keys
+        ))
+    }
+
+    pub fn address(&self, change: bool, index: u32) -> Result<Address, Error> {
+        let path = [
+            ChildNumber::from_normal_idx(change as u32)?,
+            ChildNumber::from_normal_idx(index)?,
+        ];
+        let mut keys = self
+            .cosigners
+            .iter()
+            .map(|cosigner| {
+                cosigner
+                    .xpub
+                    .derive_pub(&wallet::SECP256K1, &path)
+                    .map(|xpub| xpub.public_key)
+            })
+            .collect::<Result<Vec<PublicKey>, _>>()?;
+        if self.sorted {
+            keys.sort_by_key(|key| key.key.serialize());
+        }
+        let script = keys
+            .iter()
+            .fold(Builder::new().push_int(self.threshold as i64), |b, key| {
+                b.push_key(key)
+            })
+            .push_int(keys.len() as i64)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        Ok(self.format.address(&script, self.network()))
+    }
+
+    /// Exports BIP129 descriptor record, containing the descriptor, path
+    /// restrictions and the first receive address for verification
+    pub fn to_bsms(&self) -> Result<String, Error> {
+        Ok(format!(
+            "BSMS 1.0\n{}\n/0/*,/1/*\n{}\n",
+            desc_checksum::with_checksum(&self.descriptor())?,
+            self.address(false, 0)?
+        ))
+    }
+
+    /// Exports text configuration file in the format used by air-gapped
+    /// signing devices (Coldcard, Passport, Keystone and others)
+    pub fn to_config(&self) -> String {
+        let common_path = self
+            .cosigners
+            .iter()
+            .all(|cosigner| cosigner.path == self.cosigners[0].path);
+        let mut config = format!(
+            "# Multisig setup file (exported by Bitcoin Pro)\n#\nName: {}\n\
+             Policy: {} of {}\nFormat: {}\n",
+            self.name,
+            self.threshold,
+            self.cosigners.len(),
+            self.format
+        );
+        if common_path {
+            config += &format!("Derivation: {}\n", self.cosigners[0].path);
+        }
+        for cosigner in &self.cosigners {
+            config += "\n";
+            if !common_path {
+                config += &format!("Derivation: {}\n", cosigner.path);
+            }
+            config += &format!(
+                "{}: {}\n",
+                cosigner.fingerprint.to_string().to_uppercase(),
+                cosigner.xpub
+            );
+        }
+        config
+    }
+
+    /// Parses either BSMS descriptor record or text configuration file. The
+    /// name is used if the file does not provide its own.
+    pub fn parse(name: &str, text: &str) -> Result<MultisigSetup, Error> {
+        let setup = match text.trim_start().lines().next() {
+            None => return Err(Error::UnknownFormat),
+            Some(line) if line.starts_with("BSMS") => {
+                Self::parse_bsms(name, text)?
+            }
+            Some(_) => Self::parse_config(name, text)?,
+        };
+        setup.validate()?;
+        Ok(setup)
+    }
+
+    fn parse_bsms(name: &str, text: &str) -> Result<MultisigSetup, Error> {
+        let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty());
+        if lines.next() != Some("BSMS 1.0") {
+            return Err(Error::UnsupportedVersion);
+        }
+        let descriptor = lines.next().ok_or(Error::UnknownFormat)?;
+        let mut setup = Self::parse_descriptor(name, descriptor)?;
+        setup.validate()?;
+        // Path restrictions are not used since the descriptor already
+        // provides them with `/**` notation
+        let _ = lines.next();
+        if let Some(address) = lines.next() {
+            let derived = setup.address(false, 0)?.to_string();
+            if derived != address {
+                return Err(Error::AddressMismatch(
+                    address.to_owned(),
+                    derived,
+                ));
+            }
+        }
+        setup.name = name.to_owned();
+        Ok(setup)
+    }
+
+    /// Parses multi-signature descriptor in `sh`, `sh(wsh)` or `wsh` form
+    pub fn parse_descriptor(
+        name: &str,
+        descriptor: &str,
+    ) -> Result<MultisigSetup, Error> {
+        let desc = desc_checksum::verify(descriptor.trim())?;
+        let invalid = || Error::InvalidDescriptor(descriptor.to_owned());
+        let (format, inner) =
+            MultisigFormat::unwrap(desc).ok_or_else(invalid)?;
+        let (sorted, args) =
+            if let Some(args) = inner.strip_prefix("sortedmulti(") {
+                (true, args)
+            } else if let Some(args) = inner.strip_prefix("multi(") {
+                (false, args)
+            } else {
+                return Err(invalid());
+            };
+        let mut args = args.strip_suffix(')').ok_or_else(invalid)?.split(',');
+        let threshold = args
+            .next()
+            .and_then(|threshold| threshold.trim().parse().ok())
+            .ok_or_else(invalid)?;
+        let cosigners = args
+            .map(SetupCosigner::parse_key_expression)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(MultisigSetup {
+            name: name.to_owned(),
+            threshold,
+            format,
+            sorted,
+            cosigners,
+        })
+    }
+
+    fn parse_config(name: &str, text: &str) -> Result<MultisigSetup, Error> {
+        let mut setup = MultisigSetup {
+            name: name.to_owned(),
+            threshold: 0,
+            // Air-gapped devices default to P2SH when format is not given
+            format: MultisigFormat::P2sh,
+            sorted: true,
+            cosigners: vec![],
+        };
+        let mut derivation = None;
+        let mut policy = None;
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => return Err(Error::UnknownFormat),
+            };
+            match key.to_lowercase().as_str() {
+                "name" => setup.name = value.to_owned(),
+                "policy" => policy = Some(value.to_owned()),
+                "format" => setup.format = value.parse()?,
+                "derivation" => derivation = Some(parse_path(value)?),
+                _ if key.len() == 8 => {
+                    let fingerprint = parse_fingerprint(key)?;
+                    setup.cosigners.push(SetupCosigner {
+                        fingerprint,
+                        path: derivation
+                            .clone()
+                            .ok_or(Error::NoDerivation(fingerprint))?,
+                        xpub: parse_xpub(value)?,
+                    });
+                }
+                _ => return Err(Error::UnknownFormat),
+            }
+        }
+        let policy = policy.ok_or(Error::UnknownFormat)?;
+        let (threshold, count) = policy
+            .split_once(" of ")
+            .or_else(|| policy.split_once('/'))
+            .and_then(|(m, n)| {
+                Some((m.trim().parse().ok()?, n.trim().parse::<usize>().ok()?))
+            })
+            .ok_or_else(|| Error::InvalidPolicy(policy.clone()))?;
+        if count != setup.cosigners.len() {
+            return Err(Error::InvalidPolicy(policy));
+        }
+        setup.threshold = threshold;
+        Ok(setup)
+    }
+
+    /// Origins of the cosigner keys, which must be kept by the document
+    /// for the keys imported without their master key
+    pub fn key_origins(&self) -> KeyOrigins {
+        self.cosigners
+            .iter()
+            .map(|cosigner| {
+                (
+                    cosigner.xpub.public_key,
+                    KeyOrigin {
+                        fingerprint: cosigner.fingerprint,
+                        path: cosigner.path.clone(),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Creates descriptor accounts for the receive and change branches of
+    /// the setup (BIP129 `/**` notation), together with the tracking
+    /// accounts for the cosigner keys which are not known to the document
+    /// yet.
+    ///
+    /// Known tracking accounts (matched by the extended key) must have the
+    /// same master fingerprint and derivation path as the setup file. For
+    /// the new accounts only the account-level key is known, so they are
+    /// created with it as the master key; their origins are provided by
+    /// [`MultisigSetup::key_origins`].
+    pub fn to_accounts(
+        &self,
+        known: &[TrackingAccount],
+        origins: &KeyOrigins,
+    ) -> Result<(Vec<TrackingAccount>, Vec<DescriptorAccount>), Error> {
+        let mut new_accounts = vec![];
+        let mut descriptor_accounts = vec![];
+        for branch in 0..=1u32 {
+            let name = if branch == 0 {
+                self.name.clone()
+            } else {
+                format!("{} change", self.name)
+            };
+            let mut pubkeys = vec![];
+            for (no, cosigner) in self.cosigners.iter().enumerate() {
+                let same_key = known
+                    .iter()
+                    .filter_map(|account| match &account.key {
+                        descriptor::SingleSig::XPubDerivable(components)
+                            if components.branch_xpub.public_key
+                                == cosigner.xpub.public_key =>
+                        {
+                            Some((account, components))
+                        }
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                for (account, components) in &same_key {
+                    match KeyOrigin::with_components(components, origins) {
+                        Some(origin)
+                            if origin.fingerprint != cosigner.fingerprint
+                                || origin.path != cosigner.path =>
+                        {
+                            return Err(Error::OriginMismatch(account.name()))
+                        }
+                        _ => {}
+                    }
+                }
+                if let Some((account, _)) =
+                    same_key.iter().find(|(_, components)| {
+                        components.terminal_path == [branch]
+                    })
+                {
+                    pubkeys.push(account.key.clone());
+                    continue;
+                }
+                // Known accounts for the other branch keep the master key
+                let components = same_key
+                    .first()
+                    .map(|(_, components)| DerivationComponents {
+                        terminal_path: vec![branch],
+                        index_ranges: None,
+                        ..(*components).clone()
+                    })
+                    .unwrap_or_else(|| DerivationComponents {
+                        master_xpub: cosigner.xpub,
+                        branch_path: DerivationPath::from(vec![]),
+                        branch_xpub: cosigner.xpub,
+                        terminal_path: vec![branch],
+                        index_ranges: None,
+                    });
+                let key = descriptor::SingleSig::XPubDerivable(components);
+                pubkeys.push(key.clone());
+                new_accounts.push(TrackingAccount {
+                    name: format!(
+                        "{} cosigner #{} [{}]",
+                        name,
+                        no + 1,
+                        cosigner.fingerprint
+                    ),
+                    key,
+                });
+            }
+            descriptor_accounts.push(DescriptorAccount {
+                name,
+                generator: descriptor::Generator {
+                    template: descriptor::Template::MultiSig(
+                        descriptor::MultiSig {
+                            threshold: Some(self.threshold),
+                            pubkeys,
+                            reorder: self.sorted,
+                        },
+                    ),
+                    variants: self.format.variants(),
+                },
+            });
+        }
+        Ok((new_accounts, descriptor_accounts))
+    }
+}
+
+fn parse_fingerprint(s: &str) -> Result<Fingerprint, Error> {
+    Fingerprint::from_hex(&s.to_lowercase())
+        .map_err(|_| Error::InvalidFingerprint(s.to_owned()))
+}
+
+fn parse_path(s: &str) -> Result<DerivationPath, Error> {
+    DerivationPath::from_str(&s.trim().replace('h', "'"))
+        .map_err(|_| Error::InvalidPath(s.to_owned()))
+}
+
+fn parse_xpub(s: &str) -> Result<ExtendedPubKey, Error> {
+    ExtendedPubKey::from_slip132_str(s.trim())
+        .map_err(|_| Error::InvalidXpub(s.to_owned()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::MnemonicSeed;
+
+    const COSIGNERS: [&str; 3] = [
+        "abandon abandon abandon abandon abandon abandon abandon abandon \
+         abandon abandon abandon about",
+        "legal winner thank year wave sausage worth useful legal winner thank \
+         yellow",
+        "letter advice cage absurd amount doctor acoustic avoid letter advice \
+         cage above",
+    ];
+
+    fn components(no: usize) -> DerivationComponents {
+        MnemonicSeed::with(COSIGNERS[no], "")
+            .unwrap()
+            .derivation_components(
+                Network::Testnet,
+                &DerivationPath::from_str("m/48'/1'/0'/2'").unwrap(),
+                None,
+            )
+            .unwrap()
+    }
+
+    /// Tracking account for the receive branch of the cosigner key
+    fn receive_account(no: usize, name: String) -> TrackingAccount {
+        TrackingAccount {
+            name,
+            key: descriptor::SingleSig::XPubDerivable(DerivationComponents {
+                terminal_path: vec![0],
+                ..components(no)
+            }),
+        }
+    }
+
+    fn setup() -> MultisigSetup {
+        MultisigSetup {
+            name: s!("Vault"),
+            threshold: 2,
+            format: MultisigFormat::P2wsh,
+            sorted: true,
+            cosigners: (0..3)
+                .map(components)
+                .map(|components| SetupCosigner {
+                    fingerprint: components.master_xpub.fingerprint(),
+                    path: components.branch_path,
+                    xpub: components.branch_xpub,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn bsms_roundtrip() {
+        let setup = setup();
+        setup.validate().unwrap();
+        let bsms = setup.to_bsms().unwrap();
+        let mut lines = bsms.lines();
+        assert_eq!(lines.next(), Some("BSMS 1.0"));
+        let descriptor = lines.next().unwrap();
+        assert!(descriptor
+            .starts_with("wsh(sortedmulti(2,[73c5da0a/48'/1'/0'/2']tpub"));
+        assert!(desc_checksum::verify(descriptor).is_ok());
+        assert_eq!(lines.next(), Some("/0/*,/1/*"));
+        assert!(lines.next().unwrap().starts_with("tb1q"));
+
+        assert_eq!(MultisigSetup::parse("Vault", &bsms), Ok(setup.clone()));
+
+        let tampered = bsms.replace("sortedmulti(2,", "sortedmulti(3,");
+        assert!(matches!(
+            MultisigSetup::parse("Vault", &tampered),
+            Err(Error::Checksum(_))
+        ));
+
+        let other_address = setup.address(false, 1).unwrap().to_string();
+        let mut lines = bsms.lines().collect::<Vec<_>>();
+        lines[3] = &other_address;
+        assert!(matches!(
+            MultisigSetup::parse("Vault", &lines.join("\n")),
+            Err(Error::AddressMismatch(..))
+        ));
+    }
+
+    #[test]
+    fn config_roundtrip() {
+        let mut setup = setup();
+        setup.format = MultisigFormat::P2shP2wsh;
+        let config = setup.to_config();
+        assert!(config.contains("Policy: 2 of 3\n"));
+        assert!(config.contains("Format: P2SH-P2WSH\n"));
+        assert!(config.contains("Derivation: m/48'/1'/0'/2'\n"));
+        assert!(config.contains("73C5DA0A: tpub"));
+        assert_eq!(MultisigSetup::parse("Other", &config), Ok(setup.clone()));
+
+        // Per-key derivation paths
+        setup.cosigners[1].path =
+            DerivationPath::from_str("m/48'/1'/0'/1'").unwrap();
+        assert!(matches!(setup.validate(), Err(Error::PathMismatch(..))));
+        let config = setup.to_config().replace(
+            "Derivation: m/48'/1'/0'/1'",
+            "Derivation: m/48h/1h/0h/2h",
+        );
+        setup.cosigners[1].path =
+            DerivationPath::from_str("m/48'/1'/0'/2'").unwrap();
+        assert_eq!(MultisigSetup::parse("Other", &config), Ok(setup));
+    }
+
+    #[test]
+    fn validation() {
+        let mut setup = setup();
+        setup.threshold = 4;
+        assert_eq!(setup.validate(), Err(Error::InvalidThreshold(4, 3)));
+        setup.threshold = 0;
+        assert_eq!(setup.validate(), Err(Error::InvalidThreshold(0, 3)));
+
+        let mut setup = self::setup();
+        setup.cosigners[2] = setup.cosigners[0].clone();
+        assert!(matches!(setup.validate(), Err(Error::DuplicateKey(_))));
+
+        let mut setup = self::setup();
+        setup.cosigners[0].path = DerivationPath::from_str("m/48'/1'").unwrap();
+        assert!(matches!(setup.validate(), Err(Error::DepthMismatch(..))));
+
+        assert_eq!(
+            MultisigSetup::parse("Vault", "BSMS 2.0\nwsh()"),
+            Err(Error::UnsupportedVersion)
+        );
+        assert_eq!(
+            MultisigSetup::parse("Vault", ""),
+            Err(Error::UnknownFormat)
+        );
+        assert!(matches!(
+            MultisigSetup::parse_descriptor("Vault", "pkh(xpub)"),
+            Err(Error::InvalidDescriptor(_))
+        ));
+    }
+
+    #[test]
+    fn accounts() {
+        let setup = setup();
+        let known = receive_account(0, s!("My key"));
+        let (new_accounts, descriptor_accounts) = setup
+            .to_accounts(&[known.clone()], &KeyOrigins::new())
+            .unwrap();
+        // Both branches for the two new cosigners and the change branch for
+        // the known one
+        assert_eq!(new_accounts.len(), 5);
+        assert!(new_accounts[0].name.starts_with("Vault cosigner #2 ["));
+        assert!(new_accounts[2]
+            .name
+            .starts_with("Vault change cosigner #1 ["));
+        assert_eq!(descriptor_accounts.len(), 2);
+        assert_eq!(descriptor_accounts[1].name, "Vault change");
+        let descriptor_account = &descriptor_accounts[0];
+        assert!(matches!(
+            descriptor_account.generator.template,
+            descriptor::Template::MultiSig(ref multisig)
+                if multisig.pubkeys[0] == known.key && multisig.threshold() == 2
+        ));
+        assert!(descriptor_account.generator.variants.segwit);
+
+        // Change account of the known key keeps its origin
+        assert!(matches!(
+            new_accounts[2].key,
+            descriptor::SingleSig::XPubDerivable(ref change)
+                if change.master_xpub == components(0).master_xpub
+                    && change.terminal_path == vec![1]
+        ));
+
+        // New accounts lack master key, so their origins must be provided
+        assert_eq!(
+            MultisigSetup::with_account(descriptor_account, &KeyOrigins::new()),
+            Err(Error::NoKeyOrigin(1))
+        );
+
+        let mut wrong_origin = setup;
+        wrong_origin.cosigners[0].fingerprint = Fingerprint::default();
+        assert_eq!(
+            wrong_origin.to_accounts(&[known], &KeyOrigins::new()),
+            Err(Error::OriginMismatch(s!("My key")))
+        );
+    }
+
+    #[test]
+    fn accounts_roundtrip() {
+        let setup = setup();
+        let bsms = setup.to_bsms().unwrap();
+        let parsed = MultisigSetup::parse("Vault", &bsms).unwrap();
+        let origins = parsed.key_origins();
+        let (new_accounts, descriptor_accounts) =
+            parsed.to_accounts(&[], &origins).unwrap();
+        assert_eq!(new_accounts.len(), 6);
+        for (branch, descriptor_account) in
+            descriptor_accounts.iter().enumerate()
+        {
+            match &descriptor_account.generator.template {
+                descriptor::Template::MultiSig(multisig) => {
+                    assert!(multisig.pubkeys.iter().all(|key| matches!(
+                        key,
+                        descriptor::SingleSig::XPubDerivable(components)
+                            if components.terminal_path == vec![branch as u32]
+                    )))
+                }
+                _ => panic!("setup must produce multisig descriptors"),
+            }
+        }
+
+        let exported =
+            MultisigSetup::with_account(&descriptor_accounts[0], &origins)
+                .unwrap();
+        assert_eq!(exported, setup);
+        assert_eq!(exported.to_bsms().unwrap(), bsms);
+
+        // Re-import reuses the accounts created before
+        let (new_accounts, reimported) =
+            parsed.to_accounts(&new_accounts, &origins).unwrap();
+        assert!(new_accounts.is_empty());
+        assert_eq!(reimported, descriptor_accounts);
+    }
+
+    #[test]
+    fn account_export() {
+        let setup = setup();
+        let known = (0..3)
+            .map(|no| receive_account(no, format!("Key {}", no)))
+            .collect::<Vec<_>>();
+        let (new_accounts, descriptor_accounts) =
+            setup.to_accounts(&known, &KeyOrigins::new()).unwrap();
+        // Known keys are reused for the receive branch only
+        assert_eq!(new_accounts.len(), 3);
+        for descriptor_account in &descriptor_accounts {
+            assert_eq!(
+                MultisigSetup::with_account(
+                    descriptor_account,
+                    &KeyOrigins::new()
+                ),
+                Ok(setup.clone())
+            );
+        }
+    }
+}
@@ -0,0 +1,378 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use std::str::FromStr;
+
+use bitcoin::util::bip32::{ChildNumber, DerivationPath};
+use bitcoin::Network;
+use slip132::KeyApplication;
+use wallet::bip32::DerivationComponents;
+
+/// Script type used by BIP48 multi-signature derivation paths
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display)]
+pub enum Bip48ScriptType {
+    /// Nested SegWit multisig (P2SH-P2WSH), `1'`
+    #[display("1")]
+    Nested = 1,
+
+    /// Native SegWit multisig (P2WSH), `2'`
+    #[display("2")]
+    SegWit = 2,
+}
+
+/// Standard derivation schemes for HD wallets
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display)]
+pub enum DerivationScheme {
+    /// BIP44: legacy P2PKH single-sig, `m/44'/coin'/account'/change`
+    #[display("bip44")]
+    Bip44,
+
+    /// BIP45: legacy P2SH multisig, `m/45'/cosigner/change`
+    #[display("bip45")]
+    Bip45,
+
+    /// BIP48: SegWit multisig,
+    /// `m/48'/coin'/account'/script_type'/change`
+    #[display("bip48-{0}")]
+    Bip48(Bip48ScriptType),
+
+    /// BIP49: nested SegWit single-sig (P2SH-P2WPKH),
+    /// `m/49'/coin'/account'/change`
+    #[display("bip49")]
+    Bip49,
+
+    /// BIP84: native SegWit single-sig (P2WPKH),
+    /// `m/84'/coin'/account'/change`
+    #[display("bip84")]
+    Bip84,
+
+    /// BIP86: single-key taproot (P2TR), `m/86'/coin'/account'/change`
+    #[display("bip86")]
+    Bip86,
+
+    /// BIP87: generic multisig for descriptor wallets,
+    /// `m/87'/coin'/account'/change`
+    #[display("bip87")]
+    Bip87,
+}
+
+#[derive(
+    Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display, Error,
+)]
+#[display("unknown derivation scheme")]
+pub struct SchemeParseError;
+
+impl FromStr for DerivationScheme {
+    type Err = SchemeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "bip44" => DerivationScheme::Bip44,
+            "bip45" => DerivationScheme::Bip45,
+            "bip48-1" => DerivationScheme::Bip48(Bip48ScriptType::Nested),
+            "bip48-2" => DerivationScheme::Bip48(Bip48ScriptType::SegWit),
+            "bip49" => DerivationScheme::Bip49,
+            "bip84" => DerivationScheme::Bip84,
+            "bip86" => DerivationScheme::Bip86,
+            "bip87" => DerivationScheme::Bip87,
+            _ => return Err(SchemeParseError),
+        })
+    }
+}
+
+impl DerivationScheme {
+    pub fn purpose(self) -> u32 {
+        match self {
+            DerivationScheme::Bip44 => 44,
+            DerivationScheme::Bip45 => 45,
+            DerivationScheme::Bip48(_) => 48,
+            DerivationScheme::Bip49 => 49,
+            DerivationScheme::Bip84 => 84,
+            DerivationScheme::Bip86 => 86,
+            DerivationScheme::Bip87 => 87,
+        }
+    }
+
+    /// Whether the scheme is intended for multi-signature accounts
+    pub fn is_multisig(self) -> bool {
+        matches!(
+            self,
+            DerivationScheme::Bip45
+                | DerivationScheme::Bip48(_)
+                | DerivationScheme::Bip87
+        )
+    }
+
+    /// BIP45 does not use coin type and hardened account levels; its second
+    /// level is an unhardened cosigner index
+    pub fn has_coin_type(self) -> bool {
+        self != DerivationScheme::Bip45
+    }
+
+    /// Number of derivation levels preceding the change/receive level
+    pub fn account_depth(self) -> usize {
+        match self {
+            DerivationScheme::Bip45 => 2,
+            DerivationScheme::Bip48(_) => 4,
+            _ => 3,
+        }
+    }
+
+    /// SLIP-132 application defining version bytes for the extended keys
+    /// derived with this scheme. Schemes which do not have dedicated
+    /// SLIP-132 versions use plain `xpub`/`tpub` encoding.
+    pub fn key_application(self) -> KeyApplication {
+        match self {
+            DerivationScheme::Bip49 => KeyApplication::Nested,
+            DerivationScheme::Bip84 => KeyApplication::SegWit,
+            DerivationScheme::Bip48(Bip48ScriptType::Nested) => {
+                KeyApplication::NestedMultisig
+            }
+            DerivationScheme::Bip48(Bip48ScriptType::SegWit) => {
+                KeyApplication::SegWitMiltisig
+            }
+            _ => KeyApplication::Hashed,
+        }
+    }
+
+    /// Standard coin type for the network
+    pub fn coin_type(network: Network) -> u32 {
+        match network {
+            Network::Bitcoin => 0,
+            _ => 1,
+        }
+    }
+}
+
+/// Derivation path constructed according to one of the standard schemes
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[display("{scheme}:{coin_type}/{account}")]
+pub struct DerivationPreset {
+    pub scheme: DerivationScheme,
+
+    /// Coin type; ignored by BIP45
+    pub coin_type: u32,
+
+    /// Account number; for BIP45 this is the cosigner index
+    pub account: u32,
+
+    /// Change (`1`) or receive (`0`) branch, if the path goes down to this
+    /// level
+    pub change: Option<u32>,
+}
+
+impl DerivationPreset {
+    pub fn with(
+        scheme: DerivationScheme,
+        network: Network,
+        account: u32,
+    ) -> DerivationPreset {
+        DerivationPreset {
+            scheme,
+            coin_type: DerivationScheme::coin_type(network),
+            account,
+            change: None,
+        }
+    }
+
+    /// Hardened part of the derivation path, up to the account level
+    pub fn account_path(&self) -> DerivationPath {
+        let mut path = vec![ChildNumber::Hardened {
+            index: self.scheme.purpose(),
+        }];
+        match self.scheme {
+            DerivationScheme::Bip45 => {
+                path.push(ChildNumber::Normal {
+                    index: self.account,
+                });
+            }
+            scheme => {
+                path.push(ChildNumber::Hardened {
+                    index: self.coin_type,
+                });
+                path.push(ChildNumber::Hardened {
+                    index: self.account,
+                });
+                if let DerivationScheme::Bip48(script_type) = scheme {
+                    path.push(ChildNumber::Hardened {
+                        index: script_type as u32,
+                    });
+                }
+            }
+        }
+        path.into()
+    }
+
+    pub fn derivation_path(&self) -> DerivationPath {
+        let path = self.account_path();
+        match self.change {
+            Some(index) => path.into_child(ChildNumber::Normal { index }),
+            None => path,
+        }
+    }
+
+    /// Detects standard derivation scheme from a derivation path. The path
+    /// must either end at the account level or at the change level.
+    pub fn detect(path: &DerivationPath) -> Option<DerivationPreset> {
+        let path: &[ChildNumber] = path.as_ref();
+        let purpose = match path.first()? {
+            ChildNumber::Hardened { index } => *index,
+            ChildNumber::Normal { .. } => return None,
+        };
+        let hardened = |pos: usize| match path.get(pos) {
+            Some(ChildNumber::Hardened { index }) => Some(*index),
+            _ => None,
+        };
+        let normal = |pos: usize| match path.get(pos) {
+            Some(ChildNumber::Normal { index }) => Some(*index),
+            _ => None,
+        };
+
+        let scheme = match purpose {
+            44 => DerivationScheme::Bip44,
+            45 => DerivationScheme::Bip45,
+            48 => DerivationScheme::Bip48(match hardened(3)? {
+                1 => Bip48ScriptType::Nested,
+                2 => Bip48ScriptType::SegWit,
+                _ => return None,
+            }),
+            49 => DerivationScheme::Bip49,
+            84 => DerivationScheme::Bip84,
+            86 => DerivationScheme::Bip86,
+            87 => DerivationScheme::Bip87,
+            _ => return None,
+        };
+
+        let depth = scheme.account_depth();
+        let change = match path.len() {
+            len if len == depth => None,
+            len if len == depth + 1 => Some(normal(depth)?),
+            _ => return None,
+        };
+        let (coin_type, account) = if scheme.has_coin_type() {
+            (hardened(1)?, hardened(2)?)
+        } else {
+            (0, normal(1)?)
+        };
+
+        Some(DerivationPreset {
+            scheme,
+            coin_type,
+            account,
+            change,
+        })
+    }
+
+    pub fn detect_components(
+        components: &DerivationComponents,
+    ) -> Option<DerivationPreset> {
+        DerivationPreset::detect(&components.derivation_path())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn path(s: &str) -> DerivationPath {
+        DerivationPath::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn scheme_paths() {
+        let preset = DerivationPreset {
+            scheme: DerivationScheme::Bip84,
+            coin_type: 1,
+            account: 5,
+            change: Some(1),
+        };
+        assert_eq!(preset.account_path(), path("m/84'/1'/5'"));
+        assert_eq!(preset.derivation_path(), path("m/84'/1'/5'/1"));
+
+        let preset = DerivationPreset {
+            scheme: DerivationScheme::Bip48(Bip48ScriptType::SegWit),
+            coin_type: 0,
+            account: 0,
+            change: None,
+        };
+        assert_eq!(preset.derivation_path(), path("m/48'/0'/0'/2'"));
+
+        let preset = DerivationPreset {
+            scheme: DerivationScheme::Bip45,
+            coin_type: 0,
+            account: 3,
+            change: Some(0),
+        };
+        assert_eq!(preset.derivation_path(), path("m/45'/3/0"));
+    }
+
+    #[test]
+    fn scheme_detection() {
+        for s in &[
+            "m/44'/0'/0'",
+            "m/44'/0'/0'/0",
+            "m/45'/2/1",
+            "m/48'/1'/0'/1'",
+            "m/48'/0'/7'/2'/1",
+            "m/49'/1'/0'/0",
+            "m/84'/0'/12'",
+            "m/86'/0'/0'/1",
+            "m/87'/1'/0'",
+        ] {
+            let preset = DerivationPreset::detect(&path(s))
+                .unwrap_or_else(|| panic!("{} must be detected", s));
+            assert_eq!(preset.derivation_path(), path(s));
+        }
+    }
+
+    #[test]
+    fn non_standard_paths_are_not_detected() {
+        for s in &[
+            "m",
+            "m/84",
+            "m/84'/0'",
+            "m/84'/0/0'",
+            "m/84'/0'/0'/0/0",
+            "m/84'/0'/0'/0'",
+            "m/48'/0'/0'/3'",
+            "m/48'/0'/0'",
+            "m/45'/0'",
+            "m/340'/0'/0'",
+        ] {
+            assert_eq!(DerivationPreset::detect(&path(s)), None, "{}", s);
+        }
+    }
+
+    #[test]
+    fn scheme_names() {
+        for scheme in &[
+            DerivationScheme::Bip44,
+            DerivationScheme::Bip45,
+            DerivationScheme::Bip48(Bip48ScriptType::Nested),
+            DerivationScheme::Bip48(Bip48ScriptType::SegWit),
+            DerivationScheme::Bip49,
+            DerivationScheme::Bip84,
+            DerivationScheme::Bip86,
+            DerivationScheme::Bip87,
+        ] {
+            assert_eq!(
+                DerivationScheme::from_str(&scheme.to_string()),
+                Ok(*scheme)
+            );
+        }
+    }
+}
@@ -0,0 +1,264 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use bip39::{Language, Mnemonic};
+use bitcoin::util::bip32::{
+    self, DerivationPath, ExtendedPrivKey, ExtendedPubKey,
+};
+use bitcoin::Network;
+use wallet::bip32::{
+    DerivationComponents, DerivationRangeVec, HardenedNormalSplit,
+};
+use zeroize::Zeroize;
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// Invalid mnemonic: {0}
+    #[from]
+    Mnemonic(bip39::Error),
+
+    /// Unable to derive extended key from the seed: {0}
+    #[from]
+    Bip32(bip32::Error),
+}
+
+/// BIP39 seed, derived from a mnemonic and an optional passphrase.
+///
+/// Neither the mnemonic nor the passphrase are kept: the structure holds only
+/// the 64-byte seed, which is wiped from memory when the structure is
+/// dropped. The seed is never a part of the [`super::Profile`]; only public
+/// data derived from it are stored in the document.
+pub struct MnemonicSeed {
+    seed: [u8; 64],
+    language: Language,
+    word_count: usize,
+}
+
+impl Drop for MnemonicSeed {
+    fn drop(&mut self) {
+        self.seed.zeroize()
+    }
+}
+
+impl MnemonicSeed {
+    /// Parses mnemonic in any of the supported languages, validating its
+    /// checksum, and derives the seed using the provided passphrase (which
+    /// may be empty)
+    pub fn with(phrase: &str, passphrase: &str) -> Result<MnemonicSeed, Error> {
+        let mnemonic = Mnemonic::parse(phrase)?;
+        Ok(MnemonicSeed {
+            seed: mnemonic.to_seed(passphrase),
+            language: mnemonic.language(),
+            word_count: mnemonic.word_count(),
+        })
+    }
+
+    /// Whether a string looks like a mnemonic rather than like a
+    /// Base58-encoded key
+    pub fn is_mnemonic_like(s: &str) -> bool {
+        s.trim().split_whitespace().count() > 1
+    }
+
+    pub fn language(&self) -> Language {
+        self.language
+    }
+
+    pub fn word_count(&self) -> usize {
+        self.word_count
+    }
+
+    pub fn master_xpriv(
+        &self,
+        network: Network,
+    ) -> Result<ExtendedPrivKey, Error> {
+        Ok(ExtendedPrivKey::new_master(network, &self.seed)?)
+    }
+
+    pub fn master_xpub(
+        &self,
+        network: Network,
+    ) -> Result<ExtendedPubKey, Error> {
+        Ok(ExtendedPubKey::from_private(
+            &wallet::SECP256K1,
+            &self.master_xpriv(network)?,
+        ))
+    }
+
+    /// Derives watch-only key components for the derivation path. The
+    /// hardened part of the path is derived from the seed, so the resulting
+    /// components do not require account-level extended public key.
+    pub fn derivation_components(
+        &self,
+        network: Network,
+        derivation: &DerivationPath,
+        index_ranges: Option<DerivationRangeVec>,
+    ) -> Result<DerivationComponents, Error> {
+        let master = self.master_xpriv(network)?;
+        let (branch_path, terminal_path) = derivation.hardened_normal_split();
+        let branch_xpriv =
+            master.derive_priv(&wallet::SECP256K1, &branch_path)?;
+        Ok(DerivationComponents {
+            master_xpub: ExtendedPubKey::from_private(
+                &wallet::SECP256K1,
+                &master,
+            ),
+            branch_path,
+            branch_xpub: ExtendedPubKey::from_private(
+                &wallet::SECP256K1,
+                &branch_xpriv,
+            ),
+            terminal_path,
+            index_ranges,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+
+    // Official BIP39 test vectors, all using "TREZOR" passphrase
+    const VECTORS: [(&str, &str, &str); 6] = [
+        (
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04",
+            "xprv9s21ZrQH143K3h3fDYiay8mocZ3afhfULfb5GX8kCBdno77K4HiA15Tg23wpbeF1pLfs1c5SPmYHrEpTuuRhxMwvKDwqdKiGJS9XFKzUsAF",
+        ),
+        (
+            "legal winner thank year wave sausage worth useful legal winner thank yellow",
+            "2e8905819b8723fe2c1d161860e5ee1830318dbf49a83bd451cfb8440c28bd6fa457fe1296106559a3c80937a1c1069be3a3a5bd381ee6260e8d9739fce1f607",
+            "xprv9s21ZrQH143K2gA81bYFHqU68xz1cX2APaSq5tt6MFSLeXnCKV1RVUJt9FWNTbrrryem4ZckN8k4Ls1H6nwdvDTvnV7zEXs2HgPezuVccsq",
+        ),
+        (
+            "letter advice cage absurd amount doctor acoustic avoid letter advice cage above",
+            "d71de856f81a8acc65e6fc851a38d4d7ec216fd0796d0a6827a3ad6ed5511a30fa280f12eb2e47ed2ac03b5c462a0358d18d69fe4f985ec81778c1b370b652a8",
+            "xprv9s21ZrQH143K2shfP28KM3nr5Ap1SXjz8gc2rAqqMEynmjt6o1qboCDpxckqXavCwdnYds6yBHZGKHv7ef2eTXy461PXUjBFQg6PrwY4Gzq",
+        ),
+        (
+            "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo wrong",
+            "ac27495480225222079d7be181583751e86f571027b0497b5b5d11218e0a8a13332572917f0f8e5a589620c6f15b11c61dee327651a14c34e18231052e48c069",
+            "xprv9s21ZrQH143K2V4oox4M8Zmhi2Fjx5XK4Lf7GKRvPSgydU3mjZuKGCTg7UPiBUD7ydVPvSLtg9hjp7MQTYsW67rZHAXeccqYqrsx8LcXnyd",
+        ),
+        (
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon art",
+            "bda85446c68413707090a52022edd26a1c9462295029f2e60cd7c4f2bbd3097170af7a4d73245cafa9c3cca8d561a7c3de6f5d4a10be8ed2a5e608d68f92fcc8",
+            "xprv9s21ZrQH143K32qBagUJAMU2LsHg3ka7jqMcV98Y7gVeVyNStwYS3U7yVVoDZ4btbRNf4h6ibWpY22iRmXq35qgLs79f312g2kj5539ebPM",
+        ),
+        (
+            "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo vote",
+            "dd48c104698c30cfe2b6142103248622fb7bb0ff692eebb00089b32d22484e1613912f0a5b694407be899ffd31ed3992c456cdf60f5d4564b8ba3f05a69890ad",
+            "xprv9s21ZrQH143K2WFF16X85T2QCpndrGwx6GueB72Zf3AHwHJaknRXNF37ZmDrtHrrLSHvbuRejXcnYxoZKvRquTPyp2JiNG3XcjQyzSEgqCB",
+        ),
+    ];
+
+    #[test]
+    fn bip39_vectors() {
+        for (phrase, seed, xprv) in &VECTORS {
+            let mnemonic_seed = MnemonicSeed::with(phrase, "TREZOR").unwrap();
+            assert_eq!(mnemonic_seed.language(), Language::English);
+            assert_eq!(
+                mnemonic_seed.word_count(),
+                phrase.split_whitespace().count()
+            );
+            assert_eq!(
+                bitcoin::hashes::hex::ToHex::to_hex(&mnemonic_seed.seed[..]),
+                *seed
+            );
+            assert_eq!(
+                mnemonic_seed
+                    .master_xpriv(Network::Bitcoin)
+                    .unwrap()
+                    .to_string(),
+                *xprv
+            );
+        }
+    }
+
+    #[test]
+    fn bip39_checksum() {
+        assert!(matches!(
+            MnemonicSeed::with(
+                "abandon abandon abandon abandon abandon abandon abandon \
+                 abandon abandon abandon abandon abandon",
+                ""
+            ),
+            Err(Error::Mnemonic(bip39::Error::InvalidChecksum))
+        ));
+        assert!(matches!(
+            MnemonicSeed::with(
+                "abandon abandon abandon abandon abandon abandon abandon \
+                 abandon abandon abandon abandon bitcoinpro",
+                ""
+            ),
+            Err(Error::Mnemonic(_))
+        ));
+        assert!(matches!(
+            MnemonicSeed::with("abandon about", ""),
+            Err(Error::Mnemonic(bip39::Error::BadWordCount(2)))
+        ));
+    }
+
+    #[test]
+    fn bip39_watch_only_components() {
+        let seed = MnemonicSeed::with(VECTORS[0].0, "").unwrap();
+        let components = seed
+            .derivation_components(
+                Network::Testnet,
+                &DerivationPath::from_str("m/84'/1'/0'/0").unwrap(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            components.master_xpub.fingerprint().to_string(),
+            "73c5da0a"
+        );
+        assert_eq!(components.branch_path.to_string(), "m/84'/1'/0'");
+        assert_eq!(components.terminal_path, vec![0]);
+        assert_eq!(
+            components.branch_xpub.to_string(),
+            "tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M"
+        );
+        assert!(!components.to_string().contains("prv"));
+    }
+
+    #[test]
+    fn bip39_languages() {
+        for language in &[
+            Language::English,
+            Language::Czech,
+            Language::Japanese,
+            Language::Korean,
+            Language::Spanish,
+        ] {
+            let mnemonic =
+                Mnemonic::from_entropy_in(*language, &[0x7f; 16]).unwrap();
+            let seed = MnemonicSeed::with(&mnemonic.to_string(), "").unwrap();
+            assert_eq!(seed.language(), *language);
+            assert_eq!(seed.word_count(), 12);
+            assert_eq!(seed.seed, mnemonic.to_seed(""));
+        }
+    }
+
+    #[test]
+    fn mnemonic_detection() {
+        assert!(MnemonicSeed::is_mnemonic_like(VECTORS[3].0));
+        assert!(!MnemonicSeed::is_mnemonic_like(VECTORS[3].2));
+    }
+}
@@ -15,8 +15,63 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+use std::collections::BTreeMap;
+
+use bitcoin::util::bip32::{DerivationPath, Fingerprint};
+use bitcoin::{Network, PublicKey};
+use lnpbp::Chain;
+use wallet::bip32::DerivationComponents;
 use wallet::descriptor;
 
+use crate::util::xkey;
+
+/// Tracking account which extended keys belong to a network different from
+/// the one used by the document
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(
+    "tracking account '{account}' uses {network} extended keys, which can't \
+     be used on {chain}"
+)]
+pub struct NetworkMismatch {
+    pub account: String,
+    pub network: Network,
+    pub chain: Chain,
+}
+
+/// Origin of an extended key: fingerprint of the master key and derivation
+/// path from it to the extended key
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[strict_encoding_crate(lnpbp::strict_encoding)]
+pub struct KeyOrigin {
+    pub fingerprint: Fingerprint,
+    pub path: DerivationPath,
+}
+
+/// Origins of the extended keys imported without their master key, indexed
+/// by the public key of the extended key
+pub type KeyOrigins = BTreeMap<PublicKey, KeyOrigin>;
+
+impl KeyOrigin {
+    /// Origin of the branch key from the derivation components. It is taken
+    /// from the master key when the master key is known, and is looked up
+    /// among the imported key origins otherwise.
+    pub fn with_components(
+        components: &DerivationComponents,
+        origins: &KeyOrigins,
+    ) -> Option<KeyOrigin> {
+        if components.master_xpub != components.branch_xpub
+            || components.branch_xpub.depth == 0
+        {
+            Some(KeyOrigin {
+                fingerprint: components.master_xpub.fingerprint(),
+                path: components.branch_path.clone(),
+            })
+        } else {
+            origins.get(&components.branch_xpub.public_key).cloned()
+        }
+    }
+}
+
 #[derive(Getters, Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
 #[strict_encoding_crate(lnpbp::strict_encoding)]
 pub struct TrackingAccount {
@@ -32,4 +87,32 @@ impl TrackingAccount {
     pub fn count(&self) -> u32 {
         self.key.count()
     }
+
+    /// Networks of the extended keys used by the account: master key first,
+    /// followed by the branch key. Plain public keys are network-agnostic
+    /// and produce an empty list.
+    pub fn networks(&self) -> Vec<Network> {
+        match &self.key {
+            descriptor::SingleSig::XPubDerivable(components) => vec![
+                components.master_xpub.network,
+                components.branch_xpub.network,
+            ],
+            _ => vec![],
+        }
+    }
+
+    pub fn check_network(&self, chain: &Chain) -> Result<(), NetworkMismatch> {
+        match self
+            .networks()
+            .into_iter()
+            .find(|network| !xkey::is_network_compatible(*network, chain))
+        {
+            Some(network) => Err(NetworkMismatch {
+                account: self.name.clone(),
+                network,
+                chain: chain.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
 }
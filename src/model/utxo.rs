@@ -64,6 +64,16 @@ impl UtxoEntry {
         }
     }
 
+    /// Number of confirmations for the given blockchain tip height; zero
+    /// for unconfirmed outputs
+    pub fn confirmations(&self, tip: u32) -> u32 {
+        if self.height == 0 {
+            0
+        } else {
+            tip.saturating_sub(self.height) + 1
+        }
+    }
+
     pub fn has_match(&self, descriptor_account: &DescriptorAccount) -> bool {
         descriptor_account.generator.template == self.descriptor_template
             && descriptor_account
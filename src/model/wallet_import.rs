@@ -0,0 +1,908 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Import of watch-only wallets exported by other wallet software: Bitcoin
+//! Core `listdescriptors` output, Electrum wallet files and output
+//! descriptors exported by Sparrow and Specter
+
+use std::str::FromStr;
+
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::util::bip32::{DerivationPath, ExtendedPubKey, Fingerprint};
+use bitcoin::Network;
+use lnpbp::Chain;
+use serde_json::Value;
+use slip132::{FromSlip132, KeyApplication};
+use wallet::bip32::DerivationComponents;
+use wallet::descriptor;
+
+use super::{DescriptorAccount, KeyOrigin, KeyOrigins, TrackingAccount};
+use crate::util::desc_checksum;
+use crate::util::xkey::{self, XkeyFormat};
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// The file is not a wallet export of any supported format
+    UnknownFormat,
+
+    /// Wallet file does not contain any output descriptors
+    NoDescriptors,
+
+    /// Electrum wallet of type '{0}' can't be imported; only standard and
+    /// multi-signature wallets are supported
+    UnsupportedWallet(String),
+
+    /// Descriptor '{0}' is not supported; only single-key and
+    /// multi-signature descriptors with extended keys can be imported
+    UnsupportedDescriptor(String),
+
+    /// Invalid descriptor '{0}'
+    InvalidDescriptor(String),
+
+    /// Descriptor key '{0}' uses hardened derivation after the extended
+    /// public key
+    HardenedDerivation(String),
+
+    /// Descriptor key '{0}' has no wildcard derivation index
+    NoWildcard(String),
+
+    /// Descriptor keys use different number of derivation branches
+    BranchMismatch,
+
+    /// Invalid extended public key '{0}'
+    InvalidXpub(String),
+
+    /// Invalid derivation path '{0}'
+    InvalidPath(String),
+
+    /// Invalid master key fingerprint '{0}'
+    InvalidFingerprint(String),
+
+    /// Descriptor uses {0} extended keys, which can't be used on {1}
+    NetworkMismatch(Network, Chain),
+
+    /// {0}
+    #[from]
+    Checksum(desc_checksum::Error),
+}
+
+/// Format of the imported wallet file
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Display)]
+pub enum WalletFormat {
+    #[display("Bitcoin Core descriptors")]
+    BitcoinCore,
+
+    #[display("Electrum wallet")]
+    Electrum,
+
+    #[display("Specter wallet")]
+    Specter,
+
+    #[display("output descriptors")]
+    Descriptors,
+}
+
+/// Wallet file exported by other wallet software, with all its keys
+/// represented as output descriptors
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct WalletFile {
+    pub format: WalletFormat,
+    /// Wallet name, used as a prefix for the names of the created accounts
+    pub name: String,
+    /// Output descriptors, which may omit checksums
+    pub descriptors: Vec<String>,
+}
+
+/// Accounts which will be created by the wallet import
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct WalletImport {
+    pub tracking_accounts: Vec<TrackingAccount>,
+    pub descriptors: Vec<DescriptorAccount>,
+    /// Origins of the keys of the new tracking accounts, which are not kept
+    /// by the accounts themselves since the master keys are not known
+    pub key_origins: KeyOrigins,
+    /// Descriptors which were not imported, with the reason
+    pub skipped: Vec<(String, Error)>,
+}
+
+impl WalletFile {
+    /// Detects wallet file format and extracts descriptors from it. The name
+    /// is used if the file does not provide its own.
+    pub fn parse(name: &str, data: &str) -> Result<WalletFile, Error> {
+        let json = match serde_json::from_str::<Value>(data) {
+            Ok(json) => json,
+            Err(_) => return Self::parse_text(name, data),
+        };
+        // Output of `bitcoin-cli` may be wrapped into JSON-RPC response
+        let json = json.get("result").unwrap_or(&json);
+        let wallet = if json.get("descriptors").is_some() {
+            Self::parse_core(name, json)?
+        } else if json.get("wallet_type").is_some() {
+            Self::parse_electrum(name, json)?
+        } else if json.get("descriptor").is_some() {
+            Self::parse_specter(name, json)?
+        } else {
+            return Err(Error::UnknownFormat);
+        };
+        if wallet.descriptors.is_empty() {
+            return Err(Error::NoDescriptors);
+        }
+        Ok(wallet)
+    }
+
+    /// Descriptors, one per line; empty lines and `#` comments are ignored
+    fn parse_text(name: &str, data: &str) -> Result<WalletFile, Error> {
+        let descriptors = data
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        if descriptors.is_empty() {
+            return Err(Error::NoDescriptors);
+        }
+        if descriptors
+            .iter()
+            .any(|desc| !desc.ends_with(')') && !desc.contains(")#"))
+        {
+            return Err(Error::UnknownFormat);
+        }
+        Ok(WalletFile {
+            format: WalletFormat::Descriptors,
+            name: name.to_owned(),
+            descriptors,
+        })
+    }
+
+    fn parse_core(name: &str, json: &Value) -> Result<WalletFile, Error> {
+        let descriptors = json["descriptors"]
+            .as_array()
+            .ok_or(Error::UnknownFormat)?
+            .iter()
+            .map(|entry| entry["desc"].as_str().map(str::to_owned))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(Error::UnknownFormat)?;
+        Ok(WalletFile {
+            format: WalletFormat::BitcoinCore,
+            name: json["wallet_name"]
+                .as_str()
+                .filter(|name| !name.is_empty())
+                .unwrap_or(name)
+                .to_owned(),
+            descriptors,
+        })
+    }
+
+    /// Electrum wallet files do not contain descriptors, so they are
+    /// constructed from the keystores; script type is defined by SLIP-132
+    /// encoding of the extended keys
+    fn parse_electrum(name: &str, json: &Value) -> Result<WalletFile, Error> {
+        let wallet_type = json["wallet_type"].as_str().unwrap_or_default();
+        if let Some((m, n)) = wallet_type
+            .split_once("of")
+            .and_then(|(m, n)| Some((m.parse::<u8>().ok()?, n.parse().ok()?)))
+        {
+            if m == 0 || m > n {
+                return Err(Error::UnsupportedWallet(wallet_type.to_owned()));
+            }
+            let keystores = (1..=n)
+                .map(|no: u8| &json[format!("x{}/", no)])
+                .collect::<Vec<_>>();
+            return Self::electrum_multisig(name, m, &keystores);
+        } else if wallet_type != "standard" {
+            return Err(Error::UnsupportedWallet(wallet_type.to_owned()));
+        }
+        let (key, application) = electrum_key(&json["keystore"])?;
+        let key = format!("{}/<0;1>/*", key);
+        let descriptor = match application {
+            KeyApplication::Hashed => format!("pkh({})", key),
+            KeyApplication::Nested | KeyApplication::NestedMultisig => {
+                format!("sh(wpkh({}))", key)
+            }
+            KeyApplication::SegWit | KeyApplication::SegWitMiltisig => {
+                format!("wpkh({})", key)
+            }
+        };
+        Ok(WalletFile {
+            format: WalletFormat::Electrum,
+            name: name.to_owned(),
+            descriptors: vec![descriptor],
+        })
+    }
+
+    fn electrum_multisig(
+        name: &str,
+        threshold: u8,
+        keystores: &[&Value],
+    ) -> Result<WalletFile, Error> {
+        let keys = keystores
+            .iter()
+            .map(|keystore| electrum_key(keystore))
+            .collect::<Result<Vec<_>, _>>()?;
+        let multi = format!(
+            "sortedmulti({},{})",
+            threshold,
+            keys.iter()
+                .map(|(key, _)| format!("{}/<0;1>/*", key))
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+        let descriptor = match keys[0].1 {
+            KeyApplication::Hashed => format!("sh({})", multi),
+            KeyApplication::Nested | KeyApplication::NestedMultisig => {
+                format!("sh(wsh({}))", multi)
+            }
+            KeyApplication::SegWit | KeyApplication::SegWitMiltisig => {
+                format!("wsh({})", multi)
+            }
+        };
+        Ok(WalletFile {
+            format: WalletFormat::Electrum,
+            name: name.to_owned(),
+            descriptors: vec![descriptor],
+        })
+    }
+
+    fn parse_specter(name: &str, json: &Value) -> Result<WalletFile, Error> {
+        let descriptor =
+            json["descriptor"].as_str().ok_or(Error::UnknownFormat)?;
+        Ok(WalletFile {
+            format: WalletFormat::Specter,
+            name: json["label"]
+                .as_str()
+                .filter(|name| !name.is_empty())
+                .unwrap_or(name)
+                .to_owned(),
+            descriptors: vec![descriptor.to_owned()],
+        })
+    }
+
+    /// Creates accounts for all supported descriptors of the wallet, with
+    /// separate accounts for receive and change branches.
+    ///
+    /// Keys already present among the known tracking accounts (with the same
+    /// extended key and terminal derivation path) are reused, and
+    /// descriptors already present in the document are skipped. New tracking
+    /// accounts keep only the extended key from the descriptor, since the
+    /// master key is not known; origins of their keys are returned
+    /// separately. Descriptors with keys for a network other than the one
+    /// used by the chain are skipped.
+    pub fn to_accounts(
+        &self,
+        known_tracking: &[TrackingAccount],
+        known_descriptors: &[DescriptorAccount],
+        chain: &Chain,
+    ) -> WalletImport {
+        let mut import = WalletImport::default();
+        for desc in &self.descriptors {
+            let parsed = match ParsedDescriptor::parse(desc) {
+                Ok(parsed) => parsed,
+                Err(err) => {
+                    import.skipped.push((desc.clone(), err));
+                    continue;
+                }
+            };
+            if let Some(key) = parsed.keys.iter().find(|key| {
+                !xkey::is_network_compatible(key.xpub.network, chain)
+            }) {
+                import.skipped.push((
+                    desc.clone(),
+                    Error::NetworkMismatch(key.xpub.network, chain.clone()),
+                ));
+                continue;
+            }
+            for branch in 0..parsed.branch_count() {
+                let terminal_path = &parsed.keys[0].branches[branch];
+                let mut name =
+                    format!("{} {}", self.name, parsed.script_name());
+                if terminal_path.last() == Some(&1) {
+                    name += " change";
+                }
+                let pubkeys = parsed
+                    .keys
+                    .iter()
+                    .enumerate()
+                    .map(|(no, key)| {
+                        let key_name = if parsed.threshold.is_some() {
+                            format!(
+                                "{} cosigner #{} [{}]",
+                                name,
+                                no + 1,
+                                key.fingerprint()
+                            )
+                        } else {
+                            name.clone()
+                        };
+                        import.resolve_key(
+                            known_tracking,
+                            key.single_sig(branch),
+                            key.origin.clone(),
+                            key_name,
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                let template = match parsed.threshold {
+                    None => descriptor::Template::SingleSig(pubkeys[0].clone()),
+                    Some((threshold, reorder)) => {
+                        descriptor::Template::MultiSig(descriptor::MultiSig {
+                            threshold: Some(threshold),
+                            pubkeys,
+                            reorder,
+                        })
+                    }
+                };
+                let account = DescriptorAccount {
+                    name,
+                    generator: descriptor::Generator {
+                        template,
+                        variants: parsed.variants.clone(),
+                    },
+                };
+                if known_descriptors
+                    .iter()
+                    .chain(&import.descriptors)
+                    .all(|known| known.generator != account.generator)
+                {
+                    import.descriptors.push(account);
+                }
+            }
+        }
+        import
+    }
+}
+
+impl WalletImport {
+    fn resolve_key(
+        &mut self,
+        known: &[TrackingAccount],
+        key: descriptor::SingleSig,
+        origin: Option<KeyOrigin>,
+        name: String,
+    ) -> descriptor::SingleSig {
+        let same_key = |account: &&TrackingAccount| match (&account.key, &key) {
+            (
+                descriptor::SingleSig::XPubDerivable(known),
+                descriptor::SingleSig::XPubDerivable(new),
+            ) => {
+                known.branch_xpub.public_key == new.branch_xpub.public_key
+                    && known.branch_xpub.chain_code
+                        == new.branch_xpub.chain_code
+                    && known.terminal_path == new.terminal_path
+            }
+            _ => false,
+        };
+        if let Some(account) =
+            known.iter().chain(&self.tracking_accounts).find(same_key)
+        {
+            return account.key.clone();
+        }
+        if let (
+            Some(origin),
+            descriptor::SingleSig::XPubDerivable(components),
+        ) = (origin, &key)
+        {
+            self.key_origins
+                .insert(components.branch_xpub.public_key, origin);
+        }
+        self.tracking_accounts.push(TrackingAccount {
+            name,
+            key: key.clone(),
+        });
+        key
+    }
+}
+
+/// Extended key from a descriptor key expression
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct KeyExpression {
+    origin: Option<KeyOrigin>,
+    xpub: ExtendedPubKey,
+    /// Unhardened derivation paths between the extended key and the
+    /// wildcard index; multipath expressions provide more than one
+    branches: Vec<Vec<u32>>,
+}
+
+impl KeyExpression {
+    fn parse(s: &str) -> Result<KeyExpression, Error> {
+        let s = s.trim();
+        let (origin, key) = match s.strip_prefix('[') {
+            Some(rest) => {
+                let (origin, key) = rest
+                    .split_once(']')
+                    .ok_or_else(|| Error::InvalidDescriptor(s.to_owned()))?;
+                let (fingerprint, path) =
+                    origin.split_once('/').unwrap_or((origin, ""));
+                let fingerprint =
+                    Fingerprint::from_hex(&fingerprint.to_lowercase())
+                        .map_err(|_| {
+                            Error::InvalidFingerprint(fingerprint.to_owned())
+                        })?;
+                let path = if path.is_empty() {
+                    DerivationPath::from(vec![])
+                } else {
+                    DerivationPath::from_str(&format!(
+                        "m/{}",
+                        path.replace('h', "'")
+                    ))
+                    .map_err(|_| Error::InvalidPath(path.to_owned()))?
+                };
+                (Some(KeyOrigin { fingerprint, path }), key)
+            }
+            None => (None, s),
+        };
+        let mut segments = key.split('/');
+        let xpub = segments.next().unwrap_or_default();
+        let xpub = ExtendedPubKey::from_slip132_str(xpub)
+            .map_err(|_| Error::InvalidXpub(xpub.to_owned()))?;
+        let segments = segments.collect::<Vec<_>>();
+        match segments.last() {
+            Some(&"*") => {}
+            Some(last) if last.starts_with('*') => {
+                return Err(Error::HardenedDerivation(s.to_owned()))
+            }
+            _ => return Err(Error::NoWildcard(s.to_owned())),
+        }
+        let mut branches = vec![vec![]];
+        for segment in &segments[..segments.len() - 1] {
+            let alternatives = match segment
+                .strip_prefix('<')
+                .and_then(|s| s.strip_suffix('>'))
+                .map(|s| s.split(';'))
+            {
+                Some(alternatives) => alternatives.collect::<Vec<_>>(),
+                None => match segment
+                    .strip_prefix('{')
+                    .and_then(|s| s.strip_suffix('}'))
+                {
+                    Some(alternatives) => alternatives.split(',').collect(),
+                    None => vec![*segment],
+                },
+            };
+            let indexes = alternatives
+                .into_iter()
+                .map(|index| {
+                    if index.ends_with(|c| c == '\'' || c == 'h') {
+                        Err(Error::HardenedDerivation(s.to_owned()))
+                    } else {
+                        index
+                            .parse::<u32>()
+                            .ok()
+                            .filter(|index| *index < (1 << 31))
+                            .ok_or_else(|| {
+                                Error::InvalidDescriptor(s.to_owned())
+                            })
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if indexes.len() > 1 && branches.len() > 1 {
+                return Err(Error::InvalidDescriptor(s.to_owned()));
+            }
+            branches = indexes
+                .iter()
+                .flat_map(|index| {
+                    branches.iter().map(move |branch| {
+                        let mut branch = branch.clone();
+                        branch.push(*index);
+                        branch
+                    })
+                })
+                .collect();
+        }
+        Ok(KeyExpression {
+            origin,
+            xpub,
+            branches,
+        })
+    }
+
+    fn fingerprint(&self) -> Fingerprint {
+        self.origin
+            .as_ref()
+            .map(|origin| origin.fingerprint)
+            .unwrap_or_else(|| self.xpub.fingerprint())
+    }
+
+    fn single_sig(&self, branch: usize) -> descriptor::SingleSig {
+        descriptor::SingleSig::XPubDerivable(DerivationComponents {
+            master_xpub: self.xpub,
+            branch_path: DerivationPath::from(vec![]),
+            branch_xpub: self.xpub,
+            terminal_path: self.branches[branch].clone(),
+            index_ranges: None,
+        })
+    }
+}
+
+/// Single-key or multi-signature descriptor
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct ParsedDescriptor {
+    variants: descriptor::Variants,
+    /// Multi-signature threshold and whether keys are sorted; `None` for
+    /// single-key descriptors
+    threshold: Option<(u8, bool)>,
+    keys: Vec<KeyExpression>,
+}
+
+impl ParsedDescriptor {
+    fn parse(descriptor: &str) -> Result<ParsedDescriptor, Error> {
+        let desc = desc_checksum::verify(descriptor.trim())?;
+        let unsupported = || Error::UnsupportedDescriptor(desc.to_owned());
+        let (function, inner) = split_call(desc).ok_or_else(unsupported)?;
+        let (variants, multisig, inner) = match function {
+            "sh" => match split_call(inner) {
+                Some(("wpkh", _)) => {
+                    (variants(false, true, false, false), false, inner)
+                }
+                Some(("wsh", inner)) => {
+                    (variants(false, true, false, false), true, inner)
+                }
+                _ => (variants(true, false, false, false), true, inner),
+            },
+            "pkh" => (variants(true, false, false, false), false, desc),
+            "wpkh" => (variants(false, false, true, false), false, desc),
+            "wsh" => (variants(false, false, true, false), true, inner),
+            "tr" => (variants(false, false, false, true), false, desc),
+            _ => return Err(unsupported()),
+        };
+        let (function, args) = split_call(inner).ok_or_else(unsupported)?;
+        let args = split_args(args);
+        let parsed = match (multisig, function) {
+            (false, "pkh" | "wpkh" | "tr") if args.len() == 1 => {
+                ParsedDescriptor {
+                    variants,
+                    threshold: None,
+                    keys: vec![KeyExpression::parse(args[0])?],
+                }
+            }
+            (true, "multi" | "sortedmulti") if args.len() > 1 => {
+                let threshold = args[0]
+                    .trim()
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|threshold| {
+                        *threshold > 0 && *threshold as usize <= args.len() - 1
+                    })
+                    .ok_or_else(|| Error::InvalidDescriptor(desc.to_owned()))?;
+                ParsedDescriptor {
+                    variants,
+                    threshold: Some((threshold, function == "sortedmulti")),
+                    keys: args[1..]
+                        .iter()
+                        .map(|key| KeyExpression::parse(key))
+                        .collect::<Result<_, _>>()?,
+                }
+            }
+            _ => return Err(unsupported()),
+        };
+        if parsed
+            .keys
+            .iter()
+            .any(|key| key.branches.len() != parsed.branch_count())
+        {
+            return Err(Error::BranchMismatch);
+        }
+        Ok(parsed)
+    }
+
+    fn branch_count(&self) -> usize {
+        self.keys[0].branches.len()
+    }
+
+    fn script_name(&self) -> &'static str {
+        let variants = &self.variants;
+        match (self.threshold.is_some(), variants.hashed, variants.nested) {
+            _ if variants.taproot => "P2TR",
+            (false, true, _) => "P2PKH",
+            (false, _, true) => "P2SH-P2WPKH",
+            (false, ..) => "P2WPKH",
+            (true, true, _) => "P2SH",
+            (true, _, true) => "P2SH-P2WSH",
+            (true, ..) => "P2WSH",
+        }
+    }
+}
+
+fn variants(
+    hashed: bool,
+    nested: bool,
+    segwit: bool,
+    taproot: bool,
+) -> descriptor::Variants {
+    descriptor::Variants {
+        bare: false,
+        hashed,
+        nested,
+        segwit,
+        taproot,
+    }
+}
+
+/// Splits `function(args)` into function name and its arguments
+fn split_call(s: &str) -> Option<(&str, &str)> {
+    let (function, rest) = s.split_once('(')?;
+    Some((function, rest.strip_suffix(')')?))
+}
+
+/// Splits comma-separated arguments, ignoring commas inside nested brackets
+fn split_args(s: &str) -> Vec<&str> {
+    let mut args = vec![];
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (pos, ch) in s.char_indices() {
+        match ch {
+            '(' | '[' | '{' | '<' => depth += 1,
+            ')' | ']' | '}' | '>' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                args.push(&s[start..pos]);
+                start = pos + 1;
+            }
+            _ => {}
+        }
+    }
+    args.push(&s[start..]);
+    args
+}
+
+/// Extracts key expression (without derivation suffix) and key application
+/// from Electrum keystore
+fn electrum_key(keystore: &Value) -> Result<(String, KeyApplication), Error> {
+    let xpub = keystore["xpub"].as_str().ok_or(Error::UnknownFormat)?;
+    let application = XkeyFormat::detect(xpub)
+        .map_err(|_| Error::InvalidXpub(xpub.to_owned()))?
+        .application;
+    let key = ExtendedPubKey::from_slip132_str(xpub)
+        .map_err(|_| Error::InvalidXpub(xpub.to_owned()))?;
+    let origin = match (
+        keystore["root_fingerprint"].as_str(),
+        keystore["derivation"].as_str(),
+    ) {
+        (Some(fingerprint), Some(derivation)) => {
+            format!("[{}{}]", fingerprint, derivation.trim_start_matches('m'))
+        }
+        _ => s!(""),
+    };
+    Ok((format!("{}{}", origin, key), application))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::MnemonicSeed;
+    use crate::util::xkey;
+
+    const MNEMONICS: [&str; 2] = [
+        "abandon abandon abandon abandon abandon abandon abandon abandon \
+         abandon abandon abandon about",
+        "legal winner thank year wave sausage worth useful legal winner thank \
+         yellow",
+    ];
+
+    fn components(no: usize, path: &str) -> DerivationComponents {
+        MnemonicSeed::with(MNEMONICS[no], "")
+            .unwrap()
+            .derivation_components(
+                Network::Testnet,
+                &DerivationPath::from_str(path).unwrap(),
+                None,
+            )
+            .unwrap()
+    }
+
+    fn key(no: usize, path: &str) -> String {
+        let components = components(no, path);
+        format!(
+            "[{}{}]{}",
+            components.master_xpub.fingerprint(),
+            components.branch_path.to_string().trim_start_matches('m'),
+            components.branch_xpub
+        )
+    }
+
+    fn terminal_path(key: &descriptor::SingleSig) -> Vec<u32> {
+        match key {
+            descriptor::SingleSig::XPubDerivable(components) => {
+                components.terminal_path.clone()
+            }
+            _ => panic!("import must produce extended keys"),
+        }
+    }
+
+    #[test]
+    fn bitcoin_core() {
+        let key = key(0, "m/84'/1'/0'");
+        let json = format!(
+            r#"{{"wallet_name": "node", "descriptors": [
+                {{"desc": "{}", "timestamp": 1, "active": true,
+                  "internal": false, "range": [0, 999], "next": 0}},
+                {{"desc": "wpkh({}/1/*)", "timestamp": 1, "active": true,
+                  "internal": true, "range": [0, 999], "next": 0}},
+                {{"desc": "wpkh({}/0/*')", "timestamp": 1, "active": false}},
+                {{"desc": "combo(02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443)",
+                  "timestamp": 1, "active": false}}
+            ]}}"#,
+            desc_checksum::with_checksum(&format!("wpkh({}/0/*)", key))
+                .unwrap(),
+            key,
+            key,
+        );
+        let wallet = WalletFile::parse("other", &json).unwrap();
+        assert_eq!(wallet.format, WalletFormat::BitcoinCore);
+        assert_eq!(wallet.name, "node");
+        assert_eq!(wallet.descriptors.len(), 4);
+
+        let import = wallet.to_accounts(&[], &[], &Chain::Testnet3);
+        assert_eq!(import.tracking_accounts.len(), 2);
+        assert_eq!(import.descriptors.len(), 2);
+        assert_eq!(import.descriptors[0].name, "node P2WPKH");
+        assert_eq!(import.descriptors[1].name, "node P2WPKH change");
+        assert_eq!(terminal_path(&import.tracking_accounts[1].key), vec![1]);
+        assert!(import.descriptors[0].generator.variants.segwit);
+        assert!(matches!(import.skipped[0].1, Error::HardenedDerivation(_)));
+        assert!(matches!(
+            import.skipped[1].1,
+            Error::UnsupportedDescriptor(_)
+        ));
+
+        // Key origin is kept aside from the tracking accounts
+        let components = components(0, "m/84'/1'/0'");
+        assert_eq!(
+            import.key_origins,
+            bmap! {
+                components.branch_xpub.public_key => KeyOrigin {
+                    fingerprint: components.master_xpub.fingerprint(),
+                    path: components.branch_path,
+                }
+            }
+        );
+
+        // Testnet keys can't be imported into a mainnet document
+        let mainnet = wallet.to_accounts(&[], &[], &Chain::Mainnet);
+        assert!(mainnet.descriptors.is_empty());
+        assert_eq!(
+            mainnet.skipped[0].1,
+            Error::NetworkMismatch(Network::Testnet, Chain::Mainnet)
+        );
+
+        // Repeated import reuses keys and skips existing descriptors
+        let again = wallet.to_accounts(
+            &import.tracking_accounts,
+            &import.descriptors,
+            &Chain::Testnet3,
+        );
+        assert!(again.tracking_accounts.is_empty());
+        assert!(again.descriptors.is_empty());
+
+        let tampered = json.replace("/0/*)#", "/2/*)#");
+        let import = WalletFile::parse("other", &tampered)
+            .unwrap()
+            .to_accounts(&[], &[], &Chain::Testnet3);
+        assert!(matches!(import.skipped[0].1, Error::Checksum(_)));
+    }
+
+    #[test]
+    fn electrum() {
+        let components = components(0, "m/84'/1'/0'");
+        let vpub = xkey::convert(
+            &components.branch_xpub.to_string(),
+            KeyApplication::SegWit,
+            true,
+        )
+        .unwrap();
+        let json = format!(
+            r#"{{"wallet_type": "standard", "keystore": {{"type": "bip32",
+                "xpub": "{}", "derivation": "m/84'/1'/0'",
+                "root_fingerprint": "73c5da0a"}}}}"#,
+            vpub
+        );
+        let wallet = WalletFile::parse("electrum", &json).unwrap();
+        assert_eq!(wallet.format, WalletFormat::Electrum);
+        assert_eq!(
+            wallet.descriptors,
+            vec![format!(
+                "wpkh([73c5da0a/84'/1'/0']{}/<0;1>/*)",
+                components.branch_xpub
+            )]
+        );
+        let import = wallet.to_accounts(&[], &[], &Chain::Testnet3);
+        assert!(import.skipped.is_empty());
+        assert_eq!(import.descriptors.len(), 2);
+        assert_eq!(import.descriptors[1].name, "electrum P2WPKH change");
+
+        let keystore = |no: usize| {
+            let components = components(no, "m/48'/1'/0'/1'");
+            format!(
+                r#"{{"type": "bip32", "xpub": "{}"}}"#,
+                xkey::convert(
+                    &components.branch_xpub.to_string(),
+                    KeyApplication::NestedMultisig,
+                    true,
+                )
+                .unwrap()
+            )
+        };
+        let json = format!(
+            r#"{{"wallet_type": "2of2", "x1/": {}, "x2/": {}}}"#,
+            keystore(0),
+            keystore(1)
+        );
+        let import = WalletFile::parse("vault", &json).unwrap().to_accounts(
+            &[],
+            &[],
+            &Chain::Testnet3,
+        );
+        assert_eq!(import.tracking_accounts.len(), 4);
+        assert_eq!(import.descriptors[0].name, "vault P2SH-P2WSH");
+        assert!(import.descriptors[0].generator.variants.nested);
+        assert!(import.tracking_accounts[1]
+            .name
+            .starts_with("vault P2SH-P2WSH cosigner #2 ["));
+
+        assert_eq!(
+            WalletFile::parse("x", r#"{"wallet_type": "imported"}"#),
+            Err(Error::UnsupportedWallet(s!("imported")))
+        );
+    }
+
+    #[test]
+    fn sparrow_and_specter() {
+        let multi = format!(
+            "wsh(sortedmulti(2,{}/<0;1>/*,{}/<0;1>/*))",
+            key(0, "m/48'/1'/0'/2'"),
+            key(1, "m/48'/1'/0'/2'")
+        );
+        let text = format!(
+            "# Sparrow wallet export\n\n{}\n",
+            desc_checksum::with_checksum(&multi).unwrap()
+        );
+        let wallet = WalletFile::parse("Vault", &text).unwrap();
+        assert_eq!(wallet.format, WalletFormat::Descriptors);
+        let import = wallet.to_accounts(&[], &[], &Chain::Testnet3);
+        assert_eq!(import.tracking_accounts.len(), 4);
+        assert_eq!(import.descriptors.len(), 2);
+        assert_eq!(import.descriptors[0].name, "Vault P2WSH");
+        match &import.descriptors[1].generator.template {
+            descriptor::Template::MultiSig(multisig) => {
+                assert_eq!(multisig.threshold, Some(2));
+                assert!(multisig.reorder);
+                assert_eq!(terminal_path(&multisig.pubkeys[0]), vec![1]);
+            }
+            _ => panic!("multi-signature descriptor expected"),
+        }
+
+        let json = format!(
+            r#"{{"label": "Specter vault", "blockheight": 100,
+                "descriptor": "{}"}}"#,
+            multi.replace("<0;1>", "{0,1}")
+        );
+        let wallet = WalletFile::parse("other", &json).unwrap();
+        assert_eq!(wallet.format, WalletFormat::Specter);
+        let known = import.tracking_accounts;
+        let import = wallet.to_accounts(&known, &[], &Chain::Testnet3);
+        assert!(import.tracking_accounts.is_empty());
+        assert_eq!(import.descriptors[0].name, "Specter vault P2WSH");
+
+        assert_eq!(
+            WalletFile::parse("x", "not a wallet"),
+            Err(Error::UnknownFormat)
+        );
+        assert_eq!(
+            WalletFile::parse("x", r#"{"descriptors": []}"#),
+            Err(Error::NoDescriptors)
+        );
+    }
+}
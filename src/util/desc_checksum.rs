@@ -0,0 +1,122 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Output script descriptor checksums, as defined in BIP380 and used by
+//! Bitcoin Core
+
+const INPUT_CHARSET: &str = "0123456789()[],'/*abcdefgh@:$%{}\
+                             IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~\
+                             ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const GENERATOR: [u64; 5] = [
+    0xf5dee51989,
+    0xa9fdca3312,
+    0x1bab10e32d,
+    0x3706b1677a,
+    0x644d626ffd,
+];
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// Descriptor contains character '{0}' which is not allowed
+    InvalidChar(char),
+
+    /// Descriptor checksum {0} does not match the computed checksum {1}
+    Mismatch(String, String),
+}
+
+fn poly_mod(mut c: u64, val: u64) -> u64 {
+    let c0 = c >> 35;
+    c = ((c & 0x7ffffffff) << 5) ^ val;
+    for (bit, generator) in GENERATOR.iter().enumerate() {
+        if c0 & (1 << bit) != 0 {
+            c ^= generator;
+        }
+    }
+    c
+}
+
+/// Computes 8-character checksum for the descriptor string
+pub fn checksum(desc: &str) -> Result<String, Error> {
+    let mut c = 1u64;
+    let mut class = 0u64;
+    let mut class_count = 0;
+    for ch in desc.chars() {
+        let pos = INPUT_CHARSET.find(ch).ok_or(Error::InvalidChar(ch))? as u64;
+        c = poly_mod(c, pos & 31);
+        class = class * 3 + (pos >> 5);
+        class_count += 1;
+        if class_count == 3 {
+            c = poly_mod(c, class);
+            class = 0;
+            class_count = 0;
+        }
+    }
+    if class_count > 0 {
+        c = poly_mod(c, class);
+    }
+    for _ in 0..8 {
+        c = poly_mod(c, 0);
+    }
+    c ^= 1;
+    Ok((0..8)
+        .map(|j| CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char)
+        .collect())
+}
+
+/// Appends checksum to the descriptor string
+pub fn with_checksum(desc: &str) -> Result<String, Error> {
+    Ok(format!("{}#{}", desc, checksum(desc)?))
+}
+
+/// Strips checksum from the descriptor string, verifying it. Descriptors
+/// without checksum are returned as is.
+pub fn verify(desc: &str) -> Result<&str, Error> {
+    match desc.rsplit_once('#') {
+        None => Ok(desc),
+        Some((desc, provided)) => {
+            let computed = checksum(desc)?;
+            if computed == provided {
+                Ok(desc)
+            } else {
+                Err(Error::Mismatch(provided.to_owned(), computed))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bip380_vectors() {
+        assert_eq!(checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+        assert_eq!(
+            with_checksum("addr(mkmZxiEcEd8ZqjQWVZuC6so5dFMKEFpN2j)").unwrap(),
+            "addr(mkmZxiEcEd8ZqjQWVZuC6so5dFMKEFpN2j)#02wpgw69"
+        );
+        assert_eq!(verify("raw(deadbeef)#89f8spxm"), Ok("raw(deadbeef)"));
+        assert_eq!(verify("raw(deadbeef)"), Ok("raw(deadbeef)"));
+        assert_eq!(
+            verify("raw(deadbeef)#89f8spxn"),
+            Err(Error::Mismatch(s!("89f8spxn"), s!("89f8spxm")))
+        );
+        assert_eq!(checksum("raw(dead\u{e9})"), Err(Error::InvalidChar('é')));
+    }
+}
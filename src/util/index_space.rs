@@ -0,0 +1,176 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use std::ops::RangeInclusive;
+
+use amplify::Wrapper;
+use wallet::bip32::{DerivationRangeVec, HARDENED_INDEX_BOUNDARY};
+
+/// Largest derivation index which is not hardened
+pub const MAX_UNHARDENED_INDEX: u32 = HARDENED_INDEX_BOUNDARY - 1;
+
+/// Set of unhardened derivation indexes which may be used by a key or a
+/// descriptor, kept as sorted non-overlapping inclusive ranges
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct IndexSpace(Vec<RangeInclusive<u32>>);
+
+impl Default for IndexSpace {
+    fn default() -> Self {
+        IndexSpace::full()
+    }
+}
+
+impl From<&DerivationRangeVec> for IndexSpace {
+    fn from(ranges: &DerivationRangeVec) -> Self {
+        IndexSpace::with(
+            ranges
+                .as_inner()
+                .iter()
+                .map(|range| range.as_inner().clone()),
+        )
+    }
+}
+
+impl IndexSpace {
+    /// Space covering all unhardened indexes
+    pub fn full() -> IndexSpace {
+        IndexSpace(vec![0..=MAX_UNHARDENED_INDEX])
+    }
+
+    /// Constructs index space from arbitrary ranges, removing hardened
+    /// indexes and merging overlapping ranges
+    pub fn with(
+        ranges: impl IntoIterator<Item = RangeInclusive<u32>>,
+    ) -> IndexSpace {
+        let mut ranges = ranges
+            .into_iter()
+            .filter(|range| {
+                !range.is_empty() && *range.start() <= MAX_UNHARDENED_INDEX
+            })
+            .map(|range| {
+                *range.start()..=(*range.end()).min(MAX_UNHARDENED_INDEX)
+            })
+            .collect::<Vec<_>>();
+        ranges.sort_by_key(|range| *range.start());
+
+        let mut merged: Vec<RangeInclusive<u32>> =
+            Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last)
+                    if *range.start() <= last.end().saturating_add(1) =>
+                {
+                    if range.end() > last.end() {
+                        *last = *last.start()..=*range.end();
+                    }
+                }
+                _ => merged.push(range),
+            }
+        }
+        IndexSpace(merged)
+    }
+
+    pub fn ranges(&self) -> &[RangeInclusive<u32>] {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Number of indexes within the space
+    pub fn count(&self) -> u64 {
+        self.0
+            .iter()
+            .map(|range| (*range.end() - *range.start()) as u64 + 1)
+            .sum()
+    }
+
+    pub fn contains(&self, index: u32) -> bool {
+        self.0.iter().any(|range| range.contains(&index))
+    }
+
+    /// Returns `n`-th index of the space, counting from the lowest one
+    pub fn nth(&self, mut n: u64) -> Option<u32> {
+        for range in &self.0 {
+            let len = (*range.end() - *range.start()) as u64 + 1;
+            if n < len {
+                return Some(*range.start() + n as u32);
+            }
+            n -= len;
+        }
+        None
+    }
+
+    /// Indexes present in both spaces
+    pub fn intersect(&self, other: &IndexSpace) -> IndexSpace {
+        let mut ranges = vec![];
+        for a in &self.0 {
+            for b in &other.0 {
+                let start = *a.start().max(b.start());
+                let end = *a.end().min(b.end());
+                if start <= end {
+                    ranges.push(start..=end);
+                }
+            }
+        }
+        IndexSpace::with(ranges)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn index_space_drops_hardened_indexes() {
+        let space = IndexSpace::with(vec![
+            10..=20,
+            MAX_UNHARDENED_INDEX - 1..=u32::MAX,
+            HARDENED_INDEX_BOUNDARY..=u32::MAX,
+        ]);
+        assert_eq!(
+            space.ranges(),
+            &[10..=20, MAX_UNHARDENED_INDEX - 1..=MAX_UNHARDENED_INDEX]
+        );
+        assert_eq!(space.count(), 13);
+        assert!(!space.contains(HARDENED_INDEX_BOUNDARY));
+    }
+
+    #[test]
+    fn index_space_merges_ranges() {
+        let space = IndexSpace::with(vec![5..=9, 0..=3, 4..=4, 20..=30]);
+        assert_eq!(space.ranges(), &[0..=9, 20..=30]);
+        assert_eq!(space.nth(0), Some(0));
+        assert_eq!(space.nth(9), Some(9));
+        assert_eq!(space.nth(10), Some(20));
+        assert_eq!(space.nth(20), Some(30));
+        assert_eq!(space.nth(21), None);
+    }
+
+    #[test]
+    fn index_space_intersection() {
+        let a = IndexSpace::with(vec![0..=100, 200..=300]);
+        let b = IndexSpace::with(vec![50..=250]);
+        assert_eq!(a.intersect(&b).ranges(), &[50..=100, 200..=250]);
+        assert_eq!(a.intersect(&IndexSpace::full()), a);
+
+        let c = IndexSpace::with(vec![101..=199]);
+        assert!(a.intersect(&c).is_empty());
+        assert_eq!(a.intersect(&c).nth(0), None);
+    }
+}
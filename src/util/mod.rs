@@ -15,4 +15,7 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+pub mod desc_checksum;
+pub mod index_space;
 pub mod resolver_mode;
+pub mod xkey;
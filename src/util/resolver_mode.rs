@@ -15,6 +15,7 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+use std::collections::HashSet;
 use std::num::ParseIntError;
 use std::ops::Range;
 use std::str::FromStr;
@@ -22,6 +23,8 @@ use std::str::FromStr;
 use bitcoin::secp256k1::rand::{rngs::ThreadRng, thread_rng, RngCore};
 use wallet::bip32::{ChildIndex, UnhardenedIndex};
 
+use super::index_space::IndexSpace;
+
 #[derive(Clone, PartialEq, Eq, Debug, Display, From, Error)]
 #[display(doc_comments)]
 pub enum ParseError {
@@ -98,12 +101,27 @@ impl ResolverModeType {
     pub fn is_random(self) -> bool {
         matches!(self, ResolverModeType::Random(_))
     }
+
+    /// Iterates over derivation indexes restricted to the provided index
+    /// space. Sequential modes take the lowest indexes of the space, random
+    /// mode samples distinct indexes from the whole space.
+    pub fn iter_within(self, space: IndexSpace) -> ResolverModeIter {
+        ResolverModeIter {
+            mode: self,
+            rand: thread_rng(),
+            offset: self.range().start,
+            space,
+            used: empty!(),
+        }
+    }
 }
 
 pub struct ResolverModeIter {
     mode: ResolverModeType,
     rand: ThreadRng,
     offset: u32,
+    space: IndexSpace,
+    used: HashSet<u32>,
 }
 
 impl IntoIterator for ResolverModeType {
@@ -111,11 +129,7 @@ impl IntoIterator for ResolverModeType {
     type IntoIter = ResolverModeIter;
 
     fn into_iter(self) -> Self::IntoIter {
-        ResolverModeIter {
-            mode: self,
-            rand: thread_rng(),
-            offset: self.range().start,
-        }
+        self.iter_within(IndexSpace::full())
     }
 }
 
@@ -124,15 +138,88 @@ impl Iterator for ResolverModeIter {
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.offset >= self.mode.range().end {
-            None
-        } else {
-            let index = if self.mode.is_random() {
-                self.rand.next_u32()
-            } else {
-                self.offset
-            };
-            self.offset += 1;
-            Some(index)
+            return None;
         }
+        let count = self.space.count();
+        let index = if self.mode.is_random() && count > self.mode.count() as u64
+        {
+            loop {
+                let index = self.space.nth(self.rand.next_u64() % count)?;
+                if self.used.insert(index) {
+                    break index;
+                }
+            }
+        } else {
+            // Either a sequential mode, or the random sample covers the
+            // whole space, so all of its indexes are taken in order
+            self.space.nth(self.offset as u64)?
+        };
+        self.offset += 1;
+        Some(index)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::util::index_space::MAX_UNHARDENED_INDEX;
+
+    fn mode(s: &str) -> ResolverModeType {
+        ResolverModeType::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn first_mode_follows_index_ranges() {
+        let space = IndexSpace::with(vec![3..=4, 10..=20]);
+        let indexes: Vec<_> = mode("first5").iter_within(space).collect();
+        assert_eq!(indexes, vec![3, 4, 10, 11, 12]);
+    }
+
+    #[test]
+    fn first_mode_stops_at_space_end() {
+        let space = IndexSpace::with(vec![7..=8]);
+        let indexes: Vec<_> = mode("first10").iter_within(space).collect();
+        assert_eq!(indexes, vec![7, 8]);
+    }
+
+    #[test]
+    fn first_mode_without_ranges() {
+        let indexes: Vec<_> = mode("first3").into_iter().collect();
+        assert_eq!(indexes, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn random_mode_never_yields_hardened_indexes() {
+        let indexes: Vec<_> = mode("random1000").into_iter().collect();
+        assert_eq!(indexes.len(), 1000);
+        assert!(indexes.iter().all(|index| *index <= MAX_UNHARDENED_INDEX));
+        assert!(indexes
+            .iter()
+            .all(|index| UnhardenedIndex::from_index(*index).is_ok()));
+    }
+
+    #[test]
+    fn random_mode_samples_within_ranges() {
+        let space = IndexSpace::with(vec![100..=199, 1000..=1099]);
+        let indexes: HashSet<_> =
+            mode("random50").iter_within(space.clone()).collect();
+        assert_eq!(indexes.len(), 50);
+        assert!(indexes.iter().all(|index| space.contains(*index)));
+    }
+
+    #[test]
+    fn random_mode_covers_small_space() {
+        let space = IndexSpace::with(vec![5..=7, 42..=42]);
+        let indexes: Vec<_> = mode("random10").iter_within(space).collect();
+        assert_eq!(indexes, vec![5, 6, 7, 42]);
+    }
+
+    #[test]
+    fn empty_space_yields_nothing() {
+        let space = IndexSpace::with(vec![]);
+        assert_eq!(mode("first5").iter_within(space.clone()).count(), 0);
+        assert_eq!(mode("random5").iter_within(space).count(), 0);
     }
 }
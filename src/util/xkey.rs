@@ -0,0 +1,209 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Conversion of extended keys between SLIP-132 encodings
+
+use bitcoin::util::base58;
+use bitcoin::Network;
+use lnpbp::Chain;
+use slip132::{DefaultResolver, KeyApplication, KeyVersion, VersionResolver};
+
+/// Length of the BIP32 extended key serialization, including version bytes
+const XKEY_LEN: usize = 78;
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// Extended key is not a valid Base58Check string: {0}
+    #[from]
+    Base58(base58::Error),
+
+    /// Extended key must be 78 bytes long, while {0} bytes are provided
+    WrongLength(usize),
+
+    /// Unknown extended key version bytes {0:02x?}
+    UnknownVersion([u8; 4]),
+}
+
+/// SLIP-132 encoding of an extended key, defined by its version bytes
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct XkeyFormat {
+    /// Whether the key uses testnet version bytes (shared by testnet,
+    /// signet and regtest)
+    pub testnet: bool,
+
+    pub application: KeyApplication,
+
+    pub private: bool,
+}
+
+impl XkeyFormat {
+    pub fn with(
+        network: Network,
+        application: KeyApplication,
+        private: bool,
+    ) -> XkeyFormat {
+        XkeyFormat {
+            testnet: network != Network::Bitcoin,
+            application,
+            private,
+        }
+    }
+
+    /// Detects encoding of a Base58Check-encoded extended key
+    pub fn detect(xkey: &str) -> Result<XkeyFormat, Error> {
+        let data = base58::from_check(xkey)?;
+        if data.len() != XKEY_LEN {
+            return Err(Error::WrongLength(data.len()));
+        }
+        let mut version = [0u8; 4];
+        version.copy_from_slice(&data[..4]);
+        XkeyFormat::from_version(version)
+    }
+
+    pub fn from_version(version: [u8; 4]) -> Result<XkeyFormat, Error> {
+        let key_version = KeyVersion::from_bytes(version);
+        match (
+            DefaultResolver::network(&key_version),
+            DefaultResolver::application(&key_version),
+            DefaultResolver::is_prv(&key_version),
+        ) {
+            (Some(network), Some(application), Some(private)) => {
+                Ok(XkeyFormat::with(network, application, private))
+            }
+            _ => Err(Error::UnknownVersion(version)),
+        }
+    }
+
+    pub fn version(&self) -> [u8; 4] {
+        let network = if self.testnet {
+            Network::Testnet
+        } else {
+            Network::Bitcoin
+        };
+        DefaultResolver::resolve(network, self.application, self.private)
+            .into_bytes()
+    }
+
+    /// Human-readable prefix of the encoded key, like `zpub` or `Vprv`.
+    /// SLIP-132 version bytes are selected such that the prefix does not
+    /// depend on the rest of the key data.
+    pub fn prefix(&self) -> String {
+        let mut data = [0u8; XKEY_LEN];
+        data[..4].copy_from_slice(&self.version());
+        base58::check_encode_slice(&data)[..4].to_owned()
+    }
+
+    /// Whether keys in this encoding may be used on the given chain
+    pub fn is_chain_compatible(&self, chain: &Chain) -> bool {
+        self.testnet == is_test_chain(chain)
+    }
+}
+
+/// Re-encodes extended key with the version bytes for a different
+/// application and/or network type. Private keys stay private and public
+/// keys stay public; key data are not changed.
+pub fn convert(
+    xkey: &str,
+    application: KeyApplication,
+    testnet: bool,
+) -> Result<String, Error> {
+    let mut data = base58::from_check(xkey)?;
+    if data.len() != XKEY_LEN {
+        return Err(Error::WrongLength(data.len()));
+    }
+    let mut version = [0u8; 4];
+    version.copy_from_slice(&data[..4]);
+    let format = XkeyFormat {
+        testnet,
+        application,
+        ..XkeyFormat::from_version(version)?
+    };
+    data[..4].copy_from_slice(&format.version());
+    Ok(base58::check_encode_slice(&data))
+}
+
+/// Whether the chain uses testnet version bytes for extended keys
+pub fn is_test_chain(chain: &Chain) -> bool {
+    !matches!(chain, Chain::Mainnet | Chain::LiquidV1)
+}
+
+/// Checks that the network of an extended key (as decoded by `bitcoin` or
+/// `slip132` crates) can be used on the given chain
+pub fn is_network_compatible(network: Network, chain: &Chain) -> bool {
+    (network != Network::Bitcoin) == is_test_chain(chain)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const XPUB: &str = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+    const ZPUB: &str = "zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs";
+
+    #[test]
+    fn slip132_roundtrip() {
+        let zpub = convert(XPUB, KeyApplication::SegWit, false).unwrap();
+        assert_eq!(zpub, ZPUB);
+        assert_eq!(convert(ZPUB, KeyApplication::Hashed, false).unwrap(), XPUB);
+
+        let format = XkeyFormat::detect(ZPUB).unwrap();
+        assert_eq!(format.prefix(), "zpub");
+        assert!(!format.private);
+        assert!(format.is_chain_compatible(&Chain::Mainnet));
+        assert!(!format.is_chain_compatible(&Chain::Testnet3));
+    }
+
+    #[test]
+    fn slip132_all_encodings() {
+        for application in &[
+            KeyApplication::Hashed,
+            KeyApplication::Nested,
+            KeyApplication::SegWit,
+            KeyApplication::NestedMultisig,
+            KeyApplication::SegWitMiltisig,
+        ] {
+            for testnet in &[false, true] {
+                let key = convert(XPUB, *application, *testnet).unwrap();
+                let format = XkeyFormat::detect(&key).unwrap();
+                assert!(key.starts_with(&format.prefix()));
+                assert_eq!(format.testnet, *testnet);
+                assert_eq!(format.application, *application);
+                assert_eq!(
+                    convert(&key, KeyApplication::Hashed, false).unwrap(),
+                    XPUB
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn slip132_invalid_keys() {
+        assert!(matches!(
+            XkeyFormat::detect(&base58::check_encode_slice(&[0u8; 78])),
+            Err(Error::UnknownVersion([0, 0, 0, 0]))
+        ));
+        assert!(matches!(
+            XkeyFormat::detect(&base58::check_encode_slice(&[0u8; 33])),
+            Err(Error::WrongLength(33))
+        ));
+        assert!(matches!(
+            convert(&XPUB[..XPUB.len() - 1], KeyApplication::SegWit, true),
+            Err(Error::Base58(_))
+        ));
+    }
+}
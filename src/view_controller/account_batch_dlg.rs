@@ -0,0 +1,365 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use gtk::prelude::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use bitcoin::util::bip32::{self, ExtendedPrivKey};
+use lnpbp::Chain;
+use slip132::{self, FromSlip132};
+
+use crate::model::{
+    AccountBatch, DerivationScheme, MnemonicSeed, NetworkMismatch, SeedError,
+    TrackingAccount,
+};
+use crate::util::xkey;
+
+static UI: &str = include_str!("../view/account_batch.glade");
+
+#[derive(Debug, Display, From, Error)]
+#[display(doc_comments)]
+/// Errors from batch account creation
+pub enum Error {
+    /// BIP32-specific error
+    #[display("{0}")]
+    #[from]
+    Bip32(bip32::Error),
+
+    /// SLIP-32 specific error
+    #[display("{0}")]
+    #[from]
+    Slip32(slip132::Error),
+
+    /// BIP39 mnemonic error
+    #[display("{0}")]
+    #[from]
+    Seed(SeedError),
+
+    /// You must provide a non-empty name
+    EmptyName,
+
+    /// You have to provide master private key or mnemonic
+    NoMasterKey,
+
+    /// Passphrase can be used only with a mnemonic, not with an extended
+    /// private key
+    UnusedPassphrase,
+
+    /// At least one derivation scheme must be selected
+    NoSchemes,
+
+    /// Accounts can't be created: {0}
+    #[from]
+    Network(NetworkMismatch),
+}
+
+pub struct AccountBatchDlg {
+    dialog: gtk::Dialog,
+    msg_box: gtk::Box,
+    msg_label: gtk::Label,
+    msg_image: gtk::Image,
+    save_btn: gtk::Button,
+    preview_btn: gtk::Button,
+    cancel_btn: gtk::Button,
+
+    name_field: gtk::Entry,
+    xprv_field: gtk::Entry,
+    passphrase_field: gtk::Entry,
+    first_account: gtk::SpinButton,
+    account_count: gtk::SpinButton,
+    scheme_checks: Vec<(DerivationScheme, gtk::CheckButton)>,
+    change_chk: gtk::CheckButton,
+
+    account_store: gtk::ListStore,
+
+    chain: RefCell<Chain>,
+}
+
+impl AccountBatchDlg {
+    pub fn load_glade() -> Option<Rc<Self>> {
+        let builder = gtk::Builder::from_string(UI);
+
+        let save_btn = builder.object("save")?;
+        let preview_btn = builder.object("preview")?;
+        let cancel_btn = builder.object("cancel")?;
+
+        let msg_box = builder.object("messageBox")?;
+        let msg_image = builder.object("messageImage")?;
+        let msg_label = builder.object("messageLabel")?;
+
+        let name_field = builder.object("nameField")?;
+        let xprv_field = builder.object("xprvField")?;
+        let passphrase_field = builder.object("passphraseField")?;
+        let first_account = builder.object("firstAccount")?;
+        let account_count = builder.object("accountCount")?;
+        let change_chk = builder.object("changeCheck")?;
+
+        let mut scheme_checks = vec![];
+        for id in &[
+            "bip44", "bip45", "bip49", "bip84", "bip86", "bip48-1", "bip48-2",
+            "bip87",
+        ] {
+            let scheme = DerivationScheme::from_str(id).ok()?;
+            let chk = builder.object(&format!("{}Check", id))?;
+            scheme_checks.push((scheme, chk));
+        }
+
+        let account_store = builder.object("accountStore")?;
+
+        let me = Rc::new(Self {
+            dialog: glade_load!(builder, "batchDlg").ok()?,
+            msg_box,
+            msg_label,
+            msg_image,
+            save_btn,
+            preview_btn,
+            cancel_btn,
+            name_field,
+            xprv_field,
+            passphrase_field,
+            first_account,
+            account_count,
+            scheme_checks,
+            change_chk,
+            account_store,
+            chain: RefCell::new(Chain::default()),
+        });
+
+        for ctl in &[&me.name_field, &me.xprv_field, &me.passphrase_field] {
+            ctl.connect_changed(clone!(@weak me => move |_| {
+                me.update_ui()
+            }));
+        }
+
+        for ctl in &[&me.first_account, &me.account_count] {
+            ctl.connect_changed(clone!(@weak me => move |_| {
+                me.update_ui()
+            }));
+        }
+
+        for (_, ctl) in &me.scheme_checks {
+            ctl.connect_toggled(clone!(@weak me => move |_| {
+                me.update_ui()
+            }));
+        }
+
+        me.change_chk.connect_toggled(clone!(@weak me => move |_| {
+            me.update_ui()
+        }));
+
+        Some(me)
+    }
+}
+
+impl AccountBatchDlg {
+    pub fn run(
+        self: Rc<Self>,
+        chain: &Chain,
+        on_save: impl Fn(Vec<TrackingAccount>) + 'static,
+        on_cancel: impl Fn() + 'static,
+    ) {
+        let me = self.clone();
+
+        *me.chain.borrow_mut() = chain.clone();
+
+        me.update_ui();
+
+        me.cancel_btn
+            .connect_clicked(clone!(@weak self as me => move |_| {
+                me.clear_secrets();
+                me.dialog.close();
+                on_cancel()
+            }));
+
+        me.preview_btn
+            .connect_clicked(clone!(@weak self as me => move |_| {
+                me.preview()
+            }));
+
+        me.save_btn.connect_clicked(
+            clone!(@weak self as me => move |_| match me.tracking_accounts() {
+                Ok(tracking_accounts) => {
+                    me.clear_secrets();
+                    me.dialog.close();
+                    on_save(tracking_accounts);
+                }
+                Err(err) => {
+                    me.display_error(err);
+                    me.save_btn.set_sensitive(false);
+                }
+            }),
+        );
+
+        me.dialog.run();
+        me.clear_secrets();
+        me.dialog.close();
+    }
+
+    /// Removes master key, mnemonic and passphrase from the entry fields
+    pub fn clear_secrets(&self) {
+        self.xprv_field.set_text("");
+        self.passphrase_field.set_text("");
+    }
+
+    /// Checks the entered data without parsing the master key or running
+    /// the mnemonic key stretching, so it can be called on each keystroke
+    pub fn validate(&self) -> Result<AccountBatch, Error> {
+        let batch = self.account_batch()?;
+        let secret = self.xprv_field.text();
+        if secret.is_empty() {
+            return Err(Error::NoMasterKey);
+        }
+        if !MnemonicSeed::is_mnemonic_like(&secret)
+            && !self.passphrase_field.text().is_empty()
+        {
+            return Err(Error::UnusedPassphrase);
+        }
+        Ok(batch)
+    }
+
+    /// Parses master private key from the entry field, which may contain
+    /// either an extended private key or a BIP39 mnemonic. In the latter
+    /// case the network is taken from the document chain.
+    pub fn master_key(&self) -> Result<ExtendedPrivKey, Error> {
+        let secret = self.xprv_field.text();
+        let passphrase = self.passphrase_field.text();
+        if secret.is_empty() {
+            return Err(Error::NoMasterKey);
+        }
+        if MnemonicSeed::is_mnemonic_like(&secret) {
+            let network = if xkey::is_test_chain(&self.chain.borrow()) {
+                bitcoin::Network::Testnet
+            } else {
+                bitcoin::Network::Bitcoin
+            };
+            let seed = MnemonicSeed::with(&secret, &passphrase)?;
+            Ok(seed.master_xpriv(network)?)
+        } else if !passphrase.is_empty() {
+            Err(Error::UnusedPassphrase)
+        } else {
+            Ok(ExtendedPrivKey::from_slip132_str(&secret)?)
+        }
+    }
+
+    pub fn account_batch(&self) -> Result<AccountBatch, Error> {
+        let name = self.name_field.text();
+        if name.is_empty() {
+            return Err(Error::EmptyName);
+        }
+        let schemes = self
+            .scheme_checks
+            .iter()
+            .filter(|(_, chk)| chk.is_active())
+            .map(|(scheme, _)| *scheme)
+            .collect::<Vec<_>>();
+        if schemes.is_empty() {
+            return Err(Error::NoSchemes);
+        }
+        let first = self.first_account.value() as u32;
+        let count = self.account_count.value() as u32;
+        Ok(AccountBatch {
+            name: name.to_string(),
+            schemes,
+            accounts: first..first.saturating_add(count),
+            change: self.change_chk.is_active(),
+        })
+    }
+
+    /// Derives tracking accounts from the master key. The private key (or
+    /// the seed) is parsed from the entry field each time and is dropped
+    /// right after the public data are derived.
+    pub fn tracking_accounts(&self) -> Result<Vec<TrackingAccount>, Error> {
+        let batch = self.account_batch()?;
+        let master = self.master_key()?;
+        let tracking_accounts = batch.derive(&master)?;
+        let chain = self.chain.borrow();
+        for tracking_account in &tracking_accounts {
+            tracking_account.check_network(&chain)?;
+        }
+        Ok(tracking_accounts)
+    }
+
+    pub fn display_info(&self, msg: impl ToString) {
+        self.msg_label.set_text(&msg.to_string());
+        self.msg_image.set_from_icon_name(
+            Some("dialog-information"),
+            gtk::IconSize::SmallToolbar,
+        );
+        self.msg_box.set_visible(true);
+    }
+
+    pub fn display_error(&self, msg: impl std::error::Error) {
+        self.msg_label.set_text(&msg.to_string());
+        self.msg_image.set_from_icon_name(
+            Some("dialog-error"),
+            gtk::IconSize::SmallToolbar,
+        );
+        self.msg_box.set_visible(true);
+    }
+
+    /// Derives the accounts and lists them in the dialog
+    pub fn preview(&self) {
+        self.account_store.clear();
+        match self.tracking_accounts() {
+            Ok(tracking_accounts) => {
+                for tracking_account in &tracking_accounts {
+                    self.account_store.insert_with_values(
+                        None,
+                        &[
+                            (0, &tracking_account.name()),
+                            (1, &tracking_account.details()),
+                        ],
+                    );
+                }
+                self.display_info(format!(
+                    "{} tracking accounts will be created; only public keys \
+                     are saved to the document",
+                    tracking_accounts.len()
+                ));
+            }
+            Err(err) => {
+                self.display_error(err);
+                self.save_btn.set_sensitive(false);
+                self.preview_btn.set_sensitive(false);
+            }
+        }
+    }
+
+    /// Account derivation is slow for mnemonics, so here the data are only
+    /// validated; accounts are derived on preview and on save.
+    pub fn update_ui(&self) {
+        self.account_store.clear();
+        match self.validate() {
+            Ok(batch) => {
+                self.display_info(format!(
+                    "{} tracking accounts will be created; press Preview to \
+                     derive and check them",
+                    batch.presets(bitcoin::Network::Bitcoin).len()
+                ));
+                self.save_btn.set_sensitive(true);
+                self.preview_btn.set_sensitive(true);
+            }
+            Err(err) => {
+                self.display_error(err);
+                self.save_btn.set_sensitive(false);
+                self.preview_btn.set_sensitive(false);
+            }
+        }
+    }
+}
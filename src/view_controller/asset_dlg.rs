@@ -17,15 +17,20 @@
 
 use gtk::prelude::*;
 use std::cell::RefCell;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::rc::Rc;
 use std::str::FromStr;
 
 use bitcoin::OutPoint;
 use lnpbp::Chain;
 use rgb::{AtomicValue, ContractId, Genesis, ToBech32};
+use rgb20::schema::OwnedRightsType;
 
-use crate::model::{DescriptorAccount, Document, UtxoEntry};
+use crate::controller::asset_validation::{Issuance, Issue};
+use crate::model::{
+    inflation_cap, known_seals, known_supply, DescriptorAccount, Document,
+    UtxoEntry,
+};
 use crate::view_controller::UtxoSelectDlg;
 
 static UI: &str = include_str!("../view/asset.glade");
@@ -38,17 +43,26 @@ pub enum Error {
     #[from]
     #[display(inner)]
     Rgb20(rgb20::Error),
+
+    /// Inconsistent asset issuance parameters
+    #[from]
+    #[display(inner)]
+    Issuance(Issue),
 }
 
 pub struct AssetDlg {
     dialog: gtk::Dialog,
 
     chain: RefCell<Chain>,
+    read_only: RefCell<bool>,
     inflation_cap_saved: RefCell<f64>,
     renomination_utxo: Rc<RefCell<Option<UtxoEntry>>>,
     epoch_utxo: Rc<RefCell<Option<UtxoEntry>>>,
-    allocation: Rc<RefCell<HashMap<UtxoEntry, f64>>>,
-    inflation: Rc<RefCell<HashMap<UtxoEntry, Option<f64>>>>,
+    /// Allocated amounts as decimal text of the spin button
+    allocation: Rc<RefCell<HashMap<UtxoEntry, String>>>,
+    /// Custom inflation allowances as decimal text of the spin button
+    inflation: Rc<RefCell<HashMap<UtxoEntry, Option<String>>>>,
+    utxo_cache: RefCell<HashSet<UtxoEntry>>,
 
     msg_box: gtk::Box,
     msg_label: gtk::Label,
@@ -168,11 +182,13 @@ impl AssetDlg {
             dialog: glade_load!(builder, "assetDlg").ok()?,
 
             chain: RefCell::new(Chain::default()),
+            read_only: RefCell::new(false),
             inflation_cap_saved: RefCell::new(100000000_f64),
             renomination_utxo: none!(),
             epoch_utxo: none!(),
             allocation: none!(),
             inflation: none!(),
+            utxo_cache: none!(),
 
             msg_box,
             msg_image,
@@ -294,7 +310,7 @@ impl AssetDlg {
                     .borrow_mut()
                     .iter_mut()
                     .find(|(utxo, _)| utxo.outpoint == outpoint)
-                    { *amount = value }
+                    { *amount = me.amount_spin.text().to_string() }
                 me.allocation_store.set_value(&iter, 4, &value.to_value());
             }
             me.update_ui();
@@ -309,8 +325,8 @@ impl AssetDlg {
                     .find(|(utxo, _)| utxo.outpoint == outpoint)
                     .and_then(|(_, amount)| amount.as_mut())
                     .map(|amount| {
-                        *amount = value;
-                        *amount
+                        *amount = me.custom_spin.text().to_string();
+                        value
                     });
                 match value {
                     Some(value) => {
@@ -346,9 +362,9 @@ impl AssetDlg {
                         .find(|(utxo, _)| utxo.outpoint == outpoint)
                         .and_then(|(_, amount)| {
                             if amount.is_none() {
-                                *amount = Some(0.0);
+                                *amount = Some(s!("0"));
                             }
-                            *amount
+                            amount.as_deref().map(spin_value)
                         });
                     if let Some(value) = value { me.custom_spin.set_value(value) }
                 }
@@ -404,11 +420,11 @@ impl AssetDlg {
     ) {
         let me = self.clone();
 
+        *me.chain.borrow_mut() = doc.borrow().chain().clone();
+        *me.utxo_cache.borrow_mut() = doc.borrow().utxos().clone();
         if let Some(contract_id) = contract_id {
             self.apply_contract_id(doc.clone(), contract_id);
         }
-
-        *me.chain.borrow_mut() = doc.borrow().chain().clone();
         me.chain_combo
             .set_active_id(Some(&me.chain.borrow().to_string()));
 
@@ -468,7 +484,7 @@ impl AssetDlg {
                             (3, &utxo.outpoint.to_string()),
                             (4, &0)
                         ]);
-                        me.allocation.borrow_mut().insert(utxo, 0.0);
+                        me.allocation.borrow_mut().insert(utxo, s!("0"));
                     }),
                     || {},
                 );
@@ -529,19 +545,151 @@ impl AssetDlg {
         doc: Rc<RefCell<Document>>,
         contract_id: ContractId,
     ) {
-        let (_asset, _genesis) = if let Some((asset, genesis)) =
-            doc.borrow().asset_by_id(contract_id)
-        {
-            (asset, genesis)
-        } else {
-            return;
-        };
+        let doc = doc.borrow();
+        let (asset, nomination, genesis) =
+            if let Some(asset) = doc.asset_by_id(contract_id) {
+                asset
+            } else {
+                return;
+            };
+        let consignment = doc
+            .asset_consignment(contract_id)
+            .expect("asset consignment is always present for a known asset");
+
+        self.set_read_only();
+        *self.chain.borrow_mut() = genesis.chain().clone();
 
         self.id_field.set_text(&contract_id.to_bech32_string());
-        // TODO: Implement
+        self.ticker_field.set_text(&nomination.ticker);
+        self.title_field.set_text(&nomination.name);
+        self.fract_spin.set_value(nomination.precision as f64);
+        let contract = nomination.contract.clone();
+        self.contract_check.set_active(contract.is_some());
+        self.contract_buffer.set_text(&contract.unwrap_or_default());
+
+        let divisor = nomination.divisor() as f64;
+        for allocation in asset.known_allocations() {
+            let outpoint = *allocation.outpoint();
+            let (descriptor, name, sats) =
+                describe_seal(&doc, contract_id, outpoint);
+            self.allocation_store.insert_with_values(
+                None,
+                &[
+                    (0, &descriptor),
+                    (1, &name),
+                    (2, &sats),
+                    (3, &outpoint.to_string()),
+                    (
+                        4,
+                        &((allocation.revealed_amount().value as f64 / divisor)
+                            as f32),
+                    ),
+                ],
+            );
+        }
+
+        let inflation = asset.known_inflation();
+        self.inflation_check.set_active(!inflation.is_empty());
+        for (outpoint, cap) in inflation {
+            let (descriptor, name, sats) =
+                describe_seal(&doc, contract_id, *outpoint);
+            self.inflation_store.insert_with_values(
+                None,
+                &[
+                    (0, &descriptor),
+                    (1, &name),
+                    (2, &sats),
+                    (3, &outpoint.to_string()),
+                    (4, &(*cap as f64 / divisor).to_string()),
+                ],
+            );
+        }
+
+        for (right, check, field) in &[
+            (
+                OwnedRightsType::Renomination,
+                &self.renomen_check,
+                &self.renomen_field,
+            ),
+            (
+                OwnedRightsType::OpenEpoch,
+                &self.epoch_check,
+                &self.epoch_field,
+            ),
+        ] {
+            let seals = known_seals(consignment, *right)
+                .into_iter()
+                .map(|seal| {
+                    let (_, name, sats) =
+                        describe_seal(&doc, contract_id, seal.outpoint);
+                    format!("{}: {} ({} sats)", name, seal.outpoint, sats)
+                })
+                .collect::<Vec<_>>();
+            check.set_active(!seals.is_empty());
+            field.set_text(&seals.join("; "));
+        }
+
+        let issued = known_supply(consignment).issued;
+        let total = issued + inflation_cap(consignment);
+        for label in &[
+            &self.ticker1_label,
+            &self.ticker2_label,
+            &self.ticker3_label,
+            &self.ticker4_label,
+        ] {
+            label.set_text(&nomination.ticker);
+        }
+        self.issue_cap_display
+            .set_text(&(issued as f64 / divisor).to_string());
+        self.inflation_cap_display
+            .set_text(&((total - issued) as f64 / divisor).to_string());
+        self.total_cap_display
+            .set_text(&(total as f64 / divisor).to_string());
+        self.issue_amount_display.set_text(&issued.to_string());
+        self.inflation_amount_display
+            .set_text(&(total - issued).to_string());
+        self.total_amount_display.set_text(&total.to_string());
+    }
+
+    /// Turns the dialog into a read-only view of an existing asset
+    fn set_read_only(&self) {
+        *self.read_only.borrow_mut() = true;
+        self.dialog.set_title("Asset details");
+        self.create_btn.set_visible(false);
+        self.cancel_btn.set_label("Close");
+        self.msg_box.set_visible(false);
+        for entry in &[&self.id_field, &self.ticker_field, &self.title_field] {
+            entry.set_editable(false);
+        }
+        self.contract_text.set_editable(false);
+        for widget in &[
+            self.chain_combo.upcast_ref::<gtk::Widget>(),
+            self.fract_spin.upcast_ref(),
+            self.renomen_check.upcast_ref(),
+            self.renomen_btn.upcast_ref(),
+            self.epoch_check.upcast_ref(),
+            self.epoch_btn.upcast_ref(),
+            self.inflation_check.upcast_ref(),
+            self.inflation_combo.upcast_ref(),
+            self.inflation_spin.upcast_ref(),
+            self.contract_check.upcast_ref(),
+            self.allocation_add_btn.upcast_ref(),
+            self.allocation_remove_btn.upcast_ref(),
+            self.inflation_add_btn.upcast_ref(),
+            self.inflation_remove_btn.upcast_ref(),
+            self.amount_spin.upcast_ref(),
+            self.equal_radio.upcast_ref(),
+            self.custom_radio.upcast_ref(),
+            self.custom_spin.upcast_ref(),
+        ] {
+            widget.set_sensitive(false);
+        }
     }
 
     pub fn asset_genesis(&self) -> Result<(rgb20::Asset, Genesis), Error> {
+        if let Some(issue) = self.issues().into_iter().find(Issue::is_error) {
+            return Err(issue.into());
+        }
         Ok(rgb20::issue(
             self.chain.borrow().clone(),
             self.asset_ticker().unwrap_or_default(),
@@ -599,32 +747,11 @@ impl AssetDlg {
     }
 
     pub fn asset_allocation(&self) -> Vec<(OutPoint, AtomicValue)> {
-        self.allocation
-            .borrow()
-            .iter()
-            .map(|(utxo, amount)| {
-                (utxo.outpoint, (amount * self.precision_divisor()) as u64)
-            })
-            .collect()
+        self.issuance().allocation_values().unwrap_or_default()
     }
 
     pub fn asset_inflation(&self) -> BTreeMap<OutPoint, AtomicValue> {
-        if self.inflation_check.is_active() {
-            self.inflation
-                .borrow()
-                .iter()
-                .map(|(utxo, maybe_amount)| {
-                    (
-                        utxo.outpoint,
-                        (maybe_amount.unwrap_or(self.equal_inflation_cap())
-                            * self.precision_divisor())
-                            as u64,
-                    )
-                })
-                .collect()
-        } else {
-            bmap! {}
-        }
+        self.issuance().inflation_values().unwrap_or_default()
     }
 
     pub fn asset_renomination(&self) -> Option<OutPoint> {
@@ -650,7 +777,7 @@ impl AssetDlg {
     }
 
     pub fn max_cap(&self) -> f64 {
-        (u64::MAX - 1) as f64 / self.precision_divisor()
+        u64::MAX as f64 / self.precision_divisor()
     }
 
     pub fn precision_divisor(&self) -> f64 {
@@ -658,33 +785,19 @@ impl AssetDlg {
     }
 
     pub fn inflation_cap(&self) -> f64 {
-        if !self.inflation_check.is_active() {
-            0.0
-        } else if self.is_capped() {
-            self.inflation_spin.value()
-        } else {
-            self.max_cap() - self.assigned_cap() - 1.0 // TODO: Fix this
-        }
+        self.inflation_amount() as f64 / self.precision_divisor()
     }
 
     pub fn assigned_cap(&self) -> f64 {
         self.allocation
             .borrow()
             .iter()
-            .fold(0.0f64, |sum, (_, amount)| sum + amount)
-    }
-
-    pub fn inflation_sum(&self) -> f64 {
-        self.inflation
-            .borrow()
-            .iter()
-            .fold(0.0f64, |sum, (_, amount)| sum + amount.unwrap_or(0.0f64))
+            .fold(0.0f64, |sum, (_, amount)| sum + spin_value(amount))
     }
 
     pub fn equal_inflation_cap(&self) -> f64 {
-        let len = self.inflation.borrow().len();
-        let len = if len == 0 { 1 } else { len };
-        (self.inflation_cap() - self.inflation_sum()) / len as f64
+        self.issuance().equal_inflation().unwrap_or_default() as f64
+            / self.precision_divisor()
     }
 
     pub fn total_cap(&self) -> f64 {
@@ -692,15 +805,56 @@ impl AssetDlg {
     }
 
     pub fn assigned_amount(&self) -> u64 {
-        (self.assigned_cap() * self.precision_divisor()) as u64
+        self.issuance().issued_supply().unwrap_or(u64::MAX)
     }
 
     pub fn inflation_amount(&self) -> u64 {
-        (self.inflation_cap() * self.precision_divisor()) as u64
+        if !self.inflation_check.is_active() {
+            0
+        } else {
+            self.issuance().inflation_supply().unwrap_or(u64::MAX)
+        }
     }
 
     pub fn total_amount(&self) -> u64 {
-        self.assigned_amount() + self.inflation_amount()
+        self.assigned_amount()
+            .saturating_add(self.inflation_amount())
+    }
+
+    /// Collects asset issuance parameters from the dialog controls
+    pub fn issuance(&self) -> Issuance {
+        Issuance {
+            ticker: self.asset_ticker().unwrap_or_default(),
+            name: self.asset_title().unwrap_or_default(),
+            precision: self.asset_fractionals(),
+            allocation: self
+                .allocation
+                .borrow()
+                .iter()
+                .map(|(utxo, amount)| (utxo.clone(), amount.clone()))
+                .collect(),
+            inflation_cap: if self.is_capped() {
+                Some(self.inflation_spin.text().to_string())
+            } else {
+                None
+            },
+            inflation: if self.inflation_check.is_active() {
+                self.inflation
+                    .borrow()
+                    .iter()
+                    .map(|(utxo, amount)| (utxo.clone(), amount.clone()))
+                    .collect()
+            } else {
+                vec![]
+            },
+            renomination: self.renomination_utxo.borrow().clone(),
+            epoch: self.epoch_utxo.borrow().clone(),
+        }
+    }
+
+    /// Validates issuance parameters against the document UTXO cache
+    pub fn issues(&self) -> Vec<Issue> {
+        self.issuance().validate(&self.utxo_cache.borrow())
     }
 
     fn selected_allocation_model(
@@ -739,7 +893,7 @@ impl AssetDlg {
         self.selected_allocation_model().and_then(|(outpoint, ..)| {
             self.allocation.borrow().iter().find_map(|(utxo, amount)| {
                 if utxo.outpoint == outpoint {
-                    Some((utxo.clone(), *amount))
+                    Some((utxo.clone(), spin_value(amount)))
                 } else {
                     None
                 }
@@ -751,7 +905,7 @@ impl AssetDlg {
         self.selected_inflation_model().and_then(|(outpoint, ..)| {
             self.inflation.borrow().iter().find_map(|(utxo, amount)| {
                 if utxo.outpoint == outpoint {
-                    Some((utxo.clone(), *amount))
+                    Some((utxo.clone(), amount.as_deref().map(spin_value)))
                 } else {
                     None
                 }
@@ -759,10 +913,10 @@ impl AssetDlg {
         })
     }
 
-    pub fn display_info(&self, msg: impl ToString) {
+    pub fn display_warning(&self, msg: impl ToString) {
         self.msg_label.set_text(&msg.to_string());
         self.msg_image.set_from_icon_name(
-            Some("dialog-information"),
+            Some("dialog-warning"),
             gtk::IconSize::SmallToolbar,
         );
         self.msg_box.set_visible(true);
@@ -822,6 +976,10 @@ impl AssetDlg {
     }
 
     pub fn update_ui(&self) {
+        if *self.read_only.borrow() {
+            return;
+        }
+
         let ticker = self
             .asset_ticker()
             .map(|ticker| {
@@ -908,7 +1066,7 @@ impl AssetDlg {
                 self.create_btn.set_sensitive(true);
             }
             Ok(Some(msg)) => {
-                self.display_info(msg);
+                self.display_warning(msg);
                 self.create_btn.set_sensitive(true);
             }
             Err(err) => {
@@ -919,6 +1077,57 @@ impl AssetDlg {
     }
 
     pub fn update_ui_internal(&self) -> Result<Option<String>, Error> {
-        Ok(None)
+        let (errors, warnings): (Vec<_>, Vec<_>) =
+            self.issues().into_iter().partition(Issue::is_error);
+        if let Some(err) = errors.into_iter().next() {
+            return Err(err.into());
+        }
+        if warnings.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(
+            warnings
+                .iter()
+                .map(Issue::to_string)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ))
+    }
+}
+
+/// Value of the amount text for the spin buttons. Issued amounts are
+/// converted from the text into atomic units with integer arithmetic by
+/// [`Issuance`] instead.
+fn spin_value(amount: &str) -> f64 {
+    amount.replace(',', ".").parse().unwrap_or_default()
+}
+
+/// Returns descriptor, descriptor account name and amount of satoshis for
+/// the seal outpoint if it is present in the document UTXO cache. Seals
+/// found spent by the asset synchronization are marked in the name.
+fn describe_seal(
+    doc: &Document,
+    contract_id: ContractId,
+    outpoint: OutPoint,
+) -> (String, String, u64) {
+    let (descriptor, name, sats) = match doc.utxo_by_outpoint(outpoint) {
+        Some(utxo) => {
+            let dg = doc.descriptor_by_template(&utxo.descriptor_template);
+            (
+                dg.as_ref()
+                    .map(|g| g.descriptor())
+                    .unwrap_or_else(|| s!("-")),
+                dg.as_ref()
+                    .map(|g| g.name())
+                    .unwrap_or_else(|| s!("<unknown descriptor>")),
+                utxo.amount,
+            )
+        }
+        None => (s!("-"), s!("<not in wallet>"), 0),
+    };
+    if doc.is_seal_spent(contract_id, outpoint) {
+        (descriptor, format!("{} (spent)", name), sats)
+    } else {
+        (descriptor, name, sats)
     }
 }
@@ -16,20 +16,35 @@
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 use gtk::gdk;
-use gtk::gdk_pixbuf::{InterpType, PixbufLoader};
+use gtk::gdk_pixbuf::{InterpType, Pixbuf, PixbufLoader};
 use gtk::prelude::*;
-use qrcode_generator::QrCodeEcc;
 use std::cell::RefCell;
+use std::fs;
 use std::path::PathBuf;
 use std::rc::Rc;
 use std::str::FromStr;
+use std::time::Duration;
 
 use bitcoin::{OutPoint, Txid};
 use rgb::{Consignment, ContractId, ToBech32};
+use rgb20::schema::OwnedRightsType;
 use rgb20::SupplyMeasure;
+use wallet::descriptor;
 
-use crate::model::Document;
-use crate::view_controller::{AssetDlg, DescriptorDlg, PubkeyDlg, SaveDlg};
+use crate::controller::{
+    asset_export, asset_import, asset_invoice, asset_ops, asset_sync, fee_bump,
+    fee_estimator, psbt_coordinator, psbt_finalizer, subscriptions,
+    utxo_lookup,
+};
+use crate::model::{
+    burned_outpoints, inflation_cap, known_seals, known_supply, AssetSeal,
+    DescriptorAccount, Document, MultisigSetup, Nomination, ResolverError,
+    Timestamp, WalletFile,
+};
+use crate::view_controller::{
+    AccountBatchDlg, AssetDlg, DescriptorDlg, PubkeyDlg, SaveDlg, SignDlg,
+    UtxoSelectDlg,
+};
 
 static UI: &str = include_str!("../view/bpro.glade");
 
@@ -56,6 +71,10 @@ pub struct BproWin {
     utxo_descr_store: gtk::ListStore,
     utxo_tree: gtk::TreeView,
     utxo_store: gtk::ListStore,
+    utxo_status: gtk::Statusbar,
+    psbt_tree: gtk::TreeView,
+    psbt_store: gtk::ListStore,
+    psbt_input_store: gtk::TreeStore,
     asset_tree: gtk::TreeView,
     asset_store: gtk::ListStore,
     header_bar: gtk::HeaderBar,
@@ -65,9 +84,23 @@ pub struct BproWin {
     pubkey_remove_btn: gtk::ToolButton,
     descriptor_edit_btn: gtk::ToolButton,
     descriptor_remove_btn: gtk::ToolButton,
+    descriptor_export_btn: gtk::ToolButton,
+    descriptor_core_export_btn: gtk::ToolButton,
     utxo_descr_remove_btn: gtk::ToolButton,
     utxo_descr_clear_btn: gtk::ToolButton,
     utxo_remove_btn: gtk::ToolButton,
+    psbt_sign_btn: gtk::ToolButton,
+    psbt_finalize_btn: gtk::ToolButton,
+    psbt_bump_btn: gtk::ToolButton,
+    psbt_remove_btn: gtk::ToolButton,
+    asset_export_btn: gtk::ToolButton,
+    asset_inflate_btn: gtk::ToolButton,
+    asset_renom_btn: gtk::ToolButton,
+    asset_transfer_btn: gtk::ToolButton,
+    asset_invoice_btn: gtk::ToolButton,
+    asset_epoch_btn: gtk::ToolButton,
+    asset_burn_btn: gtk::ToolButton,
+    asset_replace_btn: gtk::ToolButton,
     asset_remove_btn: gtk::ToolButton,
     asset_id_display: gtk::Entry,
     asset_genesis_display: gtk::Entry,
@@ -76,6 +109,7 @@ pub struct BproWin {
     asset_total_display: gtk::Entry,
     asset_decimals_display: gtk::Entry,
     asset_qr_image: gtk::Image,
+    asset_qr_frames: RefCell<Vec<Pixbuf>>,
 }
 
 impl BproWin {
@@ -98,9 +132,24 @@ impl BproWin {
         let pubkey_remove_btn = builder.object("pubkeyRemove")?;
         let descriptor_edit_btn = builder.object("descriptorEdit")?;
         let descriptor_remove_btn = builder.object("descriptorRemove")?;
+        let descriptor_export_btn = builder.object("descriptorExport")?;
+        let descriptor_core_export_btn =
+            builder.object("descriptorCoreExport")?;
         let utxo_descr_remove_btn = builder.object("utxoDescrRemove")?;
         let utxo_descr_clear_btn = builder.object("utxoDescrClear")?;
         let utxo_remove_btn = builder.object("utxoRemove")?;
+        let psbt_sign_btn = builder.object("psbtSign")?;
+        let psbt_finalize_btn = builder.object("psbtFinalize")?;
+        let psbt_bump_btn = builder.object("psbtBump")?;
+        let psbt_remove_btn = builder.object("psbtRemove")?;
+        let asset_export_btn = builder.object("assetExport")?;
+        let asset_inflate_btn = builder.object("assetInflate")?;
+        let asset_renom_btn = builder.object("assetRenom")?;
+        let asset_transfer_btn = builder.object("assetTransfer")?;
+        let asset_invoice_btn = builder.object("assetInvoice")?;
+        let asset_epoch_btn = builder.object("assetEpoch")?;
+        let asset_burn_btn = builder.object("assetBurn1")?;
+        let asset_replace_btn = builder.object("assetReplace1")?;
         let asset_remove_btn = builder.object("assetRemove")?;
 
         let pubkey_tree = builder.object("pubkeyTree")?;
@@ -111,6 +160,10 @@ impl BproWin {
         let utxo_descr_store = builder.object("utxoDescrStore")?;
         let utxo_tree = builder.object("utxoTree")?;
         let utxo_store = builder.object("utxoStore")?;
+        let utxo_status = builder.object("utxoStatus")?;
+        let psbt_tree = builder.object("psbtTree")?;
+        let psbt_store = builder.object("psbtStore")?;
+        let psbt_input_store = builder.object("psbtInputStore")?;
         let asset_tree = builder.object("assetTree")?;
         let asset_store = builder.object("assetStore")?;
 
@@ -148,6 +201,10 @@ impl BproWin {
             utxo_descr_store,
             utxo_tree,
             utxo_store,
+            utxo_status,
+            psbt_tree,
+            psbt_store,
+            psbt_input_store,
             asset_tree,
             asset_store,
             header_bar,
@@ -157,9 +214,23 @@ impl BproWin {
             pubkey_remove_btn,
             descriptor_edit_btn,
             descriptor_remove_btn,
+            descriptor_export_btn,
+            descriptor_core_export_btn,
             utxo_descr_remove_btn,
             utxo_descr_clear_btn,
             utxo_remove_btn,
+            psbt_sign_btn,
+            psbt_finalize_btn,
+            psbt_bump_btn,
+            psbt_remove_btn,
+            asset_export_btn,
+            asset_inflate_btn,
+            asset_renom_btn,
+            asset_transfer_btn,
+            asset_invoice_btn,
+            asset_epoch_btn,
+            asset_burn_btn,
+            asset_replace_btn,
             asset_remove_btn,
             asset_id_display,
             asset_genesis_display,
@@ -168,12 +239,16 @@ impl BproWin {
             asset_total_display,
             asset_decimals_display,
             asset_qr_image,
+            asset_qr_frames: none!(),
         }));
 
+        me.borrow().fill_psbt_store(&doc.borrow());
+
         chain_combo.connect_changed(
-            clone!(@weak chain_combo, @strong doc => move |_| {
+            clone!(@weak me, @weak chain_combo, @strong doc => move |_| {
                 if let Some(chain_name) = chain_combo.active_id() {
                     let _ = doc.borrow_mut().set_chain(&chain_name);
+                    me.borrow().audit_tracking_accounts(&doc.borrow());
                 }
             }),
         );
@@ -250,6 +325,25 @@ impl BproWin {
             );
         }));
 
+        let tb: gtk::ToolButton = builder.object("pubkeyBatch")?;
+        tb.connect_clicked(clone!(@weak me, @strong doc => move |_| {
+            let batch_dlg = AccountBatchDlg::load_glade().expect("Must load");
+            let chain = doc.borrow().chain().clone();
+            batch_dlg.run(&chain, clone!(@weak me, @strong doc =>
+                move |tracking_accounts| {
+                    let me = me.borrow();
+                    for tracking_account in tracking_accounts {
+                        me.pubkey_store.insert_with_values(
+                            None,
+                            &[(0, &tracking_account.name()), (1, &tracking_account.details()), (2, &tracking_account.count())]
+                        );
+                        let _ = doc.borrow_mut().add_tracking_account(tracking_account);
+                    }
+                }),
+                || {},
+            );
+        }));
+
         me.borrow().pubkey_edit_btn.connect_clicked(clone!(@weak me, @strong doc => move |_| {
             let meb = me.borrow();
             let pubkey_dlg = PubkeyDlg::load_glade().expect("Must load");
@@ -304,12 +398,19 @@ impl BproWin {
                 if let Some((generator, _, _)) = me.descriptor_selection() {
                     if let Some(descriptor_generator) = doc.borrow().descriptor_by_generator(&generator) {
                         doc.borrow().fill_utxo_store(&me.utxo_descr_store, Some(&descriptor_generator));
+                        me.descriptor_export_btn.set_sensitive(matches!(
+                            descriptor_generator.generator.template,
+                            descriptor::Template::MultiSig(_)
+                        ));
                     }
                     me.descriptor_edit_btn.set_sensitive(true);
                     me.descriptor_remove_btn.set_sensitive(true);
+                    me.descriptor_core_export_btn.set_sensitive(true);
                 } else {
                     me.descriptor_edit_btn.set_sensitive(false);
                     me.descriptor_remove_btn.set_sensitive(false);
+                    me.descriptor_export_btn.set_sensitive(false);
+                    me.descriptor_core_export_btn.set_sensitive(false);
                 }
                 me.utxo_descr_clear_btn.set_sensitive(me.utxo_descr_store.iter_first().is_some());
             }),
@@ -387,6 +488,336 @@ impl BproWin {
             }
         }));
 
+        let tb: gtk::ToolButton = builder.object("descriptorImport")?;
+        tb.connect_clicked(clone!(@weak me, @strong doc => move |_| {
+            let me = me.borrow();
+            let dlg = gtk::FileChooserDialog::with_buttons(
+                Some("Import multisig setup"),
+                Some(&me.window),
+                gtk::FileChooserAction::Open,
+                &[
+                    ("_Cancel", gtk::ResponseType::Cancel),
+                    ("_Import", gtk::ResponseType::Accept),
+                ],
+            );
+            let filter = gtk::FileFilter::new();
+            filter.set_name(Some("Multisig setup files"));
+            filter.add_pattern("*.bsms");
+            filter.add_pattern("*.txt");
+            dlg.add_filter(&filter);
+            let response = dlg.run();
+            let path = dlg.filename();
+            dlg.hide();
+            let path = match path {
+                Some(path) if response == gtk::ResponseType::Accept => path,
+                _ => return,
+            };
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let result = fs::read_to_string(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|text| {
+                    let setup = MultisigSetup::parse(&name, &text)
+                        .map_err(|err| err.to_string())?;
+                    let doc = doc.borrow();
+                    let (tracking_accounts, descriptor_accounts) = setup
+                        .to_accounts(doc.tracking_accounts(), doc.key_origins())
+                        .map_err(|err| err.to_string())?;
+                    Ok((setup, tracking_accounts, descriptor_accounts))
+                });
+            let (setup, tracking_accounts, descriptor_accounts) = match result {
+                Ok(res) => res,
+                Err(err) => return me.display_error(&format!(
+                    "Unable to import multisig setup: {}",
+                    err
+                )),
+            };
+
+            let mut preview = format!(
+                "Descriptors '{}' for receive and change addresses with {} \
+                of {} {} policy will be created.",
+                setup.name,
+                setup.threshold,
+                setup.cosigners.len(),
+                setup.format
+            );
+            if !tracking_accounts.is_empty() {
+                preview += &format!(
+                    "\n\nNew tracking accounts:\n{}\n\nOnly account-level \
+                    keys are known for these cosigners; their master key \
+                    fingerprints and derivation paths are kept in the \
+                    document as key origins.",
+                    tracking_accounts
+                        .iter()
+                        .map(|account| format!("- {}", account.name))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                );
+            }
+            let dlg = gtk::MessageDialog::new(
+                Some(&me.window),
+                gtk::DialogFlags::MODAL,
+                gtk::MessageType::Question,
+                gtk::ButtonsType::OkCancel,
+                &preview,
+            );
+            let response = dlg.run();
+            dlg.hide();
+            if response != gtk::ResponseType::Ok {
+                return;
+            }
+            for tracking_account in tracking_accounts {
+                me.pubkey_store.insert_with_values(
+                    None,
+                    &[(0, &tracking_account.name()), (1, &tracking_account.details()), (2, &tracking_account.count())]
+                );
+                let _ = doc.borrow_mut().add_tracking_account(tracking_account);
+            }
+            let _ = doc.borrow_mut().add_key_origins(setup.key_origins());
+            for descriptor_account in descriptor_accounts {
+                me.descriptor_store.insert_with_values(
+                    None,
+                    &[
+                        (0, &descriptor_account.name()),
+                        (1, &descriptor_account.type_name()),
+                        (2, &descriptor_account.descriptor()),
+                    ],
+                );
+                let _ = doc.borrow_mut().add_descriptor(descriptor_account);
+            }
+        }));
+
+        let tb: gtk::ToolButton = builder.object("descriptorWalletImport")?;
+        tb.connect_clicked(clone!(@weak me, @strong doc => move |_| {
+            let me = me.borrow();
+            let dlg = gtk::FileChooserDialog::with_buttons(
+                Some("Import watch-only wallet"),
+                Some(&me.window),
+                gtk::FileChooserAction::Open,
+                &[
+                    ("_Cancel", gtk::ResponseType::Cancel),
+                    ("_Import", gtk::ResponseType::Accept),
+                ],
+            );
+            let response = dlg.run();
+            let path = dlg.filename();
+            dlg.hide();
+            let path = match path {
+                Some(path) if response == gtk::ResponseType::Accept => path,
+                _ => return,
+            };
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let wallet = match fs::read_to_string(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|data| {
+                    WalletFile::parse(&name, &data).map_err(|err| err.to_string())
+                }) {
+                Ok(wallet) => wallet,
+                Err(err) => return me.display_error(&format!(
+                    "Unable to import wallet: {}",
+                    err
+                )),
+            };
+            let import = wallet.to_accounts(
+                doc.borrow().tracking_accounts(),
+                doc.borrow().descriptors(),
+                doc.borrow().chain(),
+            );
+            if import.descriptors.is_empty() {
+                return me.display_error(&format!(
+                    "Wallet '{}' ({}) contains no descriptors which can be \
+                    imported.{}",
+                    wallet.name,
+                    wallet.format,
+                    import
+                        .skipped
+                        .iter()
+                        .map(|(desc, err)| format!("\n\n{}:\n{}", desc, err))
+                        .collect::<String>()
+                ));
+            }
+
+            let list = |names: Vec<String>| {
+                names
+                    .into_iter()
+                    .map(|name| format!("- {}", name))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+            let mut preview = format!(
+                "Importing wallet '{}' ({}).\n\nNew descriptors:\n{}",
+                wallet.name,
+                wallet.format,
+                list(import.descriptors.iter().map(DescriptorAccount::name).collect())
+            );
+            if !import.tracking_accounts.is_empty() {
+                preview += &format!(
+                    "\n\nNew tracking accounts:\n{}\n\nMaster keys are not \
+                    known for the new tracking accounts; key origins (master \
+                    key fingerprints and derivation paths) from the wallet \
+                    are kept in the document.",
+                    list(import.tracking_accounts.iter().map(|account| account.name.clone()).collect())
+                );
+            }
+            if !import.skipped.is_empty() {
+                preview += &format!(
+                    "\n\nSkipped descriptors:\n{}",
+                    list(
+                        import
+                            .skipped
+                            .iter()
+                            .map(|(desc, err)| format!("{}: {}", desc, err))
+                            .collect()
+                    )
+                );
+            }
+            let dlg = gtk::MessageDialog::new(
+                Some(&me.window),
+                gtk::DialogFlags::MODAL,
+                gtk::MessageType::Question,
+                gtk::ButtonsType::OkCancel,
+                &preview,
+            );
+            let response = dlg.run();
+            dlg.hide();
+            if response != gtk::ResponseType::Ok {
+                return;
+            }
+            for tracking_account in import.tracking_accounts {
+                me.pubkey_store.insert_with_values(
+                    None,
+                    &[(0, &tracking_account.name()), (1, &tracking_account.details()), (2, &tracking_account.count())]
+                );
+                let _ = doc.borrow_mut().add_tracking_account(tracking_account);
+            }
+            let _ = doc.borrow_mut().add_key_origins(import.key_origins);
+            for descriptor_account in import.descriptors {
+                me.descriptor_store.insert_with_values(
+                    None,
+                    &[
+                        (0, &descriptor_account.name()),
+                        (1, &descriptor_account.type_name()),
+                        (2, &descriptor_account.descriptor()),
+                    ],
+                );
+                let _ = doc.borrow_mut().add_descriptor(descriptor_account);
+            }
+        }));
+
+        me.borrow().descriptor_export_btn.connect_clicked(clone!(@weak me, @strong doc => move |_| {
+            let me = me.borrow();
+            let descriptor_account = match me
+                .descriptor_selection()
+                .and_then(|(generator, _, _)| doc.borrow().descriptor_by_generator(&generator))
+            {
+                Some(descriptor_account) => descriptor_account,
+                None => return,
+            };
+            let setup = match MultisigSetup::with_account(
+                &descriptor_account,
+                doc.borrow().key_origins(),
+            ) {
+                Ok(setup) => setup,
+                Err(err) => return me.display_error(&err.to_string()),
+            };
+            let dlg = gtk::FileChooserDialog::with_buttons(
+                Some("Export multisig setup"),
+                Some(&me.window),
+                gtk::FileChooserAction::Save,
+                &[
+                    ("_Cancel", gtk::ResponseType::Cancel),
+                    ("_Export", gtk::ResponseType::Accept),
+                ],
+            );
+            dlg.set_do_overwrite_confirmation(true);
+            dlg.set_current_name(&format!("{}.bsms", setup.name));
+            let bsms_filter = gtk::FileFilter::new();
+            bsms_filter.set_name(Some("BSMS descriptor record (*.bsms)"));
+            bsms_filter.add_pattern("*.bsms");
+            dlg.add_filter(&bsms_filter);
+            let config_filter = gtk::FileFilter::new();
+            config_filter.set_name(Some("Signing device configuration (*.txt)"));
+            config_filter.add_pattern("*.txt");
+            dlg.add_filter(&config_filter);
+            let response = dlg.run();
+            let path = dlg.filename();
+            dlg.hide();
+            let path = match path {
+                Some(path) if response == gtk::ResponseType::Accept => path,
+                _ => return,
+            };
+            let data = if path.extension().map(|ext| ext == "bsms").unwrap_or_default() {
+                setup.to_bsms().map_err(|err| err.to_string())
+            } else {
+                Ok(setup.to_config())
+            };
+            if let Err(err) = data.and_then(|data| {
+                fs::write(&path, data).map_err(|err| err.to_string())
+            }) {
+                me.display_error(&format!("Unable to export multisig setup: {}", err));
+            }
+        }));
+
+        me.borrow().descriptor_core_export_btn.connect_clicked(clone!(@weak me, @strong doc => move |_| {
+            let me = me.borrow();
+            let descriptor_account = match me
+                .descriptor_selection()
+                .and_then(|(generator, _, _)| doc.borrow().descriptor_by_generator(&generator))
+            {
+                Some(descriptor_account) => descriptor_account,
+                None => return,
+            };
+            let dlg = gtk::MessageDialog::new(
+                Some(&me.window),
+                gtk::DialogFlags::MODAL,
+                gtk::MessageType::Question,
+                gtk::ButtonsType::YesNo,
+                "Should Bitcoin Core rescan the whole blockchain for the \
+                exported descriptors? Choose \"No\" if the keys were never \
+                used before, so only new transactions will be tracked.",
+            );
+            let response = dlg.run();
+            dlg.hide();
+            let timestamp = match response {
+                gtk::ResponseType::Yes => Timestamp::Time(0),
+                gtk::ResponseType::No => Timestamp::Now,
+                _ => return,
+            };
+            let json = match descriptor_account.to_core_import(timestamp) {
+                Ok(json) => json,
+                Err(err) => return me.display_error(&err.to_string()),
+            };
+            let dlg = gtk::FileChooserDialog::with_buttons(
+                Some("Export to Bitcoin Core"),
+                Some(&me.window),
+                gtk::FileChooserAction::Save,
+                &[
+                    ("_Cancel", gtk::ResponseType::Cancel),
+                    ("_Export", gtk::ResponseType::Accept),
+                ],
+            );
+            dlg.set_do_overwrite_confirmation(true);
+            dlg.set_current_name(&format!("{}.json", descriptor_account.name));
+            let response = dlg.run();
+            let path = dlg.filename();
+            dlg.hide();
+            let path = match path {
+                Some(path) if response == gtk::ResponseType::Accept => path,
+                _ => return,
+            };
+            if let Err(err) = fs::write(&path, json) {
+                me.display_error(&format!(
+                    "Unable to export descriptor: {}",
+                    err
+                ));
+            }
+        }));
+
         me.borrow().utxo_descr_tree.selection().connect_changed(
             clone!(@weak me => move |_| {
                 let me = me.borrow();
@@ -480,41 +911,919 @@ impl BproWin {
             }
         }));
 
+        me.borrow().psbt_tree.selection().connect_changed(
+            clone!(@weak me, @strong doc => move |_| {
+                let me = me.borrow();
+                me.update_psbt_inputs(&doc.borrow());
+            }),
+        );
+
+        let tb: gtk::ToolButton = builder.object("psbtImport")?;
+        tb.connect_clicked(clone!(@weak me, @strong doc => move |_| {
+            let me = me.borrow();
+            let dlg = gtk::FileChooserDialog::with_buttons(
+                Some("Import PSBT files"),
+                Some(&me.window),
+                gtk::FileChooserAction::Open,
+                &[
+                    ("_Cancel", gtk::ResponseType::Cancel),
+                    ("_Import", gtk::ResponseType::Accept),
+                ],
+            );
+            dlg.set_select_multiple(true);
+            let filter = gtk::FileFilter::new();
+            filter.set_name(Some("Partially signed bitcoin transactions"));
+            filter.add_pattern("*.psbt");
+            filter.add_pattern("*.txt");
+            dlg.add_filter(&filter);
+            let response = dlg.run();
+            let paths = dlg.filenames();
+            dlg.hide();
+            if response != gtk::ResponseType::Accept {
+                return;
+            }
+            let failures = me.import_psbts(&mut doc.borrow_mut(), paths);
+            me.fill_psbt_store(&doc.borrow());
+            if !failures.is_empty() {
+                me.display_error(&format!(
+                    "Some of the PSBT files were not imported:\n\n{}",
+                    failures.join("\n")
+                ));
+            }
+        }));
+
+        me.borrow().psbt_sign_btn.connect_clicked(
+            clone!(@weak me, @strong doc => move |_| {
+                let txid = match me.borrow().psbt_selection() {
+                    Some((txid, _, _)) => txid,
+                    None => return,
+                };
+                let sign_dlg = SignDlg::load_glade().expect("Must load");
+                let chain = doc.borrow().chain().clone();
+                sign_dlg.run(&chain, clone!(@weak me, @strong doc =>
+                    move |signer| {
+                        let me = me.borrow();
+                        let pos = match doc.borrow().psbt_position(txid) {
+                            Some(pos) => pos,
+                            None => return,
+                        };
+                        let mut psbt = doc
+                            .borrow()
+                            .psbt_at(pos)
+                            .expect("PSBT must be known since it is selected");
+                        let report = match signer.sign(&mut psbt) {
+                            Ok(report) => report,
+                            Err(err) => return me.display_error(&format!(
+                                "PSBT was not signed: {}", err
+                            )),
+                        };
+                        let _ = doc.borrow_mut().update_psbt_at(pos, psbt);
+                        me.fill_psbt_store(&doc.borrow());
+                        let mut msg = format!(
+                            "{} signatures were added to the PSBT",
+                            report.signed
+                        );
+                        if !report.skipped.is_empty() {
+                            msg += "; some of the inputs must be signed \
+                                with other tools:";
+                            for err in report.skipped {
+                                msg += &format!("\n{}", err);
+                            }
+                        }
+                        me.display_info(&msg);
+                    }),
+                    || {},
+                );
+            }),
+        );
+
+        me.borrow().psbt_finalize_btn.connect_clicked(
+            clone!(@weak me, @strong doc => move |_| {
+                let me = me.borrow();
+                if let Some((txid, _, _)) = me.psbt_selection() {
+                    let psbt = doc
+                        .borrow()
+                        .psbt_position(txid)
+                        .and_then(|pos| doc.borrow().psbt_at(pos))
+                        .expect("PSBT must be known since it is selected");
+                    let (tx, summary) = match psbt_finalizer::extract(&psbt) {
+                        Ok(res) => res,
+                        Err(err) => return me.display_error(&err.to_string()),
+                    };
+                    let _ = doc.borrow_mut().add_transaction(tx.clone());
+                    let dlg = gtk::MessageDialog::new(
+                        Some(&me.window),
+                        gtk::DialogFlags::MODAL,
+                        gtk::MessageType::Question,
+                        gtk::ButtonsType::YesNo,
+                        &format!(
+                            "Transaction {} is finalized and saved to the \
+                            document: {}.\n\nDo you want to broadcast it now?",
+                            summary.txid, summary
+                        )
+                    );
+                    let response = dlg.run();
+                    dlg.hide();
+                    if response != gtk::ResponseType::Yes {
+                        return;
+                    }
+                    let result = doc
+                        .borrow()
+                        .resolver()
+                        .map_err(|err| err.to_string())
+                        .and_then(|resolver| {
+                            psbt_finalizer::broadcast(&resolver, &tx)
+                                .map_err(|err| err.to_string())
+                        });
+                    let broadcasted = match result {
+                        Ok(broadcasted) => broadcasted,
+                        Err(err) => return me.display_error(&err),
+                    };
+                    match doc.borrow_mut().apply_pending_transition(txid) {
+                        Ok(None) => me.display_info(&format!(
+                            "Transaction {} was broadcasted",
+                            broadcasted
+                        )),
+                        Ok(Some(_)) => me.display_info(&format!(
+                            "Transaction {} was broadcasted and the asset \
+                            state transition anchored to it was applied",
+                            broadcasted
+                        )),
+                        Err(err) => me.display_error(&err.to_string()),
+                    }
+                    doc.borrow().fill_asset_store(&me.asset_store);
+                }
+            }),
+        );
+
+        me.borrow().psbt_bump_btn.connect_clicked(
+            clone!(@weak me, @strong doc => move |_| {
+                let me = me.borrow();
+                let txid = match me.psbt_selection() {
+                    Some((txid, _, _)) => txid,
+                    None => return,
+                };
+                let bump = {
+                    let doc = doc.borrow();
+                    let tx = match doc
+                        .psbt_position(txid)
+                        .and_then(|pos| doc.psbts().get(pos))
+                        .and_then(|psbt| doc.psbt_transaction(psbt))
+                    {
+                        Some(tx) => tx,
+                        None => return,
+                    };
+                    let fee_rate = doc
+                        .resolver()
+                        .map(|resolver| {
+                            fee_estimator::estimate_fee_rate(&resolver, 2)
+                                .sat_per_vbyte
+                        })
+                        .unwrap_or(fee_estimator::DEFAULT_FEE_RATE);
+                    let wallet = fee_bump::Wallet {
+                        descriptors: doc.descriptors(),
+                        utxos: doc.utxo_index(),
+                        transactions: doc.transactions(),
+                        psbts: doc.psbts(),
+                    };
+                    match wallet.replace_by_fee(tx, fee_rate) {
+                        Err(fee_bump::Error::NotReplaceable(_))
+                        | Err(fee_bump::Error::NoChange(_))
+                        | Err(fee_bump::Error::UnknownOutput(_)) => wallet
+                            .child_pays_for_parent(tx, fee_rate, None)
+                            .map(|bump| (bump, "child-pays-for-parent")),
+                        res => res.map(|bump| (bump, "replace-by-fee")),
+                    }
+                };
+                let (bump, method) = match bump {
+                    Ok(res) => res,
+                    Err(err) => return me.display_error(&err.to_string()),
+                };
+                let dlg = gtk::MessageDialog::new(
+                    Some(&me.window),
+                    gtk::DialogFlags::MODAL,
+                    gtk::MessageType::Question,
+                    gtk::ButtonsType::OkCancel,
+                    &format!(
+                        "Fee of transaction {} can be bumped with {}, paying \
+                        {} sat for {} vbytes ({:.1} sat/vbyte).\n\nThe new \
+                        transaction will be added to the PSBT list for signing.",
+                        txid, method, bump.fee, bump.vsize, bump.fee_rate
+                    )
+                );
+                let response = dlg.run();
+                dlg.hide();
+                if response != gtk::ResponseType::Ok {
+                    return;
+                }
+                let _ = doc.borrow_mut().add_psbt(bump.psbt);
+                me.fill_psbt_store(&doc.borrow());
+            }),
+        );
+
+        me.borrow().psbt_remove_btn.connect_clicked(
+            clone!(@weak me, @strong doc => move |_| {
+                let me = me.borrow();
+                if let Some((txid, _, iter)) = me.psbt_selection() {
+                    let mut msg =
+                        format!("Please confirm deletion of PSBT for {}", txid);
+                    if doc.borrow().is_transition_pending(txid) {
+                        msg += "; the asset state transition anchored to it \
+                            will be discarded";
+                    }
+                    let dlg = gtk::MessageDialog::new(
+                        Some(&me.window),
+                        gtk::DialogFlags::MODAL,
+                        gtk::MessageType::Question,
+                        gtk::ButtonsType::YesNo,
+                        &msg
+                    );
+                    if dlg.run() == gtk::ResponseType::Yes {
+                        if let Some(pos) = doc.borrow().psbt_position(txid) {
+                            let _ = doc.borrow_mut().remove_psbt_at(pos);
+                        }
+                        let _ = doc.borrow_mut().remove_pending_transition(txid);
+                        me.psbt_store.remove(&iter);
+                    }
+                    dlg.hide();
+                }
+            }),
+        );
+
         me.borrow().asset_tree.selection().connect_changed(
             clone!(@weak me, @strong doc => move |_| {
                 let me = me.borrow();
                 if let Some((id, _, _)) = me.asset_selection() {
+                    me.asset_export_btn.set_sensitive(true);
                     me.asset_remove_btn.set_sensitive(true);
-                    if let Some((asset, genesis)) = doc.borrow().asset_by_id(id) {
+                    me.asset_invoice_btn.set_sensitive(true);
+                    for (btn, right) in [
+                        (&me.asset_inflate_btn, OwnedRightsType::Inflation),
+                        (&me.asset_renom_btn, OwnedRightsType::Renomination),
+                        (&me.asset_transfer_btn, OwnedRightsType::Assets),
+                        (&me.asset_epoch_btn, OwnedRightsType::OpenEpoch),
+                        (&me.asset_burn_btn, OwnedRightsType::BurnReplace),
+                        (&me.asset_replace_btn, OwnedRightsType::BurnReplace),
+                    ] {
+                        btn.set_sensitive(
+                            !owned_seals(&doc.borrow(), id, right).is_empty(),
+                        );
+                    }
+                    if let Some((_, nomination, genesis)) = doc.borrow().asset_by_id(id) {
+                        let (issued, total) = doc.borrow().asset_consignment(id).map(|consignment| {
+                            let issued = known_supply(consignment).issued;
+                            (issued, issued + inflation_cap(consignment))
+                        }).unwrap_or_default();
+                        let divisor = nomination.divisor() as f64;
                         me.asset_id_display.set_text(&id.to_bech32_string());
                         me.asset_genesis_display.set_text(&genesis.to_bech32_string());
-                        me.asset_contract_display.set_text(&asset.description().clone().unwrap_or_default());
-                        me.asset_issued_display.set_text(&asset.accounting_supply(SupplyMeasure::KnownCirculating).to_string());
-                        me.asset_total_display.set_text(&asset.accounting_supply(SupplyMeasure::IssueLimit).to_string());
-                        me.asset_decimals_display.set_text(&asset.decimal_precision().to_string());
-
-                        let png = qrcode_generator::to_png_to_vec(
-                            genesis.to_bech32_string(),
-                            QrCodeEcc::Low,
-                            1024,
-                        )
-                        .ok();
-                        let pixbuf = png
-                            .and_then(|vec| {
+                        me.asset_contract_display.set_text(&nomination.contract.unwrap_or_default());
+                        me.asset_issued_display.set_text(&(issued as f64 / divisor).to_string());
+                        me.asset_total_display.set_text(&(total as f64 / divisor).to_string());
+                        me.asset_decimals_display.set_text(&nomination.precision.to_string());
+
+                        let frames = asset_export::qr_frames(&genesis.to_bech32_string())
+                            .iter()
+                            .filter_map(|frame| {
+                                asset_export::qr_png(frame, asset_export::QR_IMAGE_SIZE).ok()
+                            })
+                            .filter_map(|vec| {
                                 let loader = PixbufLoader::new();
                                 loader.write(&vec).ok()?;
+                                loader.close().ok()?;
                                 loader.pixbuf()
-                            }).and_then(|pixbuf| {
+                            }).filter_map(|pixbuf| {
                                 pixbuf.scale_simple(250, 250, InterpType::Bilinear)
-                            });
-                        me.asset_qr_image.set_from_pixbuf(pixbuf.as_ref());
+                            })
+                            .collect::<Vec<_>>();
+                        me.asset_qr_image.set_from_pixbuf(frames.first());
+                        *me.asset_qr_frames.borrow_mut() = frames;
                     }
                 } else {
+                    me.asset_export_btn.set_sensitive(false);
                     me.asset_remove_btn.set_sensitive(false);
+                    me.asset_invoice_btn.set_sensitive(false);
+                    for btn in [
+                        &me.asset_inflate_btn,
+                        &me.asset_renom_btn,
+                        &me.asset_transfer_btn,
+                        &me.asset_epoch_btn,
+                        &me.asset_burn_btn,
+                        &me.asset_replace_btn,
+                    ] {
+                        btn.set_sensitive(false);
+                    }
+                }
+            }),
+        );
+
+        let mut qr_frame = 0usize;
+        glib::timeout_add_local(
+            Duration::from_millis(asset_export::QR_FRAME_INTERVAL),
+            clone!(@weak me => @default-return glib::Continue(false), move || {
+                let me = me.borrow();
+                let frames = me.asset_qr_frames.borrow();
+                if frames.len() > 1 {
+                    qr_frame = (qr_frame + 1) % frames.len();
+                    me.asset_qr_image.set_from_pixbuf(frames.get(qr_frame));
+                }
+                glib::Continue(true)
+            }),
+        );
+
+        me.borrow().asset_export_btn.connect_clicked(
+            clone!(@weak me, @strong doc => move |_| {
+                let me = me.borrow();
+                let contract_id = match me.asset_selection() {
+                    Some((contract_id, _, _)) => contract_id,
+                    None => return,
+                };
+                let doc = doc.borrow();
+                let nomination = match doc.asset_nomination(contract_id) {
+                    Some(nomination) => nomination,
+                    None => return,
+                };
+                let consignment = doc
+                    .asset_consignment(contract_id)
+                    .expect("consignment is always present for a known asset");
+
+                let dlg = gtk::MessageDialog::new(
+                    Some(&me.window),
+                    gtk::DialogFlags::MODAL,
+                    gtk::MessageType::Question,
+                    gtk::ButtonsType::None,
+                    &format!(
+                        "Export contract genesis only, which is enough to \
+                        receive {} asset, or full consignment with {} known \
+                        state transitions?",
+                        nomination.ticker,
+                        consignment.state_transitions.len()
+                    ),
+                );
+                dlg.add_buttons(&[
+                    ("_Cancel", gtk::ResponseType::Cancel),
+                    ("_Genesis", gtk::ResponseType::Other(0)),
+                    ("C_onsignment", gtk::ResponseType::Other(1)),
+                ]);
+                let response = dlg.run();
+                dlg.hide();
+                let data = match response {
+                    gtk::ResponseType::Other(0) => asset_export::ExportData::Genesis,
+                    gtk::ResponseType::Other(1) => {
+                        asset_export::ExportData::Consignment
+                    }
+                    _ => return,
+                };
+
+                me.export_consignment(
+                    consignment,
+                    data,
+                    &format!("{}-{}.rgb", nomination.ticker, data),
+                );
+            }),
+        );
+
+        me.borrow().asset_inflate_btn.connect_clicked(
+            clone!(@weak me, @strong doc => move |_| {
+                let me = me.borrow();
+                let contract_id = match me.asset_selection() {
+                    Some((id, _, _)) => id,
+                    None => return,
+                };
+                let nomination = match doc.borrow().asset_nomination(contract_id) {
+                    Some(nomination) => nomination,
+                    None => return,
+                };
+                let divisor = nomination.divisor();
+                let cap = owned_seals(
+                    &doc.borrow(),
+                    contract_id,
+                    OwnedRightsType::Inflation,
+                )
+                .iter()
+                .filter_map(|seal| seal.value)
+                .sum::<u64>();
+
+                let selected = Rc::new(RefCell::new(None));
+                let utxo_dlg = UtxoSelectDlg::load_glade().expect("Must load");
+                utxo_dlg.run(
+                    doc.clone(),
+                    clone!(@strong selected => move |utxo| {
+                        *selected.borrow_mut() = Some(utxo);
+                    }),
+                    || {},
+                );
+                let utxo = match selected.borrow_mut().take() {
+                    Some(utxo) => utxo,
+                    None => return,
+                };
+
+                let dlg = gtk::MessageDialog::new(
+                    Some(&me.window),
+                    gtk::DialogFlags::MODAL,
+                    gtk::MessageType::Question,
+                    gtk::ButtonsType::OkCancel,
+                    &format!(
+                        "Amount of {} to issue to {}; remaining inflation \
+                        cap is {}",
+                        nomination.ticker,
+                        utxo.outpoint,
+                        cap as f64 / divisor as f64
+                    ),
+                );
+                let spin = gtk::SpinButton::with_range(
+                    1.0 / divisor as f64,
+                    cap as f64 / divisor as f64,
+                    1.0 / divisor as f64,
+                );
+                spin.set_digits(nomination.precision as u32);
+                spin.set_activates_default(true);
+                dlg.set_default_response(gtk::ResponseType::Ok);
+                if let Ok(area) = dlg.message_area().downcast::<gtk::Box>() {
+                    area.pack_end(&spin, false, true, 0);
+                }
+                dlg.show_all();
+                let response = dlg.run();
+                let amount = (spin.value() * divisor as f64).round() as u64;
+                dlg.hide();
+                if response != gtk::ResponseType::Ok {
+                    return;
+                }
+
+                me.apply_asset_operation(
+                    &doc,
+                    contract_id,
+                    &format!(
+                        "Secondary issuance of {} {}",
+                        spin.value(),
+                        nomination.ticker
+                    ),
+                    |wallet, consignment, fee_rate| {
+                        asset_ops::inflate(
+                            wallet,
+                            consignment,
+                            &[(utxo.outpoint, amount)],
+                            fee_rate,
+                        )
+                    },
+                );
+            }),
+        );
+
+        me.borrow().asset_renom_btn.connect_clicked(
+            clone!(@weak me, @strong doc => move |_| {
+                let me = me.borrow();
+                let contract_id = match me.asset_selection() {
+                    Some((id, _, _)) => id,
+                    None => return,
+                };
+                let current = match doc.borrow().asset_nomination(contract_id) {
+                    Some(nomination) => nomination,
+                    None => return,
+                };
+
+                let dlg = gtk::MessageDialog::new(
+                    Some(&me.window),
+                    gtk::DialogFlags::MODAL,
+                    gtk::MessageType::Question,
+                    gtk::ButtonsType::OkCancel,
+                    &format!("New nomination for asset {}", current.ticker),
+                );
+                let grid = gtk::Grid::new();
+                grid.set_row_spacing(6);
+                grid.set_column_spacing(12);
+                let ticker_entry = gtk::Entry::new();
+                ticker_entry.set_text(&current.ticker);
+                let name_entry = gtk::Entry::new();
+                name_entry.set_text(&current.name);
+                let contract_entry = gtk::Entry::new();
+                contract_entry
+                    .set_text(&current.contract.clone().unwrap_or_default());
+                contract_entry.set_placeholder_text(Some("No contract"));
+                let precision_spin = gtk::SpinButton::with_range(0.0, 18.0, 1.0);
+                precision_spin.set_value(current.precision as f64);
+                for (row, (label, widget)) in [
+                    ("Ticker:", ticker_entry.upcast_ref::<gtk::Widget>()),
+                    ("Name:", name_entry.upcast_ref()),
+                    ("Contract:", contract_entry.upcast_ref()),
+                    ("Precision:", precision_spin.upcast_ref()),
+                ]
+                .iter()
+                .enumerate()
+                {
+                    let label = gtk::Label::new(Some(label));
+                    label.set_halign(gtk::Align::End);
+                    grid.attach(&label, 0, row as i32, 1, 1);
+                    grid.attach(*widget, 1, row as i32, 1, 1);
+                }
+                if let Ok(area) = dlg.message_area().downcast::<gtk::Box>() {
+                    area.pack_end(&grid, false, true, 0);
+                }
+                dlg.show_all();
+                let response = dlg.run();
+                let contract = contract_entry.text().to_string();
+                let nomination = Nomination {
+                    ticker: ticker_entry.text().to_uppercase(),
+                    name: name_entry.text().to_string(),
+                    contract: if contract.is_empty() {
+                        None
+                    } else {
+                        Some(contract)
+                    },
+                    precision: precision_spin.value_as_int() as u8,
+                };
+                dlg.hide();
+                if response != gtk::ResponseType::Ok {
+                    return;
+                }
+                if nomination.ticker.is_empty() || nomination.name.is_empty() {
+                    return me.display_error(
+                        "Asset ticker and name must not be empty",
+                    );
+                }
+
+                me.apply_asset_operation(
+                    &doc,
+                    contract_id,
+                    &format!(
+                        "Renomination of {} into {} ({})",
+                        current.ticker,
+                        nomination.ticker,
+                        nomination.name
+                    ),
+                    move |wallet, consignment, fee_rate| {
+                        asset_ops::renominate(
+                            wallet,
+                            consignment,
+                            nomination,
+                            fee_rate,
+                        )
+                    },
+                );
+            }),
+        );
+
+        me.borrow().asset_transfer_btn.connect_clicked(
+            clone!(@weak me, @strong doc => move |_| {
+                let me = me.borrow();
+                let contract_id = match me.asset_selection() {
+                    Some((id, _, _)) => id,
+                    None => return,
+                };
+                let nomination = match doc.borrow().asset_nomination(contract_id) {
+                    Some(nomination) => nomination,
+                    None => return,
+                };
+                let divisor = nomination.divisor();
+
+                let dlg = gtk::MessageDialog::new(
+                    Some(&me.window),
+                    gtk::DialogFlags::MODAL,
+                    gtk::MessageType::Question,
+                    gtk::ButtonsType::OkCancel,
+                    &format!(
+                        "Paste recipient invoice and amount of {} to send; \
+                        leave zero amount to use the one from the invoice",
+                        nomination.ticker
+                    ),
+                );
+                let entry = gtk::Entry::new();
+                entry.set_placeholder_text(Some("rgb20:utxob1..."));
+                let spin = gtk::SpinButton::with_range(
+                    0.0,
+                    u64::MAX as f64 / divisor as f64,
+                    1.0 / divisor as f64,
+                );
+                spin.set_digits(nomination.precision as u32);
+                if let Ok(area) = dlg.message_area().downcast::<gtk::Box>() {
+                    area.pack_end(&spin, false, true, 0);
+                    area.pack_end(&entry, false, true, 0);
+                }
+                dlg.show_all();
+                let response = dlg.run();
+                let invoice = entry.text().to_string();
+                let amount = (spin.value() * divisor as f64).round() as u64;
+                dlg.hide();
+                if response != gtk::ResponseType::Ok {
+                    return;
+                }
+
+                let invoice = match asset_invoice::Invoice::from_str(&invoice) {
+                    Ok(invoice) => invoice,
+                    Err(err) => return me.display_error(&format!(
+                        "Invalid invoice: {}",
+                        err
+                    )),
+                };
+                if let Some(id) = invoice.contract_id {
+                    if id != contract_id {
+                        return me.display_error(&format!(
+                            "The invoice requests asset {} instead of {}",
+                            id, contract_id
+                        ));
+                    }
+                }
+                let amount = match (amount, invoice.amount) {
+                    (0, Some(amount)) => amount,
+                    (0, None) => {
+                        return me.display_error("Transfer amount is not set")
+                    }
+                    (amount, _) => amount,
+                };
+
+                let selected = Rc::new(RefCell::new(None));
+                let utxo_dlg = UtxoSelectDlg::load_glade().expect("Must load");
+                utxo_dlg.run(
+                    doc.clone(),
+                    clone!(@strong selected => move |utxo| {
+                        *selected.borrow_mut() = Some(utxo);
+                    }),
+                    || {},
+                );
+                let change = match selected.borrow_mut().take() {
+                    Some(utxo) => utxo,
+                    None => return,
+                };
+
+                let outgoing = RefCell::new(None);
+                let applied = me.apply_asset_operation(
+                    &doc,
+                    contract_id,
+                    &format!(
+                        "Transfer of {} {} with change to {}",
+                        amount as f64 / divisor as f64,
+                        nomination.ticker,
+                        change.outpoint
+                    ),
+                    |wallet, consignment, fee_rate| {
+                        asset_ops::transfer(
+                            wallet,
+                            consignment,
+                            invoice.seal,
+                            amount,
+                            &change,
+                            fee_rate,
+                        )
+                        .map(|(operation, consignment)| {
+                            *outgoing.borrow_mut() = Some(consignment);
+                            operation
+                        })
+                    },
+                );
+                if let (true, Some(consignment)) = (applied, outgoing.take()) {
+                    me.export_consignment(
+                        &consignment,
+                        asset_export::ExportData::Consignment,
+                        &format!("{}-transfer.rgb", nomination.ticker),
+                    );
                 }
             }),
         );
 
+        me.borrow().asset_invoice_btn.connect_clicked(
+            clone!(@weak me, @strong doc => move |_| {
+                let me = me.borrow();
+                let contract_id = match me.asset_selection() {
+                    Some((id, _, _)) => id,
+                    None => return,
+                };
+                let nomination = match doc.borrow().asset_nomination(contract_id) {
+                    Some(nomination) => nomination,
+                    None => return,
+                };
+                let divisor = nomination.divisor();
+
+                let selected = Rc::new(RefCell::new(None));
+                let utxo_dlg = UtxoSelectDlg::load_glade().expect("Must load");
+                utxo_dlg.run(
+                    doc.clone(),
+                    clone!(@strong selected => move |utxo| {
+                        *selected.borrow_mut() = Some(utxo);
+                    }),
+                    || {},
+                );
+                let utxo = match selected.borrow_mut().take() {
+                    Some(utxo) => utxo,
+                    None => return,
+                };
+
+                let dlg = gtk::MessageDialog::new(
+                    Some(&me.window),
+                    gtk::DialogFlags::MODAL,
+                    gtk::MessageType::Question,
+                    gtk::ButtonsType::OkCancel,
+                    &format!(
+                        "Amount of {} to request; leave zero to let the payer \
+                        choose the amount",
+                        nomination.ticker
+                    ),
+                );
+                let spin = gtk::SpinButton::with_range(
+                    0.0,
+                    u64::MAX as f64 / divisor as f64,
+                    1.0 / divisor as f64,
+                );
+                spin.set_digits(nomination.precision as u32);
+                if let Ok(area) = dlg.message_area().downcast::<gtk::Box>() {
+                    area.pack_end(&spin, false, true, 0);
+                }
+                dlg.show_all();
+                let response = dlg.run();
+                let amount = (spin.value() * divisor as f64).round() as u64;
+                dlg.hide();
+                if response != gtk::ResponseType::Ok {
+                    return;
+                }
+
+                let result = doc.borrow_mut().blind_utxo(utxo.outpoint);
+                let reveal = match result {
+                    Ok(reveal) => reveal,
+                    Err(err) => return me.display_error(&err.to_string()),
+                };
+                let invoice = asset_invoice::Invoice {
+                    seal: reveal.outpoint_hash(),
+                    contract_id: Some(contract_id),
+                    amount: if amount > 0 { Some(amount) } else { None },
+                };
+
+                let dlg = gtk::MessageDialog::new(
+                    Some(&me.window),
+                    gtk::DialogFlags::MODAL,
+                    gtk::MessageType::Info,
+                    gtk::ButtonsType::Ok,
+                    &format!(
+                        "Invoice for receiving {} to {}. The payer will send \
+                        you a consignment, which has to be imported to the \
+                        document to reveal the received allocation.",
+                        nomination.ticker,
+                        utxo.outpoint
+                    ),
+                );
+                let entry = gtk::Entry::new();
+                entry.set_text(&invoice.to_string());
+                entry.set_editable(false);
+                if let Ok(area) = dlg.message_area().downcast::<gtk::Box>() {
+                    area.pack_end(&entry, false, true, 0);
+                }
+                dlg.show_all();
+                entry.select_region(0, -1);
+                dlg.run();
+                dlg.hide();
+            }),
+        );
+
+        me.borrow().asset_epoch_btn.connect_clicked(
+            clone!(@weak me, @strong doc => move |_| {
+                let me = me.borrow();
+                let contract_id = match me.asset_selection() {
+                    Some((id, _, _)) => id,
+                    None => return,
+                };
+                let nomination = match doc.borrow().asset_nomination(contract_id) {
+                    Some(nomination) => nomination,
+                    None => return,
+                };
+
+                let selected = Rc::new(RefCell::new(None));
+                let utxo_dlg = UtxoSelectDlg::load_glade().expect("Must load");
+                utxo_dlg.run(
+                    doc.clone(),
+                    clone!(@strong selected => move |utxo| {
+                        *selected.borrow_mut() = Some(utxo);
+                    }),
+                    || {},
+                );
+                let next_epoch = match selected.borrow_mut().take() {
+                    Some(utxo) => utxo.outpoint,
+                    None => return,
+                };
+
+                me.apply_asset_operation(
+                    &doc,
+                    contract_id,
+                    &format!(
+                        "Opening of a new epoch for {} with the next epoch \
+                        seal at {}",
+                        nomination.ticker,
+                        next_epoch
+                    ),
+                    |wallet, consignment, fee_rate| {
+                        asset_ops::open_epoch(
+                            wallet,
+                            consignment,
+                            next_epoch,
+                            fee_rate,
+                        )
+                    },
+                );
+            }),
+        );
+
+        me.borrow().asset_burn_btn.connect_clicked(
+            clone!(@weak me, @strong doc => move |_| {
+                let me = me.borrow();
+                let contract_id = match me.asset_selection() {
+                    Some((id, _, _)) => id,
+                    None => return,
+                };
+                let nomination = match doc.borrow().asset_nomination(contract_id) {
+                    Some(nomination) => nomination,
+                    None => return,
+                };
+                let divisor = nomination.divisor();
+                let allocation = match me.select_allocation(
+                    &doc.borrow(),
+                    contract_id,
+                    divisor,
+                    &format!("Allocation of {} to burn", nomination.ticker),
+                ) {
+                    Some(allocation) => allocation,
+                    None => return,
+                };
+                me.apply_asset_operation(
+                    &doc,
+                    contract_id,
+                    &format!(
+                        "Burning of {} {} allocated to {}",
+                        allocation.value.unwrap_or_default() as f64
+                            / divisor as f64,
+                        nomination.ticker,
+                        allocation.outpoint
+                    ),
+                    |wallet, consignment, fee_rate| {
+                        asset_ops::burn(
+                            wallet,
+                            consignment,
+                            &[allocation.outpoint],
+                            &[],
+                            fee_rate,
+                        )
+                    },
+                );
+            }),
+        );
+
+        me.borrow().asset_replace_btn.connect_clicked(
+            clone!(@weak me, @strong doc => move |_| {
+                let me = me.borrow();
+                let contract_id = match me.asset_selection() {
+                    Some((id, _, _)) => id,
+                    None => return,
+                };
+                let nomination = match doc.borrow().asset_nomination(contract_id) {
+                    Some(nomination) => nomination,
+                    None => return,
+                };
+                let divisor = nomination.divisor();
+                let allocation = match me.select_allocation(
+                    &doc.borrow(),
+                    contract_id,
+                    divisor,
+                    &format!("Allocation of {} to replace", nomination.ticker),
+                ) {
+                    Some(allocation) => allocation,
+                    None => return,
+                };
+                let amount = allocation.value.unwrap_or_default();
+
+                let selected = Rc::new(RefCell::new(None));
+                let utxo_dlg = UtxoSelectDlg::load_glade().expect("Must load");
+                utxo_dlg.run(
+                    doc.clone(),
+                    clone!(@strong selected => move |utxo| {
+                        *selected.borrow_mut() = Some(utxo);
+                    }),
+                    || {},
+                );
+                let utxo = match selected.borrow_mut().take() {
+                    Some(utxo) => utxo,
+                    None => return,
+                };
+
+                me.apply_asset_operation(
+                    &doc,
+                    contract_id,
+                    &format!(
+                        "Replacement of {} {} allocated to {} with a new \
+                        allocation to {}",
+                        amount as f64 / divisor as f64,
+                        nomination.ticker,
+                        allocation.outpoint,
+                        utxo.outpoint
+                    ),
+                    |wallet, consignment, fee_rate| {
+                        asset_ops::burn(
+                            wallet,
+                            consignment,
+                            &[allocation.outpoint],
+                            &[(utxo.outpoint, amount)],
+                            fee_rate,
+                        )
+                    },
+                );
+            }),
+        );
+
         let tb: gtk::ToolButton = builder.object("assetCreate")?;
         tb.connect_clicked(clone!(@weak me, @strong doc => move |_| {
             let issue_dlg = AssetDlg::load_glade().expect("Must load");
@@ -544,6 +1853,194 @@ impl BproWin {
             );
         }));
 
+        let tb: gtk::ToolButton = builder.object("assetImport")?;
+        tb.connect_clicked(clone!(@weak me, @strong doc => move |_| {
+            let me = me.borrow();
+            let dlg = gtk::MessageDialog::new(
+                Some(&me.window),
+                gtk::DialogFlags::MODAL,
+                gtk::MessageType::Question,
+                gtk::ButtonsType::OkCancel,
+                "Paste bech32-encoded consignment or genesis, or leave the \
+                field empty to import consignment from a file",
+            );
+            let entry = gtk::Entry::new();
+            entry.set_placeholder_text(Some("consignment1... or genesis1..."));
+            entry.set_activates_default(true);
+            dlg.set_default_response(gtk::ResponseType::Ok);
+            if let Ok(area) = dlg.message_area().downcast::<gtk::Box>() {
+                area.pack_end(&entry, false, true, 0);
+            }
+            dlg.show_all();
+            let response = dlg.run();
+            let text = entry.text().trim().to_string();
+            dlg.hide();
+            if response != gtk::ResponseType::Ok {
+                return;
+            }
+
+            let data = if text.is_empty() {
+                let dlg = gtk::FileChooserDialog::with_buttons(
+                    Some("Import RGB consignment"),
+                    Some(&me.window),
+                    gtk::FileChooserAction::Open,
+                    &[
+                        ("_Cancel", gtk::ResponseType::Cancel),
+                        ("_Import", gtk::ResponseType::Accept),
+                    ],
+                );
+                let response = dlg.run();
+                let path = dlg.filename();
+                dlg.hide();
+                match path {
+                    Some(path) if response == gtk::ResponseType::Accept => {
+                        match fs::read(&path) {
+                            Ok(data) => data,
+                            Err(err) => return me.display_error(&err.to_string()),
+                        }
+                    }
+                    _ => return,
+                }
+            } else {
+                text.into_bytes()
+            };
+
+            let consignment = match asset_import::parse(&data) {
+                Ok(consignment) => consignment,
+                Err(err) => return me.display_error(&format!(
+                    "Unable to import consignment: {}",
+                    err
+                )),
+            };
+            let resolver = doc.borrow().resolver().ok();
+            let status = match asset_import::validate(
+                &consignment,
+                doc.borrow().chain(),
+                resolver.as_ref(),
+            ) {
+                Ok(status) => status,
+                Err(err) => return me.display_error(&err.to_string()),
+            };
+            if status.validity()
+                == rgb::validation::Validity::UnresolvedTransactions
+            {
+                let dlg = gtk::MessageDialog::new(
+                    Some(&me.window),
+                    gtk::DialogFlags::MODAL,
+                    gtk::MessageType::Warning,
+                    gtk::ButtonsType::YesNo,
+                    &format!(
+                        "Consignment is valid according to the RGB20 schema, \
+                        but {} witness transaction(s) were not found{}.\n\n\
+                        Do you want to import it anyway?",
+                        status.unresolved_txids.len(),
+                        if resolver.is_none() {
+                            " since Electrum server is not configured"
+                        } else {
+                            ""
+                        }
+                    ),
+                );
+                let response = dlg.run();
+                dlg.hide();
+                if response != gtk::ResponseType::Yes {
+                    return;
+                }
+            }
+
+            let mut consignment = consignment;
+            let received = doc.borrow().reveal_seals(&mut consignment);
+            let received = if received > 0 {
+                format!(
+                    "\n\n{} allocation(s) to the document invoices were \
+                    received",
+                    received
+                )
+            } else {
+                s!("")
+            };
+
+            let contract_id = consignment.genesis.contract_id();
+            let result = doc.borrow_mut().add_asset(consignment.clone());
+            match result {
+                Err(crate::model::Error::DuplicatedContract(_)) => {}
+                Err(err) => return me.display_error(&err.to_string()),
+                Ok(_) => {
+                    doc.borrow().fill_asset_store(&me.asset_store);
+                    return me.display_info(&format!(
+                        "Asset {} was imported{}",
+                        contract_id.to_bech32_string(),
+                        received
+                    ));
+                }
+            }
+            let dlg = gtk::MessageDialog::new(
+                Some(&me.window),
+                gtk::DialogFlags::MODAL,
+                gtk::MessageType::Question,
+                gtk::ButtonsType::YesNo,
+                &format!(
+                    "{}\n\nDo you want to merge state transitions from the \
+                    imported consignment into the existing asset?",
+                    crate::model::Error::DuplicatedContract(contract_id)
+                ),
+            );
+            let response = dlg.run();
+            dlg.hide();
+            if response != gtk::ResponseType::Yes {
+                return;
+            }
+            match doc.borrow_mut().merge_asset(consignment) {
+                Ok(0) => me.display_info(
+                    "The asset already contains all state from the \
+                    consignment",
+                ),
+                Ok(count) => me.display_info(&format!(
+                    "{} new state transitions and extensions were merged{}",
+                    count, received
+                )),
+                Err(err) => me.display_error(&err.to_string()),
+            }
+            doc.borrow().fill_asset_store(&me.asset_store);
+        }));
+
+        let tb: gtk::ToolButton = builder.object("assetSync")?;
+        tb.connect_clicked(clone!(@weak me, @strong doc => move |tb| {
+            let server = match doc.borrow().electrum() {
+                Some(server) => server,
+                None => {
+                    return me.borrow().display_error(
+                        &ResolverError::ElectrumRequired.to_string(),
+                    )
+                }
+            };
+            let consignments =
+                doc.borrow().assets().values().cloned().collect();
+            let (sender, receiver) =
+                glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+            tb.set_sensitive(false);
+            receiver.attach(
+                None,
+                clone!(@weak me, @strong doc, @weak tb => @default-return glib::Continue(false), move |result| {
+                    tb.set_sensitive(true);
+                    me.borrow().apply_asset_sync(&doc, result);
+                    glib::Continue(false)
+                }),
+            );
+            asset_sync::spawn(server, consignments, sender);
+        }));
+
+        me.borrow().asset_tree.connect_row_activated(
+            clone!(@weak me, @strong doc => move |_, _, _| {
+                let contract_id = match me.borrow().asset_selection() {
+                    Some((contract_id, _, _)) => contract_id,
+                    None => return,
+                };
+                let asset_dlg = AssetDlg::load_glade().expect("Must load");
+                asset_dlg.run(doc.clone(), Some(contract_id), |_, _| {}, || {});
+            }),
+        );
+
         me.borrow().asset_remove_btn.connect_clicked(
             clone!(@weak me, @strong doc => move |_| {
                 let me = me.borrow();
@@ -596,6 +2093,37 @@ impl BproWin {
             }), || {})
         }));
 
+        let (sender, receiver) =
+            glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        receiver.attach(
+            None,
+            clone!(@weak me, @strong doc => @default-return glib::Continue(false), move |event| {
+                me.borrow().apply_subscription_event(&doc, event);
+                glib::Continue(true)
+            }),
+        );
+        let watcher = subscriptions::spawn(sender);
+        glib::timeout_add_seconds_local(
+            subscriptions::POLL_INTERVAL,
+            clone!(@weak me, @strong doc => @default-return glib::Continue(false), move || {
+                let doc = doc.borrow();
+                let server = match doc.electrum() {
+                    Some(server) => server,
+                    None => return glib::Continue(true),
+                };
+                let watched = subscriptions::Watched {
+                    server,
+                    descriptors: doc.descriptors().to_vec(),
+                    utxos: doc.utxos().clone(),
+                };
+                glib::Continue(watcher.send(watched).is_ok())
+            }),
+        );
+
+        if !needs_save {
+            me.borrow().audit_tracking_accounts(&doc.borrow());
+        }
+
         Some(me)
     }
 }
@@ -625,6 +2153,579 @@ impl BproWin {
         gtk::main();
     }
 
+    /// Applies results of the asset synchronization: records spent seals
+    /// and reports stale seals to the user
+    fn apply_asset_sync(
+        &self,
+        doc: &Rc<RefCell<Document>>,
+        result: Result<
+            Vec<(ContractId, asset_sync::SyncStatus)>,
+            utxo_lookup::Error,
+        >,
+    ) {
+        let mut report = vec![];
+        let mut checked = 0usize;
+        let mut spent_seals = vec![];
+        let contracts = match result {
+            Ok(contracts) => contracts,
+            Err(err) => return self.display_error(&err.to_string()),
+        };
+        for (contract_id, status) in contracts {
+            checked += status.checked;
+            let ticker = doc
+                .borrow()
+                .asset_nomination(contract_id)
+                .map(|nomination| nomination.ticker)
+                .unwrap_or_else(|| contract_id.to_string());
+            report.extend(status.spent.iter().map(|(right, seal)| {
+                format!(
+                    "- {} {:?} seal {} is spent",
+                    ticker, right, seal.outpoint
+                )
+            }));
+            report.extend(status.unresolved.iter().map(|(right, seal)| {
+                format!(
+                    "- {} {:?} seal {} points to an unknown transaction",
+                    ticker, right, seal.outpoint
+                )
+            }));
+            spent_seals.push((contract_id, status.spent_outpoints()));
+        }
+        if let Err(err) = doc.borrow_mut().update_spent_seals(spent_seals) {
+            self.display_error(&err.to_string());
+        }
+        doc.borrow().fill_asset_store(&self.asset_store);
+
+        if report.is_empty() {
+            self.display_info(&format!(
+                "All {} asset seals are unspent",
+                checked
+            ));
+        } else {
+            let dlg = gtk::MessageDialog::new(
+                Some(&self.window),
+                gtk::DialogFlags::MODAL,
+                gtk::MessageType::Warning,
+                gtk::ButtonsType::Ok,
+                &format!(
+                    "{} of {} asset seals are stale. Spent seals are \
+                    likely closed by state transitions which were not \
+                    imported into the document yet:\n\n{}",
+                    report.len(),
+                    checked,
+                    report.join("\n")
+                ),
+            );
+            dlg.run();
+            dlg.hide();
+        }
+    }
+
+    /// Applies event reported by the subscriptions worker: shows the
+    /// connection status and updates cached UTXOs, notifying user about new
+    /// incoming payments
+    fn apply_subscription_event(
+        &self,
+        doc: &Rc<RefCell<Document>>,
+        event: subscriptions::Event,
+    ) {
+        let context = self.utxo_status.context_id("subscriptions");
+        let (update, tip) = match event {
+            subscriptions::Event::Connected { tip, scripts } => {
+                self.utxo_status.remove_all(context);
+                self.utxo_status.push(
+                    context,
+                    &format!("Watching {} scripts for updates", scripts),
+                );
+                return self.display_tip(&doc.borrow(), tip);
+            }
+            subscriptions::Event::Failed { error, retry_in } => {
+                self.utxo_status.remove_all(context);
+                self.utxo_status.push(
+                    context,
+                    &format!(
+                        "Unable to receive updates from the server: {}; \
+                        retrying in {} seconds",
+                        error, retry_in
+                    ),
+                );
+                return;
+            }
+            subscriptions::Event::Update { update, tip } => (update, tip),
+        };
+        if update.tip.is_some() {
+            self.display_tip(&doc.borrow(), tip);
+        }
+        if update.is_empty() {
+            return;
+        }
+
+        // The worker may have polled with a snapshot taken before the
+        // previous update was applied
+        let added = update
+            .added
+            .iter()
+            .filter(|utxo| !doc.borrow().is_outpoint_known(utxo.outpoint))
+            .cloned()
+            .collect::<Vec<_>>();
+        doc.borrow_mut().replace_utxos(
+            &update.removed,
+            update.added.into_iter().chain(update.updated),
+        );
+        let doc = doc.borrow();
+        doc.fill_utxo_store(&self.utxo_store, None);
+        if let Some(descriptor_generator) =
+            self.descriptor_selection().and_then(|(generator, _, _)| {
+                doc.descriptor_by_generator(&generator)
+            })
+        {
+            doc.fill_utxo_store(
+                &self.utxo_descr_store,
+                Some(&descriptor_generator),
+            );
+        }
+
+        if added.is_empty() {
+            return;
+        }
+        let payments = added
+            .iter()
+            .map(|utxo| {
+                format!(
+                    "{} sat to {} at {} ({} confirmations)",
+                    utxo.amount,
+                    doc.descriptors()
+                        .iter()
+                        .find(|account| utxo.has_match(account))
+                        .map(DescriptorAccount::name)
+                        .unwrap_or_default(),
+                    utxo.outpoint,
+                    utxo.confirmations(tip)
+                )
+            })
+            .collect::<Vec<_>>();
+        self.display_info(&format!(
+            "New incoming payments:\n{}",
+            payments.join("\n")
+        ));
+    }
+
+    fn display_tip(&self, doc: &Document, tip: u32) {
+        self.header_bar.set_subtitle(Some(&format!(
+            "{} - block #{}",
+            doc.name(),
+            tip
+        )));
+    }
+
+    /// Warns user about tracking accounts which keys can't be used with
+    /// the chain selected for the document
+    pub fn audit_tracking_accounts(&self, doc: &Document) {
+        let mismatches = doc.audit_tracking_accounts();
+        if mismatches.is_empty() {
+            return;
+        }
+        let dlg = gtk::MessageDialog::new(
+            Some(&self.window),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Warning,
+            gtk::ButtonsType::Ok,
+            &format!(
+                "The following tracking accounts do not match {} used by \
+                the document:\n\n{}",
+                doc.chain(),
+                mismatches
+                    .iter()
+                    .map(|mismatch| format!("- {}", mismatch))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ),
+        );
+        dlg.run();
+        dlg.hide();
+    }
+
+    /// Imports PSBTs from the files, combining PSBTs for the transactions
+    /// which are already known to the document. Returns descriptions of
+    /// failures for the files which were not imported.
+    pub fn import_psbts(
+        &self,
+        doc: &mut Document,
+        paths: Vec<PathBuf>,
+    ) -> Vec<String> {
+        let mut failures = vec![];
+        for (path, psbt) in psbt_coordinator::read_files(paths) {
+            let result = psbt.map_err(|err| err.to_string()).and_then(|psbt| {
+                let txid = psbt.global.unsigned_tx.txid();
+                match doc.psbt_position(txid) {
+                    Some(pos) => {
+                        let known = doc
+                            .psbt_at(pos)
+                            .expect("position is returned by the document");
+                        let combined =
+                            psbt_coordinator::combine(&[known, psbt])
+                                .map_err(|err| err.to_string())?;
+                        doc.update_psbt_at(pos, combined)
+                    }
+                    None => doc.add_psbt(psbt),
+                }
+                .map_err(|err| err.to_string())
+            });
+            if let Err(err) = result {
+                failures.push(format!("- {}: {}", path.display(), err));
+            }
+        }
+        failures
+    }
+
+    pub fn fill_psbt_store(&self, doc: &Document) {
+        self.psbt_store.clear();
+        for psbt in doc.psbts() {
+            let status = psbt_coordinator::signing_status(
+                psbt,
+                doc.tracking_accounts(),
+                doc.key_origins(),
+                doc.descriptors(),
+            );
+            let complete =
+                status.iter().filter(|input| input.is_complete()).count();
+            self.psbt_store.insert_with_values(
+                None,
+                &[
+                    (0, &psbt.global.unsigned_tx.txid().to_string()),
+                    (1, &(psbt.inputs.len() as u32)),
+                    (2, &(psbt.outputs.len() as u32)),
+                    (
+                        3,
+                        &format!(
+                            "{} of {} inputs are ready",
+                            complete,
+                            status.len()
+                        ),
+                    ),
+                ],
+            );
+        }
+        self.update_psbt_inputs(doc);
+    }
+
+    /// Shows signing status matrix for the selected PSBT: each input with
+    /// the number of signatures remaining to reach the threshold, and the
+    /// keys which have (or have not) signed it
+    pub fn update_psbt_inputs(&self, doc: &Document) {
+        self.psbt_input_store.clear();
+        let psbt = self.psbt_selection().and_then(|(txid, _, _)| {
+            doc.psbt_position(txid).and_then(|pos| doc.psbt_at(pos))
+        });
+        let psbt = match psbt {
+            Some(psbt) => psbt,
+            None => {
+                self.psbt_sign_btn.set_sensitive(false);
+                self.psbt_finalize_btn.set_sensitive(false);
+                self.psbt_bump_btn.set_sensitive(false);
+                self.psbt_remove_btn.set_sensitive(false);
+                return;
+            }
+        };
+        let status = psbt_coordinator::signing_status(
+            &psbt,
+            doc.tracking_accounts(),
+            doc.key_origins(),
+            doc.descriptors(),
+        );
+        for (index, input) in status.iter().enumerate() {
+            let parent = self.psbt_input_store.insert_with_values(
+                None,
+                None,
+                &[
+                    (0, &format!("Input #{}", index)),
+                    (
+                        1,
+                        &psbt.global.unsigned_tx.input[index]
+                            .previous_output
+                            .to_string(),
+                    ),
+                    (
+                        2,
+                        &if input.finalized {
+                            s!("finalized")
+                        } else if input.is_complete() {
+                            format!(
+                                "{} of {} signed",
+                                input.signed(),
+                                input.threshold
+                            )
+                        } else {
+                            format!(
+                                "{} more signatures required",
+                                input.remaining()
+                            )
+                        },
+                    ),
+                ],
+            );
+            for key in &input.keys {
+                self.psbt_input_store.insert_with_values(
+                    Some(&parent),
+                    None,
+                    &[
+                        (
+                            0,
+                            &key.account
+                                .clone()
+                                .unwrap_or_else(|| s!("<Unknown key>")),
+                        ),
+                        (1, &key.pubkey.to_string()),
+                        (2, &if key.signed { "signed" } else { "not signed" }),
+                    ],
+                );
+            }
+        }
+        self.psbt_sign_btn.set_sensitive(true);
+        self.psbt_finalize_btn
+            .set_sensitive(status.iter().all(|input| input.is_complete()));
+        self.psbt_bump_btn
+            .set_sensitive(doc.psbt_transaction(&psbt).is_some());
+        self.psbt_remove_btn.set_sensitive(true);
+    }
+
+    /// Constructs asset operation using the document wallet and estimated
+    /// fee rate; once confirmed by the user, adds the witness PSBT and the
+    /// updated consignment to the document. Returns whether the operation
+    /// was applied.
+    pub fn apply_asset_operation(
+        &self,
+        doc: &Rc<RefCell<Document>>,
+        contract_id: ContractId,
+        description: &str,
+        operation: impl FnOnce(
+            &fee_bump::Wallet,
+            &Consignment,
+            f32,
+        ) -> Result<
+            asset_ops::AssetOperation,
+            asset_ops::Error,
+        >,
+    ) -> bool {
+        let operation = {
+            let doc = doc.borrow();
+            let consignment = match doc.asset_consignment(contract_id) {
+                Some(consignment) => consignment,
+                None => return false,
+            };
+            let fee_rate = doc
+                .resolver()
+                .map(|resolver| {
+                    fee_estimator::estimate_fee_rate(&resolver, 2).sat_per_vbyte
+                })
+                .unwrap_or(fee_estimator::DEFAULT_FEE_RATE);
+            let wallet = fee_bump::Wallet {
+                descriptors: doc.descriptors(),
+                utxos: doc.utxo_index(),
+                transactions: doc.transactions(),
+                psbts: doc.psbts(),
+            };
+            operation(&wallet, consignment, fee_rate).and_then(|operation| {
+                asset_ops::check_other_contracts(&operation, doc.assets())?;
+                Ok(operation)
+            })
+        };
+        let operation = match operation {
+            Ok(operation) => operation,
+            Err(err) => {
+                self.display_error(&err.to_string());
+                return false;
+            }
+        };
+        let dlg = gtk::MessageDialog::new(
+            Some(&self.window),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::OkCancel,
+            &format!(
+                "{} is anchored to transaction {} paying {} sat fee.\n\nThe \
+                transaction will be added to the PSBT list for signing; the \
+                asset is updated once the transaction is broadcast.",
+                description,
+                operation.psbt.global.unsigned_tx.txid(),
+                operation.fee
+            ),
+        );
+        let response = dlg.run();
+        dlg.hide();
+        if response != gtk::ResponseType::Ok {
+            return false;
+        }
+        let txid = operation.psbt.global.unsigned_tx.txid();
+        let result = doc.borrow_mut().add_psbt(operation.psbt);
+        let result = result.and_then(|_| {
+            doc.borrow_mut()
+                .add_pending_transition(txid, operation.consignment)
+        });
+        if let Err(err) = &result {
+            self.display_error(&err.to_string());
+        }
+        self.fill_psbt_store(&doc.borrow());
+        result.is_ok()
+    }
+
+    /// Asks user for the file name and exports the contract data, detecting
+    /// export format from the file extension
+    pub fn export_consignment(
+        &self,
+        consignment: &Consignment,
+        data: asset_export::ExportData,
+        file_name: &str,
+    ) {
+        let dlg = gtk::FileChooserDialog::with_buttons(
+            Some(
+                "Export contract data: use .rgb extension for binary \
+                data, .txt for bech32, .yaml or .json for a dump and \
+                .png for QR codes",
+            ),
+            Some(&self.window),
+            gtk::FileChooserAction::Save,
+            &[
+                ("_Cancel", gtk::ResponseType::Cancel),
+                ("_Save", gtk::ResponseType::Accept),
+            ],
+        );
+        dlg.set_do_overwrite_confirmation(true);
+        dlg.set_current_name(file_name);
+        let response = dlg.run();
+        let path = dlg.filename();
+        dlg.hide();
+        let path = match path {
+            Some(path) if response == gtk::ResponseType::Accept => path,
+            _ => return,
+        };
+
+        let format = asset_export::ExportFormat::with_path(&path);
+        let result = asset_export::export(consignment, data, format)
+            .map_err(|err| err.to_string())
+            .and_then(|exported| {
+                if format != asset_export::ExportFormat::Qr {
+                    return fs::write(&path, exported)
+                        .map(|_| 1)
+                        .map_err(|err| err.to_string());
+                }
+                let frames = asset_export::qr_frames(&String::from_utf8_lossy(
+                    &exported,
+                ));
+                let count = frames.len();
+                for (no, frame) in frames.iter().enumerate() {
+                    let mut frame_path = path.clone();
+                    if count > 1 {
+                        frame_path.set_file_name(format!(
+                            "{}-{}of{}.png",
+                            path.file_stem()
+                                .map(|stem| stem.to_string_lossy())
+                                .unwrap_or_default(),
+                            no + 1,
+                            count
+                        ));
+                    }
+                    asset_export::qr_png(frame, asset_export::QR_IMAGE_SIZE)
+                        .map_err(|err| err.to_string())
+                        .and_then(|png| {
+                            fs::write(&frame_path, png)
+                                .map_err(|err| err.to_string())
+                        })?;
+                }
+                Ok(count)
+            });
+        match result {
+            Ok(1) => self.display_info(&format!(
+                "Asset {} was exported as {}",
+                data, format
+            )),
+            Ok(count) => self.display_info(&format!(
+                "Asset {} was exported as an animated QR code of {} \
+                frames",
+                data, count
+            )),
+            Err(err) => self.display_error(&err),
+        }
+    }
+
+    /// Asks user to select one of the known asset allocations which are
+    /// not burned yet
+    pub fn select_allocation(
+        &self,
+        doc: &Document,
+        contract_id: ContractId,
+        divisor: u64,
+        title: &str,
+    ) -> Option<AssetSeal> {
+        let consignment = doc.asset_consignment(contract_id)?;
+        let burned = burned_outpoints(consignment);
+        let allocations = known_seals(consignment, OwnedRightsType::Assets)
+            .into_iter()
+            .filter(|seal| !burned.contains(&seal.outpoint))
+            .collect::<Vec<_>>();
+        if allocations.is_empty() {
+            self.display_error("The asset has no known allocations");
+            return None;
+        }
+
+        let dlg = gtk::MessageDialog::new(
+            Some(&self.window),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::OkCancel,
+            title,
+        );
+        let combo = gtk::ComboBoxText::new();
+        for seal in &allocations {
+            combo.append_text(&format!(
+                "{}: {}{}",
+                seal.outpoint,
+                seal.value.unwrap_or_default() as f64 / divisor as f64,
+                if doc.is_outpoint_known(seal.outpoint) {
+                    ""
+                } else {
+                    " (not in wallet)"
+                }
+            ));
+        }
+        combo.set_active(Some(0));
+        if let Ok(area) = dlg.message_area().downcast::<gtk::Box>() {
+            area.pack_end(&combo, false, true, 0);
+        }
+        dlg.show_all();
+        let response = dlg.run();
+        let active = combo.active();
+        dlg.hide();
+        if response != gtk::ResponseType::Ok {
+            return None;
+        }
+        active.and_then(|index| allocations.get(index as usize).copied())
+    }
+
+    pub fn display_info(&self, msg: &str) {
+        let dlg = gtk::MessageDialog::new(
+            Some(&self.window),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Info,
+            gtk::ButtonsType::Ok,
+            msg,
+        );
+        dlg.run();
+        dlg.hide();
+    }
+
+    pub fn display_error(&self, msg: &str) {
+        let dlg = gtk::MessageDialog::new(
+            Some(&self.window),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Error,
+            gtk::ButtonsType::Ok,
+            msg,
+        );
+        dlg.run();
+        dlg.hide();
+    }
+
     pub fn pubkey_selection(
         &self,
     ) -> Option<(String, gtk::TreeModel, gtk::TreeIter)> {
@@ -674,6 +2775,22 @@ impl BproWin {
         })
     }
 
+    pub fn psbt_selection(
+        &self,
+    ) -> Option<(Txid, gtk::TreeModel, gtk::TreeIter)> {
+        self.psbt_tree
+            .selection()
+            .selected()
+            .and_then(|(model, iter)| {
+                model
+                    .value(&iter, 0)
+                    .get::<String>()
+                    .ok()
+                    .and_then(|s| Txid::from_str(&s).ok())
+                    .map(|txid| (txid, model, iter))
+            })
+    }
+
     pub fn asset_selection(
         &self,
     ) -> Option<(ContractId, gtk::TreeModel, gtk::TreeIter)> {
@@ -692,3 +2809,25 @@ impl BproWin {
 
     pub fn update_ui(&self) {}
 }
+
+/// Current seals of the asset right controlled by the document UTXOs
+fn owned_seals(
+    doc: &Document,
+    contract_id: ContractId,
+    right: OwnedRightsType,
+) -> Vec<AssetSeal> {
+    let consignment = match doc.asset_consignment(contract_id) {
+        Some(consignment) => consignment,
+        None => return vec![],
+    };
+    let wallet = fee_bump::Wallet {
+        descriptors: doc.descriptors(),
+        utxos: doc.utxo_index(),
+        transactions: doc.transactions(),
+        psbts: doc.psbts(),
+    };
+    asset_ops::owned_seals(&wallet, consignment, right)
+        .into_iter()
+        .map(|(seal, _)| seal)
+        .collect()
+}
@@ -559,6 +559,7 @@ impl DescriptorDlg {
                     .ok_or(Error::LookupTypeRequired)?,
             )?,
             generator,
+            doc.borrow().derivation_cache(),
             self.utxo_set.clone(),
             Some(&self.utxo_store),
         )?;
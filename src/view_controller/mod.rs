@@ -15,6 +15,7 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+mod account_batch_dlg;
 mod asset_dlg;
 mod bpro_win;
 mod descriptor_dlg;
@@ -22,8 +23,10 @@ mod open_dlg;
 mod pubkey_dlg;
 mod pubkey_select_dlg;
 mod save_dlg;
+mod sign_dlg;
 mod utxo_select_dlg;
 
+pub use account_batch_dlg::AccountBatchDlg;
 pub use asset_dlg::AssetDlg;
 pub use bpro_win::{BproWin, Error as AppError};
 pub use descriptor_dlg::DescriptorDlg;
@@ -31,4 +34,5 @@ pub use open_dlg::OpenDlg;
 pub use pubkey_dlg::PubkeyDlg;
 pub use pubkey_select_dlg::PubkeySelectDlg;
 pub use save_dlg::SaveDlg;
+pub use sign_dlg::SignDlg;
 pub use utxo_select_dlg::UtxoSelectDlg;
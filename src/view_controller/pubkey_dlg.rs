@@ -18,6 +18,7 @@
 #![allow(clippy::needless_borrow)] // TODO: Remove unce bug in amplify_derive is fixed
 
 use gtk::prelude::*;
+use std::cell::RefCell;
 use std::ops::RangeInclusive;
 use std::rc::Rc;
 use std::str::FromStr;
@@ -30,13 +31,16 @@ use bitcoin::util::key;
 use gtk::gdk;
 use lnpbp::Chain;
 use miniscript::descriptor::DescriptorSinglePub;
-use slip132::{self, FromSlip132};
+use slip132::{self, FromSlip132, KeyApplication};
 use wallet::bip32::{
     DerivationComponents, DerivationRangeVec, HardenedNormalSplit,
 };
 use wallet::descriptor;
 
-use crate::model::TrackingAccount;
+use crate::model::{
+    DerivationPreset, DerivationScheme, NetworkMismatch, TrackingAccount,
+};
+use crate::util::xkey::{self, XkeyFormat};
 
 static UI: &str = include_str!("../view/pubkey.glade");
 
@@ -88,6 +92,18 @@ pub enum Error {
     /// For hardened derivation path you have to provide either account
     /// extended pubkey or master private key (not recommended)
     AccountXpubNeeded,
+
+    /// Extended key is encoded as {0}, which is not a key for the {1}
+    /// blockchain selected
+    NetworkMismatch(String, String),
+
+    /// Tracking account can't be added to the document: {0}
+    #[from]
+    DocumentNetwork(NetworkMismatch),
+
+    /// Unable to convert extended key: {0}
+    #[from]
+    Xkey(xkey::Error),
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Display)]
@@ -142,6 +158,7 @@ pub struct PubkeyDlg {
     range_field: gtk::Entry,
     derivation_field: gtk::Entry,
 
+    chain: RefCell<Chain>,
     network_combo: gtk::ComboBox,
     offset_index: gtk::SpinButton,
     offset_chk: gtk::CheckButton,
@@ -152,6 +169,7 @@ pub struct PubkeyDlg {
     derivation_display: gtk::Entry,
     descriptor_display: gtk::Entry,
     xpub_display: gtk::Entry,
+    xpub_format_combo: gtk::ComboBox,
 
     uncompressed_display: gtk::Entry,
     compressed_display: gtk::Entry,
@@ -212,6 +230,7 @@ impl PubkeyDlg {
         let derivation_display = builder.object("derivationDisplay")?;
         let descriptor_display = builder.object("descriptorDisplay")?;
         let xpub_display = builder.object("xpubDisplay")?;
+        let xpub_format_combo = builder.object("xpubFormatCombo")?;
 
         let uncompressed_display = builder.object("uncompressedDisplay")?;
         let compressed_display = builder.object("compressedDisplay")?;
@@ -251,6 +270,7 @@ impl PubkeyDlg {
             range_chk,
             range_field,
             derivation_field,
+            chain: RefCell::new(Chain::default()),
             network_combo,
             offset_index,
             offset_chk,
@@ -260,6 +280,7 @@ impl PubkeyDlg {
             derivation_display,
             descriptor_display,
             xpub_display,
+            xpub_format_combo,
             uncompressed_display,
             compressed_display,
             xcoordonly_display,
@@ -316,6 +337,7 @@ impl PubkeyDlg {
             &me.asset_combo,
             &me.change_combo,
             &me.network_combo,
+            &me.xpub_format_combo,
         ] {
             ctl.connect_changed(clone!(@weak me => move |_| {
                 me.update_ui()
@@ -393,6 +415,7 @@ impl PubkeyDlg {
             self.apply_tracking_account(tracking_account);
         }
 
+        *me.chain.borrow_mut() = chain.clone();
         me.network_combo.set_active_id(Some(&chain.to_string()));
 
         me.update_ui();
@@ -435,20 +458,49 @@ impl PubkeyDlg {
                 self.range_field.set_text(&keyset.index_ranges_string());
                 self.range_chk.set_active(keyset.index_ranges.is_some());
 
-                // TODO: Parse BIP44 derivation info
-                // let derivation: Vec<_> = keyset.derivation_path().into();
-                // if derivation.len() == 6 {
-                //     self.set_derive_type(DeriveType::Bip44);
-                // } else {
-                self.set_derive_type(DeriveType::Custom);
-                self.derivation_field
-                    .set_text(&keyset.derivation_path().to_string());
-                // }
+                match DerivationPreset::detect_components(&keyset) {
+                    // Standard form always includes change level, so
+                    // account-level paths are shown as custom ones
+                    Some(preset) if preset.change.is_some() => {
+                        self.apply_derivation_preset(preset);
+                        self.set_derive_type(DeriveType::Bip44);
+                    }
+                    _ => {
+                        self.set_derive_type(DeriveType::Custom);
+                        self.derivation_field
+                            .set_text(&keyset.derivation_path().to_string());
+                    }
+                }
             }
             _ => unreachable!(),
         }
     }
 
+    pub fn apply_derivation_preset(&self, preset: DerivationPreset) {
+        self.purpose_combo
+            .set_active_id(Some(&preset.scheme.to_string()));
+        if !self
+            .asset_combo
+            .set_active_id(Some(&preset.coin_type.to_string()))
+        {
+            self.asset_combo.set_active(Some(4));
+            self.asset_index.set_value(preset.coin_type as f64);
+        }
+        self.account_index.set_value(preset.account as f64);
+        if let Some(change) = preset.change {
+            if !self.change_combo.set_active_id(Some(&change.to_string())) {
+                self.change_combo.set_active(Some(2));
+                self.change_index.set_value(change as f64);
+            }
+        }
+    }
+
+    pub fn derivation_scheme(&self) -> Option<DerivationScheme> {
+        self.purpose_combo
+            .active_id()
+            .and_then(|id| DerivationScheme::from_str(&id).ok())
+    }
+
     pub fn tracking_account(&self) -> Result<TrackingAccount, Error> {
         let key = if self.sk_radio.is_active() {
             descriptor::SingleSig::Pubkey(DescriptorSinglePub {
@@ -459,18 +511,73 @@ impl PubkeyDlg {
             descriptor::SingleSig::XPubDerivable(self.derivation_components()?)
         };
 
-        Ok(TrackingAccount {
+        let tracking_account = TrackingAccount {
             name: self.name_field.text().to_string(),
             key,
-        })
+        };
+        tracking_account.check_network(&self.chain.borrow())?;
+        Ok(tracking_account)
+    }
+
+    /// SLIP-132 application selected for the derived extended public key,
+    /// if it is set explicitly instead of being taken from the scheme
+    pub fn xpub_application(&self) -> Option<KeyApplication> {
+        match self.xpub_format_combo.active_id()?.as_str() {
+            "xpub" => Some(KeyApplication::Hashed),
+            "ypub" => Some(KeyApplication::Nested),
+            "zpub" => Some(KeyApplication::SegWit),
+            "Ypub" => Some(KeyApplication::NestedMultisig),
+            "Zpub" => Some(KeyApplication::SegWitMiltisig),
+            _ => None,
+        }
+    }
+
+    /// Verifies that the extended key entered into the field matches the
+    /// network selected for the dialog. Empty fields are not checked.
+    fn check_xkey_network(
+        &self,
+        field: &gtk::Entry,
+        network: bitcoin::Network,
+    ) -> Result<(), Error> {
+        let text = field.text();
+        if text.is_empty() {
+            return Ok(());
+        }
+        let format = XkeyFormat::detect(&text)?;
+        field.set_tooltip_text(Some(&format!(
+            "{} key, {} {} encoding",
+            if format.private { "Private" } else { "Public" },
+            if format.testnet { "testnet" } else { "mainnet" },
+            format.prefix()
+        )));
+        if format.testnet != (network != bitcoin::Network::Bitcoin) {
+            return Err(Error::NetworkMismatch(
+                format.prefix(),
+                self.network_combo
+                    .active_id()
+                    .map(|id| id.to_string())
+                    .unwrap_or_else(|| network.to_string()),
+            ));
+        }
+        Ok(())
     }
 
     pub fn derivation_path(
         &self,
         extended: bool,
     ) -> Result<DerivationPath, Error> {
-        let mut derivation = if self.bip44_radio.is_active() {
-            DerivationPath::from_str(&format!(
+        let mut derivation = match (
+            self.bip44_radio.is_active(),
+            self.derivation_scheme(),
+        ) {
+            (true, Some(scheme)) => DerivationPreset {
+                scheme,
+                coin_type: self.asset_index.value() as u32,
+                account: self.account_index.value() as u32,
+                change: Some(self.change_index.value() as u32),
+            }
+            .derivation_path(),
+            (true, None) => DerivationPath::from_str(&format!(
                 "m/{}{}/{}{}/{}{}/{}{}",
                 self.purpose_index.value() as u32,
                 if self.purpose_chk.is_active() {
@@ -488,9 +595,10 @@ impl PubkeyDlg {
                 },
                 self.change_index.value() as u32,
                 if self.change_chk.is_active() { "'" } else { "" }
-            ))?
-        } else {
-            DerivationPath::from_str(&self.derivation_field.text())?
+            ))?,
+            (false, _) => {
+                DerivationPath::from_str(&self.derivation_field.text())?
+            }
         };
 
         if extended {
@@ -669,16 +777,19 @@ impl PubkeyDlg {
             );
         }
 
-        if self.purpose_combo.active() != Some(4) {
+        if let Some(scheme) = self.derivation_scheme() {
             self.purpose_index.set_sensitive(false);
             self.purpose_chk.set_sensitive(false);
-            self.purpose_index.set_value(
-                self.purpose_combo
-                    .active_id()
-                    .map(|s| f64::from_str(&s).unwrap_or_default())
-                    .unwrap_or_default(),
-            );
+            self.purpose_index.set_value(scheme.purpose() as f64);
             self.purpose_chk.set_active(true);
+            // Standard schemes define hardening of all levels
+            self.account_chk.set_sensitive(false);
+            self.account_chk.set_active(scheme.has_coin_type());
+            self.change_chk.set_sensitive(false);
+            self.change_chk.set_active(false);
+            if !scheme.has_coin_type() {
+                self.asset_combo.set_sensitive(false);
+            }
         }
 
         if self.asset_combo.active() != Some(4) {
@@ -693,6 +804,15 @@ impl PubkeyDlg {
             self.asset_chk.set_active(true);
         }
 
+        if !self
+            .derivation_scheme()
+            .map(DerivationScheme::has_coin_type)
+            .unwrap_or(true)
+        {
+            self.asset_index.set_sensitive(false);
+            self.asset_chk.set_sensitive(false);
+        }
+
         if self.change_combo.active() != Some(2) {
             self.change_index.set_sensitive(false);
             self.change_chk.set_sensitive(false);
@@ -732,12 +852,28 @@ impl PubkeyDlg {
             _ => return Err(Error::UnsupportedBlockchain),
         };
 
+        let chain = self.chain.borrow().clone();
+        if xkey::is_network_compatible(network, &chain) {
+            self.network_combo.set_tooltip_text(None);
+        } else {
+            let msg = format!(
+                "NB: Keys for the selected blockchain can't be tracked by \
+                the document, which is used for {}",
+                chain
+            );
+            self.network_combo.set_tooltip_text(Some(&msg));
+            info_msg = Some(msg);
+        }
+
         let pk = if self.sk_radio.is_active() {
             let pk_str = self.pubkey_field.text();
             bitcoin::PublicKey::from_str(&pk_str)?
         } else {
             self.offset_chk.set_sensitive(true);
 
+            self.check_xkey_network(&self.xpub_field, network)?;
+            self.check_xkey_network(&self.account_field, network)?;
+
             let derivation = self.derivation_path(true)?;
             let terminal = derivation
                 .hardened_normal_split()
@@ -812,7 +948,17 @@ impl PubkeyDlg {
                     .strip_prefix('m')
                     .unwrap_or(&derivation.to_string())
             ));
-            self.xpub_display.set_text(&xpubkey.to_string());
+            let key_application = match self.xpub_application() {
+                Some(application) => application,
+                None => DerivationPreset::detect(&self.derivation_path(false)?)
+                    .map(|preset| preset.scheme.key_application())
+                    .unwrap_or(KeyApplication::Hashed),
+            };
+            self.xpub_display.set_text(&xkey::convert(
+                &xpubkey.to_string(),
+                key_application,
+                network != bitcoin::Network::Bitcoin,
+            )?);
 
             if self.range_chk.is_active() {
                 let (lower, upper) =
@@ -0,0 +1,224 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use gtk::prelude::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use bitcoin::util::bip32::{self, ExtendedPrivKey};
+use lnpbp::Chain;
+use slip132::{self, FromSlip132};
+
+use crate::controller::psbt_signer::PsbtSigner;
+use crate::model::{MnemonicSeed, SeedError};
+use crate::util::xkey;
+
+static UI: &str = include_str!("../view/sign.glade");
+
+#[derive(Debug, Display, From, Error)]
+#[display(doc_comments)]
+/// Errors from the signing dialog
+pub enum Error {
+    /// BIP32-specific error
+    #[display("{0}")]
+    #[from]
+    Bip32(bip32::Error),
+
+    /// SLIP-32 specific error
+    #[display("{0}")]
+    #[from]
+    Slip32(slip132::Error),
+
+    /// BIP39 mnemonic error
+    #[display("{0}")]
+    #[from]
+    Seed(SeedError),
+
+    /// You have to provide master private key or mnemonic
+    NoMasterKey,
+
+    /// Passphrase can be used only with a mnemonic, not with an extended
+    /// private key
+    UnusedPassphrase,
+}
+
+pub struct SignDlg {
+    dialog: gtk::Dialog,
+    msg_box: gtk::Box,
+    msg_label: gtk::Label,
+    msg_image: gtk::Image,
+    sign_btn: gtk::Button,
+    cancel_btn: gtk::Button,
+
+    xprv_field: gtk::Entry,
+    passphrase_field: gtk::Entry,
+
+    chain: RefCell<Chain>,
+}
+
+impl SignDlg {
+    pub fn load_glade() -> Option<Rc<Self>> {
+        let builder = gtk::Builder::from_string(UI);
+
+        let sign_btn = builder.object("sign")?;
+        let cancel_btn = builder.object("cancel")?;
+
+        let msg_box = builder.object("messageBox")?;
+        let msg_image = builder.object("messageImage")?;
+        let msg_label = builder.object("messageLabel")?;
+
+        let xprv_field = builder.object("xprvField")?;
+        let passphrase_field = builder.object("passphraseField")?;
+
+        let me = Rc::new(Self {
+            dialog: glade_load!(builder, "signDlg").ok()?,
+            msg_box,
+            msg_label,
+            msg_image,
+            sign_btn,
+            cancel_btn,
+            xprv_field,
+            passphrase_field,
+            chain: RefCell::new(Chain::default()),
+        });
+
+        for ctl in &[&me.xprv_field, &me.passphrase_field] {
+            ctl.connect_changed(clone!(@weak me => move |_| {
+                me.update_ui()
+            }));
+        }
+
+        Some(me)
+    }
+}
+
+impl SignDlg {
+    /// Runs the dialog; the signer is passed to `on_sign` and is dropped
+    /// (with the key zeroized) right after it returns
+    pub fn run(
+        self: Rc<Self>,
+        chain: &Chain,
+        on_sign: impl Fn(PsbtSigner) + 'static,
+        on_cancel: impl Fn() + 'static,
+    ) {
+        let me = self.clone();
+
+        *me.chain.borrow_mut() = chain.clone();
+
+        me.update_ui();
+
+        me.cancel_btn
+            .connect_clicked(clone!(@weak self as me => move |_| {
+                me.clear_secrets();
+                me.dialog.close();
+                on_cancel()
+            }));
+
+        me.sign_btn.connect_clicked(
+            clone!(@weak self as me => move |_| match me.signer() {
+                Ok(signer) => {
+                    me.clear_secrets();
+                    me.dialog.close();
+                    on_sign(signer);
+                }
+                Err(err) => {
+                    me.display_error(err);
+                    me.sign_btn.set_sensitive(false);
+                }
+            }),
+        );
+
+        me.dialog.run();
+        me.clear_secrets();
+        me.dialog.close();
+    }
+
+    /// Removes master key, mnemonic and passphrase from the entry fields
+    pub fn clear_secrets(&self) {
+        self.xprv_field.set_text("");
+        self.passphrase_field.set_text("");
+    }
+
+    /// Creates signer from the master key or mnemonic entered into the
+    /// dialog. For mnemonics the network is taken from the document chain.
+    pub fn signer(&self) -> Result<PsbtSigner, Error> {
+        let secret = self.xprv_field.text();
+        let passphrase = self.passphrase_field.text();
+        if secret.is_empty() {
+            return Err(Error::NoMasterKey);
+        }
+        if MnemonicSeed::is_mnemonic_like(&secret) {
+            let network = if xkey::is_test_chain(&self.chain.borrow()) {
+                bitcoin::Network::Testnet
+            } else {
+                bitcoin::Network::Bitcoin
+            };
+            let seed = MnemonicSeed::with(&secret, &passphrase)?;
+            Ok(PsbtSigner::with_seed(&seed, network)?)
+        } else if !passphrase.is_empty() {
+            Err(Error::UnusedPassphrase)
+        } else {
+            Ok(PsbtSigner::with(ExtendedPrivKey::from_slip132_str(
+                &secret,
+            )?))
+        }
+    }
+
+    pub fn display_info(&self, msg: impl ToString) {
+        self.msg_label.set_text(&msg.to_string());
+        self.msg_image.set_from_icon_name(
+            Some("dialog-information"),
+            gtk::IconSize::SmallToolbar,
+        );
+        self.msg_box.set_visible(true);
+    }
+
+    pub fn display_error(&self, msg: impl std::error::Error) {
+        self.msg_label.set_text(&msg.to_string());
+        self.msg_image.set_from_icon_name(
+            Some("dialog-error"),
+            gtk::IconSize::SmallToolbar,
+        );
+        self.msg_box.set_visible(true);
+    }
+
+    /// Only checks that the fields are filled in: parsing of a mnemonic
+    /// is slow and happens when the signing is started
+    pub fn update_ui(&self) {
+        let res = if self.xprv_field.text().is_empty() {
+            Err(Error::NoMasterKey)
+        } else if !MnemonicSeed::is_mnemonic_like(&self.xprv_field.text())
+            && !self.passphrase_field.text().is_empty()
+        {
+            Err(Error::UnusedPassphrase)
+        } else {
+            Ok(())
+        };
+        match res {
+            Ok(()) => {
+                self.display_info(
+                    "Inputs with key origins matching the key will be signed",
+                );
+                self.sign_btn.set_sensitive(true);
+            }
+            Err(err) => {
+                self.display_error(err);
+                self.sign_btn.set_sensitive(false);
+            }
+        }
+    }
+}